@@ -0,0 +1,57 @@
+//! Time looking up candidates by ID, for fields of candidates from small to far larger than any
+//! real Senate ballot. The time per lookup should stay flat as the field grows.
+//!
+//! Run with `cargo bench --bench candidate_lookup` from this directory.
+#[macro_use]
+extern crate criterion;
+extern crate aus_senate;
+extern crate rand;
+
+use aus_senate::candidate::{Candidate, CandidateId, CandidateMap};
+use criterion::{BenchmarkId, Criterion, Throughput};
+use rand::{Rng, SeedableRng, StdRng};
+
+const FIELD_SIZES: &[u16] = &[10, 100, 1_000, 10_000];
+const NUM_LOOKUPS: usize = 1_000;
+
+fn candidates(n: u16) -> CandidateMap {
+    (0..n)
+        .map(CandidateId)
+        .map(|id| {
+            let candidate = Candidate {
+                id,
+                surname: format!("CANDIDATE{}", id),
+                other_names: String::new(),
+                group_name: String::new(),
+                party: String::new(),
+                state: "XYZ".to_string(),
+                withdrawn: false,
+            };
+            (id, candidate)
+        })
+        .collect()
+}
+
+fn bench_candidate_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("candidate_lookup");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    for &n in FIELD_SIZES {
+        let candidates = candidates(n);
+        let mut rng: StdRng = SeedableRng::from_seed(&[usize::from(n)][..]);
+        let lookups: Vec<_> = (0..NUM_LOOKUPS)
+            .map(|_| CandidateId(rng.gen_range(0, n)))
+            .collect();
+        group.bench_with_input(BenchmarkId::new("by_id", n), &lookups, |b, lookups| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|&&id| !candidates[&id].withdrawn)
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_candidate_lookup);
+criterion_main!(benches);
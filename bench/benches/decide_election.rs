@@ -0,0 +1,115 @@
+//! Time `decide_election` on synthetic ballots for a half-Senate election in a single state.
+//!
+//! Run with `cargo bench` from this directory.
+#[macro_use]
+extern crate criterion;
+extern crate aus_senate;
+extern crate rand;
+
+use aus_senate::ballot::Ballot;
+use aus_senate::candidate::{Candidate, CandidateId, CandidateMap};
+use aus_senate::config::ElectionConfig;
+use aus_senate::quota::{Droop, Hare, Imperial, Quota};
+use aus_senate::transfer::SurplusTransfer;
+use aus_senate::voting::decide_election;
+use criterion::{BenchmarkId, Criterion, Throughput};
+use rand::{Rng, SeedableRng, StdRng};
+
+const NUM_GROUPS: u16 = 20;
+const GROUP_SIZE: u16 = 3;
+const NUM_VACANCIES: usize = 6;
+const BALLOT_COUNTS: &[usize] = &[1_000, 10_000, 100_000];
+
+fn candidates() -> CandidateMap {
+    (0..NUM_GROUPS * GROUP_SIZE)
+        .map(CandidateId)
+        .map(|id| {
+            let candidate = Candidate {
+                id,
+                surname: format!("CANDIDATE{}", id),
+                other_names: String::new(),
+                group_name: format!("{}", id.0 / GROUP_SIZE),
+                party: format!("Party {}", id.0 / GROUP_SIZE),
+                state: "XYZ".to_string(),
+                withdrawn: false,
+            };
+            (id, candidate)
+        })
+        .collect()
+}
+
+/// Mostly above the line votes numbering at least 6 groups, with bigger groups earlier on the
+/// ballot paper, and some below the line votes.
+fn ballots(num_ballots: usize) -> Vec<Ballot> {
+    let mut rng: StdRng = SeedableRng::from_seed(&[2016usize][..]);
+    (0..num_ballots)
+        .map(|_| {
+            if rng.gen_weighted_bool(10) {
+                let mut prefs: Vec<_> = (0..NUM_GROUPS * GROUP_SIZE).map(CandidateId).collect();
+                rng.shuffle(&mut prefs);
+                prefs.truncate(rng.gen_range(6, prefs.len() + 1));
+                return Ballot::single(prefs);
+            }
+            let mut groups: Vec<u16> = (0..NUM_GROUPS).collect();
+            groups.sort_by_key(|&g| rng.gen_range(0, u32::from(g) * u32::from(g) + 2));
+            groups.truncate(rng.gen_range(6, groups.len() + 1));
+            let prefs = groups
+                .into_iter()
+                .flat_map(|g| (g * GROUP_SIZE..(g + 1) * GROUP_SIZE).map(CandidateId))
+                .collect();
+            Ballot::single(prefs)
+        })
+        .collect()
+}
+
+fn quota_methods() -> Vec<(&'static str, Box<dyn Quota>)> {
+    vec![
+        ("droop", Box::new(Droop)),
+        ("hare", Box::new(Hare)),
+        ("imperial", Box::new(Imperial)),
+    ]
+}
+
+fn bench_decide_election(c: &mut Criterion) {
+    let candidates = candidates();
+    let transfers = [
+        ("inclusive_gregory", SurplusTransfer::InclusiveGregory),
+        (
+            "weighted_inclusive_gregory",
+            SurplusTransfer::WeightedInclusiveGregory,
+        ),
+        ("last_parcel", SurplusTransfer::LastParcel),
+    ];
+
+    let ballot_sets: Vec<_> = BALLOT_COUNTS.iter().map(|&n| ballots(n)).collect();
+
+    for &(transfer_name, transfer) in &transfers {
+        let mut group = c.benchmark_group(format!("decide_election/{}", transfer_name));
+        group.sample_size(10);
+
+        for (quota_name, quota) in quota_methods() {
+            let mut config = ElectionConfig::builder(NUM_VACANCIES)
+                .transfer(transfer)
+                .tie_break_seed(0)
+                .build();
+            config.quota = quota;
+            for ballots in &ballot_sets {
+                group.throughput(Throughput::Elements(ballots.len() as u64));
+                group.bench_with_input(
+                    BenchmarkId::new(quota_name, ballots.len()),
+                    ballots,
+                    |b, ballots| {
+                        b.iter(|| {
+                            let ballots = ballots.iter().cloned().map(Ok);
+                            decide_election(&candidates, &[], ballots, &config).unwrap()
+                        })
+                    },
+                );
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_decide_election);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! Time parsing of synthetic preference strings, with and without reusing the parsing buffers.
+//!
+//! Run with `cargo bench --bench parse_ballots` from this directory.
+#[macro_use]
+extern crate criterion;
+extern crate aus_senate;
+extern crate rand;
+
+use aus_senate::ballot_parse::{
+    parse_ballot_str, parse_ballot_str_with, Constraints, ParseScratch,
+};
+use aus_senate::candidate::CandidateId;
+use aus_senate::group::{ticket_letter, Group};
+use criterion::{Criterion, Throughput};
+use rand::{Rng, SeedableRng, StdRng};
+
+const NUM_GROUPS: u16 = 20;
+const GROUP_SIZE: u16 = 3;
+const NUM_BALLOTS: usize = 10_000;
+
+fn groups() -> Vec<Group> {
+    (0..NUM_GROUPS)
+        .map(|g| Group {
+            name: format!("{}", g),
+            ticket_letter: Some(ticket_letter(usize::from(g))),
+            candidate_ids: (g * GROUP_SIZE..(g + 1) * GROUP_SIZE)
+                .map(CandidateId)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Preference strings in the 2016 layout, mostly above the line, as in the AEC's files.
+fn pref_strings() -> Vec<String> {
+    let mut rng: StdRng = SeedableRng::from_seed(&[2016usize][..]);
+    let num_boxes = usize::from(NUM_GROUPS + NUM_GROUPS * GROUP_SIZE);
+    (0..NUM_BALLOTS)
+        .map(|_| {
+            let mut boxes = vec![String::new(); num_boxes];
+            let (offset, len) = if rng.gen_weighted_bool(10) {
+                (
+                    usize::from(NUM_GROUPS),
+                    usize::from(NUM_GROUPS * GROUP_SIZE),
+                )
+            } else {
+                (0, usize::from(NUM_GROUPS))
+            };
+            let mut order: Vec<usize> = (0..len).collect();
+            rng.shuffle(&mut order);
+            let num_prefs = rng.gen_range(1, 13);
+            for (pref, &idx) in order.iter().take(num_prefs).enumerate() {
+                boxes[offset + idx] = (pref + 1).to_string();
+            }
+            boxes.join(",")
+        })
+        .collect()
+}
+
+fn bench_parse_ballots(c: &mut Criterion) {
+    let groups = groups();
+    let candidates: Vec<_> = (0..NUM_GROUPS * GROUP_SIZE).map(CandidateId).collect();
+    let constraints = Constraints::official();
+    let pref_strings = pref_strings();
+
+    let mut group = c.benchmark_group("parse_ballots");
+    group.throughput(Throughput::Elements(pref_strings.len() as u64));
+    group.bench_function("fresh_buffers", |b| {
+        b.iter(|| {
+            pref_strings
+                .iter()
+                .filter(|s| parse_ballot_str(s, &groups, &candidates, &constraints).is_ok())
+                .count()
+        })
+    });
+    group.bench_function("reused_buffers", |b| {
+        let mut scratch = ParseScratch::default();
+        b.iter(|| {
+            pref_strings
+                .iter()
+                .filter(|s| {
+                    parse_ballot_str_with(&mut scratch, s, &groups, &candidates, &constraints)
+                        .is_ok()
+                })
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_ballots);
+criterion_main!(benches);
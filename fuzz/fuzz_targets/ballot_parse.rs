@@ -0,0 +1,69 @@
+//! Feed arbitrary preference strings to the ballot parser, checking that it never panics and that
+//! every formal ballot is a valid ordering of candidates.
+//!
+//! Run with `cargo fuzz run ballot_parse`, from the repository root.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate aus_senate;
+
+use aus_senate::ballot_parse::*;
+use aus_senate::candidate::CandidateId;
+use aus_senate::error::Error;
+use aus_senate::group::{ticket_letter, Group, UNGROUPED};
+
+/// Three groups, the last with a single candidate, and an ungrouped candidate below the line.
+fn ballot_paper() -> (Vec<Group>, Vec<CandidateId>) {
+    let group = |name: &str, ticket_letter: Option<String>, ids: &[u16]| Group {
+        name: name.to_string(),
+        ticket_letter,
+        candidate_ids: ids.iter().cloned().map(CandidateId).collect(),
+    };
+    let groups = vec![
+        group("A", Some(ticket_letter(0)), &[0, 1, 2]),
+        group("B", Some(ticket_letter(1)), &[3, 4]),
+        group("C", Some(ticket_letter(2)), &[5]),
+        group(UNGROUPED, None, &[6]),
+    ];
+    (groups, (0..7).map(CandidateId).collect())
+}
+
+fn check_ballot(prefs: &[CandidateId], candidates: &[CandidateId]) {
+    assert!(!prefs.is_empty());
+    for (i, id) in prefs.iter().enumerate() {
+        assert!(candidates.contains(id), "unknown candidate {}", id);
+        assert!(
+            !prefs[..i].contains(id),
+            "candidate {} preferenced twice",
+            id
+        );
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let pref_string = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let (groups, candidates) = ballot_paper();
+
+    for constraints in &[
+        Constraints::official(),
+        Constraints::with_min_preferences(6, 12),
+    ] {
+        match parse_ballot_str(pref_string, &groups, &candidates, constraints) {
+            Ok(ballot) => check_ballot(&ballot.prefs, &candidates),
+            Err(InvalidBallot(_)) => (),
+            Err(InputError(Error::WrongNumberOfPreferences { expected, found })) => {
+                assert_eq!(expected, 10);
+                assert_ne!(found, expected);
+            }
+            Err(InputError(e)) => panic!("unexpected input error: {}", e),
+        }
+
+        // Any number of boxes is fine for the formality check alone.
+        if let Ok(ballot) = check_formality(pref_string, &groups, &candidates, constraints) {
+            check_ballot(&ballot.prefs, &candidates);
+        }
+    }
+});
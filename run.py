@@ -28,7 +28,12 @@ def run():
 
         state_csv = os.path.join(data_dir, "{}.csv".format(state))
 
-        args = [candidate_ordering, state_csv, state, str(num_senators)]
+        args = [
+            "--candidates", candidate_ordering,
+            "--prefs", state_csv,
+            "--state", state,
+            "--seats", str(num_senators),
+        ]
 
         sp.call(cargo + args)
 
@@ -2,17 +2,19 @@ use ballot::Ballot;
 use candidate::*;
 use stats::Stats;
 use util::*;
+use value::VoteValue;
 use vote_map::*;
 
 /// Given a list of ballots, group them by next valid candidate.
-pub fn group_ballots_by_candidate<'a>(
-    vote_map: &VoteMap<'a>,
+pub fn group_ballots_by_candidate<'a, V: VoteValue>(
+    vote_map: &VoteMap<'a, V>,
     ballots: Vec<&'a mut Ballot>,
     vote_round: usize,
-    transfer_val: &Frac,
+    transfer_val: &V,
     stats: &mut Stats,
 ) -> HashMap<CandidateId, Vec<&'a mut Ballot>> {
     let mut map = HashMap::new();
+    let exhausted_val = transfer_val.to_frac();
 
     for ballot in ballots {
         if let Some(i) = vote_map.find_next_valid_preference(ballot) {
@@ -23,16 +25,18 @@ pub fn group_ballots_by_candidate<'a>(
             let bucket = map.entry(continuing_candidate).or_insert_with(Vec::new);
             bucket.push(ballot);
         } else {
-            stats.record_exhausted_vote(vote_round, transfer_val);
+            stats.record_exhausted_vote(vote_round, ballot, &exhausted_val);
         }
     }
 
     map
 }
 
-/// Compute the value of a list of ballots at a given weight (transfer value).
-pub fn ballot_value<'a>(weight: &Frac, ballots: &[&'a mut Ballot]) -> Int {
-    let num_ballots: u32 = ballots.iter().map(|b| b.weight()).sum();
-    let value = weight * frac!(num_ballots);
-    value.floor()
+/// Compute the exact value of a list of ballots at a given weight (transfer value).
+///
+/// This is exact whatever the arithmetic, unlike `VoteValue::times_papers`, which rounds down
+/// if it has to.
+pub fn exact_ballot_value<V: VoteValue>(weight: &V, ballots: &[&mut Ballot]) -> Frac {
+    let num_ballots: u64 = ballots.iter().map(|b| b.weight()).sum();
+    weight.to_frac() * Frac::from(Int::from(num_ballots))
 }
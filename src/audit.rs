@@ -0,0 +1,132 @@
+//! Checking that the books balance at every count of a finished election.
+//!
+//! At the end of each count, the votes of the continuing candidates, the votes kept by the
+//! senators whose ballots have left the count, and the votes exhausted so far should add up to
+//! the formal ballots, less the votes lost by fraction. Any other difference is a bug in the
+//! arithmetic of the count.
+
+use error::Error;
+use senate_result::Senate;
+use transcript::CountAction;
+use util::*;
+
+/// Check that the votes add up at every count of `result`, from its transcript.
+///
+/// Counts in the middle of a transfer made over several counts are skipped, as some of the
+/// ballots being transferred are still in transit, as is the last count if the count stopped
+/// before all of an exclusion was transferred, which can only lose votes.
+pub fn audit(result: &Senate) -> Result<(), Error> {
+    let counts = match result.transcript {
+        Some(ref transcript) => &transcript.counts,
+        None => {
+            return Err(Error::Config(
+                "auditing the count needs a transcript".to_string(),
+            ))
+        }
+    };
+    let total = Frac::from(Int::from(result.stats.num_valid_votes()));
+    // Senators elected so far, with their votes when elected.
+    let mut senators: BTreeMap<_, Int> = BTreeMap::new();
+    let mut surplus_transferred = vec![];
+    let mut expected_loss = frac!(0u64);
+    let zero = frac!(0u64);
+
+    for (i, record) in counts.iter().enumerate() {
+        if let CountAction::Surplus(id) = record.action {
+            surplus_transferred.push(id);
+        }
+        expected_loss += &record.loss_by_fraction;
+        let transfer_continues = counts
+            .get(i + 1)
+            .is_some_and(|next| next.action == record.action);
+
+        if !transfer_continues {
+            let mut accounted = frac!(0u64);
+            for count in record.votes.values() {
+                accounted += Frac::from(count.total.clone());
+            }
+            // A senator keeps a quota once their surplus is transferred, and every vote until then.
+            for (id, tally) in &senators {
+                let retained = if surplus_transferred.contains(id) {
+                    &result.quota
+                } else {
+                    tally
+                };
+                accounted += Frac::from(retained.clone());
+            }
+            for (&count, (_, value)) in &result.stats.exhausted_votes {
+                if count < record.count {
+                    accounted += value;
+                }
+            }
+
+            let discrepancy = total.clone() - accounted - &expected_loss;
+            let stopped_early =
+                i + 1 == counts.len() && matches!(record.action, CountAction::Exclusion(_));
+            if discrepancy != zero && !(stopped_early && discrepancy > zero) {
+                return Err(Error::Audit {
+                    count: record.count,
+                    discrepancy,
+                });
+            }
+        }
+
+        for id in &record.elected {
+            senators.insert(*id, record.votes[id].total.clone());
+        }
+    }
+
+    let discrepancy = result.stats.loss_by_fraction() - expected_loss;
+    if discrepancy != zero {
+        return Err(Error::Audit {
+            count: counts.len(),
+            discrepancy,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot::Ballot;
+    use candidate::{ids, test_candidates, CandidateId};
+    use config::ElectionConfig;
+    use voting::decide_election;
+
+    #[test]
+    fn books_balance() {
+        let ballots = [
+            Ballot::multi(7, ids(&[0, 1, 2])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2, 0])),
+            Ballot::multi(1, ids(&[3, 2])),
+        ];
+        let count = |config: &ElectionConfig| {
+            decide_election(
+                &test_candidates(4),
+                &[],
+                ballots.iter().cloned().map(Ok),
+                config,
+            )
+        };
+        let mut result = count(&ElectionConfig::builder(2).transcript(true).build()).unwrap();
+        audit(&result).unwrap();
+        let audited = ElectionConfig::builder(2).transcript(true).audit(true);
+        assert!(count(&audited.build()).is_ok());
+        assert!(count(&ElectionConfig::builder(2).audit(true).build()).is_err());
+
+        // Lose a vote from the second count on.
+        let counts = &mut result.transcript.as_mut().unwrap().counts;
+        for record in &mut counts[1..] {
+            record.votes.get_mut(&CandidateId(1)).unwrap().total -= Int::from(1);
+        }
+        match audit(&result) {
+            Err(Error::Audit { count, discrepancy }) => {
+                assert_eq!(count, 2);
+                assert_eq!(discrepancy, frac!(1u64));
+            }
+            other => panic!("expected the audit to fail, got {:?}", other),
+        }
+    }
+}
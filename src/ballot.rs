@@ -1,14 +1,19 @@
+use std::iter::FromIterator;
+
+use sha2::{Digest, Sha256};
+
 use candidate::*;
+use util::*;
 
 /// A Ballot represents an individual's order of preferences.
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Ballot {
     /// Ordering of candidates.
     pub prefs: Vec<CandidateId>,
     /// Index of the first candidate in `prefs` who is still in the running.
     current: usize,
-    #[cfg(feature = "support2013")]
-    weight: u32,
+    /// Number of identical ballot papers represented by this ballot.
+    weight: u64,
 }
 
 impl Ballot {
@@ -16,13 +21,11 @@ impl Ballot {
         Ballot {
             prefs,
             current: 0,
-            #[cfg(feature = "support2013")]
             weight: 1,
         }
     }
 
-    #[cfg(feature = "support2013")]
-    pub fn multi(weight: u32, prefs: Vec<CandidateId>) -> Ballot {
+    pub fn multi(weight: u64, prefs: Vec<CandidateId>) -> Ballot {
         Ballot {
             prefs,
             current: 0,
@@ -30,16 +33,10 @@ impl Ballot {
         }
     }
 
-    #[cfg(feature = "support2013")]
-    pub fn weight(&self) -> u32 {
+    pub fn weight(&self) -> u64 {
         self.weight
     }
 
-    #[cfg(not(feature = "support2013"))]
-    pub fn weight(&self) -> u32 {
-        1
-    }
-
     pub fn current(&self) -> usize {
         self.current
     }
@@ -50,3 +47,149 @@ impl Ballot {
         self.current = idx;
     }
 }
+
+/// Collection of ballots in which identical preference sequences are stored once, with a weight.
+#[derive(Debug, Default)]
+pub struct BallotSet {
+    weights: HashMap<Vec<CandidateId>, u64>,
+}
+
+impl BallotSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a ballot, combining it with any identical ballots already added.
+    pub fn add(&mut self, ballot: Ballot) {
+        let weight = self.weights.entry(ballot.prefs).or_insert(0);
+        *weight = weight
+            .checked_add(ballot.weight)
+            .expect("overflow in ballot weight");
+    }
+
+    /// Number of distinct preference sequences.
+    pub fn num_distinct(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Convert into a list of weighted ballots, one per distinct preference sequence.
+    pub fn into_ballots(self) -> Vec<Ballot> {
+        self.weights
+            .into_iter()
+            .map(|(prefs, weight)| Ballot::multi(weight, prefs))
+            .collect()
+    }
+}
+
+impl FromIterator<Ballot> for BallotSet {
+    fn from_iter<I: IntoIterator<Item = Ballot>>(iter: I) -> Self {
+        let mut set = BallotSet::new();
+        for ballot in iter {
+            set.add(ballot);
+        }
+        set
+    }
+}
+
+/// Deterministic fingerprint of a set of ballots, to check that two counts read the same ballots.
+///
+/// This is the SHA-256 hash of the ballots in a canonical form, with identical preference
+/// sequences combined and the sequences sorted, so it doesn't depend on the order of the ballots
+/// or how they're combined into weighted ballots. Each sequence is hashed as its weight, its
+/// length and its candidate IDs, as little-endian `u64`, `u32` and `u16`s respectively.
+pub fn ballots_fingerprint(ballots: &[Ballot]) -> [u8; 32] {
+    let mut sorted: Vec<&Ballot> = ballots.iter().collect();
+    sorted.sort_by(|a, b| a.prefs.cmp(&b.prefs));
+
+    let mut hasher = Sha256::new();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < sorted.len() {
+        let prefs = &sorted[i].prefs;
+        let mut weight = 0u64;
+        while i < sorted.len() && sorted[i].prefs == *prefs {
+            weight += sorted[i].weight;
+            i += 1;
+        }
+
+        bytes.clear();
+        bytes.extend_from_slice(&weight.to_le_bytes());
+        bytes.extend_from_slice(&(prefs.len() as u32).to_le_bytes());
+        for id in prefs {
+            bytes.extend_from_slice(&id.0.to_le_bytes());
+        }
+        hasher.update(&bytes);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ballot_set_combines_identical() {
+        let set: BallotSet = vec![
+            Ballot::single(ids(&[0, 1])),
+            Ballot::single(ids(&[1])),
+            Ballot::multi(3, ids(&[0, 1])),
+            Ballot::single(ids(&[1, 0])),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(set.num_distinct(), 3);
+
+        let mut ballots = set.into_ballots();
+        ballots.sort_by(|a, b| a.prefs.cmp(&b.prefs));
+        let weights: Vec<_> = ballots
+            .iter()
+            .map(|b| (b.prefs.clone(), b.weight()))
+            .collect();
+        assert_eq!(
+            weights,
+            vec![(ids(&[0, 1]), 4), (ids(&[1]), 1), (ids(&[1, 0]), 1)]
+        );
+    }
+
+    #[test]
+    fn fingerprint_is_canonical() {
+        let ballots = vec![
+            Ballot::multi(2, ids(&[0, 1])),
+            Ballot::single(ids(&[1])),
+            Ballot::single(ids(&[2, 0])),
+        ];
+        let reordered = vec![
+            Ballot::single(ids(&[2, 0])),
+            Ballot::single(ids(&[0, 1])),
+            Ballot::single(ids(&[1])),
+            Ballot::single(ids(&[0, 1])),
+        ];
+        let fingerprint = ballots_fingerprint(&ballots);
+        assert_eq!(fingerprint, ballots_fingerprint(&reordered));
+
+        let set: BallotSet = reordered.into_iter().collect();
+        assert_eq!(fingerprint, ballots_fingerprint(&set.into_ballots()));
+
+        let different = vec![
+            Ballot::multi(2, ids(&[0, 1])),
+            Ballot::single(ids(&[1])),
+            Ballot::single(ids(&[2])),
+        ];
+        assert_ne!(fingerprint, ballots_fingerprint(&different));
+        assert_ne!(fingerprint, ballots_fingerprint(&[]));
+    }
+
+    #[test]
+    fn fingerprint_known_answer() {
+        // SHA-256 of the weight, length and candidate IDs of [0, 1] twice, then of [1] once.
+        let ballots = [Ballot::multi(2, ids(&[0, 1])), Ballot::single(ids(&[1]))];
+        let hex: String = ballots_fingerprint(&ballots)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            hex,
+            "1170353d1c412ea021ae647560b0a994f1fd3573bbcc0417855425d180911bd0"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Caches of the ballots read from a state's preferences files, so that the election can be
+//! counted again without parsing them.
+//!
+//! A cache holds each distinct sequence of preferences on the formal ballots once, with the number
+//! of ballots that had it, along with the statistics gathered while reading the files, like the
+//! numbers of informal ballots. Counting from a cache gives the same result as counting from the
+//! files it was read from, with the same formality rules.
+//!
+//! The file starts with `MAGIC`, then a little-endian `u64` giving the length of a JSON header
+//! holding everything but the ballots. Each distinct sequence follows as its number of ballots,
+//! its length and its candidate IDs, as little-endian `u64`, `u32` and `u16`s respectively, like
+//! the input to `ballots_fingerprint`. The whole file is gzipped if its name ends in `.gz`.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Read, Write};
+
+use ballot::{Ballot, BallotSet};
+use ballot_parse::BallotParseErr::InvalidBallot;
+use ballot_parse::IOBallot;
+use candidate::{CandidateId, CandidateMap};
+use config::{ElectionConfig, FormalityRules, MalformedBallots};
+use error::Error;
+use stats::Stats;
+use util::open_input;
+
+/// Bytes at the start of every ballot cache, ending in the version of the format.
+pub const MAGIC: &[u8; 8] = b"AUSBC\x00\x00\x01";
+
+/// The formal ballots read from a state's preferences files.
+#[derive(Debug, Clone)]
+pub struct BallotCache {
+    /// Abbreviation of the state the ballots were cast in.
+    pub state: String,
+    /// Rules the ballots were read with, which decide which of them are formal.
+    pub formality: FormalityRules,
+    pub malformed_ballots: MalformedBallots,
+    /// Statistics of reading the files, without any from counting the ballots.
+    pub stats: Stats,
+    /// The formal ballots, one for each distinct sequence of preferences.
+    pub ballots: Vec<Ballot>,
+}
+
+/// Everything in a cache but the ballots, as written at the start of the file.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    state: String,
+    formality: FormalityRules,
+    malformed_ballots: MalformedBallots,
+    stats: Stats,
+    num_sequences: u64,
+}
+
+impl BallotCache {
+    /// Collect the formal ballots read with `config`, combining those with the same preferences.
+    pub fn new(state: &str, ballots: BallotSet, stats: Stats, config: &ElectionConfig) -> Self {
+        let mut ballots = ballots.into_ballots();
+        // Sorted so that the same ballots always make the same file.
+        ballots.sort_by(|a, b| a.prefs.cmp(&b.prefs));
+        BallotCache {
+            state: state.to_string(),
+            formality: config.formality,
+            malformed_ballots: config.malformed_ballots,
+            stats,
+            ballots,
+        }
+    }
+
+    /// Check that the ballots can be counted for `state` with `config` and `candidates`, as if
+    /// they'd been read from the preferences files.
+    pub fn check(
+        &self,
+        state: &str,
+        config: &ElectionConfig,
+        candidates: &CandidateMap,
+    ) -> Result<(), Error> {
+        let mismatch = if self.state != state {
+            "state"
+        } else if self.formality != config.formality {
+            "formality rules"
+        } else if self.malformed_ballots != config.malformed_ballots {
+            "option for malformed rows"
+        } else if config.row_filter.is_some() {
+            return Err(Error::BallotCache(
+                "ballots can't be filtered by row once cached".to_string(),
+            ));
+        } else {
+            let unknown = self
+                .ballots
+                .iter()
+                .flat_map(|b| &b.prefs)
+                .find(|id| !candidates.contains_key(id));
+            return match unknown {
+                Some(id) => Err(Error::BallotCache(format!(
+                    "ballots have preferences for candidate {}, who isn't standing in {}",
+                    id, state
+                ))),
+                None => Ok(()),
+            };
+        };
+        Err(Error::BallotCache(format!(
+            "ballots were cached with a different {}",
+            mismatch
+        )))
+    }
+
+    /// The ballots to count, with an informal ballot for each one counted while reading the
+    /// files, so that they're recorded just as they were then.
+    pub fn ballot_stream<'a>(&'a self) -> impl Iterator<Item = IOBallot> + 'a {
+        let informal = self
+            .stats
+            .invalid_votes()
+            .iter()
+            .flat_map(|(&reason, &n)| (0..n).map(move |_| Err(InvalidBallot(reason))));
+        self.ballots.iter().cloned().map(Ok).chain(informal)
+    }
+
+    /// Write the cache, compressed with gzip if the file name ends in `.gz`.
+    ///
+    /// Like a checkpoint, the cache is written to a temporary file that then replaces `filename`.
+    pub fn write(&self, filename: &str) -> Result<(), Error> {
+        let tmp_filename = format!("{}.tmp", filename);
+        let file = File::create(&tmp_filename)?;
+        if filename.ends_with(".gz") {
+            let mut writer = BufWriter::new(GzEncoder::new(&file, Compression::fast()));
+            self.write_to(&mut writer)?;
+            writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        } else {
+            let mut writer = BufWriter::new(&file);
+            self.write_to(&mut writer)?;
+            writer.flush()?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_filename, filename)?;
+        Ok(())
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let header = Header {
+            state: self.state.clone(),
+            formality: self.formality,
+            malformed_ballots: self.malformed_ballots,
+            stats: self.stats.clone(),
+            num_sequences: self.ballots.len() as u64,
+        };
+        let header = serde_json::to_vec(&header).map_err(cache_error)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(header.len() as u64).to_le_bytes())?;
+        writer.write_all(&header)?;
+        for ballot in &self.ballots {
+            writer.write_all(&ballot.weight().to_le_bytes())?;
+            writer.write_all(&(ballot.prefs.len() as u32).to_le_bytes())?;
+            for id in &ballot.prefs {
+                writer.write_all(&id.0.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a cache written by `write`.
+    pub fn read(filename: &str) -> Result<BallotCache, Error> {
+        let mut reader = open_input(filename)?;
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::BallotCache(format!(
+                "{} isn't a ballot cache, or is from another version",
+                filename
+            )));
+        }
+        let mut header = vec![0; read_u64(&mut reader)? as usize];
+        reader.read_exact(&mut header)?;
+        let header: Header = serde_json::from_slice(&header).map_err(cache_error)?;
+
+        let mut ballots = Vec::with_capacity(header.num_sequences as usize);
+        for _ in 0..header.num_sequences {
+            let weight = read_u64(&mut reader)?;
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let prefs = (0..u32::from_le_bytes(len))
+                .map(|_| {
+                    let mut id = [0; 2];
+                    reader.read_exact(&mut id)?;
+                    Ok(CandidateId(u16::from_le_bytes(id)))
+                })
+                .collect::<Result<_, Error>>()?;
+            ballots.push(Ballot::multi(weight, prefs));
+        }
+        if !reader.fill_buf()?.is_empty() {
+            return Err(Error::BallotCache(format!(
+                "{} has more ballots than its header says",
+                filename
+            )));
+        }
+
+        Ok(BallotCache {
+            state: header.state,
+            formality: header.formality,
+            malformed_ballots: header.malformed_ballots,
+            stats: header.stats,
+            ballots,
+        })
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn cache_error(e: serde_json::Error) -> Error {
+    Error::BallotCache(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot_parse::InformalReason;
+    use candidate::{ids, test_candidates};
+    use senate_result::Senate;
+    use std::env;
+    use voting::{collect_ballots, decide_election};
+
+    #[test]
+    fn count_from_cache() {
+        let ballots = || {
+            vec![
+                Ok(Ballot::single(ids(&[0, 1]))),
+                Ok(Ballot::multi(5, ids(&[0, 1]))),
+                Err(InvalidBallot(InformalReason::BlankBallot)),
+                Ok(Ballot::multi(3, ids(&[1, 2]))),
+                Ok(Ballot::multi(2, ids(&[2]))),
+            ]
+        };
+        let candidates = test_candidates(3);
+        let config = ElectionConfig::new(2);
+        let direct = decide_election(&candidates, &[], ballots(), &config).unwrap();
+        let senators = |result: &Senate| -> Vec<_> {
+            result
+                .senators
+                .iter()
+                .map(|(c, votes)| (c.id, votes.clone()))
+                .collect()
+        };
+
+        let (set, stats) = collect_ballots(ballots(), &config).unwrap();
+        let cache = BallotCache::new("TAS", set, stats, &config);
+        assert_eq!(cache.ballots.len(), 3);
+        for name in &[
+            "aus_senate_test_ballot_cache.bin",
+            "aus_senate_test_ballot_cache.bin.gz",
+        ] {
+            let path = env::temp_dir().join(name);
+            let path = path.to_str().unwrap();
+            cache.write(path).unwrap();
+            let read = BallotCache::read(path).unwrap();
+            assert_eq!(read.ballots, cache.ballots);
+            read.check("TAS", &config, &candidates).unwrap();
+
+            let cached = decide_election(&candidates, &[], read.ballot_stream(), &config).unwrap();
+            assert_eq!(senators(&cached), senators(&direct));
+            assert_eq!(cached.stats.num_invalid_votes(), 1);
+            assert_eq!(cached.first_preferences, direct.first_preferences);
+        }
+
+        assert!(cache.check("NSW", &config, &candidates).is_err());
+        let strict = ElectionConfig::builder(2).min_preferences(6, 12).build();
+        assert!(cache.check("TAS", &strict, &candidates).is_err());
+        assert!(cache.check("TAS", &config, &test_candidates(2)).is_err());
+    }
+}
@@ -1,39 +1,94 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::cmp::Ordering::*;
-use std::cmp::{min, Ordering};
 use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+use std::str;
 
 use ballot::*;
 use candidate::*;
-use group::Group;
-use std::error::Error;
+use config::FormalityRules;
+use error::Error;
+use group::{above_the_line_groups, Group};
+use stats::BallotErrorMap;
 
 pub use self::BallotParseErr::*;
 pub use self::ChoiceConstraint::*;
 pub use self::CountConstraint::*;
-pub use self::InvalidBallotErr::*;
+pub use self::InformalReason::*;
 
 #[derive(Debug)]
 pub enum BallotParseErr {
-    InvalidBallot(InvalidBallotErr),
-    InputError(Box<Error>),
+    InvalidBallot(InformalReason),
+    InputError(Error),
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
-pub enum InvalidBallotErr {
-    InvalidCharacter,
-    InvalidMinAbove(usize),
-    InvalidMaxAbove(usize),
-    InvalidMinBelow(usize),
-    InvalidMaxBelow(usize),
-    InvalidStrict,
-    EmptyBallot,
+/// Section of the ballot paper.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Section {
+    AboveTheLine,
+    BelowTheLine,
+}
+
+/// Reason that a ballot is informal.
+///
+/// If only one section of a ballot is marked, the reason given is for that section.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum InformalReason {
+    /// No boxes are marked in either section.
+    BlankBallot,
+    /// Boxes are marked, but there's no single first preference.
+    NoFirstPreference,
+    /// Both sections are formal, and the constraints require that only one is marked.
+    BothSectionsMarked,
+    /// Both sections are marked, but neither is formal.
+    NeitherSectionFormal,
+    /// Fewer consecutive preferences than the minimum for the section, which are given.
+    BelowMinimumPreferences(Section, usize),
+    /// More consecutive preferences than the maximum for the section, which are given.
+    AboveMaximumPreferences(Section, usize),
+}
+
+/// Whether a ballot is formal, and if not, why not.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Formality {
+    Formal,
+    Informal(InformalReason),
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Section::AboveTheLine => write!(f, "above the line"),
+            Section::BelowTheLine => write!(f, "below the line"),
+        }
+    }
+}
+
+impl fmt::Display for InformalReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlankBallot => write!(f, "blank ballot"),
+            NoFirstPreference => write!(f, "no first preference"),
+            BothSectionsMarked => write!(f, "marked both above and below the line"),
+            NeitherSectionFormal => write!(f, "neither section formal"),
+            BelowMinimumPreferences(section, _) => {
+                write!(f, "too few preferences {}", section)
+            }
+            AboveMaximumPreferences(section, _) => {
+                write!(f, "too many preferences {}", section)
+            }
+        }
+    }
 }
 
 /// This type is yielded from iterators used during ballot parsing.
 ///
 /// It allows us to capture GVT multi-votes, and handle the two different types of errors:
 ///     1. Ballot parsing errors, which are recoverable (skip the ballot).
-///     2. IO errors, CSV parsing errors, which are not recoverable (stop the algorithm).
+///     2. IO errors, CSV parsing errors, which stop the algorithm, unless configured to skip
+///        malformed rows (see `config::MalformedBallots`).
 pub type IOBallot = Result<Ballot, BallotParseErr>;
 
 #[derive(Clone, Copy)]
@@ -57,11 +112,22 @@ pub struct Constraints {
 }
 
 impl Constraints {
-    // Preferring below the line votes is codified in Section 269(2) of the Electoral Act.
+    /// The formality rules introduced in 2016, and still in use.
+    ///
+    /// Voters are instructed to number at least 6 boxes above the line or 12 below, but the savings
+    /// provisions of section 269 admit any above the line vote with a first preference, and any
+    /// below the line vote with at least 6 preferences. Preferences are counted up to the first
+    /// gap or repeated number. Preferring below the line votes is codified in section 269(2).
     pub fn official() -> Constraints {
+        Constraints::with_min_preferences(1, 6)
+    }
+
+    /// Formality rules requiring at least `min_above` consecutive preferences for an above the
+    /// line vote, or `min_below` for a below the line vote.
+    pub fn with_min_preferences(min_above: usize, min_below: usize) -> Constraints {
         Constraints {
             choice: PreferBelow,
-            counts: vec![MinAbove(1), MinBelow(6)],
+            counts: vec![MinAbove(min_above), MinBelow(min_below)],
         }
     }
 
@@ -70,223 +136,1059 @@ impl Constraints {
         vote_length: usize,
         val: usize,
         err: F,
-    ) -> Result<(), BallotParseErr>
+    ) -> Result<(), InformalReason>
     where
-        F: Fn(usize) -> InvalidBallotErr,
+        F: Fn(usize) -> InformalReason,
     {
         if vote_length.cmp(&val) == invalid {
-            Err(InvalidBallot(err(vote_length)))
+            Err(err(vote_length))
         } else {
             Ok(())
         }
     }
 
-    fn check_min<F>(vote_length: usize, min: usize, err: F) -> Result<(), BallotParseErr>
+    fn check_min<F>(vote_length: usize, min: usize, err: F) -> Result<(), InformalReason>
     where
-        F: Fn(usize) -> InvalidBallotErr,
+        F: Fn(usize) -> InformalReason,
     {
         // good if: vote_length >= min, bad if: vote_length < min
         Constraints::check_cmp(Less, vote_length, min, err)
     }
 
-    fn check_max<F>(vote_length: usize, max: usize, err: F) -> Result<(), BallotParseErr>
+    fn check_max<F>(vote_length: usize, max: usize, err: F) -> Result<(), InformalReason>
     where
-        F: Fn(usize) -> InvalidBallotErr,
+        F: Fn(usize) -> InformalReason,
     {
         // good if: vote_length <= max, i.e. bad if vote_length > max
         Constraints::check_cmp(Greater, vote_length, max, err)
     }
 
-    /// Validate an above the line vote.
-    fn check_above<'a>(&self, vote: GroupPrefMap<'a>) -> Result<GroupPrefMap<'a>, BallotParseErr> {
+    /// Validate an above the line vote with `len` consecutive preferences.
+    fn check_above(&self, len: usize) -> Result<(), InformalReason> {
         for &count_constraint in &self.counts {
             match count_constraint {
-                MinAbove(min) => Constraints::check_min(vote.len(), min, InvalidMinAbove)?,
-                MaxAbove(max) => Constraints::check_max(vote.len(), max, InvalidMaxAbove)?,
+                MinAbove(min) => Constraints::check_min(len, min, |n| {
+                    BelowMinimumPreferences(Section::AboveTheLine, n)
+                })?,
+                MaxAbove(max) => Constraints::check_max(len, max, |n| {
+                    AboveMaximumPreferences(Section::AboveTheLine, n)
+                })?,
                 _ => (),
             }
         }
-        Ok(vote)
+        Ok(())
     }
 
-    fn check_below(&self, vote: PrefMap) -> Result<PrefMap, BallotParseErr> {
+    fn check_below(&self, len: usize) -> Result<(), InformalReason> {
         for &count_constraint in &self.counts {
             match count_constraint {
-                MinBelow(min) => Constraints::check_min(vote.len(), min, InvalidMinBelow)?,
-                MaxBelow(max) => Constraints::check_max(vote.len(), max, InvalidMaxBelow)?,
+                MinBelow(min) => Constraints::check_min(len, min, |n| {
+                    BelowMinimumPreferences(Section::BelowTheLine, n)
+                })?,
+                MaxBelow(max) => Constraints::check_max(len, max, |n| {
+                    AboveMaximumPreferences(Section::BelowTheLine, n)
+                })?,
                 _ => (),
             }
         }
-        Ok(vote)
+        Ok(())
     }
 }
 
-fn remove_repeats_and_gaps<T>(
-    (mut map, cutoff): BallotRes<T>,
-) -> Result<BTreeMap<u32, T>, BallotParseErr> {
-    // Search for a gap in the order of preferences.
-    let missing_pref = map
-        .keys()
-        .zip(1..)
-        .find(|&(&pref, idx)| pref != idx)
-        .map(|(_, idx)| idx);
-
-    // Cut-off at the minimum of the provided cutoff (for doubled prefs) and any missing pref.
-    let new_cutoff = match (cutoff, missing_pref) {
-        (Some(prev), Some(new)) => Some(min(prev, new)),
-        (x @ Some(_), _) | (_, x) => x,
-    };
+/// The box marked with a particular preference number, if any.
+#[derive(Clone, Copy)]
+enum Mark {
+    Unmarked,
+    Box(usize),
+    /// More than one box has the number, so it's ambiguous which candidate it was for.
+    Repeated,
+}
+
+/// Buffers that are reused from one ballot to the next, so that parsing a ballot doesn't
+/// allocate anything besides the ballot itself.
+#[derive(Default)]
+pub struct ParseScratch {
+    /// Box marked with each preference number, from 0 up to the number of boxes.
+    marks: Vec<Mark>,
+    /// Group boxes above the line, in preference order.
+    above: Vec<usize>,
+    /// Candidate boxes below the line, in preference order.
+    below: Vec<usize>,
+    /// Section used for the most recent formal ballot.
+    section: Option<Section>,
+    /// Whether both sections of the most recent formal ballot were formal.
+    both_formal: bool,
+}
+
+impl ParseScratch {
+    /// Section the most recent ballot parsed was counted from, or `None` if it was informal.
+    pub fn section(&self) -> Option<Section> {
+        self.section
+    }
+
+    /// Whether both sections of the most recent ballot parsed were formal, so that the section it
+    /// was counted from was chosen by the constraints' order of precedence.
+    pub fn both_sections_formal(&self) -> bool {
+        self.both_formal
+    }
+
+    /// Number of preferences the most recent ballot parsed was counted with in its section: the
+    /// boxes numbered up to the first gap or repeated number. It's 0 if the ballot was informal.
+    pub fn num_preferences(&self) -> usize {
+        match self.section {
+            Some(Section::AboveTheLine) => self.above.len(),
+            Some(Section::BelowTheLine) => self.below.len(),
+            None => 0,
+        }
+    }
+
+    /// Whether the most recent ballot parsed was a donkey vote.
+    ///
+    /// A donkey vote numbers at least two boxes of the section it was counted from straight down
+    /// the ballot paper, in the order of its columns: 1, 2, 3 and so on.
+    pub fn is_donkey_vote(&self) -> bool {
+        let order = match self.section {
+            Some(Section::AboveTheLine) => &self.above,
+            Some(Section::BelowTheLine) => &self.below,
+            None => return false,
+        };
+        order.len() >= 2 && order.iter().enumerate().all(|(i, &idx)| i == idx)
+    }
+}
 
-    if let Some(cut) = new_cutoff {
-        map.split_off(&cut);
+/// Split a preference string into the contents of each box.
+///
+/// Splitting the bytes is several times faster than `str::split`, which adds up over millions of
+/// ballots. A comma is a single byte in UTF-8, so the contents of every box are still valid UTF-8.
+fn split_boxes(pref_string: &str) -> impl Iterator<Item = &str> {
+    pref_string
+        .as_bytes()
+        .split(|&b| b == b',')
+        .map(|b| str::from_utf8(b).expect("boxes are split at a comma"))
+}
+
+/// Read the preferences for a section of `num_boxes` boxes, writing the indices of the boxes in
+/// preference order to `order`.
+///
+/// Preferences stop at the first repeated or missing preference number. If a preference is
+/// repeated, it's ignored along with any higher numbered preferences, because it's ambiguous which
+/// candidate it was for, as per sections 268A(2)(b)(i) and 269(1A)(b)(i). Numbers higher than the
+/// number of boxes always come after a gap, so they never count.
+///
+/// A tick or cross (recorded as `*` or `/`) is a first preference, unless a box is numbered 1, in
+/// which case it's a stray mark. Stray marks and anything else that isn't a number give no
+/// preference for their box, as if it were blank, and the numbers in the other boxes still count
+/// up to the first one missing. A section marked only in this way isn't blank, but has no first
+/// preference.
+///
+/// Whitespace around the contents of a box is ignored, including the `\r` left on the last box by
+/// a file with Windows line endings.
+fn read_section<'a, P>(
+    prefs: P,
+    num_boxes: usize,
+    marks: &mut Vec<Mark>,
+    order: &mut Vec<usize>,
+) -> Result<(), InformalReason>
+where
+    P: Iterator<Item = &'a str>,
+{
+    marks.clear();
+    marks.resize(num_boxes + 1, Mark::Unmarked);
+    order.clear();
+    let mut blank = true;
+    // Box marked with a tick or cross.
+    let mut tick = Mark::Unmarked;
+
+    for (index, raw_pref) in prefs.enumerate() {
+        let raw_pref = raw_pref.trim();
+        if raw_pref.is_empty() {
+            continue;
+        }
+        blank = false;
+        let pref = match raw_pref {
+            "*" | "/" => {
+                tick = match tick {
+                    Mark::Unmarked => Mark::Box(index),
+                    _ => Mark::Repeated,
+                };
+                continue;
+            }
+            _ => match raw_pref.parse::<u32>() {
+                Ok(pref) => pref,
+                Err(_) => continue,
+            },
+        };
+
+        if let Some(mark) = marks.get_mut(pref as usize) {
+            *mark = match *mark {
+                Mark::Unmarked => Mark::Box(index),
+                _ => Mark::Repeated,
+            };
+        }
+    }
+
+    if blank {
+        return Err(BlankBallot);
+    }
+    if let Some(first @ &mut Mark::Unmarked) = marks.get_mut(1) {
+        *first = tick;
+    }
+
+    // A zero sorts before the first preference, leaving a gap after it.
+    let run = match marks[0] {
+        Mark::Unmarked => &marks[1..],
+        _ => &marks[..1],
+    };
+    for mark in run {
+        match *mark {
+            Mark::Box(index) => order.push(index),
+            _ => break,
+        }
     }
 
-    if !map.is_empty() {
-        Ok(map)
+    if order.is_empty() {
+        Err(NoFirstPreference)
     } else {
-        Err(InvalidBallot(EmptyBallot))
+        Ok(())
     }
 }
 
+/// Parse a ballot from its comma-separated preferences, in ballot paper order.
+///
+/// There's one preference for each group box above the line, followed by one for each candidate
+/// below the line. Ungrouped candidates only have boxes below the line, so a final ungrouped
+/// pseudo-group in `groups` doesn't take up a box above the line.
 pub fn parse_ballot_str(
     pref_string: &str,
     groups: &[Group],
     candidates: &[CandidateId],
     constraints: &Constraints,
 ) -> IOBallot {
-    // Iterator over integer preferences.
-    let mut pref_iter = pref_string.split(',');
-
-    let above_the_line = create_group_pref_map(pref_iter.by_ref().take(groups.len()), groups)
-        .and_then(remove_repeats_and_gaps)
-        .and_then(|v| constraints.check_above(v))
-        .map(flatten_group_pref_map);
-
-    let below_the_line = create_pref_map(pref_iter, candidates)
-        .and_then(remove_repeats_and_gaps)
-        .and_then(|v| constraints.check_below(v))
-        .map(flatten_pref_map);
-
-    match (constraints.choice, above_the_line, below_the_line) {
-        (_, Ok(prefs), Err(_))
-        | (_, Err(_), Ok(prefs))
-        | (PreferAbove, Ok(prefs), Ok(_))
-        | (PreferBelow, Ok(_), Ok(prefs)) => Ok(Ballot::single(prefs)),
-        (Strict, Ok(_), Ok(_)) => Err(InvalidBallot(InvalidStrict)),
-        (_, Err(e1), Err(_)) => Err(e1),
-    }
+    let mut scratch = ParseScratch::default();
+    parse_ballot_str_with(&mut scratch, pref_string, groups, candidates, constraints)
 }
 
-/// Mapping from preferences to candidate IDs (below the line voting).
-pub type PrefMap = BTreeMap<u32, CandidateId>;
+/// Parse a ballot like `parse_ballot_str`, reusing the buffers in `scratch`.
+pub fn parse_ballot_str_with(
+    scratch: &mut ParseScratch,
+    pref_string: &str,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+) -> IOBallot {
+    // A row with nothing at all in its preferences field is a blank ballot paper, rather than one
+    // with the wrong number of boxes.
+    if pref_string.trim().is_empty() {
+        return Err(InvalidBallot(BlankBallot));
+    }
 
-/// Mapping from preferences to groups of candidates (above the line voting).
-pub type GroupPrefMap<'a> = BTreeMap<u32, &'a [CandidateId]>;
+    // Check the number of preferences, so that they can't be matched to the wrong boxes.
+    let expected = above_the_line_groups(groups).len() + candidates.len();
+    let found = pref_string.bytes().filter(|&b| b == b',').count() + 1;
+    if found != expected {
+        return Err(InputError(Error::WrongNumberOfPreferences {
+            expected,
+            found,
+        }));
+    }
 
-/// Ballot parse result including a map, and an optional preference cut off.
-type BallotRes<T> = (BTreeMap<u32, T>, Option<u32>);
+    let ballot = check_formality_with(scratch, pref_string, groups, candidates, constraints)
+        .map_err(InvalidBallot)?;
 
-pub fn flatten_pref_map(pref_map: PrefMap) -> Vec<CandidateId> {
-    pref_map.into_iter().map(|(_, x)| x).collect()
+    // A box above the line for a group without any candidates would have its preference dropped
+    // without a trace, so it's an error in the groups rather than something to count around.
+    if scratch.section == Some(Section::AboveTheLine) {
+        let above = above_the_line_groups(groups);
+        if let Some(&g) = scratch
+            .above
+            .iter()
+            .find(|&&g| above[g].candidate_ids.is_empty())
+        {
+            return Err(InputError(Error::Schema(format!(
+                "ballot has a preference above the line for group {}, which has no candidates",
+                above[g].name
+            ))));
+        }
+    }
+    Ok(ballot)
 }
 
-pub fn flatten_group_pref_map(group_pref_map: GroupPrefMap) -> Vec<CandidateId> {
-    let size = group_pref_map.values().map(|x| x.len()).sum();
-    let mut flat = Vec::with_capacity(size);
+/// Numbers of informal ballots, by the reason they're informal, as collected by
+/// `formal_ballots`.
+///
+/// Reasons are tallied without their details, as in `Stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InformalTally {
+    pub reasons: BallotErrorMap,
+}
 
-    for (_, group) in group_pref_map {
-        flat.extend_from_slice(group);
+impl InformalTally {
+    pub fn record(&mut self, reason: InformalReason) {
+        *self.reasons.entry(reason.erase_detail()).or_insert(0) += 1;
     }
 
-    flat
+    /// Total number of informal ballots.
+    pub fn total(&self) -> u64 {
+        self.reasons.values().sum()
+    }
 }
 
-fn create_group_pref_map<'a, 'g, P>(
-    prefs: P,
-    groups: &'g [Group],
-) -> Result<BallotRes<&'g [CandidateId]>, BallotParseErr>
+/// Iterator over the formal ballots among some preference strings, created by `formal_ballots`.
+pub struct FormalBallots<'a, I> {
+    pref_strings: I,
+    groups: &'a [Group],
+    candidates: &'a [CandidateId],
+    constraints: Constraints,
+    scratch: ParseScratch,
+    tally: Rc<RefCell<InformalTally>>,
+}
+
+/// Parse preference strings under `rules`, skipping the informal ballots and tallying the reasons
+/// they're informal.
+///
+/// The iterator can be passed straight to `decide_election`, with the tally read afterwards.
+/// Rows that can't be read as a ballot at all, like those with the wrong number of preferences,
+/// are still yielded as errors.
+pub fn formal_ballots<'a, I, S>(
+    pref_strings: I,
+    groups: &'a [Group],
+    candidates: &'a [CandidateId],
+    rules: FormalityRules,
+) -> (FormalBallots<'a, I::IntoIter>, Rc<RefCell<InformalTally>>)
 where
-    P: Iterator<Item = &'a str>,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
 {
-    let group_candidates = |idx| {
-        let group: &'g Group = &groups[idx];
-        group.candidate_ids.as_slice()
+    let tally = Rc::new(RefCell::new(InformalTally::default()));
+    let iter = FormalBallots {
+        pref_strings: pref_strings.into_iter(),
+        groups,
+        candidates,
+        constraints: rules.constraints(),
+        scratch: ParseScratch::default(),
+        tally: tally.clone(),
     };
-    create_map(prefs, group_candidates)
+    (iter, tally)
 }
 
-fn create_pref_map<'a, P>(
-    prefs: P,
-    candidates: &[CandidateId],
-) -> Result<BallotRes<CandidateId>, BallotParseErr>
+impl<'a, I, S> Iterator for FormalBallots<'a, I>
 where
-    P: Iterator<Item = &'a str>,
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
 {
-    create_map(prefs, |idx| candidates[idx])
+    type Item = IOBallot;
+
+    fn next(&mut self) -> Option<IOBallot> {
+        for pref_string in self.pref_strings.by_ref() {
+            match parse_ballot_str_with(
+                &mut self.scratch,
+                pref_string.as_ref(),
+                self.groups,
+                self.candidates,
+                &self.constraints,
+            ) {
+                Err(InvalidBallot(reason)) => self.tally.borrow_mut().record(reason),
+                res => return Some(res),
+            }
+        }
+        None
+    }
 }
 
-fn create_map<'a, F, T, P>(prefs: P, func: F) -> Result<BallotRes<T>, BallotParseErr>
-where
-    F: Fn(usize) -> T,
-    P: Iterator<Item = &'a str>,
-{
-    let mut map = BTreeMap::new();
-    let mut pref_cutoff = None;
+/// Apply the formality rules to a single ballot's comma-separated preferences.
+///
+/// Each section is truncated at its first gap or repeated preference and checked against
+/// `constraints`, which also choose between the sections if both are formal. The result is the
+/// ballot's preferences for candidates, or the reason it's informal.
+///
+/// Boxes are matched to groups and candidates in order, with missing boxes treated as blank and
+/// extra boxes ignored. Use `parse_ballot_str` to reject strings with the wrong number of boxes.
+pub fn check_formality(
+    pref_string: &str,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+) -> Result<Ballot, InformalReason> {
+    let mut scratch = ParseScratch::default();
+    check_formality_with(&mut scratch, pref_string, groups, candidates, constraints)
+}
 
-    for (index, raw_pref) in prefs.enumerate() {
-        let pref = match raw_pref {
-            "" => continue,
-            "*" | "/" => 1,
-            _ => raw_pref
-                .parse::<u32>()
-                .map_err(|_| InvalidBallot(InvalidCharacter))?,
-        };
+/// Apply the formality rules like `check_formality`, reusing the buffers in `scratch`.
+pub fn check_formality_with(
+    scratch: &mut ParseScratch,
+    pref_string: &str,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+) -> Result<Ballot, InformalReason> {
+    let groups = above_the_line_groups(groups);
+    let ParseScratch {
+        ref mut marks,
+        ref mut above,
+        ref mut below,
+        ref mut section,
+        ref mut both_formal,
+    } = *scratch;
+    *section = None;
+    *both_formal = false;
+
+    // Iterator over integer preferences.
+    let mut pref_iter = split_boxes(pref_string);
+
+    // Without any groups there are no boxes above the line, which is the same as leaving them all
+    // blank: the ballot is formal only if it's formal below the line.
+    let above_the_line = if groups.is_empty() {
+        above.clear();
+        Err(BlankBallot)
+    } else {
+        read_section(
+            pref_iter.by_ref().take(groups.len()),
+            groups.len(),
+            marks,
+            above,
+        )
+        .and_then(|()| constraints.check_above(above.len()))
+    };
+
+    let below_the_line = read_section(
+        pref_iter.take(candidates.len()),
+        candidates.len(),
+        marks,
+        below,
+    )
+    .and_then(|()| constraints.check_below(below.len()));
+
+    let chosen = match (constraints.choice, above_the_line, below_the_line) {
+        (_, Ok(()), Err(_)) | (PreferAbove, Ok(()), Ok(())) => Section::AboveTheLine,
+        (_, Err(_), Ok(())) | (PreferBelow, Ok(()), Ok(())) => Section::BelowTheLine,
+        (Strict, Ok(()), Ok(())) => return Err(BothSectionsMarked),
+        (_, Err(BlankBallot), Err(e)) | (_, Err(e), Err(BlankBallot)) => return Err(e),
+        (_, Err(_), Err(_)) => return Err(NeitherSectionFormal),
+    };
+    *section = Some(chosen);
+    *both_formal = above_the_line.is_ok() && below_the_line.is_ok();
+
+    let prefs = match chosen {
+        Section::AboveTheLine => {
+            let size = above.iter().map(|&g| groups[g].candidate_ids.len()).sum();
+            let mut prefs = Vec::with_capacity(size);
+            for &g in above.iter() {
+                prefs.extend_from_slice(&groups[g].candidate_ids);
+            }
+            prefs
+        }
+        Section::BelowTheLine => below.iter().map(|&c| candidates[c]).collect(),
+    };
+    Ok(Ballot::single(prefs))
+}
+
+/// A ballot paper with one candidate removed, for counting as if they had never nominated.
+///
+/// The candidate's box below the line is removed, along with their group's box above the line if
+/// they were its only member. Preferences are closed up around the removed boxes, and the result is
+/// parsed against the new ballot paper with the usual formality rules, so a ballot can become
+/// informal if the removed candidate held one of its few preferences.
+pub struct RemovedCandidate {
+    /// Groups of the new ballot paper.
+    pub groups: Vec<Group>,
+    /// Candidates of the new ballot paper, in ballot paper order.
+    pub candidates: Vec<CandidateId>,
+    num_group_boxes: usize,
+    group_box: Option<usize>,
+    candidate_box: usize,
+}
+
+impl RemovedCandidate {
+    /// Remove `removed` from a ballot paper, returning `None` if they aren't on it.
+    pub fn new(
+        groups: &[Group],
+        candidates: &[CandidateId],
+        removed: CandidateId,
+    ) -> Option<RemovedCandidate> {
+        let candidate_box = candidates.iter().position(|&c| c == removed)?;
+        let above = above_the_line_groups(groups);
+        let group_box = above.iter().position(|g| g.candidate_ids == [removed]);
+
+        let new_groups = groups
+            .iter()
+            .map(|g| Group {
+                name: g.name.clone(),
+                ticket_letter: g.ticket_letter.clone(),
+                candidate_ids: g
+                    .candidate_ids
+                    .iter()
+                    .cloned()
+                    .filter(|&c| c != removed)
+                    .collect(),
+            })
+            .filter(|g| !g.candidate_ids.is_empty())
+            .collect();
+        let new_candidates = candidates
+            .iter()
+            .cloned()
+            .filter(|&c| c != removed)
+            .collect();
+
+        Some(RemovedCandidate {
+            groups: new_groups,
+            candidates: new_candidates,
+            num_group_boxes: above.len(),
+            group_box,
+            candidate_box,
+        })
+    }
+
+    /// Rewrite a preference string for the original ballot paper to one for the new paper.
+    ///
+    /// Strings with the wrong number of preferences are returned unchanged, so that parsing
+    /// reports the error.
+    pub fn remove_from(&self, pref_string: &str) -> String {
+        let mut above: Vec<String> = pref_string.split(',').map(String::from).collect();
+        if above.len() != self.num_group_boxes + self.candidates.len() + 1 {
+            return pref_string.to_string();
+        }
+        let mut below = above.split_off(self.num_group_boxes);
+
+        if let Some(idx) = self.group_box {
+            close_up(&mut above, idx);
+        }
+        close_up(&mut below, self.candidate_box);
+
+        above.extend(below);
+        above.join(",")
+    }
+}
 
-        let value = func(index);
-        let prev_value = map.insert(pref, value);
-
-        // If a preference is repeated, we ignore that preference and any
-        // higher numbered preferences.
-        // Sections 268A(2)(b)(i) and 269(1A)(b)(i).
-        if prev_value.is_some() {
-            pref_cutoff = Some(match pref_cutoff {
-                Some(cutoff) => min(cutoff, pref),
-                None => pref,
-            });
+/// Remove the box at `idx` from a section, renumbering the later preferences to close the gap.
+fn close_up(section: &mut Vec<String>, idx: usize) {
+    let removed = section.remove(idx);
+    let removed_pref = match removed.trim() {
+        "*" | "/" if !section.iter().any(|p| p == "1") => 1,
+        raw => match raw.parse::<u32>() {
+            Ok(pref) => pref,
+            Err(_) => return,
+        },
+    };
+    for raw_pref in section.iter_mut() {
+        if let Ok(pref) = raw_pref.trim().parse::<u32>() {
+            if pref > removed_pref {
+                *raw_pref = (pref - 1).to_string();
+            }
         }
     }
+}
 
-    Ok((map, pref_cutoff))
+/// Mapping from preferences to candidate IDs (below the line voting).
+///
+/// Being ordered by preference, it flattens to the same ballot every time, whatever order the
+/// preferences were marked in.
+pub type PrefMap = BTreeMap<u32, CandidateId>;
+
+pub fn flatten_pref_map(pref_map: PrefMap) -> Vec<CandidateId> {
+    pref_map.into_values().collect()
 }
 
 #[cfg(test)]
 mod test {
-    use super::remove_repeats_and_gaps;
-    use std::collections::BTreeMap;
-    use std::iter::FromIterator;
+    use super::*;
+
+    fn test_groups() -> Vec<Group> {
+        vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0, 1, 2, 3]),
+            },
+            Group {
+                name: "B".to_string(),
+                ticket_letter: Some("B".to_string()),
+                candidate_ids: ids(&[4, 5]),
+            },
+            Group {
+                name: "C".to_string(),
+                ticket_letter: Some("C".to_string()),
+                candidate_ids: ids(&[6, 7]),
+            },
+        ]
+    }
+
+    /// Parse a preference string for 8 candidates in 3 groups, returning `None` if informal.
+    fn parse(pref_string: &str) -> Option<Vec<CandidateId>> {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let constraints = Constraints::official();
+        match parse_ballot_str(pref_string, &test_groups(), &candidates, &constraints) {
+            Ok(ballot) => Some(ballot.prefs),
+            Err(InvalidBallot(_)) => None,
+            Err(InputError(e)) => panic!("unexpected input error: {}", e),
+        }
+    }
+
+    fn parse_above(prefs: &str) -> Option<Vec<CandidateId>> {
+        parse(&format!("{},,,,,,,,", prefs))
+    }
+
+    fn parse_below(prefs: &str) -> Option<Vec<CandidateId>> {
+        parse(&format!(",,,{}", prefs))
+    }
 
     #[test]
-    fn remove_gaps() {
-        let mut pref_map = BTreeMap::from_iter((1..10).zip(1..10));
-        pref_map.insert(11, 11);
+    fn above_the_line_formality() {
+        assert_eq!(parse_above("2,1,3"), Some(ids(&[4, 5, 0, 1, 2, 3, 6, 7])));
+        // Savings provision: a single first preference is enough.
+        assert_eq!(parse_above(",1,"), Some(ids(&[4, 5])));
+        assert_eq!(parse_above("*,,"), Some(ids(&[0, 1, 2, 3])));
+        // No clear first preference.
+        assert_eq!(parse_above("2,3,4"), None);
+        assert_eq!(parse_above("1,1,2"), None);
+        // Preferences stop at the first gap or repeat.
+        assert_eq!(parse_above("1,3,4"), Some(ids(&[0, 1, 2, 3])));
+        assert_eq!(parse_above("1,2,2"), Some(ids(&[0, 1, 2, 3])));
+    }
 
+    #[test]
+    fn below_the_line_formality() {
         assert_eq!(
-            remove_repeats_and_gaps((pref_map.clone(), None))
-                .unwrap()
-                .len(),
-            9
+            parse_below("1,2,3,4,5,6,7,8"),
+            Some((0..8).map(CandidateId).collect())
+        );
+        // Savings provision: 6 consecutive preferences is enough.
+        assert_eq!(parse_below("6,5,4,3,2,1,,"), Some(ids(&[5, 4, 3, 2, 1, 0])));
+        assert_eq!(parse_below("1,2,3,4,5,,,"), None);
+        // No clear first preference.
+        assert_eq!(parse_below(",2,3,4,5,6,7,8"), None);
+        assert_eq!(parse_below("1,1,2,3,4,5,6,7"), None);
+        // Preferences stop at the first gap or repeat.
+        assert_eq!(
+            parse_below("1,2,3,4,5,6,7,9"),
+            Some((0..7).map(CandidateId).collect())
+        );
+        assert_eq!(
+            parse_below("1,2,3,4,5,6,7,7"),
+            Some((0..6).map(CandidateId).collect())
+        );
+        assert_eq!(parse_below("1,2,3,4,5,5,6,7"), None);
+    }
+
+    #[test]
+    fn marked_above_and_below() {
+        // Formal below the line, so the below the line preferences are used.
+        assert_eq!(parse("1,,,2,1,3,4,5,6,,"), Some(ids(&[1, 0, 2, 3, 4, 5])));
+        // Informal below the line, so the above the line preferences are used.
+        assert_eq!(parse(",1,2,1,2,3,,,,,"), Some(ids(&[4, 5, 6, 7])));
+        // Informal in both sections.
+        assert_eq!(parse("2,,,1,2,3,,,,,"), None);
+    }
+
+    #[test]
+    fn formality_reasons() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let check = |prefs| {
+            check_formality(prefs, &test_groups(), &candidates, &Constraints::official())
+                .map(|b| b.prefs)
+        };
+        assert_eq!(check(",1,,,,,,,,,"), Ok(ids(&[4, 5])));
+        assert_eq!(check(",,,,,,,,,,"), Err(BlankBallot));
+        assert_eq!(check("x,,,,,,,,,,"), Err(NoFirstPreference));
+        assert_eq!(check("1,x,2,,,,,,,,"), Ok(ids(&[0, 1, 2, 3, 6, 7])));
+        assert_eq!(check("2,3,,,,,,,,,"), Err(NoFirstPreference));
+        assert_eq!(
+            check(",,,1,2,3,,,,,"),
+            Err(BelowMinimumPreferences(Section::BelowTheLine, 3))
         );
+        // Missing boxes are blank.
+        assert_eq!(check("1"), Ok(ids(&[0, 1, 2, 3])));
+    }
+
+    #[test]
+    fn formal_ballots_only() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let groups = test_groups();
+        let rows = vec![
+            "1,2,,,,,,,,,",
+            ",,,,,,,,,,",
+            "2,3,,,,,,,,,",
+            ",,,1,2,3,,,,,",
+            "1,,",
+            ",,,1,2,3,4,5,6,,",
+            ",,,,,,,,,,",
+        ];
+        let (ballots, tally) =
+            formal_ballots(rows, &groups, &candidates, FormalityRules::default());
+        let ballots: Vec<_> = ballots.collect();
+
+        assert_eq!(ballots.len(), 3);
+        assert_eq!(ballots[0].as_ref().unwrap().prefs, ids(&[0, 1, 2, 3, 4, 5]));
+        assert!(matches!(
+            ballots[1],
+            Err(InputError(Error::WrongNumberOfPreferences { .. }))
+        ));
+        assert_eq!(ballots[2].as_ref().unwrap().prefs, ids(&[0, 1, 2, 3, 4, 5]));
+
+        let tally = tally.borrow();
+        assert_eq!(tally.total(), 4);
+        assert_eq!(tally.reasons[&BlankBallot], 2);
+        assert_eq!(tally.reasons[&NoFirstPreference], 1);
         assert_eq!(
-            remove_repeats_and_gaps((pref_map.clone(), Some(10)))
+            tally.reasons[&BelowMinimumPreferences(Section::BelowTheLine, 0)],
+            1
+        );
+    }
+
+    #[test]
+    fn deterministic_parse() {
+        // Boxes are marked out of preference order, and the 2 above the line is repeated.
+        let rows = [
+            ("1,,2,6,5,4,3,2,1,8,7", Some(ids(&[5, 4, 3, 2, 1, 0, 7, 6]))),
+            ("3,1,2,,,,,,,,", Some(ids(&[4, 5, 6, 7, 0, 1, 2, 3]))),
+            ("2,1,2,,,,,,,,", Some(ids(&[4, 5]))),
+        ];
+        for (row, ballot) in &rows {
+            for _ in 0..3 {
+                assert_eq!(&parse(row), ballot);
+            }
+        }
+    }
+
+    /// Read a single section, returning the marked boxes in preference order.
+    fn read(prefs: &str) -> Result<Vec<usize>, InformalReason> {
+        let num_boxes = prefs.split(',').count();
+        let (mut marks, mut order) = (vec![], vec![]);
+        read_section(prefs.split(','), num_boxes, &mut marks, &mut order).map(|()| order)
+    }
+
+    #[test]
+    fn repeated_preference() {
+        // Both 3s are ambiguous, so the ballot is counted up to the 2.
+        assert_eq!(read("1,2,3,3,4"), Ok(vec![0, 1]));
+        assert_eq!(read("1,1,2"), Err(NoFirstPreference));
+    }
+
+    #[test]
+    fn gap_in_preferences() {
+        assert_eq!(read("1,2,4,5"), Ok(vec![0, 1]));
+        // Preferences higher than the number of boxes can only come after a gap.
+        assert_eq!(read("2,1,7"), Ok(vec![1, 0]));
+        assert_eq!(read(",,"), Err(BlankBallot));
+        assert_eq!(read(",9,"), Err(NoFirstPreference));
+    }
+
+    #[test]
+    fn non_numeric_marks() {
+        // A tick or cross is a first preference when no box is numbered 1.
+        assert_eq!(read(",*,2,3"), Ok(vec![1, 2, 3]));
+        assert_eq!(read("/,,"), Ok(vec![0]));
+        assert_eq!(read("*,/,2"), Err(NoFirstPreference));
+        // With a 1, a tick is a stray mark, and other marks never hold a preference.
+        assert_eq!(read("1,2,*,3"), Ok(vec![0, 1, 3]));
+        assert_eq!(read("1,*,,2,x,3"), Ok(vec![0, 3, 5]));
+        // A stray mark in place of a number leaves a gap, where the preferences stop.
+        assert_eq!(read("1,?,2,,4"), Ok(vec![0, 2]));
+        assert_eq!(read("x,,?"), Err(NoFirstPreference));
+        assert_eq!(read("x,2,3"), Err(NoFirstPreference));
+    }
+
+    #[test]
+    fn configurable_minimum() {
+        let constraints = Constraints::with_min_preferences(1, 3);
+        let candidates: Vec<_> = (0..4).map(CandidateId).collect();
+        let ballot = parse_ballot_str("1,2,3,", &[], &candidates, &constraints).unwrap();
+        assert_eq!(ballot.prefs, ids(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn wrong_number_of_preferences() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let constraints = Constraints::official();
+        let wrong_length = |pref_string: &str| match parse_ballot_str(
+            pref_string,
+            &test_groups(),
+            &candidates,
+            &constraints,
+        ) {
+            Err(InputError(Error::WrongNumberOfPreferences { expected, found })) => {
+                assert_eq!(expected, 11);
+                found
+            }
+            res => panic!("expected a malformed row, got {:?}", res),
+        };
+        assert_eq!(wrong_length("1,,,,,,,,,"), 10);
+        assert_eq!(wrong_length("1,,,,,,,,,,,"), 12);
+        assert_eq!(wrong_length(",,,1,2,3,4,5,6,7,8,9"), 12);
+        // Only the boxes above the line.
+        assert_eq!(wrong_length("1,2,3"), 3);
+    }
+
+    #[test]
+    fn no_groups() {
+        // Every candidate is ungrouped, so all of the boxes are below the line.
+        let groups = [Group {
+            name: ::group::UNGROUPED.to_string(),
+            ticket_letter: None,
+            candidate_ids: ids(&[0, 1, 2]),
+        }];
+        let candidates = ids(&[0, 1, 2]);
+        let constraints = Constraints::with_min_preferences(1, 2);
+        for groups in &[&groups[..], &[]] {
+            let parse = |pref_string: &str| {
+                parse_ballot_str(pref_string, groups, &candidates, &constraints).map(|b| b.prefs)
+            };
+            assert_eq!(parse("2,1,3").unwrap(), ids(&[1, 0, 2]));
+            assert_eq!(parse("2,1,").unwrap(), ids(&[1, 0]));
+            match parse(",1,") {
+                Err(InvalidBallot(BelowMinimumPreferences(Section::BelowTheLine, 1))) => (),
+                res => panic!("expected too few preferences, got {:?}", res),
+            }
+            match parse(",,") {
+                Err(InvalidBallot(BlankBallot)) => (),
+                res => panic!("expected a blank ballot, got {:?}", res),
+            }
+            match parse("1,2,3,4") {
+                Err(InputError(Error::WrongNumberOfPreferences { expected, found })) => {
+                    assert_eq!((expected, found), (3, 4))
+                }
+                res => panic!("expected a malformed row, got {:?}", res),
+            }
+
+            let removed = RemovedCandidate::new(groups, &candidates, CandidateId(0)).unwrap();
+            assert_eq!(removed.remove_from("3,1,2"), "1,2");
+        }
+    }
+
+    #[test]
+    fn empty_preferences_field() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let constraints = Constraints::official();
+        for pref_string in &["", " "] {
+            match parse_ballot_str(pref_string, &test_groups(), &candidates, &constraints) {
+                Err(InvalidBallot(BlankBallot)) => (),
+                res => panic!("expected a blank ballot, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn whitespace_in_boxes() {
+        assert_eq!(
+            parse_below("1,2,3,4,5,6,7,8\r"),
+            Some(ids(&[0, 1, 2, 3, 4, 5, 6, 7]))
+        );
+        assert_eq!(
+            parse_below("8,7,6,5,4,3,2, 1 \r\n"),
+            parse_below("8,7,6,5,4,3,2,1")
+        );
+        assert_eq!(parse_above(" 2 ,1\t,3"), parse_above("2,1,3"));
+        assert_eq!(read("1,2, \r"), Ok(vec![0, 1]));
+    }
+
+    #[test]
+    fn ungrouped_candidates() {
+        // Candidate 4 is ungrouped, so there are only 2 boxes above the line.
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0, 1]),
+            },
+            Group {
+                name: "B".to_string(),
+                ticket_letter: Some("B".to_string()),
+                candidate_ids: ids(&[2, 3]),
+            },
+            Group {
+                name: ::group::UNGROUPED.to_string(),
+                ticket_letter: None,
+                candidate_ids: ids(&[4]),
+            },
+        ];
+        let candidates: Vec<_> = (0..5).map(CandidateId).collect();
+        let constraints = Constraints::with_min_preferences(1, 5);
+        let parse = |prefs: &str| {
+            parse_ballot_str(prefs, &groups, &candidates, &constraints)
                 .unwrap()
-                .len(),
-            9
+                .prefs
+        };
+
+        assert_eq!(parse("2,1,,,,,"), ids(&[2, 3, 0, 1]));
+        assert_eq!(parse(",,1,2,3,4,5"), ids(&[0, 1, 2, 3, 4]));
+        assert_eq!(parse(",,5,4,3,2,1"), ids(&[4, 3, 2, 1, 0]));
+    }
+
+    #[test]
+    fn informal_reasons() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let reason = |pref_string: &str, constraints: &Constraints| match parse_ballot_str(
+            pref_string,
+            &test_groups(),
+            &candidates,
+            constraints,
+        ) {
+            Err(InvalidBallot(reason)) => reason,
+            res => panic!("expected an informal ballot, got {:?}", res),
+        };
+        let official = Constraints::official();
+
+        assert_eq!(reason(",,,,,,,,,,", &official), BlankBallot);
+        assert_eq!(reason("2,3,,,,,,,,,", &official), NoFirstPreference);
+        assert_eq!(reason("x,,,,,,,,,,", &official), NoFirstPreference);
+        assert_eq!(
+            reason(",,,1,2,3,,,,,", &official),
+            BelowMinimumPreferences(Section::BelowTheLine, 3)
         );
+        // Both sections marked: one formal section is enough, but with neither formal the reason
+        // can't be pinned on either.
         assert_eq!(
-            remove_repeats_and_gaps((pref_map.clone(), Some(5)))
+            parse_ballot_str("1,,,1,2,3,,,,,", &test_groups(), &candidates, &official)
                 .unwrap()
-                .len(),
-            4
+                .prefs,
+            ids(&[0, 1, 2, 3])
         );
+        assert_eq!(reason("2,3,,1,2,3,,,,,", &official), NeitherSectionFormal);
+        assert_eq!(reason("x,2,,1,2,3,,,,,", &official), NeitherSectionFormal);
+
+        let strict = Constraints {
+            choice: Strict,
+            counts: vec![MinAbove(1), MaxAbove(2)],
+        };
+        assert_eq!(reason("1,,,1,,,,,,,", &strict), BothSectionsMarked);
+        assert_eq!(reason("2,,,2,,,,,,,", &strict), NeitherSectionFormal);
+        assert_eq!(
+            reason("1,2,3,,,,,,,,", &strict),
+            AboveMaximumPreferences(Section::AboveTheLine, 3)
+        );
+        assert_eq!(
+            BelowMinimumPreferences(Section::BelowTheLine, 3).to_string(),
+            "too few preferences below the line"
+        );
+    }
+
+    #[test]
+    fn remove_gaps() {
+        let mut prefs: Vec<String> = (1..10).map(|p| p.to_string()).collect();
+        prefs.push("11".to_string());
+        assert_eq!(read(&prefs.join(",")).unwrap().len(), 9);
+
+        // Repeating the 5 removes it and everything after it.
+        prefs[8] = "5".to_string();
+        assert_eq!(read(&prefs.join(",")).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn reused_scratch() {
+        // A ballot parsed after a longer one isn't affected by its leftovers.
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let constraints = Constraints::official();
+        let mut scratch = ParseScratch::default();
+        let mut parse = |prefs: &str| {
+            parse_ballot_str_with(
+                &mut scratch,
+                prefs,
+                &test_groups(),
+                &candidates,
+                &constraints,
+            )
+            .map(|b| b.prefs)
+            .ok()
+        };
+        assert_eq!(
+            parse(",,,1,2,3,4,5,6,7,8"),
+            Some((0..8).map(CandidateId).collect())
+        );
+        assert_eq!(parse("3,1,2,,,,,,,,"), Some(ids(&[4, 5, 6, 7, 0, 1, 2, 3])));
+        assert_eq!(parse(",,,,,,,,,,"), None);
+        assert_eq!(parse("1,,,,,,,,,,"), Some(ids(&[0, 1, 2, 3])));
+    }
+
+    #[test]
+    fn donkey_votes() {
+        let candidates: Vec<_> = (0..8).map(CandidateId).collect();
+        let constraints = Constraints::official();
+        let mut scratch = ParseScratch::default();
+        let mut is_donkey = |prefs: &str| {
+            let _ = parse_ballot_str_with(
+                &mut scratch,
+                prefs,
+                &test_groups(),
+                &candidates,
+                &constraints,
+            );
+            scratch.is_donkey_vote()
+        };
+        assert!(is_donkey("1,2,3,,,,,,,,"));
+        assert!(is_donkey("1,2,,,,,,,,,"));
+        assert!(is_donkey(",,,1,2,3,4,5,6,7,8"));
+        // Formal below the line, so the straight run above the line doesn't count.
+        assert!(!is_donkey("1,2,3,2,1,3,4,5,6,,"));
+        // A single first preference, or a run that doesn't start from the first column.
+        assert!(!is_donkey("1,,,,,,,,,,"));
+        assert!(!is_donkey(",1,2,,,,,,,,"));
+        assert!(!is_donkey("2,1,3,,,,,,,,"));
+        // Informal ballots are never donkey votes.
+        assert!(!is_donkey(",,,1,2,3,,,,,"));
+    }
+
+    #[test]
+    fn removed_candidate() {
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0, 1]),
+            },
+            Group {
+                name: "B".to_string(),
+                ticket_letter: Some("B".to_string()),
+                candidate_ids: ids(&[2]),
+            },
+        ];
+        let candidates = ids(&[0, 1, 2]);
+        let constraints = Constraints::with_min_preferences(1, 2);
+        let removed = RemovedCandidate::new(&groups, &candidates, CandidateId(2)).unwrap();
+        assert_eq!(removed.groups.len(), 1);
+        assert_eq!(removed.candidates, ids(&[0, 1]));
+
+        let reparse = |pref_string: &str| {
+            let new_prefs = removed.remove_from(pref_string);
+            parse_ballot_str(
+                &new_prefs,
+                &removed.groups,
+                &removed.candidates,
+                &constraints,
+            )
+            .map(|b| b.prefs)
+        };
+
+        // Group B's box goes with its only candidate, and group A's preference closes up.
+        assert_eq!(removed.remove_from("2,1,,,"), "1,,");
+        assert_eq!(reparse("2,1,,,").unwrap(), ids(&[0, 1]));
+        assert_eq!(reparse(",,3,1,2").unwrap(), ids(&[1, 0]));
+        // Left with one preference below the line, which is too few.
+        match reparse(",,,1,2") {
+            Err(InvalidBallot(BelowMinimumPreferences(Section::BelowTheLine, 1))) => (),
+            res => panic!("expected too few preferences, got {:?}", res),
+        }
+
+        assert!(RemovedCandidate::new(&groups, &candidates, CandidateId(7)).is_none());
+    }
+
+    #[test]
+    fn empty_group() {
+        let mut groups = test_groups();
+        groups[1].candidate_ids.clear();
+        let candidates = ids(&[0, 1, 2, 3, 6, 7]);
+        let constraints = Constraints::with_min_preferences(1, 1);
+        let parse = |pref_string: &str| {
+            parse_ballot_str(pref_string, &groups, &candidates, &constraints).map(|b| b.prefs)
+        };
+
+        // Preferences for the other groups, or below the line, are unaffected.
+        assert_eq!(parse("1,,2,,,,,,").unwrap(), ids(&[0, 1, 2, 3, 6, 7]));
+        assert_eq!(parse(",1,,1,,,,,").unwrap(), ids(&[0]));
+        match parse("1,2,3,,,,,,") {
+            Err(InputError(Error::Schema(msg))) => assert_eq!(
+                msg,
+                "ballot has a preference above the line for group B, which has no candidates"
+            ),
+            res => panic!("expected an error for the empty group, got {:?}", res),
+        }
     }
 }
@@ -0,0 +1,125 @@
+//! A plain text format for small sets of ballots, for attaching a reproducible count to a bug
+//! report instead of a whole preferences file.
+//!
+//! Each line gives a number of ballot papers and their preferences by candidate ID, like
+//! `3x [5,2,9]` for three papers numbering candidates 5, 2 and 9 in that order. Blank lines and
+//! lines starting with `#` are ignored. `write_ballots` combines identical sequences and sorts
+//! them, so the same ballots always make the same text.
+
+use std::io::{self, BufRead, Write};
+
+use ballot::{Ballot, BallotSet};
+use candidate::CandidateId;
+use error::Error;
+
+/// Read ballots written in the text format, one per line.
+pub fn read_ballots<R: BufRead>(reader: R) -> Result<Vec<Ballot>, Error> {
+    let mut ballots = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let ballot = parse_ballot(line)
+            .ok_or_else(|| Error::Schema(format!("line {} isn't a ballot: {}", i + 1, line)))?;
+        ballots.push(ballot);
+    }
+    Ok(ballots)
+}
+
+fn parse_ballot(line: &str) -> Option<Ballot> {
+    let x = line.find('x')?;
+    let weight = line[..x].trim().parse().ok()?;
+    let prefs = line[x + 1..].trim();
+    if !prefs.starts_with('[') || !prefs.ends_with(']') {
+        return None;
+    }
+    let prefs = prefs[1..prefs.len() - 1].trim();
+    let prefs = if prefs.is_empty() {
+        vec![]
+    } else {
+        prefs
+            .split(',')
+            .map(|id| id.trim().parse().ok().map(CandidateId))
+            .collect::<Option<_>>()?
+    };
+    Some(Ballot::multi(weight, prefs))
+}
+
+/// Write ballots in the text format, combining those with identical preferences, in order of
+/// their preferences.
+pub fn write_ballots<W: Write>(out: &mut W, ballots: &[Ballot]) -> io::Result<()> {
+    let mut ballots = ballots
+        .iter()
+        .cloned()
+        .collect::<BallotSet>()
+        .into_ballots();
+    ballots.sort_by(|a, b| a.prefs.cmp(&b.prefs));
+    for ballot in &ballots {
+        let prefs: Vec<String> = ballot.prefs.iter().map(|id| id.0.to_string()).collect();
+        writeln!(out, "{}x [{}]", ballot.weight(), prefs.join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use candidate::ids;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    #[test]
+    fn read_and_write() {
+        let text = "# Candidate 5's surplus\n3x [5,2,9]\n\n 1x [ 2 , 5 ]\n2x []\n";
+        let ballots = read_ballots(text.as_bytes()).unwrap();
+        assert_eq!(
+            ballots,
+            vec![
+                Ballot::multi(3, ids(&[5, 2, 9])),
+                Ballot::multi(1, ids(&[2, 5])),
+                Ballot::multi(2, vec![]),
+            ]
+        );
+
+        let mut out = vec![];
+        write_ballots(&mut out, &ballots).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2x []\n1x [2,5]\n3x [5,2,9]\n"
+        );
+
+        for line in &["3 [1]", "x [1]", "3x 1,2", "3x [1,,2]", "3x [a]"] {
+            assert!(read_ballots(line.as_bytes()).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[138usize][..]);
+
+        for _ in 0..50 {
+            let ballots: Vec<_> = (0..rng.gen_range(0, 50))
+                .map(|_| {
+                    let prefs = (0..rng.gen_range(0, 10))
+                        .map(|_| CandidateId(rng.gen_range(0, 200)))
+                        .collect();
+                    Ballot::multi(rng.gen_range(1, 1000), prefs)
+                })
+                .collect();
+
+            let mut text = vec![];
+            write_ballots(&mut text, &ballots).unwrap();
+            let read = read_ballots(&text[..]).unwrap();
+            let mut rewritten = vec![];
+            write_ballots(&mut rewritten, &read).unwrap();
+            assert_eq!(rewritten, text);
+
+            let papers = |ballots: &[Ballot]| ballots.iter().map(Ballot::weight).sum::<u64>();
+            assert_eq!(papers(&read), papers(&ballots));
+            let mut combined = ballots.into_iter().collect::<BallotSet>().into_ballots();
+            combined.sort_by(|a, b| a.prefs.cmp(&b.prefs));
+            assert_eq!(read, combined);
+        }
+    }
+}
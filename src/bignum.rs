@@ -0,0 +1,335 @@
+//! Pure Rust stand-ins for GMP's integers and rationals, for building without the `gmp` feature.
+//!
+//! These wrap the `num` crates' big numbers in the parts of the `rust-gmp` API the count uses, so
+//! the rest of the crate is the same whichever backs `Int` and `Frac`. They're slower than GMP, but
+//! build for any target, including `wasm32-unknown-unknown`.
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::fmt;
+use std::ops::*;
+use std::str::FromStr;
+
+/// Arbitrary precision integer, like `gmp::mpz::Mpz`.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mpz(BigInt);
+
+/// Arbitrary precision rational, kept in lowest terms, like `gmp::mpq::Mpq`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mpq(BigRational);
+
+/// Error parsing an `Mpz` or `Mpq` from a string.
+#[derive(Debug)]
+pub struct ParseError;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid number")
+    }
+}
+
+impl ::std::error::Error for ParseError {}
+
+impl Mpz {
+    pub fn new() -> Mpz {
+        Mpz(BigInt::zero())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn abs(&self) -> Mpz {
+        Mpz(self.0.abs())
+    }
+
+    pub fn pow(&self, exp: u32) -> Mpz {
+        Mpz(num_traits::pow(self.0.clone(), exp as usize))
+    }
+}
+
+impl Mpq {
+    pub fn new() -> Mpq {
+        Mpq(BigRational::zero())
+    }
+
+    /// `num / den` in lowest terms.
+    ///
+    /// Panics if `den` is zero.
+    pub fn ratio(num: &Mpz, den: &Mpz) -> Mpq {
+        Mpq(BigRational::new(num.0.clone(), den.0.clone()))
+    }
+
+    /// Set to exactly the value of a finite `f64`.
+    pub fn set_d(&mut self, value: f64) {
+        self.0 = BigRational::from_f64(value).expect("finite floating point number");
+    }
+
+    pub fn get_num(&self) -> Mpz {
+        Mpz(self.0.numer().clone())
+    }
+
+    pub fn get_den(&self) -> Mpz {
+        Mpz(self.0.denom().clone())
+    }
+
+    pub fn abs(&self) -> Mpq {
+        Mpq(self.0.abs())
+    }
+
+    pub fn floor(&self) -> Mpz {
+        Mpz(self.0.numer().div_floor(self.0.denom()))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl Default for Mpq {
+    fn default() -> Mpq {
+        Mpq::new()
+    }
+}
+
+/// Implement an operator and its assigning form for every mix of values and references.
+macro_rules! impl_oper {
+    ($t:ident, $tr:ident, $meth:ident, $tr_assign:ident, $meth_assign:ident) => {
+        impl $tr<$t> for $t {
+            type Output = $t;
+            fn $meth(self, other: $t) -> $t {
+                $t($tr::$meth(self.0, other.0))
+            }
+        }
+
+        impl<'a> $tr<&'a $t> for $t {
+            type Output = $t;
+            fn $meth(self, other: &$t) -> $t {
+                $t($tr::$meth(self.0, &other.0))
+            }
+        }
+
+        impl<'a> $tr<$t> for &'a $t {
+            type Output = $t;
+            fn $meth(self, other: $t) -> $t {
+                $t($tr::$meth(&self.0, other.0))
+            }
+        }
+
+        impl<'a, 'b> $tr<&'b $t> for &'a $t {
+            type Output = $t;
+            fn $meth(self, other: &$t) -> $t {
+                $t($tr::$meth(&self.0, &other.0))
+            }
+        }
+
+        impl $tr_assign<$t> for $t {
+            fn $meth_assign(&mut self, other: $t) {
+                $tr_assign::$meth_assign(&mut self.0, other.0)
+            }
+        }
+
+        impl<'a> $tr_assign<&'a $t> for $t {
+            fn $meth_assign(&mut self, other: &$t) {
+                $tr_assign::$meth_assign(&mut self.0, &other.0)
+            }
+        }
+    };
+}
+
+/// Implement an operator between `Mpz` and a primitive integer, on the right.
+macro_rules! impl_prim_oper {
+    ($prim:ident, $tr:ident, $meth:ident, $tr_assign:ident, $meth_assign:ident) => {
+        impl $tr<$prim> for Mpz {
+            type Output = Mpz;
+            fn $meth(self, other: $prim) -> Mpz {
+                Mpz($tr::$meth(self.0, other))
+            }
+        }
+
+        impl<'a> $tr<$prim> for &'a Mpz {
+            type Output = Mpz;
+            fn $meth(self, other: $prim) -> Mpz {
+                Mpz($tr::$meth(&self.0, other))
+            }
+        }
+
+        impl $tr_assign<$prim> for Mpz {
+            fn $meth_assign(&mut self, other: $prim) {
+                $tr_assign::$meth_assign(&mut self.0, other)
+            }
+        }
+    };
+}
+
+impl_oper!(Mpz, Add, add, AddAssign, add_assign);
+impl_oper!(Mpz, Sub, sub, SubAssign, sub_assign);
+impl_oper!(Mpz, Mul, mul, MulAssign, mul_assign);
+impl_oper!(Mpz, Div, div, DivAssign, div_assign);
+impl_oper!(Mpz, Rem, rem, RemAssign, rem_assign);
+impl_prim_oper!(u64, Add, add, AddAssign, add_assign);
+impl_prim_oper!(u64, Sub, sub, SubAssign, sub_assign);
+impl_prim_oper!(u64, Mul, mul, MulAssign, mul_assign);
+impl_prim_oper!(u64, Div, div, DivAssign, div_assign);
+impl_prim_oper!(u64, Rem, rem, RemAssign, rem_assign);
+impl_prim_oper!(i64, Mul, mul, MulAssign, mul_assign);
+
+impl_oper!(Mpq, Add, add, AddAssign, add_assign);
+impl_oper!(Mpq, Sub, sub, SubAssign, sub_assign);
+impl_oper!(Mpq, Mul, mul, MulAssign, mul_assign);
+impl_oper!(Mpq, Div, div, DivAssign, div_assign);
+
+impl Neg for Mpz {
+    type Output = Mpz;
+    fn neg(self) -> Mpz {
+        Mpz(-self.0)
+    }
+}
+
+impl Neg for &Mpz {
+    type Output = Mpz;
+    fn neg(self) -> Mpz {
+        Mpz(-&self.0)
+    }
+}
+
+impl Neg for Mpq {
+    type Output = Mpq;
+    fn neg(self) -> Mpq {
+        Mpq(-self.0)
+    }
+}
+
+impl Neg for &Mpq {
+    type Output = Mpq;
+    fn neg(self) -> Mpq {
+        Mpq(-&self.0)
+    }
+}
+
+macro_rules! impl_from_prim {
+    ($($prim:ident),*) => {
+        $(
+            impl From<$prim> for Mpz {
+                fn from(value: $prim) -> Mpz {
+                    Mpz(BigInt::from(value))
+                }
+            }
+
+            impl From<$prim> for Mpq {
+                fn from(value: $prim) -> Mpq {
+                    Mpq(BigRational::from_integer(BigInt::from(value)))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_prim!(u32, u64, i32, i64);
+
+impl From<Mpz> for Mpq {
+    fn from(value: Mpz) -> Mpq {
+        Mpq(BigRational::from_integer(value.0))
+    }
+}
+
+impl From<&Mpz> for Mpq {
+    fn from(value: &Mpz) -> Mpq {
+        Mpq(BigRational::from_integer(value.0.clone()))
+    }
+}
+
+impl From<&Mpz> for Option<u64> {
+    fn from(value: &Mpz) -> Option<u64> {
+        value.0.to_u64()
+    }
+}
+
+impl From<&Mpz> for Option<i64> {
+    fn from(value: &Mpz) -> Option<i64> {
+        value.0.to_i64()
+    }
+}
+
+impl From<&Mpz> for f64 {
+    fn from(value: &Mpz) -> f64 {
+        value.0.to_f64().expect("integer converts to a float")
+    }
+}
+
+impl From<&Mpq> for f64 {
+    fn from(value: &Mpq) -> f64 {
+        value.0.to_f64().expect("rational converts to a float")
+    }
+}
+
+impl From<Mpq> for f64 {
+    fn from(value: Mpq) -> f64 {
+        f64::from(&value)
+    }
+}
+
+impl FromStr for Mpz {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Mpz, ParseError> {
+        s.parse().map(Mpz).map_err(|_| ParseError)
+    }
+}
+
+impl FromStr for Mpq {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Mpq, ParseError> {
+        s.parse().map(Mpq).map_err(|_| ParseError)
+    }
+}
+
+impl fmt::Display for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Renders as `numer/denom`, or just `numer` for a whole number, as GMP does.
+impl fmt::Display for Mpq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Mpq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn like_gmp() {
+        let third = Mpq::ratio(&Mpz::from(2), &Mpz::from(6));
+        assert_eq!(third.to_string(), "1/3");
+        assert_eq!((&third * Mpq::from(3)).to_string(), "1");
+        assert_eq!("2/6".parse::<Mpq>().unwrap(), third);
+        assert_eq!(
+            Mpq::ratio(&Mpz::from(-7), &Mpz::from(2)).floor(),
+            Mpz::from(-4)
+        );
+        assert_eq!(Mpz::from(-7) / 2u64, Mpz::from(-3));
+        assert_eq!(Option::<u64>::from(&Mpz::from(-1)), None);
+
+        let mut exact = Mpq::new();
+        exact.set_d(0.1);
+        assert!(exact > Mpq::ratio(&Mpz::from(1), &Mpz::from(10)));
+        assert_eq!(f64::from(&exact), 0.1);
+    }
+}
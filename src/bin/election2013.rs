@@ -3,10 +3,12 @@ extern crate csv;
 
 use std::env;
 use std::error::Error;
+use std::process;
 
 use aus_senate::ballot::*;
 use aus_senate::ballot_parse::*;
 use aus_senate::candidate::*;
+use aus_senate::config::ElectionConfig;
 use aus_senate::parse::gvt2013::GVT;
 use aus_senate::parse::gvt_usage2013::GVTUsage;
 use aus_senate::parse::*;
@@ -22,11 +24,16 @@ fn create_gvt_ballot_list(gvt: &GVT, gvt_usage: &GVTUsage, state: &str) -> Vec<I
         // with a count of 0, but absent are from the actual GVT description.
         .filter(|&(_, &vote_count)| vote_count != 0)
         // We then create a ballot with the right list of preferences from the GVT description.
-        .map(|(group, &vote_count)| Ok(Ballot::multi(vote_count, gvt[state][group].clone())))
+        .map(|(group, &vote_count)| {
+            Ok(Ballot::multi(
+                u64::from(vote_count),
+                gvt[state][group].clone(),
+            ))
+        })
         .collect()
 }
 
-fn main_with_result() -> Result<(), Box<Error>> {
+fn main_with_result() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
 
     if args.len() != 6 {
@@ -62,17 +69,17 @@ fn main_with_result() -> Result<(), Box<Error>> {
     // Then extend it with the below the line votes.
     ballots.extend(
         btl_votes
-            .into_iter()
-            .map(|(_, pref_map)| Ok(Ballot::single(flatten_pref_map(pref_map)))),
+            .into_values()
+            .map(|pref_map| Ok(Ballot::single(flatten_pref_map(pref_map)))),
     );
 
-    let result = decide_election(&candidates, &[], ballots, 6)?;
+    let result = decide_election(&candidates, &[], ballots, &ElectionConfig::new(6))?;
 
-    for &(ref s, _) in &result.senators {
-        println!("Elected: {} {} ({})", s.other_names, s.surname, s.party);
+    for (s, _) in &result.senators {
+        println!("Elected: {}", s);
     }
 
-    if result.tied {
+    if result.tied() {
         println!("Those last two tied for the last seat.");
     }
 
@@ -81,6 +88,7 @@ fn main_with_result() -> Result<(), Box<Error>> {
 
 fn main() {
     if let Err(e) = main_with_result() {
-        println!("Error: {:?}", e);
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
     }
 }
@@ -1,54 +1,1178 @@
 #[macro_use]
 extern crate log;
 extern crate aus_senate;
+extern crate clap;
+extern crate rayon;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
-use aus_senate::{election2016, exhausted_votes};
+use aus_senate::ballot_cache::BallotCache;
+use aus_senate::ballot_parse::Section;
+use aus_senate::candidate::{distinct_name, get_state_candidates};
+use aus_senate::config::{ElectionConfig, MalformedBallots};
+use aus_senate::parse::prefs2016::RowFilter;
+use aus_senate::parse::{
+    first_preferences_from_path, official_distribution_from_path, parse_candidates_from_path,
+};
+use aus_senate::quota::{self, Quota};
+use aus_senate::report::{
+    first_count_divergence, formal_total_divergence, validate_first_preferences, write_markdown,
+    write_narrative, write_transcript_csv, CountDivergence,
+};
+use aus_senate::senate_result::{ElectionOutcome, Senate};
+use aus_senate::stats::Stats;
+use aus_senate::tie_break::{self, TieBreak};
+use aus_senate::transfer::SurplusTransfer;
+use aus_senate::util::{
+    check_vacancies, normalise_state, num_vacancies, state_of_file, states_and_territories,
+    BTreeMap, ElectionType, STDIN,
+};
+use aus_senate::{election2016, exhausted_votes, term};
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, Parser, ValueEnum};
+use rayon::prelude::*;
 use std::env;
 use std::error::Error;
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+/// Count the Senate election in a state or territory from the AEC's 2016 files.
+///
+/// Options can also be given in a TOML file with --config, named like those on the command line,
+/// which override them.
+#[derive(Parser)]
+#[command(
+    name = "election2016",
+    override_usage = "election2016 --candidates <file> --prefs <file>... --state <state> [options]
+       election2016 --candidates <file> --prefs <file>... --all-states [options]
+       election2016 --candidates <file> --resume <file> --state <state> [options]
+       election2016 --candidates <file> --ballot-cache <file> --state <state> [options]",
+    after_help = AFTER_HELP,
+    group(
+        ArgGroup::new("ballots")
+            .required(true)
+            .multiple(true)
+            .args(["prefs", "resume", "ballot_cache"])
+    ),
+    group(ArgGroup::new("states").required(true).args(["state", "all_states"])),
+)]
+struct Options {
+    /// TOML file of options to use, overridden by any given on the command line; see
+    /// examples/run.toml
+    #[arg(long, value_name = "file")]
+    config: Option<String>,
+    /// AEC candidate information file
+    #[arg(long, value_name = "file")]
+    candidates: String,
+    /// AEC formal preferences file, repeated for split files
+    #[arg(long, value_name = "file", conflicts_with = "resume")]
+    prefs: Vec<String>,
+    /// State or territory to count, e.g. NSW
+    #[arg(long, value_name = "state")]
+    state: Option<String>,
+    /// Count every state and territory with a preferences file, telling them apart by the
+    /// abbreviation in each file's name
+    #[arg(long, conflicts_with_all = [
+        "seats", "transcript", "validate", "first_prefs", "check_distribution", "formality",
+        "division_first_prefs", "terms", "checkpoint", "resume", "ballot_cache",
+    ])]
+    all_states: bool,
+    /// double-dissolution or half-senate, which sets the number of vacancies for each state
+    #[arg(
+        long,
+        value_name = "type",
+        value_parser = parse_election_type,
+        default_value = "double-dissolution"
+    )]
+    election_type: ElectionType,
+    /// Number of vacancies [default: 12 for a state after a double dissolution, 6 at a
+    /// half-Senate election, 2 for a territory]
+    #[arg(long, value_name = "n")]
+    seats: Option<usize>,
+    /// droop, hare, imperial, or a fixed number of votes [default: droop]
+    #[arg(long, value_name = "quota", value_parser = parse_quota)]
+    quota: Option<Box<dyn Quota>>,
+    /// Method of transferring surplus votes [default: inclusive-gregory]. Meek's method isn't
+    /// how the Senate is counted, and is only for comparison with jurisdictions that use it
+    #[arg(long, value_name = "method", value_enum)]
+    transfer: Option<Transfer>,
+    /// countback, random or unresolved, for resolving ties between candidates with equal votes
+    /// [default: countback]
+    #[arg(long, value_name = "policy", value_parser = parse_tie_break)]
+    tie_break: Option<Box<dyn TieBreak>>,
+    /// Seed for breaking ties that countback can't resolve at random. Without one, such a tie
+    /// leaves the count undecided
+    #[arg(long, value_name = "n")]
+    tie_break_seed: Option<usize>,
+    /// Stop after this many counts, leaving the rest of the candidates continuing, for
+    /// debugging a count
+    #[arg(long, value_name = "n")]
+    max_counts: Option<usize>,
+    /// Add where the ballots with each sequence of preferences ended up to the result, which
+    /// takes a lot of memory. Only with the json format
+    #[arg(long)]
+    ballot_destinations: bool,
+    /// Check that the votes add up to the formal ballots at every count, failing with the count
+    /// and the discrepancy if they don't
+    #[arg(long)]
+    audit: bool,
+    /// Format to write the result in
+    #[arg(long, value_name = "format", value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// File to write the result to [default: standard output]
+    #[arg(long, value_name = "file")]
+    output: Option<String>,
+    /// File to write the distribution of preferences to, as CSV
+    #[arg(long, value_name = "file")]
+    transcript: Option<String>,
+    /// Add a column to the transcript of the ballot papers each candidate would need to reach
+    /// the quota, at the average value of their papers
+    #[arg(long, requires = "transcript")]
+    quota_papers: bool,
+    /// Directory to write exhausted votes to
+    #[arg(long, value_name = "dir", default_value = "results")]
+    results_dir: String,
+    /// Skip malformed rows instead of stopping
+    #[arg(long)]
+    skip_malformed: bool,
+    /// Only accept votes below the line numbering at least as many boxes as instructed, without
+    /// the savings provision for fewer
+    #[arg(long)]
+    no_btl_savings: bool,
+    /// Only count the ballots from this vote collection point, by name or ID
+    #[arg(long, value_name = "p")]
+    collection_point: Option<String>,
+    /// Leave out the ballots from this vote collection point, by name or ID; repeated to leave
+    /// out several
+    #[arg(long, value_name = "p")]
+    exclude_collection_point: Vec<String>,
+    /// Leave out the ballots in batches with this number; repeated to leave out several
+    #[arg(long, value_name = "n")]
+    exclude_batch: Vec<String>,
+    /// Only parse the files, reporting the ballots without counting them
+    #[arg(long, requires = "prefs", conflicts_with_all = ["resume", "ballot_cache"])]
+    validate: bool,
+    /// AEC first preferences by candidate file, to check the first preferences on the ballots
+    /// against
+    #[arg(long, value_name = "file")]
+    first_prefs: Option<String>,
+    /// Fraction of the total of --first-prefs that the number of formal ballots can differ by
+    /// without a warning
+    #[arg(long, value_name = "x", value_parser = parse_threshold, default_value_t = 0.001)]
+    formal_threshold: f64,
+    /// AEC distribution of preferences file, to check the count against count by count,
+    /// stopping at the first difference
+    #[arg(long, value_name = "file")]
+    check_distribution: Option<String>,
+    /// File to write the formality of every ballot to, as CSV keyed by batch and paper number,
+    /// with the reason for each informal ballot
+    #[arg(long, value_name = "file", requires = "prefs")]
+    formality: Option<String>,
+    /// File to write the first preferences of the formal ballots to, as CSV by division and vote
+    /// type like the AEC's SenateStateFirstPrefsByDivisionByVoteType file
+    #[arg(long, value_name = "file", requires = "prefs")]
+    division_first_prefs: Option<String>,
+    /// Also allocate long and short terms after a double dissolution, both in the order the
+    /// senators were elected and by re-counting the ballots for half the vacancies under
+    /// section 282, and report where the two differ. Only with the text format
+    #[arg(long, requires = "prefs")]
+    terms: bool,
+    /// File to save the state of the count to as it goes, so that it can be resumed with
+    /// --resume; gzipped if the name ends in .gz
+    #[arg(long, value_name = "file")]
+    checkpoint: Option<String>,
+    /// Number of counts between checkpoints
+    #[arg(
+        long,
+        value_name = "n",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        default_value_t = 10
+    )]
+    checkpoint_every: usize,
+    /// Resume the count from a checkpoint instead of reading --prefs, with the same options it
+    /// was started with. Checkpoints are saved to the same file unless --checkpoint is given
+    #[arg(long, value_name = "file", conflicts_with_all = [
+        "ballot_cache", "formality", "division_first_prefs", "terms",
+    ])]
+    resume: Option<String>,
+    /// File to keep the formal ballots in, so that later counts read it instead of --prefs,
+    /// which is much faster. It's written from --prefs if it doesn't exist, and gzipped if the
+    /// name ends in .gz. Delete it when the preferences change. The rows of a ballot cache
+    /// can't be chosen by collection point or batch
+    #[arg(long, value_name = "file", conflicts_with_all = [
+        "checkpoint", "collection_point", "exclude_collection_point", "exclude_batch",
+    ])]
+    ballot_cache: Option<String>,
+    /// Also print a fingerprint of the formal ballots, to check that two runs read the same
+    /// ballots, each group's primary vote, each party's first preferences and seats, first
+    /// preferences above and below the line, and the formal ballots by number of preferences,
+    /// and list the groups with --validate. With the text format, also tell the story of the
+    /// count, one paragraph per count, unless the candidates are read from standard input
+    #[arg(short, long)]
+    verbose: bool,
+    /// Options for the count from the configuration file, which those on the command line
+    /// override.
+    #[arg(skip)]
+    count: Option<ElectionConfig>,
+}
+
+const AFTER_HELP: &str = "\
+Either the candidates or the preferences can be read from standard input by giving - as the
+file, such as with zcat prefs.csv.gz | election2016 --prefs - .... Standard input can only be
+read once, so it can't be used with options that read the file again: --formality,
+--division-first-prefs and --terms for the preferences, and --transcript, --first-prefs,
+--check-distribution, --formality, --division-first-prefs and --terms for the candidates.
+Neither can it be used with --all-states, which needs the state in each file's name.
+
+Set RUST_LOG=info to log each senator elected, debug to also log every count, or trace to log
+the tallies after each count as well.";
+
+/// Format to write the result in.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    /// A table of the elected senators, for pasting into reports and wikis.
+    Markdown,
+}
+
+/// Method of transferring surplus votes, named as on the command line.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Transfer {
+    InclusiveGregory,
+    WeightedInclusiveGregory,
+    LastParcel,
+    Meek,
+}
+
+impl From<Transfer> for SurplusTransfer {
+    fn from(transfer: Transfer) -> SurplusTransfer {
+        match transfer {
+            Transfer::InclusiveGregory => SurplusTransfer::InclusiveGregory,
+            Transfer::WeightedInclusiveGregory => SurplusTransfer::WeightedInclusiveGregory,
+            Transfer::LastParcel => SurplusTransfer::LastParcel,
+            Transfer::Meek => SurplusTransfer::Meek,
+        }
+    }
+}
+
+/// Method of computing the quota, other than fixing it in advance.
+#[derive(Clone, Copy, ValueEnum)]
+enum QuotaMethod {
+    Droop,
+    Hare,
+    Imperial,
+}
+
+fn parse_quota(quota: &str) -> Result<Box<dyn Quota>, String> {
+    if let Ok(method) = QuotaMethod::from_str(quota, false) {
+        return Ok(match method {
+            QuotaMethod::Droop => Box::new(quota::Droop),
+            QuotaMethod::Hare => Box::new(quota::Hare),
+            QuotaMethod::Imperial => Box::new(quota::Imperial),
+        });
+    }
+    let votes = quota
+        .parse()
+        .map_err(|_| "expected droop, hare, imperial or a number of votes".to_string())?;
+    Ok(Box::new(quota::Fixed(votes)))
+}
+
+fn parse_tie_break(policy: &str) -> Result<Box<dyn TieBreak>, String> {
+    tie_break::from_name(policy).ok_or_else(|| "expected countback, random or unresolved".into())
+}
+
+fn parse_election_type(election_type: &str) -> Result<ElectionType, String> {
+    match election_type {
+        "double-dissolution" => Ok(ElectionType::DoubleDissolution),
+        "half-senate" => Ok(ElectionType::HalfSenate),
+        _ => Err("expected double-dissolution or half-senate".to_string()),
+    }
+}
+
+fn parse_threshold(threshold: &str) -> Result<f64, String> {
+    match threshold.parse::<f64>() {
+        Ok(x) if x >= 0.0 => Ok(x),
+        _ => Err("expected a fraction of at least 0".to_string()),
+    }
+}
+
+/// Options read from a `--config` file, named like the command line options.
+///
+/// The `[count]` table holds the configuration of the count itself, as an `ElectionConfig`.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    candidates: Option<String>,
+    prefs: Vec<String>,
+    state: Option<String>,
+    all_states: bool,
+    election_type: Option<String>,
+    format: Option<String>,
+    output: Option<String>,
+    transcript: Option<String>,
+    quota_papers: bool,
+    results_dir: Option<String>,
+    collection_point: Option<String>,
+    exclude_collection_point: Vec<String>,
+    exclude_batch: Vec<String>,
+    first_prefs: Option<String>,
+    formal_threshold: Option<f64>,
+    check_distribution: Option<String>,
+    formality: Option<String>,
+    division_first_prefs: Option<String>,
+    terms: bool,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<usize>,
+    ballot_cache: Option<String>,
+    verbose: bool,
+    count: Option<ElectionConfig>,
+}
+
+impl ConfigFile {
+    fn read(path: &str) -> Result<ConfigFile, String> {
+        let input = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        toml::from_str(&input).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// The options in the file as command line arguments, leaving out those already `given` on
+    /// the command line.
+    fn args(self, given: &ArgMatches) -> Vec<String> {
+        let mut args = FileArgs {
+            given,
+            args: vec![],
+        };
+        args.value("candidates", self.candidates);
+        args.values("prefs", self.prefs);
+        if !args.given("state") && !args.given("all_states") {
+            args.value("state", self.state);
+            args.flag("all_states", self.all_states);
+        }
+        args.value("election_type", self.election_type);
+        args.value("format", self.format);
+        args.value("output", self.output);
+        args.value("transcript", self.transcript);
+        args.flag("quota_papers", self.quota_papers);
+        args.value("results_dir", self.results_dir);
+        args.value("collection_point", self.collection_point);
+        args.values("exclude_collection_point", self.exclude_collection_point);
+        args.values("exclude_batch", self.exclude_batch);
+        args.value("first_prefs", self.first_prefs);
+        args.value("formal_threshold", self.formal_threshold);
+        args.value("check_distribution", self.check_distribution);
+        args.value("formality", self.formality);
+        args.value("division_first_prefs", self.division_first_prefs);
+        args.flag("terms", self.terms);
+        args.value("checkpoint", self.checkpoint);
+        args.value("checkpoint_every", self.checkpoint_every);
+        args.value("ballot_cache", self.ballot_cache);
+        args.flag("verbose", self.verbose);
+        args.args
+    }
+}
+
+/// Command line arguments made from the options in a configuration file.
+struct FileArgs<'a> {
+    given: &'a ArgMatches,
+    args: Vec<String>,
+}
+
+impl<'a> FileArgs<'a> {
+    fn given(&self, id: &str) -> bool {
+        self.given.value_source(id) == Some(ValueSource::CommandLine)
+    }
+
+    fn push(&mut self, id: &str) {
+        self.args.push(format!("--{}", id.replace('_', "-")));
+    }
+
+    fn value<T: ToString>(&mut self, id: &str, value: Option<T>) {
+        if let (Some(value), false) = (value, self.given(id)) {
+            self.push(id);
+            self.args.push(value.to_string());
+        }
+    }
+
+    fn values(&mut self, id: &str, values: Vec<String>) {
+        if !self.given(id) {
+            for value in values {
+                self.push(id);
+                self.args.push(value);
+            }
+        }
+    }
+
+    fn flag(&mut self, id: &str, set: bool) {
+        if set && !self.given(id) {
+            self.push(id);
+        }
+    }
+}
+
+impl Options {
+    /// Parse the command line, filling in anything not given on it from the configuration file,
+    /// or exit with the usage if the options are invalid.
+    fn from_args() -> Options {
+        Options::try_from_args(env::args().collect()).unwrap_or_else(|e| e.exit())
+    }
 
-fn main_with_result() -> Result<(), Box<Error>> {
+    fn try_from_args(mut args: Vec<String>) -> Result<Options, clap::Error> {
+        // Find the configuration file, and which options are on the command line, before
+        // checking the options together with those from the file.
+        let given = Options::command()
+            .ignore_errors(true)
+            .try_get_matches_from(&args)?;
+        let file = match given.get_one::<String>("config") {
+            Some(path) => ConfigFile::read(path)
+                .map_err(|e| Options::command().error(ErrorKind::InvalidValue, e))?,
+            None => ConfigFile::default(),
+        };
+        let count = file.count.clone();
+        args.extend(file.args(&given));
+        let mut opts = Options::try_parse_from(args)?;
+
+        if let Some(count) = count {
+            opts.skip_malformed |= count.malformed_ballots == MalformedBallots::Skip;
+            // Counting every state gives each all of its vacancies, whatever the file says, and
+            // choosing the type of election on the command line also overrides them.
+            let chose_election_type =
+                given.value_source("election_type") == Some(ValueSource::CommandLine);
+            if opts.seats.is_none() && !opts.all_states && !chose_election_type {
+                opts.seats = Some(count.num_positions);
+            }
+            opts.count = Some(count);
+        }
+        opts.check()?;
+        Ok(opts)
+    }
+
+    /// Check the options that conflict only with particular values of each other.
+    fn check(&self) -> Result<(), clap::Error> {
+        let conflict = |msg: &str| Err(Options::command().error(ErrorKind::ArgumentConflict, msg));
+        if self.ballot_destinations && self.format != Format::Json {
+            return conflict("--ballot-destinations needs --format json");
+        }
+        if self.terms && self.election_type != ElectionType::DoubleDissolution {
+            return conflict("--terms needs a double dissolution count");
+        }
+        if self.terms && self.format != Format::Text {
+            return conflict("--terms needs the text format");
+        }
+        let transfer = match self.transfer {
+            Some(transfer) => Some(transfer.into()),
+            None => self.count.as_ref().map(|c| c.surplus_transfer),
+        };
+        let needs_transcript =
+            self.transcript.is_some() || self.audit || self.check_distribution.is_some();
+        if transfer == Some(SurplusTransfer::Meek)
+            && (needs_transcript || self.checkpoint.is_some() || self.resume.is_some())
+        {
+            return conflict(
+                "--transfer meek keeps no transcript and can't be checkpointed, so it conflicts \
+                 with --transcript, --audit, --check-distribution, --checkpoint and --resume",
+            );
+        }
+
+        // Standard input can only be read once.
+        let prefs_stdin = self.prefs.iter().filter(|p| *p == STDIN).count();
+        let candidates_stdin = self.candidates == STDIN;
+        if prefs_stdin + candidates_stdin as usize > 1 {
+            return conflict("only one file can be read from standard input");
+        }
+        if (prefs_stdin > 0 || candidates_stdin) && self.all_states {
+            return conflict("--all-states can't read from standard input");
+        }
+        let reads_prefs_again =
+            self.formality.is_some() || self.division_first_prefs.is_some() || self.terms;
+        if prefs_stdin > 0 && reads_prefs_again {
+            return conflict(
+                "--formality, --division-first-prefs and --terms read the preferences again, \
+                 so they can't be read from standard input",
+            );
+        }
+        if candidates_stdin
+            && (reads_prefs_again
+                || self.transcript.is_some()
+                || self.first_prefs.is_some()
+                || self.check_distribution.is_some())
+        {
+            return conflict(
+                "--transcript, --first-prefs, --check-distribution, --formality, \
+                 --division-first-prefs and --terms read the candidates again, so they can't be \
+                 read from standard input",
+            );
+        }
+        Ok(())
+    }
+}
+
+fn main_with_result() -> Result<(), Box<dyn Error>> {
     env_logger::init()?;
 
-    let args: Vec<String> = env::args().collect();
+    let opts = Options::from_args();
 
-    if args.len() != 4 && args.len() != 5 {
-        println!("Usage: ./election2016 <candidates file> <prefs file> <state> [num candidates]");
-        Err("invalid command line arguments.".to_string())?;
+    let state = match opts.state {
+        Some(ref state) => normalise_state(state)?,
+        None => return count_all_states(&opts),
+    };
+    let num_candidates = match opts.seats {
+        Some(n) => {
+            check_vacancies(state, n)?;
+            n
+        }
+        None => num_vacancies(state, opts.election_type)?,
+    };
+    let config = election_config(&opts, num_candidates);
+
+    let prefs_file_names: Vec<&str> = opts.prefs.iter().map(|s| &s[..]).collect();
+
+    if let Some(ref path) = opts.formality {
+        election2016::write_formality(
+            &opts.candidates,
+            &prefs_file_names,
+            state,
+            &config,
+            BufWriter::new(File::create(path)?),
+        )?;
+        eprintln!("Wrote the formality of each ballot to {}", path);
+    }
+
+    if let Some(ref path) = opts.division_first_prefs {
+        election2016::write_first_prefs_by_division(
+            &opts.candidates,
+            &prefs_file_names,
+            state,
+            &config,
+            BufWriter::new(File::create(path)?),
+        )?;
+        eprintln!(
+            "Wrote first preferences by division and vote type to {}",
+            path
+        );
     }
 
-    let candidates_file_name = &args[1];
-    let prefs_file_name = &args[2];
-    let state = &args[3];
-    let num_candidates = match args.get(4) {
-        Some(x) => x.parse::<usize>()?,
-        None => 12,
+    if opts.validate {
+        let validation =
+            election2016::validate(&opts.candidates, &prefs_file_names, state, &config)?;
+        let mut out = open_output(&opts)?;
+        writeln!(out, "Groups: {}", validation.num_groups)?;
+        if opts.verbose {
+            for group in &validation.groups {
+                let label = match group.ticket_letter {
+                    Some(ref letter) => format!("Group {}", letter),
+                    None => "Ungrouped".to_string(),
+                };
+                writeln!(out, "  {}: {} candidates", label, group.candidate_ids.len())?;
+            }
+        }
+        writeln!(out, "Candidates: {}", validation.num_candidates)?;
+        writeln!(
+            out,
+            "Formal: {} ballots",
+            validation.stats.num_valid_votes()
+        )?;
+        write_ballot_stats(&mut out, &validation.stats, &opts)?;
+        out.flush()?;
+        return check_first_preferences(&opts, state, &validation.stats);
+    }
+    let election_result = match (opts.resume.as_ref(), opts.checkpoint.as_ref()) {
+        (Some(resume), checkpoint) => election2016::resume(
+            &opts.candidates,
+            state,
+            &config,
+            resume,
+            checkpoint.unwrap_or(resume),
+            opts.checkpoint_every,
+        )?,
+        (None, Some(checkpoint)) => election2016::run_with_checkpoints(
+            &opts.candidates,
+            &prefs_file_names,
+            state,
+            &config,
+            checkpoint,
+            opts.checkpoint_every,
+        )?,
+        (None, None) => match opts.ballot_cache {
+            Some(ref path) => count_from_cache(&opts, &prefs_file_names, state, &config, path)?,
+            None => election2016::run(&opts.candidates, &prefs_file_names, state, &config)?,
+        },
     };
+    let election_result = decided(state, election_result)?;
+
+    DirBuilder::new()
+        .recursive(true)
+        .create(&opts.results_dir)?;
+    write_exhausted(&opts, "exhausted.csv", &election_result)?;
+
+    if let Some(ref path) = opts.transcript {
+        let all_candidates = parse_candidates_from_path(&opts.candidates)?;
+        let candidates = get_state_candidates(&all_candidates, state);
+        if let Some(ref transcript) = election_result.transcript {
+            let quota = if opts.quota_papers {
+                Some(&election_result.quota)
+            } else {
+                None
+            };
+            write_transcript_csv(transcript, &candidates, quota, File::create(path)?)?;
+            eprintln!("Wrote transcript to {}", path);
+        }
+    }
+
+    let mut out = open_output(&opts)?;
+    match opts.format {
+        Format::Text => {
+            write_text(&mut out, &election_result, &opts)?;
+            write_count_narrative(&mut out, &opts, state, &election_result)?;
+            if opts.terms {
+                write_terms(&mut out, &opts, state, &config, &election_result)?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &election_result)?;
+            writeln!(out)?;
+        }
+        Format::Markdown => write_markdown(&mut out, &election_result)?,
+    }
+    out.flush()?;
+    if let Some(ref path) = opts.output {
+        eprintln!("Wrote results to {}", path);
+    }
+
+    check_first_preferences(&opts, state, &election_result.stats)?;
+    check_distribution(&opts, state, &election_result)
+}
+
+/// With `--verbose`, write the story of the count from its transcript.
+///
+/// This reads the candidates again to name those who weren't elected, so it's left out if they
+/// were read from standard input. It's also left out for Meek's method, which keeps no
+/// transcript.
+fn write_count_narrative<W: Write>(
+    out: &mut W,
+    opts: &Options,
+    state: &str,
+    result: &Senate,
+) -> Result<(), Box<dyn Error>> {
+    if !opts.verbose || opts.candidates == STDIN || result.transcript.is_none() {
+        return Ok(());
+    }
+    let all_candidates = parse_candidates_from_path(&opts.candidates)?;
+    writeln!(out)?;
+    write_narrative(out, result, &get_state_candidates(&all_candidates, state))?;
+    Ok(())
+}
 
-    let election_result =
-        election2016::run(candidates_file_name, prefs_file_name, state, num_candidates)?;
+/// Write the terms of the senators elected at a double dissolution, by both the order-elected
+/// and re-count methods, and tell whether the two agree.
+fn write_terms<W: Write>(
+    out: &mut W,
+    opts: &Options,
+    state: &str,
+    config: &ElectionConfig,
+    result: &Senate,
+) -> Result<(), Box<dyn Error>> {
+    let prefs_file_names: Vec<&str> = opts.prefs.iter().map(|s| &s[..]).collect();
+    let allocation =
+        election2016::allocate_terms(&opts.candidates, &prefs_file_names, state, config, result)?;
 
-    println!("=== Elected ===");
-    for &(ref c, ref votes) in &election_result.senators {
-        println!(
-            "{} {} ({}) [{} votes]",
-            c.other_names, c.surname, c.party, votes
+    writeln!(out, "Terms by order elected and by re-count:")?;
+    for (name, &(id, order_term)) in result.senator_names.iter().zip(&allocation.order_elected) {
+        let recount_term = allocation
+            .recount
+            .iter()
+            .find(|&&(s, _)| s == id)
+            .map(|&(_, t)| t);
+        match recount_term {
+            Some(t) if t != order_term => {
+                writeln!(out, "  {}: {}, {} by re-count", name, order_term, t)?
+            }
+            _ => writeln!(out, "  {}: {}", name, order_term)?,
+        }
+    }
+    if let Some(tie) = term::term_allocation_tie(result) {
+        let names: Vec<&str> = result
+            .senators
+            .iter()
+            .zip(&result.senator_names)
+            .filter(|((c, _), _)| tie.candidates.contains(&c.id))
+            .map(|(_, name)| &name[..])
+            .collect();
+        writeln!(
+            out,
+            "Tie in the {} at count {} between {}, broken {}",
+            tie.kind,
+            tie.count,
+            names.join(", "),
+            if tie.drawn { "by lot" } else { "by countback" }
+        )?;
+    }
+    if !allocation.methods_differ() {
+        writeln!(out, "The two methods agree for every senator")?;
+    } else {
+        writeln!(
+            out,
+            "The two methods differ for {} senators",
+            allocation.differences.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// Check the first preferences on the ballots against the official totals from `--first-prefs`,
+/// printing every candidate whose totals differ.
+fn check_first_preferences(
+    opts: &Options,
+    state: &str,
+    stats: &Stats,
+) -> Result<(), Box<dyn Error>> {
+    let path = match opts.first_prefs {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+    let all_candidates = parse_candidates_from_path(&opts.candidates)?;
+    let summary = first_preferences_from_path(path, &all_candidates, state)?;
+    if let Some(divergence) =
+        formal_total_divergence(&summary, stats.num_valid_votes(), opts.formal_threshold)
+    {
+        warn!(
+            "{} formal ballots were counted, but the first preferences in {} total {}, a \
+             difference of {:.3}%; the preferences may not have been read correctly",
+            stats.num_valid_votes(),
+            path,
+            summary.values().sum::<u64>(),
+            divergence * 100.0
+        );
+    }
+    let discrepancies = validate_first_preferences(&summary, stats.first_preferences());
+    if discrepancies.is_empty() {
+        eprintln!("First preferences match {} for every candidate", path);
+        return Ok(());
+    }
+    let candidates = get_state_candidates(&all_candidates, state);
+    for d in &discrepancies {
+        eprintln!(
+            "First preferences for {}: {} official, {} on the ballots",
+            distinct_name(&candidates, d.candidate),
+            d.official,
+            d.computed
         );
     }
+    Err(format!(
+        "first preferences differ from {} for {} candidates",
+        path,
+        discrepancies.len()
+    )
+    .into())
+}
 
-    if election_result.tied {
-        println!("Tie for the last place");
+/// Check the count against the official distribution of preferences, if one was given, failing
+/// at the first count that differs.
+fn check_distribution(opts: &Options, state: &str, result: &Senate) -> Result<(), Box<dyn Error>> {
+    let path = match opts.check_distribution {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+    let transcript = match result.transcript {
+        Some(ref transcript) => transcript,
+        None => return Err("checking the distribution of preferences needs a transcript".into()),
+    };
+    let all_candidates = parse_candidates_from_path(&opts.candidates)?;
+    let official = official_distribution_from_path(path, &all_candidates, state)?;
+    let candidates = get_state_candidates(&all_candidates, state);
+    let difference = match first_count_divergence(&official, transcript) {
+        None => {
+            eprintln!(
+                "The count matches {} at all {} counts",
+                path,
+                transcript.counts.len()
+            );
+            return Ok(());
+        }
+        Some(CountDivergence::Votes {
+            count,
+            candidate,
+            official,
+            computed,
+        }) => format!(
+            "at count {}, {} has {} votes, but {} in {}",
+            count,
+            distinct_name(&candidates, candidate),
+            computed,
+            official.map_or_else(|| "none".to_string(), |v| v.to_string()),
+            path
+        ),
+        Some(CountDivergence::ExtraCount { count }) => {
+            format!(
+                "the count reached count {}, which {} doesn't have",
+                count, path
+            )
+        }
+        Some(CountDivergence::MissingCount { count }) => {
+            format!(
+                "{} goes on to count {}, which the count didn't reach",
+                path, count
+            )
+        }
+    };
+    Err(format!(
+        "the count differs from the official distribution: {}",
+        difference
+    )
+    .into())
+}
+
+fn election_config(opts: &Options, num_positions: usize) -> ElectionConfig {
+    let mut config = match opts.count {
+        Some(ref count) => ElectionConfig {
+            num_positions,
+            ..count.clone()
+        },
+        None => ElectionConfig::new(num_positions),
+    };
+    if let Some(ref quota) = opts.quota {
+        config.quota = quota.clone();
+    }
+    if let Some(transfer) = opts.transfer {
+        config.surplus_transfer = transfer.into();
+    }
+    if let Some(ref tie_break) = opts.tie_break {
+        config.tie_break = tie_break.clone();
     }
+    if opts.tie_break_seed.is_some() {
+        config.tie_break_seed = opts.tie_break_seed;
+    }
+    if opts.max_counts.is_some() {
+        config.max_counts = opts.max_counts;
+    }
+    config.ballot_destinations |= opts.ballot_destinations;
+    if opts.skip_malformed {
+        config.malformed_ballots = MalformedBallots::Skip;
+    }
+    if opts.no_btl_savings {
+        config.formality = config.formality.without_btl_savings();
+    }
+    // The audit, the check against the official distribution and the story of the count with
+    // --verbose are made from the transcript, which Meek's method doesn't keep.
+    let story = opts.verbose && config.surplus_transfer != SurplusTransfer::Meek;
+    config.transcript |=
+        opts.transcript.is_some() || opts.audit || opts.check_distribution.is_some() || story;
+    config.audit |= opts.audit;
+    config.fingerprint_ballots |= opts.verbose;
+    let mut filters = vec![];
+    if let Some(ref point) = opts.collection_point {
+        filters.push(RowFilter::collection_point(point));
+    }
+    if !opts.exclude_collection_point.is_empty() {
+        filters.push(RowFilter::excluding_collection_points(
+            &opts.exclude_collection_point,
+        ));
+    }
+    if !opts.exclude_batch.is_empty() {
+        filters.push(RowFilter::excluding_batches(&opts.exclude_batch));
+    }
+    let mut filters = filters.into_iter();
+    if let Some(first) = filters.next() {
+        config.row_filter = Some(filters.fold(first, RowFilter::and));
+    }
+    config
+}
 
+/// The result of a count, or an error if a tie left it undecided.
+fn decided(state: &str, result: Senate) -> Result<Senate, Box<dyn Error>> {
+    match result.into_outcome() {
+        ElectionOutcome::Decided(senate) => Ok(*senate),
+        ElectionOutcome::Undecided {
+            contested_seat,
+            candidates,
+            count,
+        } => {
+            let candidates: Vec<_> = candidates.iter().map(ToString::to_string).collect();
+            Err(format!(
+                "the count for {} is undecided: candidates {} are tied at count {} for seat {}, \
+                 which the tie break policy can't resolve; give --tie-break-seed to break the tie \
+                 at random",
+                state,
+                candidates.join(", "),
+                count,
+                contested_seat
+            )
+            .into())
+        }
+    }
+}
+
+fn open_output(opts: &Options) -> io::Result<Box<dyn Write>> {
+    Ok(match opts.output {
+        Some(ref path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+/// Count from the ballot cache at `path`, first writing it from the preferences files if it
+/// doesn't exist.
+fn count_from_cache(
+    opts: &Options,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    path: &str,
+) -> Result<Senate, Box<dyn Error>> {
+    let cache = if Path::new(path).exists() {
+        BallotCache::read(path)?
+    } else if prefs_file_names.is_empty() {
+        return Err(format!(
+            "ballot cache {} doesn't exist, and there's no --prefs to write it from",
+            path
+        )
+        .into());
+    } else {
+        let cache = election2016::cache_ballots(&opts.candidates, prefs_file_names, state, config)?;
+        cache.write(path)?;
+        eprintln!("Wrote the formal ballots to {}", path);
+        cache
+    };
+    Ok(election2016::run_from_cache(
+        &opts.candidates,
+        state,
+        config,
+        &cache,
+    )?)
+}
+
+fn write_exhausted(opts: &Options, file_name: &str, result: &Senate) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(&opts.results_dir).join(file_name);
     exhausted_votes::write_out(
-        &election_result.stats.exhausted_votes,
-        "results/exhausted.csv",
+        &result.stats.exhausted_votes,
+        path.to_str().ok_or("invalid --results-dir directory")?,
     )?;
+    Ok(())
+}
+
+/// Count every state with a preferences file, and summarise the national result.
+///
+/// Each state gets all of its vacancies for the type of election, so 12 for a state after a
+/// double dissolution, 6 at a half-Senate election, and 2 for a territory.
+fn count_all_states(opts: &Options) -> Result<(), Box<dyn Error>> {
+    let mut prefs_files: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for file_name in &opts.prefs {
+        let state = state_of_file(file_name)
+            .ok_or_else(|| format!("can't tell which state {} is for", file_name))?;
+        prefs_files.entry(state).or_default().push(file_name);
+    }
+
+    DirBuilder::new()
+        .recursive(true)
+        .create(&opts.results_dir)?;
+    let mut counts = vec![];
+    for state in states_and_territories() {
+        match prefs_files.remove(state) {
+            Some(files) => {
+                let config = election_config(opts, num_vacancies(state, opts.election_type)?);
+                counts.push((state, files, config));
+            }
+            None => warn!("No preferences file for {}, skipping", state),
+        }
+    }
+
+    // Count the states in parallel, collecting the results in the order of the states.
+    let results: Vec<_> = counts
+        .into_par_iter()
+        .map(|(state, files, config)| {
+            info!("Counting {}", state);
+            election2016::run(&opts.candidates, &files, state, &config).map(|r| (state, r))
+        })
+        .collect::<Result<_, _>>()?;
+    let results = results
+        .into_iter()
+        .map(|(state, result)| decided(state, result).map(|r| (state, r)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let results: BTreeMap<_, _> = results.into_iter().collect();
+    for (state, result) in &results {
+        write_exhausted(opts, &format!("exhausted-{}.csv", state), result)?;
+    }
+
+    let mut out = open_output(opts)?;
+    match opts.format {
+        Format::Text => {
+            for (state, result) in &results {
+                writeln!(out, "##### {} #####", state)?;
+                write_text(&mut out, result, opts)?;
+                write_count_narrative(&mut out, opts, state, result)?;
+                writeln!(out)?;
+            }
+            write_national_summary(&mut out, &results)?;
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &results)?;
+            writeln!(out)?;
+        }
+        Format::Markdown => {
+            for (state, result) in &results {
+                writeln!(out, "## {}\n", state)?;
+                write_markdown(&mut out, result)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    out.flush()?;
+    if let Some(ref path) = opts.output {
+        eprintln!("Wrote results to {}", path);
+    }
+    Ok(())
+}
+
+/// Write the number of senators elected for each party across all of the states counted.
+fn write_national_summary<W: Write>(
+    out: &mut W,
+    results: &BTreeMap<&str, Senate>,
+) -> io::Result<()> {
+    let mut seats: BTreeMap<&str, usize> = BTreeMap::new();
+    for result in results.values() {
+        for (c, _) in &result.senators {
+            *seats.entry(&c.party).or_insert(0) += 1;
+        }
+    }
+    let mut seats: Vec<_> = seats.into_iter().collect();
+    seats.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let vacancies: usize = results.values().map(|r| r.vacancies).sum();
+    let unfilled: usize = results.values().map(Senate::unfilled_vacancies).sum();
+    writeln!(out, "=== National ===")?;
+    writeln!(
+        out,
+        "{} states and territories, {} vacancies",
+        results.len(),
+        vacancies
+    )?;
+    for (party, n) in seats {
+        writeln!(out, "{}: {}", party, n)?;
+    }
+    if unfilled > 0 {
+        writeln!(
+            out,
+            "Not enough candidates: {} vacancies unfilled",
+            unfilled
+        )?;
+    }
+    Ok(())
+}
+
+/// Write a summary of the result for people to read.
+fn write_text<W: Write>(out: &mut W, result: &Senate, opts: &Options) -> io::Result<()> {
+    write!(out, "{}", result)?;
+
+    let stats = &result.stats;
+    writeln!(
+        out,
+        "Exhausted: {} ballots, {} votes ({:.2}% of formal votes)",
+        stats.num_exhausted_ballots(),
+        stats.exhausted_vote_value().floor(),
+        100.0 * stats.exhaustion_rate()
+    )?;
+    writeln!(
+        out,
+        "Loss by fraction: {:.2} votes",
+        f64::from(&stats.loss_by_fraction())
+    )?;
+    if opts.verbose {
+        writeln!(out, "Primary vote:")?;
+        for (group, percentage) in result.first_preferences.primary_votes() {
+            writeln!(out, "  {}: {:.2}%", group.trim(), percentage)?;
+        }
+        writeln!(out, "First preferences and seats by party:")?;
+        let seats = result.seats_by_party();
+        for (party, tally) in &result.first_preferences.parties {
+            writeln!(
+                out,
+                "  {}: {:.2}%, {} seats",
+                party,
+                result.first_preferences.percentage(tally),
+                seats.get(party).cloned().unwrap_or(0)
+            )?;
+        }
+    }
+
+    write_ballot_stats(out, stats, opts)
+}
 
+/// Write the numbers of informal ballots, by reason, and of malformed rows if they were skipped.
+///
+/// With `--verbose`, also write the sections each group's first preferences came from.
+fn write_ballot_stats<W: Write>(out: &mut W, stats: &Stats, opts: &Options) -> io::Result<()> {
+    if let (Some(n), Some(pc)) = (stats.num_donkey_votes(), stats.donkey_vote_percentage()) {
+        writeln!(out, "Donkey votes: {} ({:.2}%)", n, pc)?;
+    }
+    if let Some(s) = stats.section_formality() {
+        writeln!(
+            out,
+            "Formal: {} above the line only, {} below the line only, {} in both sections",
+            s.above_the_line_only, s.below_the_line_only, s.both
+        )?;
+    }
+    if let Some(n) = stats.num_filtered_rows() {
+        writeln!(out, "Left out: {} ballots", n)?;
+    }
+    if let Some(fingerprint) = stats.ballots_fingerprint() {
+        writeln!(out, "Ballot fingerprint: {}", fingerprint)?;
+    }
+    writeln!(out, "Informal: {} ballots", stats.num_invalid_votes())?;
+    for (reason, count) in stats.invalid_votes() {
+        writeln!(out, "  {}: {}", reason, count)?;
+    }
+    if opts.verbose && !stats.group_first_preferences().is_empty() {
+        writeln!(out, "First preferences by group:")?;
+        for (group, counts) in stats.group_first_preferences() {
+            writeln!(
+                out,
+                "  {}: {} above the line ({:.2}%), {} below the line",
+                group,
+                counts.above_the_line,
+                counts.above_the_line_percentage(),
+                counts.below_the_line
+            )?;
+        }
+    }
+    if let (true, Some(lengths)) = (opts.verbose, stats.preference_lengths()) {
+        writeln!(out, "Formal ballots by number of preferences:")?;
+        for &(name, section) in &[
+            ("above the line", Section::AboveTheLine),
+            ("below the line", Section::BelowTheLine),
+        ] {
+            for (&n, &count) in lengths.section(section) {
+                writeln!(
+                    out,
+                    "  {} {}: {} ({:.2}%)",
+                    n,
+                    name,
+                    count,
+                    lengths.percentage(section, n)
+                )?;
+            }
+        }
+    }
+    if opts.skip_malformed {
+        writeln!(
+            out,
+            "Malformed: {} rows skipped",
+            stats.num_malformed_rows()
+        )?;
+        for (kind, count) in stats.malformed_rows() {
+            writeln!(out, "  {:?}: {}", kind, count)?;
+        }
+    }
     Ok(())
 }
 
 fn main() {
     if let Err(e) = main_with_result() {
         error!("Error: {:?}", e);
+        process::exit(1);
     }
 }
@@ -0,0 +1,438 @@
+//! Tests of how robust a result is to small changes in the vote: bootstrap resampling of the
+//! ballots, the smallest switch of first preferences that changes who fills the last seat, and
+//! swings simulated by weighting the ballots.
+
+use rand::{Rng, SeedableRng, StdRng};
+use std::cmp::Reverse;
+
+use ballot::Ballot;
+use ballot_parse::IOBallot;
+use candidate::*;
+use config::ElectionConfig;
+use error::Error;
+use senate_result::Senate;
+use util::BTreeMap;
+use voting::decide_election;
+
+/// Outcome of counting many resamples of the ballots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bootstrap {
+    /// Number of resamples counted.
+    pub runs: usize,
+    /// Number of resamples in which each candidate filled the final vacancy.
+    pub final_seat_wins: BTreeMap<CandidateId, usize>,
+}
+
+impl Bootstrap {
+    /// Fraction of resamples in which `id` filled the final vacancy.
+    pub fn final_seat_share(&self, id: CandidateId) -> f64 {
+        let wins = self.final_seat_wins.get(&id).cloned().unwrap_or(0);
+        wins as f64 / self.runs as f64
+    }
+}
+
+/// Draw as many ballot papers as there are in `ballots`, with replacement.
+///
+/// Papers are drawn by weight from the distinct preference sequences, so the resample is built
+/// without expanding the ballots into individual papers. Sequences that aren't drawn are left out.
+pub fn resample<R: Rng>(ballots: &[Ballot], rng: &mut R) -> Vec<Ballot> {
+    let mut cumulative = Vec::with_capacity(ballots.len());
+    let mut total = 0u64;
+    for b in ballots {
+        total += b.weight();
+        cumulative.push(total);
+    }
+
+    let mut weights = vec![0u64; ballots.len()];
+    for _ in 0..total {
+        let paper = rng.gen_range(0, total);
+        weights[cumulative.partition_point(|&c| c <= paper)] += 1;
+    }
+
+    ballots
+        .iter()
+        .zip(weights)
+        .filter(|&(_, weight)| weight > 0)
+        .map(|(b, weight)| Ballot::multi(weight, b.prefs.clone()))
+        .collect()
+}
+
+/// Count `runs` resamples of the ballots, and tally who fills the final vacancy in each.
+///
+/// Each resample is drawn from an RNG seeded by `seed` and the number of the run, so the whole
+/// bootstrap can be replayed, provided `config` also has a tie break seed.
+pub fn bootstrap(
+    candidates: &CandidateMap,
+    ballots: &[Ballot],
+    runs: usize,
+    seed: usize,
+    config: &ElectionConfig,
+) -> Result<Bootstrap, Error> {
+    let mut final_seat_wins = BTreeMap::new();
+
+    for run in 0..runs {
+        let mut rng: StdRng = SeedableRng::from_seed(&[seed, run][..]);
+        let sample = resample(ballots, &mut rng);
+        let result = decide_election(candidates, &[], sample.into_iter().map(Ok), config)?;
+        if let Some((last, _)) = result.senators.last() {
+            *final_seat_wins.entry(last.id).or_insert(0) += 1;
+        }
+    }
+
+    Ok(Bootstrap {
+        runs,
+        final_seat_wins,
+    })
+}
+
+/// Multiply the number of papers of each formal ballot by `multiplier`, to simulate a swing by
+/// counting some ballots more than once.
+///
+/// Multipliers are whole numbers, as every ballot counts a whole number of papers, so a swing of
+/// a fraction of a group's vote is simulated by scaling every other ballot up instead. Ballots
+/// given a multiplier of 0 are left out, and informal ballots are passed through unchanged.
+pub fn weight_ballots<I, F>(ballots: I, multiplier: F) -> impl Iterator<Item = IOBallot>
+where
+    I: IntoIterator<Item = IOBallot>,
+    F: Fn(&Ballot) -> u64,
+{
+    ballots.into_iter().filter_map(move |ballot| match ballot {
+        Ok(b) => match multiplier(&b) {
+            0 => None,
+            m => {
+                let weight = b
+                    .weight()
+                    .checked_mul(m)
+                    .expect("overflow in ballot weight");
+                Some(Ok(Ballot::multi(weight, b.prefs)))
+            }
+        },
+        Err(e) => Some(Err(e)),
+    })
+}
+
+/// Smallest change of first preferences found to change the result, by `seat_margin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeatMargin {
+    /// The senator who filled the final vacancy.
+    pub last_elected: CandidateId,
+    /// The runner-up for the final vacancy.
+    pub runner_up: CandidateId,
+    /// Number of ballot papers that have to switch their first preference from `last_elected` to
+    /// `runner_up` to change who is elected.
+    pub votes: u64,
+}
+
+/// The ballots whose switch from the last senator elected to the runner-up changes the result,
+/// found by `pivotal_ballots`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PivotalBallots {
+    pub margin: SeatMargin,
+    /// The ballots to switch, as they were cast, each with the number of its papers that switch.
+    /// Their weights add up to `margin.votes`.
+    pub ballots: Vec<Ballot>,
+}
+
+/// The first `papers` ballot papers with a first preference for `from`, taken in the same way as
+/// `switch_first_preferences`.
+pub fn first_preferences_taken(ballots: &[Ballot], from: CandidateId, papers: u64) -> Vec<Ballot> {
+    let mut remaining = papers;
+    let mut taken = vec![];
+    for b in ballots {
+        if remaining == 0 {
+            break;
+        }
+        if b.prefs.first() != Some(&from) {
+            continue;
+        }
+        let num = b.weight().min(remaining);
+        remaining -= num;
+        taken.push(Ballot::multi(num, b.prefs.clone()));
+    }
+    taken
+}
+
+/// Switch `papers` ballot papers with a first preference for `from` to `to`, by swapping the two
+/// candidates' places.
+///
+/// Papers are taken from the ballots in order, splitting a ballot cast by several papers if only
+/// some of them are needed.
+pub fn switch_first_preferences(
+    ballots: &[Ballot],
+    from: CandidateId,
+    to: CandidateId,
+    papers: u64,
+) -> Vec<Ballot> {
+    let mut remaining = papers;
+    let mut switched = Vec::with_capacity(ballots.len() + 1);
+    for b in ballots {
+        if remaining == 0 || b.prefs.first() != Some(&from) {
+            switched.push(b.clone());
+            continue;
+        }
+        let num = b.weight().min(remaining);
+        remaining -= num;
+        if num < b.weight() {
+            switched.push(Ballot::multi(b.weight() - num, b.prefs.clone()));
+        }
+        let prefs = b
+            .prefs
+            .iter()
+            .map(|&c| match c {
+                c if c == from => to,
+                c if c == to => from,
+                c => c,
+            })
+            .collect();
+        switched.push(Ballot::multi(num, prefs));
+    }
+    switched
+}
+
+/// The senators elected, in ascending order of ID rather than the order they were elected.
+fn senator_ids(result: &Senate) -> Vec<CandidateId> {
+    let mut ids: Vec<_> = result.senators.iter().map(|(c, _)| c.id).collect();
+    ids.sort();
+    ids
+}
+
+/// Find the fewest first preferences that would have to switch from the last senator elected to
+/// the runner-up to change the senators elected, recounting the ballots for each number tried.
+///
+/// The number is found by binary search, which assumes that switching more papers never changes
+/// the result back. Returns `None` if there was no runner-up, or if switching every one of the
+/// last senator's first preferences doesn't change the result. As with `bootstrap`, `config`
+/// should have a tie break seed for the search to be repeatable.
+pub fn seat_margin(
+    candidates: &CandidateMap,
+    ballots: &[Ballot],
+    config: &ElectionConfig,
+) -> Result<Option<SeatMargin>, Error> {
+    let result = decide_election(candidates, &[], ballots.iter().cloned().map(Ok), config)?;
+    let (last_elected, runner_up) = match result.final_margin {
+        Some(ref m) => (m.elected, m.runner_up),
+        None => return Ok(None),
+    };
+    let original = senator_ids(&result);
+    let changed = |papers: u64| -> Result<bool, Error> {
+        let switched = switch_first_preferences(ballots, last_elected, runner_up, papers);
+        let result = decide_election(candidates, &[], switched.into_iter().map(Ok), config)?;
+        Ok(senator_ids(&result) != original)
+    };
+
+    let first_prefs: u64 = ballots
+        .iter()
+        .filter(|b| b.prefs.first() == Some(&last_elected))
+        .map(Ballot::weight)
+        .sum();
+    if !changed(first_prefs)? {
+        return Ok(None);
+    }
+
+    // The result is unchanged by switching `lo` papers, and changed by switching `hi`.
+    let (mut lo, mut hi) = (0, first_prefs);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if changed(mid)? {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+
+    Ok(Some(SeatMargin {
+        last_elected,
+        runner_up,
+        votes: hi,
+    }))
+}
+
+/// Find ballots actually cast whose switch from the last senator elected to the runner-up would
+/// change the senators elected, as by `seat_margin`.
+///
+/// The ballots cast by the most papers are switched first, so that the margin is made up of as
+/// few preference sequences as possible. This works best on ballots with equal sequences combined,
+/// as in a `BallotSet` or a ballot cache. Returns `None` when `seat_margin` would.
+pub fn pivotal_ballots(
+    candidates: &CandidateMap,
+    ballots: &[Ballot],
+    config: &ElectionConfig,
+) -> Result<Option<PivotalBallots>, Error> {
+    let mut ordered = ballots.to_vec();
+    ordered.sort_by_key(|b| Reverse(b.weight()));
+    Ok(
+        seat_margin(candidates, &ordered, config)?.map(|margin| PivotalBallots {
+            ballots: first_preferences_taken(&ordered, margin.last_elected, margin.votes),
+            margin,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resample_keeps_papers() {
+        let ballots = vec![
+            Ballot::multi(5, ids(&[0, 1])),
+            Ballot::single(ids(&[1])),
+            Ballot::multi(4, ids(&[2, 0])),
+        ];
+        let mut rng: StdRng = SeedableRng::from_seed(&[1][..]);
+        let sample = resample(&ballots, &mut rng);
+        let papers: u64 = sample.iter().map(Ballot::weight).sum();
+        assert_eq!(papers, 10);
+        assert!(sample
+            .iter()
+            .all(|s| ballots.iter().any(|b| b.prefs == s.prefs)));
+    }
+
+    #[test]
+    fn bootstrap_final_seat() {
+        let candidates = test_candidates(3);
+        let ballots = vec![
+            Ballot::multi(60, ids(&[0])),
+            Ballot::multi(30, ids(&[1])),
+            Ballot::multi(29, ids(&[2])),
+        ];
+        let config = ElectionConfig::builder(2).tie_break_seed(3).build();
+        let result = bootstrap(&candidates, &ballots, 20, 7, &config).unwrap();
+
+        assert_eq!(result.runs, 20);
+        assert_eq!(result.final_seat_wins.values().sum::<usize>(), 20);
+        // Candidate 0 always wins the first seat, never the last.
+        assert!(!result.final_seat_wins.contains_key(&CandidateId(0)));
+        // Candidates 1 and 2 are close, so the resample decides, and is the same for the same seed.
+        assert_eq!(
+            result,
+            bootstrap(&candidates, &ballots, 20, 7, &config).unwrap()
+        );
+        assert_eq!(
+            result.final_seat_share(CandidateId(1)) + result.final_seat_share(CandidateId(2)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn weighted_group() {
+        let mut candidates = test_candidates(4);
+        for (id, group) in &[(0, "A"), (1, "A"), (2, "B"), (3, "C")] {
+            candidates.get_mut(&CandidateId(*id)).unwrap().group_name = group.to_string();
+        }
+        let ballots = || {
+            vec![
+                Ok(Ballot::multi(40, ids(&[0, 1]))),
+                Ok(Ballot::multi(10, ids(&[1]))),
+                Ok(Ballot::multi(30, ids(&[2, 0]))),
+                Ok(Ballot::multi(20, ids(&[3]))),
+            ]
+        };
+        let config = ElectionConfig::new(2);
+        let count = |ballots| decide_election(&candidates, &[], ballots, &config).unwrap();
+        let primary = |result: &Senate| -> BTreeMap<String, f64> {
+            result
+                .first_preferences
+                .primary_votes()
+                .into_iter()
+                .map(|(name, share)| (name.to_string(), share))
+                .collect()
+        };
+
+        let before = count(ballots());
+        assert_eq!(primary(&before)["B"], 30.0);
+
+        // Doubling group B's ballots gives them 60 of 130 papers.
+        let b = CandidateId(2);
+        let after = count(
+            weight_ballots(ballots(), |ballot| if ballot.prefs[0] == b { 2 } else { 1 }).collect(),
+        );
+        assert_eq!(after.stats.num_valid_votes(), 130);
+        let after_primary = primary(&after);
+        assert!((after_primary["B"] - 6000.0 / 130.0).abs() < 1e-9);
+        assert!((after_primary["A"] - 5000.0 / 130.0).abs() < 1e-9);
+        assert_eq!(after.stats.first_preferences()[&b], 60);
+
+        // Every paper is still counted once at first preferences.
+        assert_eq!(after.stats.first_preferences().values().sum::<u64>(), 130);
+
+        assert_eq!(
+            weight_ballots(ballots(), |_| 0).count(),
+            0,
+            "a multiplier of 0 leaves the ballot out"
+        );
+    }
+
+    #[test]
+    fn switch_splits_ballots() {
+        let ballots = vec![
+            Ballot::multi(3, ids(&[0, 1, 2])),
+            Ballot::multi(2, ids(&[1, 0])),
+            Ballot::multi(4, ids(&[0, 2])),
+        ];
+        let switched = switch_first_preferences(&ballots, CandidateId(0), CandidateId(1), 5);
+        assert_eq!(
+            switched
+                .iter()
+                .map(|b| (b.weight(), b.prefs.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, ids(&[1, 0, 2])),
+                (2, ids(&[1, 0])),
+                (2, ids(&[0, 2])),
+                (2, ids(&[1, 2])),
+            ]
+        );
+    }
+
+    #[test]
+    fn last_seat_margin() {
+        let candidates = test_candidates(3);
+        let ballots = vec![
+            Ballot::multi(60, ids(&[0])),
+            Ballot::multi(35, ids(&[1])),
+            Ballot::multi(24, ids(&[2])),
+        ];
+        let config = ElectionConfig::builder(2).tie_break_seed(3).build();
+        let margin = seat_margin(&candidates, &ballots, &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(margin.last_elected, CandidateId(1));
+        assert_eq!(margin.runner_up, CandidateId(2));
+        // Candidate 2 overtakes candidate 1 once 6 papers switch, leaving them with 29 to 30.
+        assert_eq!(margin.votes, 6);
+
+        // A single candidate for a single seat can't be overtaken.
+        let one = test_candidates(1);
+        let ballots = vec![Ballot::multi(5, ids(&[0]))];
+        let config = ElectionConfig::builder(1).build();
+        assert_eq!(seat_margin(&one, &ballots, &config).unwrap(), None);
+    }
+
+    #[test]
+    fn pivotal_last_seat() {
+        let candidates = test_candidates(3);
+        let ballots = vec![
+            Ballot::multi(60, ids(&[0])),
+            Ballot::multi(2, ids(&[1, 0])),
+            Ballot::multi(33, ids(&[1, 2])),
+            Ballot::multi(24, ids(&[2])),
+        ];
+        let config = ElectionConfig::builder(2).tie_break_seed(3).build();
+        let pivotal = pivotal_ballots(&candidates, &ballots, &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pivotal.margin.votes, 6);
+        // All 6 papers come from the larger of candidate 1's two sequences.
+        assert_eq!(pivotal.ballots, vec![Ballot::multi(6, ids(&[1, 2]))]);
+
+        let switched = switch_first_preferences(&ballots, CandidateId(1), CandidateId(2), 6);
+        assert_eq!(
+            first_preferences_taken(&switched, CandidateId(2), 100)
+                .iter()
+                .map(Ballot::weight)
+                .sum::<u64>(),
+            30
+        );
+    }
+}
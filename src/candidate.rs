@@ -1,8 +1,17 @@
-use std::fmt::{Debug, Error, Formatter};
+use std::fmt::{self, Debug, Display, Error, Formatter};
 use util::*;
 
-/// Integer representing a candidate.
-pub type CandidateId = u16;
+/// Identifier of a candidate, distinct from the integers used for preferences and positions.
+///
+/// IDs are assigned from the order of the candidate file, and serialize as plain integers.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub struct CandidateId(pub u16);
+
+impl Display for CandidateId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 /// Description of a candidate including name and party affiliation.
 #[derive(Serialize, Clone)]
@@ -13,6 +22,8 @@ pub struct Candidate {
     pub group_name: String,
     pub party: String,
     pub state: String,
+    /// Whether the candidate withdrew after nominations closed, leaving them on the ballot paper.
+    pub withdrawn: bool,
 }
 
 /// User-input description of a candidate with first name and surname.
@@ -22,13 +33,15 @@ pub struct CandidateName {
     pub last: String,
 }
 
+impl Display for Candidate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {} ({})", self.other_names, self.surname, self.party)
+    }
+}
+
 impl Debug for Candidate {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        write!(
-            fmt,
-            "{} {} ({})",
-            self.other_names, self.surname, self.party
-        )
+        Display::fmt(self, fmt)
     }
 }
 
@@ -43,6 +56,17 @@ pub fn get_state_candidates(all_candidates: &[Candidate], state: &str) -> Candid
     result
 }
 
+/// Get the candidates that withdrew, who are excluded before the count begins.
+pub fn get_withdrawn_candidates(candidates: &CandidateMap) -> Vec<CandidateId> {
+    let mut withdrawn: Vec<_> = candidates
+        .values()
+        .filter(|c| c.withdrawn)
+        .map(|c| c.id)
+        .collect();
+    withdrawn.sort();
+    withdrawn
+}
+
 /// Get the list of candidate IDs, in canonical order (used for parsing).
 pub fn get_candidate_id_list(all_candidates: &[Candidate], state: &str) -> Vec<CandidateId> {
     all_candidates
@@ -52,6 +76,42 @@ pub fn get_candidate_id_list(all_candidates: &[Candidate], state: &str) -> Vec<C
         .collect()
 }
 
+/// Name of a candidate for people to read, told apart from any other candidate of the same name.
+///
+/// This is the candidate as displayed, with their group added if another candidate shares their
+/// given names and surname, as the ballot paper tells them apart by their column.
+pub fn distinct_name(candidates: &CandidateMap, id: CandidateId) -> String {
+    let c = &candidates[&id];
+    let shared = candidates.values().any(|other| {
+        other.id != id && other.surname == c.surname && other.other_names == c.other_names
+    });
+    if shared {
+        format!(
+            "{} {} ({}, group {})",
+            c.other_names, c.surname, c.party, c.group_name
+        )
+    } else {
+        c.to_string()
+    }
+}
+
+/// Party that candidates without one are counted under, along with those standing as
+/// independents.
+pub const INDEPENDENT: &str = "Independent";
+
+/// The party a candidate stands for, for adding up votes and seats by party across groups.
+///
+/// Candidates with no party, and those giving their party as independent, are all counted under
+/// `INDEPENDENT`.
+pub fn party_name(candidate: &Candidate) -> &str {
+    let party = candidate.party.trim();
+    if party.is_empty() || party.eq_ignore_ascii_case(INDEPENDENT) {
+        INDEPENDENT
+    } else {
+        party
+    }
+}
+
 /// Convert a list of candidate names into a list of candidate IDs.
 pub fn find_candidates_with_names(
     candidate_names: &[CandidateName],
@@ -73,3 +133,59 @@ pub fn find_candidates_with_names(
         })
         .collect()
 }
+
+/// Create a map of `n` anonymous candidates, for use in tests.
+#[cfg(test)]
+pub fn test_candidates(n: u16) -> CandidateMap {
+    (0..n)
+        .map(|id| {
+            let id = CandidateId(id);
+            let candidate = Candidate {
+                id,
+                surname: format!("C{}", id),
+                other_names: String::new(),
+                group_name: String::new(),
+                party: String::new(),
+                state: "TAS".to_string(),
+                withdrawn: false,
+            };
+            (id, candidate)
+        })
+        .collect()
+}
+
+/// Candidate IDs from plain integers, for use in tests.
+#[cfg(test)]
+pub fn ids(raw: &[u16]) -> Vec<CandidateId> {
+    raw.iter().cloned().map(CandidateId).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distinct_names() {
+        let mut candidates = test_candidates(3);
+        for &(id, other_names, group) in &[(0, "Jo", "A"), (1, "Jo", "B"), (2, "Al", "B")] {
+            let c = candidates.get_mut(&CandidateId(id)).unwrap();
+            c.surname = "SMITH".to_string();
+            c.other_names = other_names.to_string();
+            c.group_name = group.to_string();
+            c.party = "Party".to_string();
+        }
+        assert_eq!(
+            distinct_name(&candidates, CandidateId(0)),
+            "Jo SMITH (Party, group A)"
+        );
+        assert_eq!(
+            distinct_name(&candidates, CandidateId(1)),
+            "Jo SMITH (Party, group B)"
+        );
+        // Al's given names are enough to tell them apart.
+        assert_eq!(
+            distinct_name(&candidates, CandidateId(2)),
+            "Al SMITH (Party)"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Checkpoints of a count in progress, so that a long count can be resumed after a crash.
+//!
+//! A checkpoint is taken at the start of a count, before any ballots are transferred, and holds
+//! everything needed to carry on from there: the ballots that haven't exhausted, each candidate's
+//! votes and parcels, the transfers still to be made, and the result so far. Resuming from a
+//! checkpoint gives exactly the same result as counting without stopping.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use ballot::Ballot;
+use candidate::CandidateId;
+use config::ElectionConfig;
+use error::Error;
+use senate_result::{CandidateStanding, Election, FirstPreferenceCount, TieEvent};
+use stats::Stats;
+use transcript::{Tally, Transcript, TransferKind};
+use transfer::SurplusTransfer;
+use util::*;
+use value::Arithmetic;
+
+/// A ballot that was still in the count when the checkpoint was taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BallotState {
+    pub prefs: Vec<CandidateId>,
+    pub weight: u64,
+    /// Index of the preference for the candidate currently holding the ballot.
+    pub current: usize,
+}
+
+impl From<&Ballot> for BallotState {
+    fn from(ballot: &Ballot) -> Self {
+        BallotState {
+            prefs: ballot.prefs.clone(),
+            weight: ballot.weight(),
+            current: ballot.current(),
+        }
+    }
+}
+
+impl From<BallotState> for Ballot {
+    fn from(state: BallotState) -> Self {
+        let mut ballot = Ballot::multi(state.weight, state.prefs);
+        ballot.set_current(state.current);
+        ballot
+    }
+}
+
+/// A parcel of ballots at a single value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParcelState {
+    #[serde(with = "as_string")]
+    pub value: Frac,
+    /// Indices of the parcel's ballots in `CountState::ballots`.
+    pub ballots: Vec<usize>,
+}
+
+/// A candidate's votes at every count so far, and the ballots making them up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CandidateState {
+    pub id: CandidateId,
+    #[serde(with = "as_strings")]
+    pub votes: Vec<Int>,
+    pub parcels: Vec<ParcelState>,
+    /// Index of the count at which each of the parcels was received.
+    pub received: Vec<usize>,
+    /// Whether the candidate has been elected or excluded.
+    pub eliminated: bool,
+}
+
+/// A transfer of ballots waiting to be made.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferState {
+    pub from: Vec<CandidateId>,
+    pub kind: TransferKind,
+    pub parcels: Vec<ParcelState>,
+}
+
+/// A senator elected before the checkpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SenatorState {
+    pub id: CandidateId,
+    #[serde(with = "as_string")]
+    pub votes: Int,
+    pub election: Election,
+}
+
+/// The most recent exclusion, with the tallies just before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExclusionState {
+    pub count: usize,
+    pub excluded: Vec<CandidateId>,
+    #[serde(with = "as_pairs")]
+    pub before: BTreeMap<CandidateId, Tally>,
+}
+
+/// State of a count at the start of one of its counts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountState {
+    /// The count to resume at.
+    pub count: usize,
+    pub num_positions: usize,
+    pub arithmetic: Arithmetic,
+    pub surplus_transfer: SurplusTransfer,
+    /// Name of the tie break policy.
+    pub tie_break: String,
+    pub tie_break_seed: Option<usize>,
+    /// The quota, which may have been reduced if the configuration allows.
+    #[serde(with = "as_string")]
+    pub quota: Int,
+    /// Every ballot that hasn't exhausted, referred to by index from parcels.
+    pub ballots: Vec<BallotState>,
+    /// Every candidate, in ascending order of ID.
+    pub candidates: Vec<CandidateState>,
+    /// Transfers still to be made, in the order they'll be made.
+    pub pending: Vec<TransferState>,
+    /// Senators elected so far, in the order they were elected.
+    pub senators: Vec<SenatorState>,
+    pub ties: Vec<TieEvent>,
+    /// Candidates excluded so far, in the order they were excluded.
+    pub excluded: Vec<CandidateStanding>,
+    pub last_exclusion: Option<ExclusionState>,
+    /// Number of counts so far at which a surplus was transferred.
+    pub surplus_transfers: usize,
+    pub stats: Stats,
+    pub first_preferences: FirstPreferenceCount,
+    pub transcript: Option<Transcript>,
+}
+
+impl CountState {
+    /// Check that the count is being resumed with the options it was started with.
+    pub fn check_config(&self, config: &ElectionConfig) -> Result<(), Error> {
+        let mismatch = if self.num_positions != config.num_positions {
+            "number of vacancies"
+        } else if self.arithmetic != config.arithmetic {
+            "arithmetic"
+        } else if self.surplus_transfer != config.surplus_transfer {
+            "surplus transfer method"
+        } else if self.tie_break != config.tie_break.name() {
+            "tie break policy"
+        } else if self.tie_break_seed != config.tie_break_seed {
+            "tie break seed"
+        } else if self.transcript.is_some() != config.transcript {
+            "transcript option"
+        } else {
+            return Ok(());
+        };
+        Err(Error::Config(format!(
+            "checkpoint was taken with a different {}",
+            mismatch
+        )))
+    }
+
+    /// Write the checkpoint as JSON, compressed with gzip if the file name ends in `.gz`.
+    ///
+    /// The checkpoint is written to a temporary file that then replaces `filename`, so a crash
+    /// while writing leaves any previous checkpoint intact.
+    pub fn write(&self, filename: &str) -> Result<(), Error> {
+        let tmp_filename = format!("{}.tmp", filename);
+        let file = File::create(&tmp_filename)?;
+        if filename.ends_with(".gz") {
+            // Buffer in front of the encoder, which is slow to take many small writes.
+            let mut writer = BufWriter::new(GzEncoder::new(&file, Compression::fast()));
+            serde_json::to_writer(&mut writer, self).map_err(checkpoint_error)?;
+            writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        } else {
+            let mut writer = BufWriter::new(&file);
+            serde_json::to_writer(&mut writer, self).map_err(checkpoint_error)?;
+            writer.flush()?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_filename, filename)?;
+        Ok(())
+    }
+
+    /// Read a checkpoint written by `write`.
+    pub fn read(filename: &str) -> Result<CountState, Error> {
+        serde_json::from_reader(open_input(filename)?).map_err(checkpoint_error)
+    }
+}
+
+fn checkpoint_error(e: serde_json::Error) -> Error {
+    Error::Checkpoint(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use candidate::{ids, test_candidates};
+    use std::env;
+    use voting::decide_election_with_checkpoints;
+
+    #[test]
+    fn write_and_read() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1, 2])),
+            Ballot::multi(2, ids(&[2])),
+            Ballot::multi(2, ids(&[3, 0])),
+        ];
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let mut states = vec![];
+        decide_election_with_checkpoints(
+            &test_candidates(4),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+            1,
+            |state| {
+                states.push(state.clone());
+                Ok(())
+            },
+            |_| (),
+        )
+        .unwrap();
+        let state = &states[0];
+        assert_eq!(state.count, 2);
+        // Candidate 0 is elected on first preferences, and their surplus is still to be sent on.
+        assert_eq!(state.senators[0].id, CandidateId(0));
+        assert_eq!(state.pending[0].kind, TransferKind::Surplus);
+        assert!(state.check_config(&config).is_ok());
+
+        for name in &[
+            "aus_senate_test_checkpoint.json",
+            "aus_senate_test_checkpoint.json.gz",
+        ] {
+            let path = env::temp_dir().join(name);
+            let path = path.to_str().unwrap();
+            state.write(path).unwrap();
+            let read = CountState::read(path).unwrap();
+            assert_eq!(
+                serde_json::to_value(&read).unwrap(),
+                serde_json::to_value(state).unwrap()
+            );
+        }
+    }
+}
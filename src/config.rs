@@ -0,0 +1,465 @@
+use ballot_parse::Constraints;
+use parse::prefs2016::RowFilter;
+use quota::{self, Droop, Quota};
+use tie_break::{self, Countback, TieBreak};
+use transfer::SurplusTransfer;
+use value::Arithmetic;
+
+/// What to do with rows of input that can't be read as a ballot.
+///
+/// Informal ballots are always skipped and tallied in the result's statistics. This only concerns
+/// rows that are malformed, like a line of a CSV file with the wrong number of fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MalformedBallots {
+    /// Stop the count at the first malformed row.
+    #[default]
+    Abort,
+    /// Skip malformed rows, tallying them by the kind of problem.
+    ///
+    /// IO errors still stop the count, as it isn't possible to keep reading after them.
+    Skip,
+}
+
+/// Minimum numbers of preferences for a formal vote, above and below the line.
+///
+/// Voters are instructed to number at least `atl_min` boxes above the line or `btl_min` below, but
+/// savings provisions can admit ballots with fewer preferences. Without a savings provision for a
+/// section, its instruction is enforced strictly. Preferences are always counted up to the first
+/// gap or repeated number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormalityRules {
+    /// Number of boxes voters are instructed to number above the line.
+    pub atl_min: usize,
+    /// Number of boxes voters are instructed to number below the line.
+    pub btl_min: usize,
+    /// Fewest preferences above the line saved by a savings provision, if there is one.
+    pub atl_savings: Option<usize>,
+    /// Fewest preferences below the line saved by a savings provision, if there is one.
+    pub btl_savings: Option<usize>,
+}
+
+impl FormalityRules {
+    /// The rules introduced in 2016: at least 6 boxes above the line or 12 below, with a single
+    /// preference above the line or 6 below saved by section 269.
+    pub fn senate_2016() -> Self {
+        FormalityRules {
+            atl_min: 6,
+            btl_min: 12,
+            atl_savings: Some(1),
+            btl_savings: Some(6),
+        }
+    }
+
+    /// Rules that strictly require the given numbers of preferences, without savings provisions.
+    pub fn strict(atl_min: usize, btl_min: usize) -> Self {
+        FormalityRules {
+            atl_min,
+            btl_min,
+            atl_savings: None,
+            btl_savings: None,
+        }
+    }
+
+    /// The same rules without the savings provision below the line, so that a vote below the line
+    /// is only formal if it numbers at least as many boxes as instructed.
+    pub fn without_btl_savings(self) -> Self {
+        FormalityRules {
+            btl_savings: None,
+            ..self
+        }
+    }
+
+    /// Fewest preferences above the line for a formal vote.
+    pub fn min_above(&self) -> usize {
+        self.atl_savings
+            .map_or(self.atl_min, |s| s.min(self.atl_min))
+    }
+
+    /// Fewest preferences below the line for a formal vote.
+    pub fn min_below(&self) -> usize {
+        self.btl_savings
+            .map_or(self.btl_min, |s| s.min(self.btl_min))
+    }
+
+    /// Constraints for parsing ballots under these rules.
+    pub fn constraints(&self) -> Constraints {
+        Constraints::with_min_preferences(self.min_above(), self.min_below())
+    }
+}
+
+impl Default for FormalityRules {
+    fn default() -> Self {
+        FormalityRules::senate_2016()
+    }
+}
+
+/// Parameters controlling how an election is counted.
+///
+/// When deserialized, only the number of vacancies is required, and options that are left out
+/// take their defaults from `new`. Formality rules that are given must have both minimums, and
+/// leaving out a savings threshold means there's no savings provision for that section. The row
+/// filter can't be serialized, and is always left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ElectionConfig {
+    /// Number of vacancies to fill.
+    pub num_positions: usize,
+    /// Method for computing the quota.
+    #[serde(with = "quota::by_name", default = "default_quota")]
+    pub quota: Box<dyn Quota>,
+    /// Method for transferring the surpluses of elected candidates.
+    #[serde(default)]
+    pub surplus_transfer: SurplusTransfer,
+    /// Arithmetic used for transfer values.
+    #[serde(default)]
+    pub arithmetic: Arithmetic,
+    /// Policy for resolving ties between candidates with equal votes.
+    #[serde(with = "tie_break::by_name", default = "default_tie_break")]
+    pub tie_break: Box<dyn TieBreak>,
+    /// Seed for drawing lots to break ties that the tie break policy can't otherwise resolve.
+    ///
+    /// If `None`, such ties are left unresolved, and broken using an unseeded RNG so that the
+    /// count can carry on. Either way, they're recorded in the result.
+    #[serde(default)]
+    pub tie_break_seed: Option<usize>,
+    /// Whether to record a transcript of every count.
+    #[serde(default)]
+    pub transcript: bool,
+    /// What to do with malformed rows of input.
+    #[serde(default)]
+    pub malformed_ballots: MalformedBallots,
+    /// Numbers of preferences required for a formal vote.
+    #[serde(default)]
+    pub formality: FormalityRules,
+    /// Whether to recompute the quota after each exclusion, from the votes that haven't exhausted
+    /// or been lost to fractions.
+    ///
+    /// The quota only ever goes down, making it easier to fill the last vacancies. The Senate, the
+    /// Hare-Clark counts in Tasmania and the ACT, and the other Australian upper houses all fix
+    /// the quota at the start of the count, which is the default. A reducing quota is a feature of
+    /// Meek's method, used for STV local elections in New Zealand, and is offered here for
+    /// comparison.
+    #[serde(default)]
+    pub recalculate_quota_on_exhaustion: bool,
+    /// Whether to compute a fingerprint of the formal ballots (see `ballots_fingerprint`).
+    #[serde(default)]
+    pub fingerprint_ballots: bool,
+    /// Rows of the preferences files to count, or `None` to count them all.
+    ///
+    /// This only applies when reading ballots from files, as with `election2016::run`. The count
+    /// itself counts whatever ballots it's given.
+    #[serde(skip)]
+    pub row_filter: Option<RowFilter>,
+    /// Number of counts to stop after, or `None` to carry on until every vacancy is filled.
+    ///
+    /// This is for debugging a count that seems to run forever. A count stopped at the limit
+    /// leaves the rest of its candidates continuing, and is marked with
+    /// `Senate::count_limit_reached`.
+    #[serde(default)]
+    pub max_counts: Option<usize>,
+    /// Whether to record where the ballots with each distinct sequence of preferences ended up
+    /// (see `Stats::ballot_destinations`).
+    ///
+    /// This takes a copy of every distinct sequence, so it's off by default.
+    #[serde(default)]
+    pub ballot_destinations: bool,
+    /// Whether to check that the votes add up at every count once the count is finished (see
+    /// `audit::audit`), failing the count if they don't. This needs a transcript.
+    #[serde(default)]
+    pub audit: bool,
+}
+
+fn default_quota() -> Box<dyn Quota> {
+    Box::new(Droop)
+}
+
+fn default_tie_break() -> Box<dyn TieBreak> {
+    Box::new(Countback)
+}
+
+impl ElectionConfig {
+    /// Configuration for the Senate count, with the given number of vacancies.
+    pub fn new(num_positions: usize) -> Self {
+        ElectionConfig {
+            num_positions,
+            quota: default_quota(),
+            surplus_transfer: SurplusTransfer::default(),
+            arithmetic: Arithmetic::default(),
+            tie_break: default_tie_break(),
+            tie_break_seed: None,
+            transcript: false,
+            malformed_ballots: MalformedBallots::default(),
+            formality: FormalityRules::default(),
+            recalculate_quota_on_exhaustion: false,
+            fingerprint_ballots: false,
+            row_filter: None,
+            max_counts: None,
+            ballot_destinations: false,
+            audit: false,
+        }
+    }
+
+    /// Start building a configuration for the given number of vacancies, from the defaults of
+    /// `new`.
+    pub fn builder(num_positions: usize) -> ElectionConfigBuilder {
+        ElectionConfigBuilder {
+            config: ElectionConfig::new(num_positions),
+        }
+    }
+
+    /// Formality rules for parsing ballots, according to the minimum numbers of preferences.
+    pub fn constraints(&self) -> Constraints {
+        self.formality.constraints()
+    }
+}
+
+/// Builder for an `ElectionConfig`, with any options not set left at their defaults.
+#[derive(Debug, Clone)]
+pub struct ElectionConfigBuilder {
+    config: ElectionConfig,
+}
+
+impl ElectionConfigBuilder {
+    pub fn quota<Q: Quota + 'static>(mut self, quota: Q) -> Self {
+        self.config.quota = Box::new(quota);
+        self
+    }
+
+    pub fn transfer(mut self, surplus_transfer: SurplusTransfer) -> Self {
+        self.config.surplus_transfer = surplus_transfer;
+        self
+    }
+
+    pub fn arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.config.arithmetic = arithmetic;
+        self
+    }
+
+    pub fn tie_break<T: TieBreak + 'static>(mut self, tie_break: T) -> Self {
+        self.config.tie_break = Box::new(tie_break);
+        self
+    }
+
+    pub fn tie_break_seed(mut self, seed: usize) -> Self {
+        self.config.tie_break_seed = Some(seed);
+        self
+    }
+
+    pub fn transcript(mut self, transcript: bool) -> Self {
+        self.config.transcript = transcript;
+        self
+    }
+
+    pub fn malformed_ballots(mut self, malformed_ballots: MalformedBallots) -> Self {
+        self.config.malformed_ballots = malformed_ballots;
+        self
+    }
+
+    /// Set the minimum number of preferences above and below the line for a formal vote.
+    pub fn min_preferences(mut self, above: usize, below: usize) -> Self {
+        self.config.formality = FormalityRules::strict(above, below);
+        self
+    }
+
+    pub fn formality(mut self, formality: FormalityRules) -> Self {
+        self.config.formality = formality;
+        self
+    }
+
+    pub fn recalculate_quota_on_exhaustion(mut self, recalculate: bool) -> Self {
+        self.config.recalculate_quota_on_exhaustion = recalculate;
+        self
+    }
+
+    pub fn fingerprint_ballots(mut self, fingerprint: bool) -> Self {
+        self.config.fingerprint_ballots = fingerprint;
+        self
+    }
+
+    pub fn row_filter(mut self, row_filter: RowFilter) -> Self {
+        self.config.row_filter = Some(row_filter);
+        self
+    }
+
+    pub fn max_counts(mut self, max_counts: usize) -> Self {
+        self.config.max_counts = Some(max_counts);
+        self
+    }
+
+    pub fn ballot_destinations(mut self, ballot_destinations: bool) -> Self {
+        self.config.ballot_destinations = ballot_destinations;
+        self
+    }
+
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.config.audit = audit;
+        self
+    }
+
+    pub fn build(self) -> ElectionConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot_parse::{check_formality, InformalReason, Section};
+    use candidate::{ids, CandidateId};
+    use group::{ticket_letter, Group};
+    use quota::Hare;
+    use util::Int;
+
+    #[test]
+    fn builder() {
+        let config = ElectionConfig::builder(6)
+            .quota(Hare)
+            .transfer(SurplusTransfer::WeightedInclusiveGregory)
+            .tie_break_seed(42)
+            .min_preferences(6, 12)
+            .build();
+        assert_eq!(config.num_positions, 6);
+        assert_eq!(config.quota.compute(60, 6), Int::from(10));
+        assert_eq!(
+            config.surplus_transfer,
+            SurplusTransfer::WeightedInclusiveGregory
+        );
+        assert_eq!(config.tie_break_seed, Some(42));
+        assert_eq!(config.arithmetic, Arithmetic::Exact);
+        assert_eq!(config.formality.min_below(), 12);
+        assert!(!config.transcript);
+    }
+
+    #[test]
+    fn formality_rules() {
+        // 7 groups of 2 candidates.
+        let groups: Vec<_> = (0..7)
+            .map(|g| Group {
+                name: g.to_string(),
+                ticket_letter: Some(ticket_letter(g as usize)),
+                candidate_ids: ids(&[2 * g, 2 * g + 1]),
+            })
+            .collect();
+        let candidates: Vec<_> = (0..14).map(CandidateId).collect();
+        // 3 boxes numbered above the line, and 8 below.
+        let above = "1,2,3,,,,,,,,,,,,,,,,,,";
+        let below = ",,,,,,,1,2,3,4,5,6,7,8,,,,,,";
+        let formal = |rules: FormalityRules, prefs: &str| {
+            check_formality(prefs, &groups, &candidates, &rules.constraints()).is_ok()
+        };
+
+        // 2016: both saved, despite numbering fewer boxes than instructed.
+        let senate = FormalityRules::default();
+        assert_eq!((senate.min_above(), senate.min_below()), (1, 6));
+        assert!(formal(senate, above));
+        assert!(formal(senate, below));
+
+        // Without savings provisions, the instructions are enforced.
+        assert!(!formal(FormalityRules::strict(6, 12), above));
+        assert!(!formal(FormalityRules::strict(6, 12), below));
+        assert!(formal(FormalityRules::strict(3, 12), above));
+        assert!(formal(FormalityRules::strict(6, 8), below));
+
+        // Savings provisions that save fewer ballots.
+        let atl_savings = FormalityRules {
+            atl_savings: Some(4),
+            ..senate
+        };
+        assert!(!formal(atl_savings, above));
+        let btl_savings = FormalityRules {
+            btl_savings: Some(9),
+            ..senate
+        };
+        assert!(!formal(btl_savings, below));
+
+        // A savings threshold above the instruction doesn't raise the bar.
+        let generous = FormalityRules {
+            atl_min: 2,
+            atl_savings: Some(4),
+            ..senate
+        };
+        assert!(formal(generous, above));
+    }
+
+    #[test]
+    fn without_btl_savings() {
+        let groups: Vec<_> = (0..7)
+            .map(|g| Group {
+                name: g.to_string(),
+                ticket_letter: Some(ticket_letter(g as usize)),
+                candidate_ids: ids(&[2 * g, 2 * g + 1]),
+            })
+            .collect();
+        let candidates: Vec<_> = (0..14).map(CandidateId).collect();
+        let parse = |rules: FormalityRules, prefs: &str| {
+            check_formality(prefs, &groups, &candidates, &rules.constraints()).map(|b| b.prefs)
+        };
+        let savings = FormalityRules::senate_2016();
+        let strict = savings.without_btl_savings();
+        assert_eq!((strict.min_above(), strict.min_below()), (1, 12));
+
+        // 8 boxes numbered below the line are saved, but fall short of the full 12 without
+        // savings.
+        let below = ",,,,,,,1,2,3,4,5,6,7,8,,,,,,";
+        assert_eq!(parse(savings, below).unwrap().len(), 8);
+        assert_eq!(
+            parse(strict, below),
+            Err(InformalReason::BelowMinimumPreferences(
+                Section::BelowTheLine,
+                8
+            ))
+        );
+        let full = ",,,,,,,1,2,3,4,5,6,7,8,9,10,11,12,,";
+        assert_eq!(parse(strict, full), parse(savings, full));
+
+        // The savings provision above the line still applies, so a ballot marked in both
+        // sections falls back to its single preference above the line.
+        let both = "1,,,,,,,1,2,3,4,5,6,7,8,,,,,,";
+        assert_eq!(
+            parse(savings, both).unwrap(),
+            ids(&[0, 1, 2, 3, 4, 5, 6, 7])
+        );
+        assert_eq!(parse(strict, both).unwrap(), ids(&[0, 1]));
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let config = ElectionConfig::builder(6)
+            .quota(Hare)
+            .transfer(SurplusTransfer::WeightedInclusiveGregory)
+            .arithmetic(Arithmetic::Scaled)
+            .tie_break_seed(42)
+            .malformed_ballots(MalformedBallots::Skip)
+            .formality(FormalityRules::strict(1, 6))
+            .build();
+        let written = toml::to_string(&config).unwrap();
+        assert!(written.contains("surplus_transfer = \"weighted-inclusive-gregory\"\n"));
+
+        let read: ElectionConfig = toml::from_str(&written).unwrap();
+        assert_eq!(read.num_positions, 6);
+        assert_eq!(read.quota.compute(60, 6), Int::from(10));
+        assert_eq!(read.surplus_transfer, config.surplus_transfer);
+        assert_eq!(read.arithmetic, config.arithmetic);
+        assert_eq!(read.tie_break_seed, Some(42));
+        assert_eq!(read.malformed_ballots, MalformedBallots::Skip);
+        assert_eq!(read.formality, config.formality);
+
+        // Everything but the number of vacancies has a default.
+        let read: ElectionConfig = toml::from_str("num_positions = 2\n").unwrap();
+        assert_eq!(read.quota.name(), "droop");
+        assert_eq!(read.formality, FormalityRules::senate_2016());
+        assert!(toml::from_str::<ElectionConfig>("num_positions = 2\nseats = 2\n").is_err());
+    }
+
+    #[test]
+    fn sample_config() {
+        let sample: toml::Table = toml::from_str(include_str!("../examples/run.toml")).unwrap();
+        assert_eq!(sample["state"].as_str(), Some("TAS"));
+        let config: ElectionConfig = sample["count"].clone().try_into().unwrap();
+        assert_eq!(config.num_positions, 12);
+        assert_eq!(config.tie_break_seed, Some(2016));
+        assert_eq!(config.formality, FormalityRules::senate_2016());
+    }
+}
@@ -1,43 +1,792 @@
-use std::error::Error;
-use std::fs::File;
-
-use ballot_parse::*;
+use ballot_cache::BallotCache;
+use ballot_parse::{IOBallot, RemovedCandidate};
 use candidate::*;
+use checkpoint::CountState;
+use config::ElectionConfig;
+use error::Error;
 use group::*;
-use parse::candidates2016;
+use parse::prefs2016::{
+    concat_rows, first_prefs_by_division, write_formality_csv, BallotParser, ParallelBallots,
+    PrefRow, SectionCounter, DEFAULT_CHUNK_SIZE,
+};
+use parse::{candidates2016, parse_candidates_from_path};
 use senate_result::Senate;
+use stats::Stats;
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use term::{Term, TermAllocation};
+use util::{normalise_state, open_input};
 use voting::*;
 
 /// Parse ballots and compute the election outcome (2016 edition)
+///
+/// A state's preferences may be split across several files, which are counted as one. Candidates
+/// marked as withdrawn in the candidate file are excluded before the first count, with their
+/// ballots passing to the next preference.
 pub fn run(
     candidates_file_name: &str,
-    prefs_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+) -> Result<Senate, Error> {
+    let input = Input::from_paths(candidates_file_name, prefs_file_names)?;
+    run_inner(input, state, None, config)
+}
+
+/// Parse the candidates and ballots from readers and count the election, like `run` does with
+/// files.
+///
+/// The candidates are read as by `candidates2016::parse`, and each reader of preferences is one
+/// of the files the state's preferences are split across. Unlike files, the readers aren't
+/// decompressed, so they must give plain CSV.
+///
+/// ```no_run
+/// # use aus_senate::config::ElectionConfig;
+/// # use std::fs::File;
+/// let candidates = File::open("SenateCandidates.csv").unwrap();
+/// let prefs = vec![File::open("aec-senate-formalpreferences-TAS.csv").unwrap()];
+/// let senate = aus_senate::run_election(candidates, prefs, "TAS", &ElectionConfig::new(12));
+/// ```
+pub fn run_election<C: Read, P: Read>(
+    candidates: C,
+    prefs: Vec<P>,
+    state: &str,
+    config: &ElectionConfig,
+) -> Result<Senate, Error> {
+    let input = Input {
+        all_candidates: candidates2016::parse(candidates)?,
+        prefs: prefs
+            .into_iter()
+            .map(|prefs| Box::new(prefs) as Box<dyn Read>)
+            .collect(),
+    };
+    run_inner(input, state, None, config)
+}
+
+/// Count the election as if a candidate hadn't run.
+///
+/// The candidate is removed from the ballot paper, and every ballot is re-parsed with its
+/// preferences closed up around them (see `RemovedCandidate`). Ballots left with too few
+/// preferences are informal, and don't count towards the quota.
+pub fn run_without_candidate(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    removed: CandidateId,
+    config: &ElectionConfig,
+) -> Result<Senate, Error> {
+    let input = Input::from_paths(candidates_file_name, prefs_file_names)?;
+    run_inner(input, state, Some(removed), config)
+}
+
+/// Outcome of parsing the files for an election, without counting it.
+#[derive(Debug)]
+pub struct Validation {
+    /// Number of groups with a box above the line.
+    pub num_groups: usize,
+    /// Every group in ballot paper order, including any ungrouped candidates.
+    pub groups: Vec<Group>,
+    /// Number of candidates, grouped or not.
+    pub num_candidates: usize,
+    /// Formal, informal and malformed ballots.
+    pub stats: Stats,
+}
+
+/// Parse the candidates and ballots for a state, checking they can be read, without counting.
+pub fn validate(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+) -> Result<Validation, Error> {
+    let (mut validation, counts) = with_ballots(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |candidates, groups, ballots, _| {
+            Ok(Validation {
+                num_groups: above_the_line_groups(groups).len(),
+                groups: groups.to_vec(),
+                num_candidates: candidates.len(),
+                stats: validate_ballots(ballots, config)?,
+            })
+        },
+    )?;
+    counts.record(&mut validation.stats);
+    Ok(validation)
+}
+
+/// Write the formality of every ballot for a state as CSV, keyed by batch and paper number, with
+/// the reason each informal ballot is informal (see `prefs2016::write_formality_csv`).
+///
+/// Only the rows chosen by the configuration's row filter are written.
+pub fn write_formality<W: Write>(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    writer: W,
+) -> Result<(), Error> {
+    with_rows(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |ballot_paper, rows, _| {
+            write_formality_csv(rows, &ballot_paper.parser, config.malformed_ballots, writer)
+        },
+    )
+}
+
+/// Write the first preferences of the formal ballots for a state as CSV, by division and vote
+/// type, laid out like the AEC's first preferences by division by vote type file (see
+/// `DivisionFirstPrefs::write_csv`).
+///
+/// Only the rows chosen by the configuration's row filter are counted.
+pub fn write_first_prefs_by_division<W: Write>(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    writer: W,
+) -> Result<(), Error> {
+    let state = normalise_state(state)?;
+    with_rows(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |ballot_paper, rows, _| {
+            let first_prefs =
+                first_prefs_by_division(rows, &ballot_paper.parser, config.malformed_ballots)?;
+            first_prefs.write_csv(
+                state,
+                ballot_paper.parser.groups,
+                ballot_paper.candidates,
+                writer,
+            )
+        },
+    )
+}
+
+fn run_inner(
+    input: Input,
+    state: &str,
+    removed: Option<CandidateId>,
+    config: &ElectionConfig,
+) -> Result<Senate, Error> {
+    let (mut senate, counts) = with_ballots(
+        input,
+        state,
+        removed,
+        config,
+        |candidates, _, ballots, _| {
+            let withdrawn = get_withdrawn_candidates(candidates);
+            decide_election(candidates, &withdrawn, ballots, config)
+        },
+    )?;
+    counts.record(&mut senate.stats);
+    Ok(senate)
+}
+
+/// Allocate long and short terms to the senators of a double dissolution count by the re-count
+/// method of section 282 (see `term::recount_terms`), reading the ballots again.
+///
+/// The files and configuration should be those `senate` was counted with.
+pub fn recount_terms(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    senate: &Senate,
+) -> Result<Vec<(CandidateId, Term)>, Error> {
+    let (terms, _) = with_ballots(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |candidates, _, ballots, _| {
+            let withdrawn = get_withdrawn_candidates(candidates);
+            ::term::recount_terms(candidates, &withdrawn, ballots, senate, config)
+        },
+    )?;
+    Ok(terms)
+}
+
+/// Allocate long and short terms to the senators of a double dissolution count by both the
+/// order-elected and re-count methods (see `term::allocate_terms`), reading the ballots again.
+///
+/// The files and configuration should be those `senate` was counted with.
+pub fn allocate_terms(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    senate: &Senate,
+) -> Result<TermAllocation, Error> {
+    let (allocation, _) = with_ballots(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |candidates, _, ballots, _| {
+            let withdrawn = get_withdrawn_candidates(candidates);
+            ::term::allocate_terms(candidates, &withdrawn, ballots, senate, config)
+        },
+    )?;
+    Ok(allocation)
+}
+
+/// Count the election like `run`, writing a checkpoint to `checkpoint_file_name` at the start of
+/// every `every`th count.
+///
+/// The checkpoint file is replaced each time, so it always holds the latest checkpoint.
+pub fn run_with_checkpoints(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+    checkpoint_file_name: &str,
+    every: usize,
+) -> Result<Senate, Error> {
+    let (mut senate, counts) = with_ballots(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |candidates, _, ballots, counts| {
+            let withdrawn = get_withdrawn_candidates(candidates);
+            decide_election_with_checkpoints(
+                candidates,
+                &withdrawn,
+                ballots,
+                config,
+                every,
+                |checkpoint| {
+                    // Every ballot has been read by the time the first checkpoint is taken.
+                    counts.record(&mut checkpoint.stats);
+                    checkpoint.write(checkpoint_file_name)
+                },
+                |_| (),
+            )
+        },
+    )?;
+    counts.record(&mut senate.stats);
+    Ok(senate)
+}
+
+/// Read the formal ballots for a state, to count them without reading the files again with
+/// `run_from_cache`.
+pub fn cache_ballots(
+    candidates_file_name: &str,
+    prefs_file_names: &[&str],
+    state: &str,
+    config: &ElectionConfig,
+) -> Result<BallotCache, Error> {
+    let state = normalise_state(state)?;
+    let (mut cache, counts) = with_ballots(
+        Input::from_paths(candidates_file_name, prefs_file_names)?,
+        state,
+        None,
+        config,
+        |_, _, ballots, _| {
+            let (ballots, stats) = collect_ballots(ballots, config)?;
+            Ok(BallotCache::new(state, ballots, stats, config))
+        },
+    )?;
+    counts.record(&mut cache.stats);
+    Ok(cache)
+}
+
+/// Count the election like `run`, from ballots read earlier by `cache_ballots`.
+///
+/// Only the candidates are read, so candidates marked as withdrawn are taken from the candidate
+/// file as it is now.
+pub fn run_from_cache(
+    candidates_file_name: &str,
     state: &str,
-    num_candidates: usize,
-) -> Result<Senate, Box<Error>> {
-    let candidates_file = File::open(candidates_file_name)?;
-    let all_candidates = candidates2016::parse(candidates_file)?;
+    config: &ElectionConfig,
+    cache: &BallotCache,
+) -> Result<Senate, Error> {
+    let state = normalise_state(state)?;
+    let all_candidates = parse_candidates_from_path(candidates_file_name)?;
+    let candidates = state_candidates(&all_candidates, state)?;
+    cache.check(state, config, &candidates)?;
+    let withdrawn = get_withdrawn_candidates(&candidates);
+    let mut senate = decide_election(&candidates, &withdrawn, cache.ballot_stream(), config)?;
+    senate.stats.record_reading(&cache.stats);
+    Ok(senate)
+}
+
+/// Resume a count from a checkpoint in `resume_file_name`, written by `run_with_checkpoints`,
+/// carrying on writing checkpoints to `checkpoint_file_name`.
+///
+/// Only the candidates are read, as the ballots are all in the checkpoint.
+pub fn resume(
+    candidates_file_name: &str,
+    state: &str,
+    config: &ElectionConfig,
+    resume_file_name: &str,
+    checkpoint_file_name: &str,
+    every: usize,
+) -> Result<Senate, Error> {
+    let state = normalise_state(state)?;
+    let all_candidates = parse_candidates_from_path(candidates_file_name)?;
+    let candidates = state_candidates(&all_candidates, state)?;
+    let checkpoint = CountState::read(resume_file_name)?;
+    resume_election(
+        &candidates,
+        checkpoint,
+        config,
+        every,
+        |checkpoint| checkpoint.write(checkpoint_file_name),
+        |_| (),
+    )
+}
+
+/// The candidates standing in a state, which must have at least one.
+fn state_candidates(all_candidates: &[Candidate], state: &str) -> Result<CandidateMap, Error> {
+    let candidates = get_state_candidates(all_candidates, state);
+    if candidates.is_empty() {
+        return Err(Error::NoCandidates {
+            state: Some(state.to_string()),
+        });
+    }
+    Ok(candidates)
+}
+
+/// The candidates for an election, and the preferences files to read its ballots from.
+struct Input<'r> {
+    all_candidates: Vec<Candidate>,
+    prefs: Vec<Box<dyn Read + 'r>>,
+}
+
+impl Input<'static> {
+    /// Parse a candidate file, and open the preferences files.
+    fn from_paths(
+        candidates_file_name: &str,
+        prefs_file_names: &[&str],
+    ) -> Result<Input<'static>, Error> {
+        let prefs = prefs_file_names
+            .iter()
+            .map(|name| Ok(Box::new(open_input(name)?) as Box<dyn Read>))
+            .collect::<Result<_, Error>>()?;
+        Ok(Input {
+            all_candidates: parse_candidates_from_path(candidates_file_name)?,
+            prefs,
+        })
+    }
+}
+
+/// Running counts of rows of interest, kept while the ballots are read.
+struct RowCounts {
+    donkey_votes: Rc<Cell<u64>>,
+    /// Rows left out by the row filter, if there is one.
+    filtered_rows: Option<Rc<Cell<u64>>>,
+    sections: SectionCounter,
+}
+
+impl RowCounts {
+    /// Record the counts so far in `stats`.
+    fn record(&self, stats: &mut Stats) {
+        stats.record_donkey_votes(self.donkey_votes.get());
+        if let Some(ref filtered_rows) = self.filtered_rows {
+            stats.record_filtered_rows(filtered_rows.get());
+        }
+        stats.record_group_first_preferences(self.sections.by_group());
+        stats.record_section_formality(self.sections.formality());
+        stats.record_preference_lengths(self.sections.preference_lengths());
+    }
+}
+
+/// Parse the candidates for a state, and pass them to `f` along with the state's groups, an
+/// iterator over its ballots and running counts of the rows read.
+///
+/// Returns the result of `f`, and the counts of the rows it read.
+fn with_ballots<T, F>(
+    input: Input,
+    state: &str,
+    removed: Option<CandidateId>,
+    config: &ElectionConfig,
+    f: F,
+) -> Result<(T, RowCounts), Error>
+where
+    F: FnOnce(
+        &CandidateMap,
+        &[Group],
+        &mut dyn Iterator<Item = IOBallot>,
+        &RowCounts,
+    ) -> Result<T, Error>,
+{
+    with_rows(
+        input,
+        state,
+        removed,
+        config,
+        |ballot_paper, rows, filtered_rows| {
+            let mut ballots_iter =
+                ParallelBallots::from_rows(rows, ballot_paper.parser, DEFAULT_CHUNK_SIZE);
+            let counts = RowCounts {
+                donkey_votes: ballots_iter.donkey_vote_counter(),
+                filtered_rows,
+                sections: ballots_iter.section_counter(),
+            };
+            let result = f(
+                ballot_paper.candidates,
+                ballot_paper.parser.groups,
+                &mut ballots_iter,
+                &counts,
+            )?;
+            Ok((result, counts))
+        },
+    )
+}
+
+/// The candidates and layout of a state's ballot paper, and the rules for reading it.
+struct BallotPaper<'a> {
+    candidates: &'a CandidateMap,
+    parser: BallotParser<'a>,
+}
+
+/// Parse the candidates for a state, and pass them to `f` along with the rows of its preferences
+/// files that `config` chooses to count, and a running count of the rows it leaves out.
+fn with_rows<T, F>(
+    input: Input,
+    state: &str,
+    removed: Option<CandidateId>,
+    config: &ElectionConfig,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(
+        &BallotPaper,
+        &mut dyn Iterator<Item = csv::Result<PrefRow>>,
+        Option<Rc<Cell<u64>>>,
+    ) -> Result<T, Error>,
+{
+    let state = normalise_state(state)?;
+    let all_candidates = input.all_candidates;
 
     for c in &all_candidates {
         debug!("{}: {} {} ({})", c.id, c.other_names, c.surname, c.party);
     }
 
     // Extract candidate and group information from the complete list of candidates.
-    let candidates = get_state_candidates(&all_candidates, state);
+    let mut candidates = state_candidates(&all_candidates, state)?;
     let candidate_ids = get_candidate_id_list(&all_candidates, state);
     let groups = get_group_list(&all_candidates, state);
 
-    let constraints = Constraints::official();
+    // Without a candidate, ballots are parsed against the ballot paper they're absent from.
+    let removal = match removed {
+        Some(id) => {
+            let removal = RemovedCandidate::new(&groups, &candidate_ids, id).ok_or_else(|| {
+                Error::Config(format!("candidate {} isn't standing in {}", id, state))
+            })?;
+            candidates.remove(&id);
+            Some(removal)
+        }
+        None => None,
+    };
+    let (groups, candidate_ids) = match removal {
+        Some(ref removal) => (&removal.groups[..], &removal.candidates[..]),
+        None => (&groups[..], &candidate_ids[..]),
+    };
+
+    check_ballot_paper(groups, candidate_ids)?;
+    let constraints = config.constraints();
 
     debug!("Num groups: {}", groups.len());
+    for group in groups {
+        debug!(
+            "Group {}: {} candidates",
+            group.ticket_letter.as_ref().unwrap_or(&group.name),
+            group.candidate_ids.len()
+        );
+    }
     trace!("Groups: {:#?}", groups);
 
-    let prefs_file = File::open(prefs_file_name)?;
+    let csv_readers = input
+        .prefs
+        .into_iter()
+        .map(|prefs| {
+            csv::ReaderBuilder::new()
+                .comment(Some(b'-'))
+                .from_reader(prefs)
+        })
+        .collect();
+    let filtered_rows = config.row_filter.as_ref().map(|_| Rc::new(Cell::new(0)));
+    let filter_counter = filtered_rows.clone();
+    let mut rows = concat_rows(csv_readers)?
+        .filter(
+            move |row| match (row, &config.row_filter, &filter_counter) {
+                (Ok(row), Some(filter), Some(counter)) => {
+                    let accepted = filter.accepts(row);
+                    if !accepted {
+                        counter.set(counter.get() + 1);
+                    }
+                    accepted
+                }
+                _ => true,
+            },
+        )
+        .map(|row| match removal {
+            Some(ref removal) => row.map(|mut row| {
+                row.preferences = removal.remove_from(&row.preferences);
+                row
+            }),
+            None => row,
+        });
+    let ballot_paper = BallotPaper {
+        candidates: &candidates,
+        parser: BallotParser::new(groups, candidate_ids, &constraints),
+    };
+    f(&ballot_paper, &mut rows, filtered_rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse::prefs2016::RowFilter;
+    use serde_json;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn withdrawn_below_the_line_candidate() {
+        let candidates = "state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                          party_ballot_nm,withdrawn\n\
+                          TAS,A,1,SMITH,Jo,Party A,\n\
+                          TAS,A,2,JONES,Al,Party A,Y\n\
+                          TAS,B,1,BROWN,Jo,Party B,\n\
+                          TAS,B,2,GREEN,Al,Party B,\n";
+        let mut prefs = String::from(
+            "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+             Preferences\n",
+        );
+        // Boxes are A, B, then SMITH, JONES, BROWN and GREEN below the line.
+        for &(n, ballot) in &[(5, "1,,,,,"), (4, ",,,1,2,"), (3, ",,,,,1"), (2, ",1,,,,")] {
+            for _ in 0..n {
+                prefs.push_str(&format!("Denison,X,1,1,1,\"{}\"\n", ballot));
+            }
+        }
+
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_withdrawn_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_withdrawn_prefs.csv");
+        fs::write(&candidates_file, candidates).unwrap();
+        fs::write(&prefs_file, &prefs).unwrap();
+
+        let config = ElectionConfig::builder(2).min_preferences(1, 1).build();
+        let result = run(
+            candidates_file.to_str().unwrap(),
+            &[prefs_file.to_str().unwrap()],
+            "TAS",
+            &config,
+        )
+        .unwrap();
+
+        // JONES's 4 ballots go to BROWN before the first count, so BROWN tops the poll.
+        let elected: Vec<_> = result
+            .senators
+            .iter()
+            .map(|(c, _)| c.surname.as_str())
+            .collect();
+        assert_eq!(elected, vec!["BROWN", "SMITH"]);
+        assert_eq!(result.stats.num_valid_votes(), 14);
+        let from_readers = run_election(
+            candidates.as_bytes(),
+            vec![prefs.as_bytes()],
+            "TAS",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_readers).unwrap(),
+            serde_json::to_value(&result).unwrap()
+        );
+        // None of the ballots number the boxes of a section straight down from the first.
+        assert_eq!(result.stats.num_donkey_votes(), Some(0));
+        // JONES's ballots still gave their first preference to group A below the line.
+        let sections = result.stats.group_first_preferences();
+        assert_eq!(
+            (sections["A"].above_the_line, sections["A"].below_the_line),
+            (5, 4)
+        );
+        assert_eq!(
+            (sections["B"].above_the_line, sections["B"].below_the_line),
+            (2, 3)
+        );
+        let formality = result.stats.section_formality().unwrap();
+        assert_eq!(
+            (formality.above_the_line_only, formality.below_the_line_only),
+            (7, 7)
+        );
+        assert!(result
+            .final_standings
+            .iter()
+            .any(|s| s.id == CandidateId(1) && s.count == 0));
+    }
+
+    #[test]
+    fn no_candidates_in_state() {
+        let candidates = "state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                          party_ballot_nm\n\
+                          TAS,A,1,SMITH,Jo,Party A\n";
+        let prefs = "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+                     Preferences\n";
+
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_no_candidates_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_no_candidates_prefs.csv");
+        fs::write(&candidates_file, candidates).unwrap();
+        fs::write(&prefs_file, prefs).unwrap();
+
+        let result = run(
+            candidates_file.to_str().unwrap(),
+            &[prefs_file.to_str().unwrap()],
+            "vic",
+            &ElectionConfig::new(12),
+        );
+        match result {
+            Err(Error::NoCandidates { state: Some(state) }) => assert_eq!(state, "VIC"),
+            res => panic!("expected no candidates, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn formality_by_paper() {
+        let candidates = "state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                          party_ballot_nm\n\
+                          TAS,A,1,SMITH,Jo,Party A\n\
+                          TAS,B,1,BROWN,Jo,Party B\n";
+        let prefs = "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+                     Preferences\n\
+                     Denison,Hobart,1,1,1,\",,1,\"\n\
+                     Denison,Hobart,1,1,2,\"1,1,,\"\n\
+                     Denison,Launceston,2,7,1,\",,1,1\"\n";
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .comment(Some(b'-'))
-        .from_reader(prefs_file);
-    let ballots_iter = parse_preferences_file!(csv_reader, &groups, &candidate_ids, &constraints);
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_formality_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_formality_prefs.csv");
+        fs::write(&candidates_file, candidates).unwrap();
+        fs::write(&prefs_file, prefs).unwrap();
 
-    decide_election(&candidates, &[], ballots_iter, num_candidates)
+        let config = ElectionConfig::builder(1)
+            .min_preferences(1, 1)
+            .row_filter(RowFilter::excluding_batches(&["7".to_string()]))
+            .build();
+        let mut out = vec![];
+        write_formality(
+            candidates_file.to_str().unwrap(),
+            &[prefs_file.to_str().unwrap()],
+            "TAS",
+            &config,
+            &mut out,
+        )
+        .unwrap();
+        // The informal paper in batch 7 is left out by the filter.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ElectorateNm,VoteCollectionPointId,BatchNo,PaperNo,Formality,Reason\n\
+             Denison,1,1,1,Formal,\n\
+             Denison,1,1,2,Informal,no first preference\n"
+        );
+    }
+
+    #[test]
+    fn filter_by_collection_point() {
+        let candidates = "state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                          party_ballot_nm\n\
+                          TAS,A,1,SMITH,Jo,Party A\n\
+                          TAS,B,1,BROWN,Jo,Party B\n";
+        let mut prefs = String::from(
+            "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+             Preferences\n",
+        );
+        // SMITH wins at Hobart, but BROWN wins overall.
+        for &(n, point, ballot) in &[(3, "Hobart,1", ",,1,"), (5, "Launceston,2", ",,,1")] {
+            for _ in 0..n {
+                prefs.push_str(&format!("Denison,{},1,1,\"{}\"\n", point, ballot));
+            }
+        }
+
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_filter_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_filter_prefs.csv");
+        fs::write(&candidates_file, candidates).unwrap();
+        fs::write(&prefs_file, prefs).unwrap();
+
+        for &(point, winner, formal) in &[("Hobart", "SMITH", 3), ("2", "BROWN", 5)] {
+            let config = ElectionConfig::builder(1)
+                .min_preferences(1, 1)
+                .row_filter(RowFilter::collection_point(point))
+                .build();
+            let result = run(
+                candidates_file.to_str().unwrap(),
+                &[prefs_file.to_str().unwrap()],
+                "TAS",
+                &config,
+            )
+            .unwrap();
+            assert_eq!(result.senators[0].0.surname, winner);
+            assert_eq!(result.stats.num_valid_votes(), formal);
+            assert_eq!(result.stats.num_filtered_rows(), Some(8 - formal));
+        }
+    }
+
+    #[test]
+    fn exclude_batch() {
+        let candidates = "state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                          party_ballot_nm\n\
+                          TAS,A,1,SMITH,Jo,Party A\n\
+                          TAS,B,1,BROWN,Jo,Party B\n";
+        let mut prefs = String::from(
+            "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+             Preferences\n",
+        );
+        // BROWN only wins because of batch 2.
+        for &(n, batch, ballot) in &[(4, 1, ",,1,"), (3, 1, ",,,1"), (2, 2, ",,,1")] {
+            for _ in 0..n {
+                prefs.push_str(&format!("Denison,Hobart,1,{},1,\"{}\"\n", batch, ballot));
+            }
+        }
+
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_exclude_batch_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_exclude_batch_prefs.csv");
+        fs::write(&candidates_file, candidates).unwrap();
+        fs::write(&prefs_file, prefs).unwrap();
+
+        let count = |filter: Option<RowFilter>| {
+            let mut config = ElectionConfig::builder(1).min_preferences(1, 1).build();
+            config.row_filter = filter;
+            run(
+                candidates_file.to_str().unwrap(),
+                &[prefs_file.to_str().unwrap()],
+                "TAS",
+                &config,
+            )
+            .unwrap()
+        };
+        let all = count(None);
+        assert_eq!(all.senators[0].0.surname, "BROWN");
+        assert_eq!(all.stats.num_filtered_rows(), None);
+
+        let batches = vec!["2".to_string()];
+        let without = count(Some(RowFilter::excluding_batches(&batches)));
+        assert_eq!(without.senators[0].0.surname, "SMITH");
+        assert_eq!(without.stats.num_valid_votes(), 7);
+        assert_eq!(without.stats.num_filtered_rows(), Some(2));
+
+        // Leaving out the only collection point, as well, leaves nothing to count.
+        let points = vec!["Hobart".to_string()];
+        let none = count(Some(
+            RowFilter::excluding_batches(&batches)
+                .and(RowFilter::excluding_collection_points(&points)),
+        ));
+        assert_eq!(none.stats.num_valid_votes(), 0);
+        assert_eq!(none.stats.num_filtered_rows(), Some(9));
+    }
 }
@@ -0,0 +1,143 @@
+//! Errors that can occur while reading the input and counting an election.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use ballot_parse::{BallotParseErr, InformalReason};
+use csv;
+use util::Frac;
+use zip::result::ZipError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failure reading an input file.
+    Io(io::Error),
+    /// Failure reading or decoding a CSV file.
+    Csv(csv::Error),
+    /// Failure reading a ZIP archive.
+    Zip(ZipError),
+    /// An input file doesn't have the expected layout, like a candidate file that's missing a
+    /// column, or preferences files with different headers.
+    Schema(String),
+    /// A row of a preferences file with the wrong number of preferences for the groups and
+    /// candidates.
+    WrongNumberOfPreferences { expected: usize, found: usize },
+    /// An error in the ballot on a row of a preferences file, with the division, batch and paper
+    /// numbers identifying the ballot paper. The row is the number of the row among all of those
+    /// read, counting from 1 and not counting headers, if it's known.
+    Row {
+        row: Option<u64>,
+        division: String,
+        batch: String,
+        paper: String,
+        error: Box<Error>,
+    },
+    /// A ballot that isn't formal, where a formal ballot was required.
+    Ballot(InformalReason),
+    /// The options or candidates for the count are invalid.
+    Config(String),
+    /// A checkpoint of a count couldn't be read, or doesn't fit the count being resumed.
+    Checkpoint(String),
+    /// A ballot cache couldn't be read, or doesn't fit the count.
+    BallotCache(String),
+    /// The votes at a count don't add up to the formal ballots, less those exhausted and lost by
+    /// fraction, as found by `audit::audit`. The discrepancy is the number of votes missing.
+    Audit { count: usize, discrepancy: Frac },
+    /// There are no candidates to count, like when the candidate file has none for the state.
+    /// The state is `None` when counting a list of candidates that didn't come with one.
+    NoCandidates { state: Option<String> },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "IO error: {}", e),
+            Error::Csv(ref e) => write!(f, "CSV error: {}", e),
+            Error::Zip(ref e) => write!(f, "ZIP archive error: {}", e),
+            Error::Schema(ref msg) => write!(f, "unexpected file layout: {}", msg),
+            Error::WrongNumberOfPreferences { expected, found } => write!(
+                f,
+                "expected {} preferences on the ballot, found {}",
+                expected, found
+            ),
+            Error::Row {
+                row,
+                ref division,
+                ref batch,
+                ref paper,
+                ref error,
+            } => {
+                write!(
+                    f,
+                    "ballot paper {} of batch {} in {}",
+                    paper, batch, division
+                )?;
+                if let Some(row) = row {
+                    write!(f, " (row {})", row)?;
+                }
+                write!(f, ": {}", error)
+            }
+            Error::Ballot(reason) => write!(f, "informal ballot: {}", reason),
+            Error::Config(ref msg) => write!(f, "invalid configuration: {}", msg),
+            Error::Checkpoint(ref msg) => write!(f, "invalid checkpoint: {}", msg),
+            Error::BallotCache(ref msg) => write!(f, "invalid ballot cache: {}", msg),
+            Error::Audit {
+                count,
+                ref discrepancy,
+            } => write!(
+                f,
+                "the votes don't add up at count {}: {} votes are unaccounted for",
+                count, discrepancy
+            ),
+            Error::NoCandidates {
+                state: Some(ref state),
+            } => write!(
+                f,
+                "no candidates are standing in {}; is the candidate file for the right election?",
+                state
+            ),
+            Error::NoCandidates { state: None } => write!(f, "there are no candidates to count"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Csv(ref e) => Some(e),
+            Error::Zip(ref e) => Some(e),
+            Error::Row { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(e: ZipError) -> Self {
+        Error::Zip(e)
+    }
+}
+
+/// Treat an informal ballot as an error, for callers that require every ballot to be formal.
+impl From<BallotParseErr> for Error {
+    fn from(e: BallotParseErr) -> Self {
+        match e {
+            BallotParseErr::InvalidBallot(reason) => Error::Ballot(reason),
+            BallotParseErr::InputError(e) => e,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use util::*;
 
 pub type ExhaustedVotes = BTreeMap<usize, (usize, Frac)>;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ExhaustedVoteRow {
     pub round: usize,
     pub ballots_exhausted: usize,
@@ -12,18 +12,133 @@ pub struct ExhaustedVoteRow {
     pub value_exhausted_den: String,
 }
 
-pub fn write_out(exhausted_votes: &ExhaustedVotes, filename: &str) -> csv::Result<()> {
-    let mut wtr = csv::Writer::from_path(filename)?;
+/// Ballots and votes exhausted by the end of a count, cumulatively.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExhaustionPoint {
+    pub count: usize,
+    pub ballots: usize,
+    pub votes: Frac,
+    /// Fraction of the formal vote exhausted, between 0 and 1.
+    pub fraction: f64,
+}
+
+/// Cumulative exhaustion at the end of every count from 1 to `num_counts`.
+///
+/// Votes exhausted in round `r` were exhausted during count `r + 1`, with round 0 covering
+/// the ballots of disqualified candidates, which are transferred before the first count.
+pub fn exhaustion_curve(
+    exhausted_votes: &ExhaustedVotes,
+    formal_votes: u64,
+    num_counts: usize,
+) -> Vec<ExhaustionPoint> {
+    let mut ballots = 0;
+    let mut votes = frac!(0u64);
+    let mut rounds = exhausted_votes.iter().peekable();
 
-    for (&round, &(ballots_exhausted, ref vote_value)) in exhausted_votes {
-        wtr.serialize(ExhaustedVoteRow {
+    (1..=num_counts)
+        .map(|count| {
+            while let Some((_, &(round_ballots, ref round_votes))) =
+                rounds.next_if(|&(&round, _)| round < count)
+            {
+                ballots += round_ballots;
+                votes += round_votes;
+            }
+            ExhaustionPoint {
+                count,
+                ballots,
+                votes: votes.clone(),
+                fraction: vote_fraction(&votes, formal_votes),
+            }
+        })
+        .collect()
+}
+
+/// Fraction of `formal_votes` that `votes` make up, or 0 if there are no formal votes.
+pub fn vote_fraction(votes: &Frac, formal_votes: u64) -> f64 {
+    if formal_votes == 0 {
+        0.0
+    } else {
+        f64::from(votes) / formal_votes as f64
+    }
+}
+
+/// One row per round, with the value of the votes exhausted split into numerator and denominator.
+fn rows(exhausted_votes: &ExhaustedVotes) -> impl Iterator<Item = ExhaustedVoteRow> + '_ {
+    exhausted_votes.iter().map(
+        |(&round, &(ballots_exhausted, ref vote_value))| ExhaustedVoteRow {
             round,
             ballots_exhausted,
             value_exhausted_num: format!("{}", vote_value.get_num()),
             value_exhausted_den: format!("{}", vote_value.get_den()),
-        })?;
+        },
+    )
+}
+
+pub fn write_out(exhausted_votes: &ExhaustedVotes, filename: &str) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    for row in rows(exhausted_votes) {
+        wtr.serialize(row)?;
     }
     wtr.flush()?;
 
     Ok(())
 }
+
+/// Serialize exhausted votes as a list of `ExhaustedVoteRow`s, as they're written out.
+///
+/// For use with `#[serde(with = "exhausted_votes::as_rows")]`.
+pub mod as_rows {
+    use super::*;
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(
+        exhausted_votes: &ExhaustedVotes,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(rows(exhausted_votes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ExhaustedVotes, D::Error> {
+        Vec::<ExhaustedVoteRow>::deserialize(deserializer)?
+            .into_iter()
+            .map(|row| {
+                let num = row.value_exhausted_num.parse::<Int>();
+                let den = row.value_exhausted_den.parse::<Int>();
+                match (num, den) {
+                    (Ok(ref num), Ok(ref den)) if !den.is_zero() => {
+                        Ok((row.round, (row.ballots_exhausted, Frac::ratio(num, den))))
+                    }
+                    _ => Err(de::Error::custom(format!(
+                        "invalid exhausted vote value in round {}",
+                        row.round
+                    ))),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cumulative_curve() {
+        let mut exhausted = ExhaustedVotes::new();
+        exhausted.insert(1, (4, frac!(4u64)));
+        exhausted.insert(3, (6, frac!(3u64)));
+
+        let curve = exhaustion_curve(&exhausted, 20, 5);
+        let ballots: Vec<_> = curve.iter().map(|p| p.ballots).collect();
+        assert_eq!(ballots, vec![0, 4, 4, 10, 10]);
+        assert_eq!(curve[1].fraction, 0.2);
+        assert_eq!(curve[4].votes, frac!(7u64));
+        assert_eq!(curve[4].fraction, 0.35);
+
+        assert_eq!(vote_fraction(&frac!(1u64), 0), 0.0);
+    }
+}
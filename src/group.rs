@@ -1,28 +1,281 @@
 use candidate::*;
+use error::Error;
 
-#[derive(Debug)]
+/// Ticket of the candidates who aren't members of any group.
+pub const UNGROUPED: &str = "UG";
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Group {
+    /// Ticket of the group on the ballot paper, like "A" or "AB", or `UNGROUPED`.
     pub name: String,
+    /// Letter of the group's box above the line, from its position on the ballot paper, or `None`
+    /// for the ungrouped candidates.
+    ///
+    /// This is the same as `name` for the AEC's files, but doesn't rely on the candidate file to
+    /// name the groups.
+    pub ticket_letter: Option<String>,
+    /// Candidates in the group, in the order they appear below the line.
     pub candidate_ids: Vec<CandidateId>,
 }
 
+impl Group {
+    /// Whether this is the pseudo-group of ungrouped candidates, which has no box above the line.
+    pub fn is_ungrouped(&self) -> bool {
+        self.name == UNGROUPED
+    }
+}
+
+/// Letter of the ticket in the given column above the line, counting from 0: A to Z, then AA to
+/// AZ, BA to BZ, and so on.
+pub fn ticket_letter(column: usize) -> String {
+    let mut letters = vec![];
+    let mut n = column + 1;
+    while n > 0 {
+        n -= 1;
+        letters.push(b'A' + (n % 26) as u8);
+        n /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).expect("letters are ASCII")
+}
+
+/// Position of a ticket on the ballot paper: A to Z, then AA, AB, and so on, then the ungrouped
+/// candidates.
+fn ticket_order(ticket: &str) -> (bool, usize, &str) {
+    (ticket == UNGROUPED, ticket.len(), ticket)
+}
+
+/// Get the groups of a state in ballot paper order.
+///
+/// If the state has any ungrouped candidates, they're collected into a final pseudo-group named
+/// `UNGROUPED`. Within each group, candidates are kept in the order of the candidate list.
+///
+/// Groups are only made for the tickets of the state's candidates, so every box above the line
+/// stands for at least one candidate.
 pub fn get_group_list(candidates: &[Candidate], state: &str) -> Vec<Group> {
     let mut groups: Vec<Group> = vec![];
     for c in candidates.iter().filter(|c| c.state == state) {
-        // If there's already a group for this candidate, add them and continue.
-        if let Some(current_group) = groups.last_mut() {
-            if current_group.name == c.group_name {
-                current_group.candidate_ids.push(c.id);
-                continue;
-            }
-        }
-        // Otherwise, push a new group to the list (skipping the ungrouped group).
-        if c.group_name != "UG" {
-            groups.push(Group {
+        match groups.iter_mut().find(|g| g.name == c.group_name) {
+            Some(group) => group.candidate_ids.push(c.id),
+            None => groups.push(Group {
                 name: c.group_name.clone(),
+                ticket_letter: None,
                 candidate_ids: vec![c.id],
-            });
+            }),
+        }
+    }
+    groups.sort_by(|g1, g2| ticket_order(&g1.name).cmp(&ticket_order(&g2.name)));
+
+    let mut columns = 0..;
+    for group in groups.iter_mut().filter(|g| !g.is_ungrouped()) {
+        let letter = ticket_letter(columns.next().unwrap());
+        if letter != group.name {
+            warn!(
+                "Group {} of {} is in the column for group {}",
+                group.name, state, letter
+            );
         }
+        group.ticket_letter = Some(letter);
     }
+    debug_assert!(groups.iter().all(|g| !g.candidate_ids.is_empty()));
     groups
 }
+
+/// The groups with a box above the line, which excludes the ungrouped candidates.
+///
+/// The ungrouped pseudo-group must come last, as it does in the list from `get_group_list`.
+pub fn above_the_line_groups(groups: &[Group]) -> &[Group] {
+    match groups.iter().position(Group::is_ungrouped) {
+        Some(idx) => {
+            debug_assert_eq!(idx, groups.len() - 1, "ungrouped candidates must come last");
+            &groups[..idx]
+        }
+        None => groups,
+    }
+}
+
+/// Check that the groups cover the boxes below the line, in order.
+///
+/// Preferences are matched to boxes by position alone: the first boxes are the groups above the
+/// line and the rest are `candidates`. If the groups were derived from the candidate list
+/// differently, every ballot would be misread, so this is checked before reading any of them.
+///
+/// Every box above the line must also stand for at least one candidate, or the preferences for
+/// it would be lost.
+pub fn check_ballot_paper(groups: &[Group], candidates: &[CandidateId]) -> Result<(), Error> {
+    if let Some(group) = above_the_line_groups(groups)
+        .iter()
+        .find(|g| g.candidate_ids.is_empty())
+    {
+        return Err(Error::Schema(format!(
+            "group {} has a box above the line, but no candidates",
+            group.name
+        )));
+    }
+    if let Some(idx) = groups.iter().position(Group::is_ungrouped) {
+        if idx + 1 != groups.len() {
+            return Err(Error::Schema(format!(
+                "ungrouped candidates must come after the groups, but group {} comes after them",
+                groups[idx + 1].name
+            )));
+        }
+    }
+    let mut boxes = 0;
+    for group in groups {
+        for &id in &group.candidate_ids {
+            match candidates.get(boxes) {
+                Some(&c) if c == id => (),
+                Some(&c) => {
+                    return Err(Error::Schema(format!(
+                        "box {} below the line is for candidate {}, but group {} puts candidate \
+                         {} there",
+                        boxes + 1,
+                        c,
+                        group.name,
+                        id
+                    )))
+                }
+                None => break,
+            }
+            boxes += 1;
+        }
+    }
+    let grouped: usize = groups.iter().map(|g| g.candidate_ids.len()).sum();
+    if grouped != candidates.len() {
+        return Err(Error::Schema(format!(
+            "groups have {} candidates, but there are {} boxes below the line",
+            grouped,
+            candidates.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(id: u16, ticket: &str, state: &str) -> Candidate {
+        Candidate {
+            id: CandidateId(id),
+            surname: format!("C{}", id),
+            other_names: String::new(),
+            group_name: ticket.to_string(),
+            party: String::new(),
+            state: state.to_string(),
+            withdrawn: false,
+        }
+    }
+
+    #[test]
+    fn ballot_paper_order() {
+        let candidates = vec![
+            candidate(0, "B", "TAS"),
+            candidate(1, "A", "TAS"),
+            candidate(2, "UG", "TAS"),
+            candidate(3, "AA", "TAS"),
+            candidate(4, "B", "TAS"),
+            candidate(5, "A", "VIC"),
+            candidate(6, "UG", "TAS"),
+            candidate(7, "C", "TAS"),
+        ];
+        let groups = get_group_list(&candidates, "TAS");
+        let summary: Vec<_> = groups
+            .iter()
+            .map(|g| (g.name.as_str(), g.candidate_ids.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("A", ids(&[1])),
+                ("B", ids(&[0, 4])),
+                ("C", ids(&[7])),
+                ("AA", ids(&[3])),
+                ("UG", ids(&[2, 6])),
+            ]
+        );
+        assert!(groups[4].is_ungrouped());
+
+        let atl: Vec<_> = above_the_line_groups(&groups)
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect();
+        assert_eq!(atl, vec!["A", "B", "C", "AA"]);
+
+        let letters: Vec<_> = groups.iter().map(|g| g.ticket_letter.as_ref()).collect();
+        assert_eq!(
+            letters,
+            vec![
+                Some(&"A".to_string()),
+                Some(&"B".to_string()),
+                Some(&"C".to_string()),
+                Some(&"D".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn ticket_letters() {
+        assert_eq!(ticket_letter(0), "A");
+        assert_eq!(ticket_letter(25), "Z");
+        assert_eq!(ticket_letter(26), "AA");
+        assert_eq!(ticket_letter(27), "AB");
+        assert_eq!(ticket_letter(51), "AZ");
+        assert_eq!(ticket_letter(52), "BA");
+        assert_eq!(ticket_letter(26 + 26 * 26), "AAA");
+    }
+
+    #[test]
+    fn more_than_26_groups() {
+        let candidates: Vec<_> = (0..30)
+            .map(|i| candidate(i, &ticket_letter(i as usize), "NSW"))
+            .collect();
+        let groups = get_group_list(&candidates, "NSW");
+        for group in &groups {
+            assert_eq!(group.ticket_letter.as_ref(), Some(&group.name));
+        }
+        assert_eq!(groups[26].ticket_letter, Some("AA".to_string()));
+        assert_eq!(groups[29].ticket_letter, Some("AD".to_string()));
+    }
+
+    #[test]
+    fn ballot_paper_layout() {
+        let candidates = vec![
+            candidate(0, "A", "TAS"),
+            candidate(1, "A", "TAS"),
+            candidate(2, "B", "TAS"),
+            candidate(3, "UG", "TAS"),
+        ];
+        let groups = get_group_list(&candidates, "TAS");
+        assert!(check_ballot_paper(&groups, &ids(&[0, 1, 2, 3])).is_ok());
+
+        let layout_error =
+            |candidate_ids: &[u16]| match check_ballot_paper(&groups, &ids(candidate_ids)) {
+                Err(Error::Schema(msg)) => msg,
+                res => panic!("expected a layout error, got {:?}", res),
+            };
+        assert_eq!(
+            layout_error(&[0, 2, 1, 3]),
+            "box 2 below the line is for candidate 2, but group A puts candidate 1 there"
+        );
+        assert_eq!(
+            layout_error(&[0, 1, 2]),
+            "groups have 4 candidates, but there are 3 boxes below the line"
+        );
+        assert_eq!(
+            layout_error(&[0, 1, 2, 3, 4]),
+            "groups have 4 candidates, but there are 5 boxes below the line"
+        );
+
+        // A group whose only candidate was filtered out of the ballot paper.
+        let mut groups = groups;
+        groups[1].candidate_ids.clear();
+        match check_ballot_paper(&groups, &ids(&[0, 1, 3])) {
+            Err(Error::Schema(msg)) => {
+                assert_eq!(msg, "group B has a box above the line, but no candidates")
+            }
+            res => panic!("expected an empty group error, got {:?}", res),
+        }
+    }
+}
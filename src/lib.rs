@@ -6,32 +6,66 @@ extern crate alloc_system;
 #[macro_use]
 extern crate log;
 extern crate csv;
-extern crate env_logger;
+extern crate flate2;
+#[cfg(feature = "gmp")]
 extern crate gmp;
+#[cfg(not(feature = "gmp"))]
+extern crate num_bigint;
+#[cfg(not(feature = "gmp"))]
+extern crate num_integer;
+#[cfg(not(feature = "gmp"))]
+extern crate num_rational;
+#[cfg(not(feature = "gmp"))]
+extern crate num_traits;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 #[macro_use]
 extern crate unwrap;
 extern crate itertools;
+#[cfg(test)]
+extern crate proptest;
 extern crate rand;
-#[macro_use]
-extern crate lazy_static;
+extern crate rayon;
+extern crate sha2;
+#[cfg(test)]
+extern crate toml;
+extern crate zip;
 
 #[macro_use]
 pub mod util;
-#[macro_use]
-pub mod parse;
 mod arith;
+pub mod audit;
 pub mod ballot;
+pub mod ballot_cache;
 pub mod ballot_parse;
+pub mod ballot_text;
+#[cfg(not(feature = "gmp"))]
+mod bignum;
+pub mod bootstrap;
 pub mod candidate;
+pub mod checkpoint;
+pub mod config;
 pub mod election2016;
+pub mod error;
 pub mod exhausted_votes;
-pub mod file_logger;
 pub mod group;
+mod meek;
+pub mod parse;
+pub mod progress;
+pub mod quota;
+pub mod report;
 pub mod senate_result;
 pub mod stats;
+pub mod term;
+pub mod testgen;
+pub mod tie_break;
+pub mod transcript;
+pub mod transfer;
+pub mod value;
 mod vote_log;
 mod vote_map;
 pub mod voting;
+
+pub use election2016::run_election;
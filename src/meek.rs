@@ -0,0 +1,440 @@
+//! Meek's method of counting, for comparing the Senate count with jurisdictions that use it,
+//! such as New Zealand's local elections.
+//!
+//! This is not how the Senate is counted, and the result of a Meek count of Senate ballots is for
+//! analysis only. Rather than transferring parcels of ballots at fixed transfer values, each
+//! elected candidate keeps a fraction of the value of every ballot that reaches them, their keep
+//! value, and passes the rest on to the next preference. Every ballot is looked at again each
+//! time the keep values are adjusted, until each elected candidate keeps just a quota, which
+//! falls as ballots exhaust. Excluded candidates keep nothing, so their ballots go on to later
+//! preferences as if they had never stood.
+//!
+//! Votes are counted in floating point whatever the configured arithmetic, and a tie for
+//! exclusion excludes the candidate with the highest ID. The count keeps no transcript, so it
+//! can't be audited or checkpointed. Progress is reported as each candidate is elected, and at the
+//! end of the count, but there are no exclusions or surpluses to report as parcels of ballots.
+
+use std::cmp::Ordering;
+
+use ballot::Ballot;
+use candidate::*;
+use config::ElectionConfig;
+use progress::CountEvent;
+use senate_result::*;
+use transcript::{CountAction, Tally};
+use util::*;
+
+/// Surplus left with the elected candidates, as a fraction of the quota, below which the keep
+/// values are taken to have converged.
+const TOLERANCE: f64 = 1e-10;
+
+/// Number of times to adjust the keep values before giving up on them converging.
+const MAX_ITERATIONS: usize = 10_000;
+
+/// Votes and ballot papers of every candidate from one look at the ballots.
+#[derive(Debug)]
+struct Distribution {
+    votes: BTreeMap<CandidateId, f64>,
+    /// Ballot papers giving each candidate some of their value.
+    papers: BTreeMap<CandidateId, u64>,
+    exhausted: f64,
+}
+
+impl Distribution {
+    fn tally(&self, id: CandidateId) -> Tally {
+        Tally {
+            votes: whole_votes(self.votes[&id]),
+            papers: self.papers[&id],
+        }
+    }
+}
+
+fn whole_votes(votes: f64) -> Int {
+    Int::from(votes.floor() as u64)
+}
+
+/// Share the value of every ballot between its preferences, each keeping their keep value of
+/// what reaches them.
+fn distribute(ballots: &[Ballot], keep: &BTreeMap<CandidateId, f64>) -> Distribution {
+    let mut votes: BTreeMap<_, f64> = keep.keys().map(|&id| (id, 0.0)).collect();
+    let mut papers: BTreeMap<_, u64> = keep.keys().map(|&id| (id, 0)).collect();
+    let mut exhausted = 0.0;
+    for ballot in ballots {
+        let weight = ballot.weight() as f64;
+        let mut remaining = 1.0;
+        for id in &ballot.prefs {
+            let k = keep[id];
+            if k > 0.0 {
+                *votes.get_mut(id).unwrap() += weight * remaining * k;
+                *papers.get_mut(id).unwrap() += ballot.weight();
+                remaining *= 1.0 - k;
+            }
+            if remaining <= 0.0 {
+                break;
+            }
+        }
+        exhausted += weight * remaining;
+    }
+    Distribution {
+        votes,
+        papers,
+        exhausted,
+    }
+}
+
+/// Adjust the keep values of the `elected` candidates until each of them has a quota, returning
+/// the votes and the quota once they do.
+///
+/// The quota is the votes not exhausted divided by one more than the number of vacancies.
+fn converge(
+    ballots: &[Ballot],
+    keep: &mut BTreeMap<CandidateId, f64>,
+    elected: &[CandidateId],
+    num_positions: usize,
+) -> (Distribution, f64) {
+    let total: f64 = ballots.iter().map(|b| b.weight() as f64).sum();
+    let mut iterations = 0;
+    loop {
+        let dist = distribute(ballots, keep);
+        let quota = (total - dist.exhausted) / (num_positions + 1) as f64;
+        let surplus: f64 = elected
+            .iter()
+            .map(|id| (dist.votes[id] - quota).max(0.0))
+            .sum();
+        if surplus <= TOLERANCE * quota {
+            return (dist, quota);
+        }
+        if iterations == MAX_ITERATIONS {
+            warn!(
+                "Keep values didn't converge after {} iterations, leaving a surplus of {} votes",
+                MAX_ITERATIONS, surplus
+            );
+            return (dist, quota);
+        }
+        for id in elected {
+            let votes = dist.votes[id];
+            if votes > 0.0 {
+                let k = keep[id] * quota / votes;
+                keep.insert(*id, k.min(1.0));
+            }
+        }
+        iterations += 1;
+    }
+}
+
+/// Count an election by Meek's method, filling in `result` with the senators and final standings
+/// as the Senate count would.
+///
+/// The ballots have already been read into `result.stats`. The quota of the result is the quota
+/// when the last senator was elected, truncated to whole votes like the votes of the candidates.
+pub fn count_meek(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &[Ballot],
+    config: &ElectionConfig,
+    result: &mut Senate,
+    progress: &mut dyn FnMut(CountEvent),
+) {
+    let num_positions = config.num_positions;
+    let mut keep: BTreeMap<CandidateId, f64> = candidates.keys().map(|&id| (id, 1.0)).collect();
+    let mut hopeful: Vec<CandidateId> = candidates.keys().cloned().collect();
+    let mut elected = vec![];
+    let mut excluded_standings = vec![];
+
+    let first_preferences = distribute(ballots, &keep);
+    for &id in disqualified_candidates {
+        info!("Disqualified {:?}", candidates[&id]);
+        let tally = first_preferences.tally(id);
+        excluded_standings.push(CandidateStanding {
+            id,
+            status: Status::Excluded,
+            votes: tally.votes,
+            papers: tally.papers,
+            count: 0,
+        });
+        keep.insert(id, 0.0);
+        hopeful.retain(|&h| h != id);
+    }
+
+    let mut count = 1;
+    let mut action = CountAction::FirstPreferences;
+    let continuing = loop {
+        let (dist, quota) = converge(ballots, &mut keep, &elected, num_positions);
+        if count == 1 {
+            let tallies = hopeful.iter().map(|&id| (id, dist.tally(id))).collect();
+            result.first_preferences =
+                FirstPreferenceCount::new(tallies, candidates, &result.stats);
+        }
+        debug!("Count #{}: quota of {} votes", count, quota);
+
+        // Highest votes first, and lowest ID first among equal votes.
+        hopeful.sort_by(|a, b| {
+            dist.votes[b]
+                .partial_cmp(&dist.votes[a])
+                .unwrap_or(Ordering::Equal)
+                .then(a.cmp(b))
+        });
+        let vacancies = num_positions - elected.len();
+        let reached: Vec<_> = hopeful
+            .iter()
+            .cloned()
+            .filter(|id| dist.votes[id] >= quota)
+            .take(vacancies)
+            .collect();
+
+        let remaining = hopeful.len() <= vacancies;
+        if !reached.is_empty() || remaining {
+            result.quota = whole_votes(quota);
+            let (newly_elected, action) = if reached.is_empty() {
+                (hopeful.clone(), None)
+            } else {
+                (reached, Some(&action))
+            };
+            for &id in &newly_elected {
+                info!(
+                    "Elected {:?} with {} votes",
+                    candidates[&id], dist.votes[&id]
+                );
+                let tally = dist.tally(id);
+                if action.is_some() {
+                    progress(CountEvent::QuotaReached {
+                        count,
+                        candidate: id,
+                        votes: tally.votes.clone(),
+                    });
+                }
+                result.add_senator(id, tally.votes, tally.papers, count, action, candidates);
+            }
+            if action.is_none() {
+                progress(CountEvent::ElectedRemaining {
+                    count,
+                    candidates: newly_elected.clone(),
+                });
+            }
+            hopeful.retain(|id| !newly_elected.contains(id));
+            elected.extend(newly_elected);
+        } else {
+            let id = *hopeful.last().expect("a candidate to exclude");
+            let votes = dist.votes[&id];
+            if hopeful.len() > 1 && dist.votes[&hopeful[hopeful.len() - 2]] == votes {
+                warn!("Tie for exclusion at count {} broken by ID", count);
+            }
+            info!("Excluded {:?} with {} votes", candidates[&id], votes);
+            let tally = dist.tally(id);
+            excluded_standings.push(CandidateStanding {
+                id,
+                status: Status::Excluded,
+                votes: tally.votes,
+                papers: tally.papers,
+                count,
+            });
+            keep.insert(id, 0.0);
+            hopeful.pop();
+        }
+
+        let standing = |&id: &CandidateId| {
+            let tally = dist.tally(id);
+            CandidateStanding {
+                id,
+                status: Status::Continuing,
+                votes: tally.votes,
+                papers: tally.papers,
+                count,
+            }
+        };
+        if elected.len() == num_positions || hopeful.is_empty() {
+            break hopeful.iter().map(standing).collect::<Vec<_>>();
+        }
+        if config.max_counts.is_some_and(|max| count >= max) {
+            warn!("Stopped at the limit of {} counts", count);
+            result.count_limit_reached = true;
+            break hopeful.iter().map(standing).collect();
+        }
+
+        action = match excluded_standings.last() {
+            Some(last) if last.count == count => CountAction::Exclusion(vec![last.id]),
+            _ => CountAction::Surplus(*elected.last().expect("a senator elected")),
+        };
+        count += 1;
+    };
+
+    let mut standings: Vec<_> = result
+        .senators
+        .iter()
+        .zip(&result.elections)
+        .map(|((c, votes), e)| CandidateStanding {
+            id: c.id,
+            status: Status::Elected,
+            votes: votes.clone(),
+            papers: e.papers,
+            count: e.elected_at_count,
+        })
+        .collect();
+    standings.extend(continuing);
+    standings.extend(excluded_standings.into_iter().rev());
+    result.final_standings = standings;
+
+    progress(CountEvent::FinalResult {
+        elected: result.senators.iter().map(|(c, _)| c.id).collect(),
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use error::Error;
+    use transfer::SurplusTransfer;
+    use voting::{decide_election, decide_election_with_progress};
+
+    #[test]
+    fn keep_values_converge() {
+        let ballots = [
+            Ballot::multi(60, ids(&[0, 1])),
+            Ballot::multi(30, ids(&[2])),
+            Ballot::multi(25, ids(&[3, 2])),
+        ];
+        let mut keep: BTreeMap<_, _> = (0..4).map(|id| (CandidateId(id), 1.0)).collect();
+
+        // Candidate 0 keeps a quota of 115 / 3, passing the rest of their 60 votes on.
+        let (dist, quota) = converge(&ballots, &mut keep, &ids(&[0]), 2);
+        assert!((quota - 115.0 / 3.0).abs() < 1e-6);
+        assert!((dist.votes[&CandidateId(0)] - quota).abs() < 1e-6);
+        assert!((dist.votes[&CandidateId(1)] - (60.0 - quota)).abs() < 1e-6);
+        assert!((keep[&CandidateId(0)] - 23.0 / 36.0).abs() < 1e-9);
+
+        // Without candidate 1, the rest exhausts, so the quota falls to (55 + 60k) / 3 = 60k.
+        keep.insert(CandidateId(1), 0.0);
+        let (dist, quota) = converge(&ballots, &mut keep, &ids(&[0]), 2);
+        assert!((quota - 27.5).abs() < 1e-6);
+        assert!((dist.exhausted - 32.5).abs() < 1e-6);
+        assert!((keep[&CandidateId(0)] - 55.0 / 120.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn meek_count() {
+        let ballots = [
+            Ballot::multi(60, ids(&[0, 1])),
+            Ballot::multi(30, ids(&[2])),
+            Ballot::multi(25, ids(&[3, 2])),
+        ];
+        let config = ElectionConfig::builder(2)
+            .transfer(SurplusTransfer::Meek)
+            .build();
+        let result = decide_election(
+            &test_candidates(4),
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &config,
+        )
+        .unwrap();
+
+        // Candidate 1 gets 60 - 115 / 3 of candidate 0's vote, and is excluded. With their
+        // ballots exhausting, the quota falls to 27.5, which candidate 2 has.
+        let senators: Vec<_> = result.senators.iter().map(|(c, _)| c.id).collect();
+        assert_eq!(senators, ids(&[0, 2]));
+        let counts: Vec<_> = result
+            .elections
+            .iter()
+            .map(|e| e.elected_at_count)
+            .collect();
+        assert_eq!(counts, vec![1, 3]);
+        assert!(result
+            .elections
+            .iter()
+            .all(|e| e.elected_by == ElectedBy::Quota));
+        assert_eq!(result.quota, Int::from(27));
+        assert_eq!(
+            result.first_preferences.candidates[&CandidateId(0)].votes,
+            Int::from(60)
+        );
+
+        let standings: Vec<_> = result
+            .final_standings
+            .iter()
+            .map(|s| (s.id, s.status, s.votes.clone(), s.count))
+            .collect();
+        assert_eq!(
+            standings,
+            vec![
+                (CandidateId(0), Status::Elected, Int::from(60), 1),
+                (CandidateId(2), Status::Elected, Int::from(30), 3),
+                (CandidateId(3), Status::Continuing, Int::from(25), 3),
+                (CandidateId(1), Status::Excluded, Int::from(21), 2),
+            ]
+        );
+
+        let transcript = ElectionConfig {
+            transcript: true,
+            ..config.clone()
+        };
+        assert!(decide_election(
+            &test_candidates(4),
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &transcript
+        )
+        .is_err());
+        let audit = ElectionConfig {
+            audit: true,
+            ..config
+        };
+        match decide_election(
+            &test_candidates(4),
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &audit,
+        ) {
+            Err(Error::Config(msg)) => assert!(msg.contains("audited"), "{}", msg),
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn meek_progress() {
+        let ballots = [
+            Ballot::multi(60, ids(&[0, 1])),
+            Ballot::multi(30, ids(&[2])),
+            Ballot::multi(25, ids(&[3, 2])),
+            Ballot::multi(10, ids(&[4])),
+        ];
+        let config = ElectionConfig::builder(3)
+            .transfer(SurplusTransfer::Meek)
+            .build();
+        let mut events = vec![];
+        let result = decide_election_with_progress(
+            &test_candidates(5),
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &config,
+            |event| events.push(event),
+        )
+        .unwrap();
+
+        // Candidate 0 has a quota on first preferences. Excluding candidate 4, whose ballots
+        // exhaust, lowers the quota, and candidates 1 and 2 reach it together at count 3.
+        let quota_reached = match events[0] {
+            CountEvent::QuotaReached {
+                count, candidate, ..
+            } => (count, candidate),
+            ref other => panic!("unexpected event {:?}", other),
+        };
+        assert_eq!(quota_reached, (1, CandidateId(0)));
+        assert_eq!(events.len(), 4);
+        let senators: Vec<_> = result.senators.iter().map(|(c, _)| c.id).collect();
+        assert_eq!(
+            events.last(),
+            Some(&CountEvent::FinalResult {
+                elected: senators.clone()
+            })
+        );
+        let elected_in_events: Vec<_> = events
+            .iter()
+            .flat_map(|event| match *event {
+                CountEvent::QuotaReached { candidate, .. } => vec![candidate],
+                CountEvent::ElectedRemaining { ref candidates, .. } => candidates.clone(),
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(elected_in_events, senators);
+    }
+}
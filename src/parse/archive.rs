@@ -0,0 +1,117 @@
+//! Reading CSV files directly out of the ZIP archives published by the AEC.
+
+use flate2::bufread::DeflateDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use zip::{CompressionMethod, ZipArchive};
+
+use super::prelude::*;
+
+/// Open the CSV file inside a ZIP archive, returning a streaming reader over its contents.
+///
+/// If the archive contains a single file it's used regardless of its name, otherwise the archive
+/// must contain exactly one file with a `.csv` extension.
+pub fn open_zip_csv(filename: &str) -> Result<Box<dyn BufRead>, Error> {
+    let mut archive = ZipArchive::new(File::open(filename)?)?;
+
+    let names = archive
+        .file_names()
+        .map(|name| name.map(|n| n.into_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let csv_members: Vec<_> = (0..names.len())
+        .filter(|&i| names[i].to_lowercase().ends_with(".csv"))
+        .collect();
+
+    let index = match (names.len(), csv_members.len()) {
+        (1, _) => 0,
+        (_, 1) => csv_members[0],
+        (_, 0) => {
+            return Err(Error::Schema(format!("no CSV file found in {}", filename)));
+        }
+        _ => {
+            let csv_names: Vec<_> = csv_members.iter().map(|&i| names[i].as_str()).collect();
+            return Err(Error::Schema(format!(
+                "multiple CSV files found in {}: {}",
+                filename,
+                csv_names.join(", ")
+            )));
+        }
+    };
+
+    // Locate the member's data, then read it directly from the file so that the reader
+    // doesn't borrow from the archive.
+    let (data_start, compressed_size, compression) = {
+        let member = archive.by_index_raw(index)?;
+        let data_start = member.data_start().ok_or_else(|| {
+            Error::Schema(format!("couldn't locate {} in {}", names[index], filename))
+        })?;
+        (data_start, member.compressed_size(), member.compression())
+    };
+
+    let mut file = archive.into_inner();
+    file.seek(SeekFrom::Start(data_start))?;
+    let data = BufReader::new(file.take(compressed_size));
+
+    match compression {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(BufReader::new(DeflateDecoder::new(data)))),
+        method => Err(Error::Schema(format!(
+            "unsupported compression method for {}: {}",
+            names[index], method
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn write_zip(name: &str, members: &[(&str, CompressionMethod)]) -> String {
+        let path = env::temp_dir().join(name);
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        for &(member, method) in members {
+            let options = SimpleFileOptions::default().compression_method(method);
+            writer.start_file(member, options).unwrap();
+            writer.write_all(member.as_bytes()).unwrap();
+            writer.write_all(b",contents\n").unwrap();
+        }
+        writer.finish().unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn read_member(name: &str, members: &[(&str, CompressionMethod)]) -> Result<String, String> {
+        let path = write_zip(name, members);
+        let mut contents = String::new();
+        open_zip_csv(&path)
+            .map_err(|e| e.to_string())?
+            .read_to_string(&mut contents)
+            .unwrap();
+        Ok(contents)
+    }
+
+    #[test]
+    fn csv_member() {
+        use self::CompressionMethod::*;
+        assert_eq!(
+            read_member("aus_senate_single.zip", &[("TAS.csv", Deflated)]),
+            Ok("TAS.csv,contents\n".to_string())
+        );
+        assert_eq!(
+            read_member(
+                "aus_senate_mixed.zip",
+                &[("README.txt", Stored), ("TAS.CSV", Stored)]
+            ),
+            Ok("TAS.CSV,contents\n".to_string())
+        );
+        assert!(read_member(
+            "aus_senate_multiple.zip",
+            &[("TAS.csv", Deflated), ("ACT.csv", Deflated)]
+        )
+        .unwrap_err()
+        .contains("multiple CSV files"));
+    }
+}
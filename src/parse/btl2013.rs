@@ -11,7 +11,7 @@ struct BTLRow {
     paper: u32,
 }
 
-pub fn parse<R: Read>(input: R) -> Result<BelowTheLine, Box<Error>> {
+pub fn parse<R: Read>(input: R) -> Result<BelowTheLine, Error> {
     let mut btl_votes = HashMap::new();
     let mut invalid_votes = HashSet::new();
     let mut reader = ::csv::Reader::from_reader(input);
@@ -36,11 +36,11 @@ pub fn parse<R: Read>(input: R) -> Result<BelowTheLine, Box<Error>> {
     }
 
     // Remove invalid votes.
-    println!("Invalid BTL votes: {}", invalid_votes.len());
+    info!("Invalid BTL votes: {}", invalid_votes.len());
     for vote_id in invalid_votes {
         btl_votes.remove(&vote_id);
     }
-    println!("Valid BTL votes: {}", btl_votes.len());
+    info!("Valid BTL votes: {}", btl_votes.len());
 
     Ok(btl_votes)
 }
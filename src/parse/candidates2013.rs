@@ -3,6 +3,7 @@ use super::prelude::*;
 // TODO: Use this parser for 2016 candidate files as well.
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct CandidateRow {
     state_ab: String,
     party_ab: String,
@@ -14,7 +15,7 @@ struct CandidateRow {
     historic_elected: String,
 }
 
-pub fn parse<R: Read>(input: R) -> Result<Vec<Candidate>, Box<Error>> {
+pub fn parse<R: Read>(input: R) -> Result<Vec<Candidate>, Error> {
     let mut result = vec![];
     let mut reader = ::csv::Reader::from_reader(input);
 
@@ -27,6 +28,7 @@ pub fn parse<R: Read>(input: R) -> Result<Vec<Candidate>, Box<Error>> {
             group_name: row.party_ab,
             party: row.party_name,
             state: row.state_ab,
+            withdrawn: false,
         });
     }
 
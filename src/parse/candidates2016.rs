@@ -1,52 +1,270 @@
 use super::prelude::*;
 
+/// Row of the AEC's candidate file, listing every nominated candidate.
+///
+/// Columns are matched by name, in any order. Only the columns in `REQUIRED_COLUMNS` have to be
+/// present, as the others have varied between elections.
 #[derive(Deserialize, Debug)]
-struct CandidateRow {
-    txn_nm: String,
-    nom_ty: String,
-    state_ab: String,
-    div_nm: String,
-    ticket: String,
-    ballot_position: u32,
-    surname: String,
-    ballot_given_nm: String,
-    party_ballot_nm: String,
-    occupation: String,
-    address_1: String,
-    address_2: String,
-    postcode: String,
-    suburb: String,
-    address_state_ab: String,
-    contact_work_ph: String,
-    contact_home_ph: String,
-    postal_address_1: String,
-    postal_address_2: String,
-    postal_suburb: String,
-    postal_postcode: String,
-    contact_fax: String,
-    postal_state_ab: String,
-    contact_mobile_no: String,
-    contact_email: String,
+#[allow(dead_code)]
+pub struct CandidateRow {
+    #[serde(default)]
+    pub txn_nm: String,
+    #[serde(default)]
+    pub nom_ty: String,
+    pub state_ab: String,
+    #[serde(default)]
+    pub div_nm: String,
+    pub ticket: String,
+    pub ballot_position: u32,
+    pub surname: String,
+    pub ballot_given_nm: String,
+    pub party_ballot_nm: String,
+    #[serde(default)]
+    pub occupation: String,
+    #[serde(default)]
+    pub address_1: String,
+    #[serde(default)]
+    pub address_2: String,
+    #[serde(default)]
+    pub postcode: String,
+    #[serde(default)]
+    pub suburb: String,
+    #[serde(default)]
+    pub address_state_ab: String,
+    #[serde(default)]
+    pub contact_work_ph: String,
+    #[serde(default)]
+    pub contact_home_ph: String,
+    #[serde(default)]
+    pub postal_address_1: String,
+    #[serde(default)]
+    pub postal_address_2: String,
+    #[serde(default)]
+    pub postal_suburb: String,
+    #[serde(default)]
+    pub postal_postcode: String,
+    #[serde(default)]
+    pub contact_fax: String,
+    #[serde(default)]
+    pub postal_state_ab: String,
+    #[serde(default)]
+    pub contact_mobile_no: String,
+    #[serde(default)]
+    pub contact_email: String,
+    /// Not an AEC column: marks a candidate who withdrew after nominations closed.
+    #[serde(default)]
+    pub withdrawn: String,
 }
 
-pub fn parse<R: Read>(input: R) -> Result<Vec<Candidate>, Box<Error>> {
+/// Columns of the candidate file that are needed to describe a candidate.
+pub const REQUIRED_COLUMNS: &[&str] = &[
+    "state_ab",
+    "ticket",
+    "ballot_position",
+    "surname",
+    "ballot_given_nm",
+    "party_ballot_nm",
+];
+
+/// Value of the `nom_ty` column marking a Senate candidate in the 2016 nominations file, where
+/// House of Representatives candidates are marked `H`.
+pub const SENATE_NOM_TY: &str = "S";
+
+/// Layout of a candidate file, detected from its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateFormat {
+    /// Nominations for both houses, with the house given by the `nom_ty` column (2016).
+    Nominations,
+    /// Senate candidates only, without a `nom_ty` column.
+    SenateOnly,
+}
+
+impl CandidateFormat {
+    pub fn detect(header: &::csv::StringRecord) -> CandidateFormat {
+        if header.iter().any(|h| h == "nom_ty") {
+            CandidateFormat::Nominations
+        } else {
+            CandidateFormat::SenateOnly
+        }
+    }
+
+    /// Whether a row of a file in this format describes a Senate candidate, given the `nom_ty`
+    /// value marking Senate nominations.
+    pub fn is_senate(self, row: &CandidateRow, senate_nom_ty: &str) -> bool {
+        match self {
+            CandidateFormat::Nominations => row.nom_ty.trim() == senate_nom_ty,
+            CandidateFormat::SenateOnly => true,
+        }
+    }
+}
+
+/// Whether a value of the `withdrawn` column marks the candidate as withdrawn.
+fn is_withdrawn(value: &str) -> bool {
+    ["Y", "YES", "TRUE", "1"]
+        .iter()
+        .any(|v| v.eq_ignore_ascii_case(value.trim()))
+}
+
+/// Parse the Senate candidates from the AEC's candidate file, skipping House of Representatives
+/// candidates.
+///
+/// Candidate IDs are assigned in the order of the file, which matches the order of the columns in
+/// the preferences files. A candidate who withdrew can be marked with a `Y` in an optional
+/// `withdrawn` column: they keep their place on the ballot paper, but are excluded from the count.
+///
+/// Senate candidates are those marked with `SENATE_NOM_TY`; see `parse_with_senate_nom_ty` for
+/// files that mark them some other way.
+pub fn parse<R: Read>(input: R) -> Result<Vec<Candidate>, Error> {
+    parse_with_senate_nom_ty(input, SENATE_NOM_TY)
+}
+
+/// Parse the Senate candidates like `parse`, from a file whose `nom_ty` column marks Senate
+/// candidates with `senate_nom_ty` instead of `SENATE_NOM_TY`.
+///
+/// The marker is ignored for a file without a `nom_ty` column, in which every row is a Senate
+/// candidate.
+pub fn parse_with_senate_nom_ty<R: Read>(
+    input: R,
+    senate_nom_ty: &str,
+) -> Result<Vec<Candidate>, Error> {
     let mut result = vec![];
     let mut reader = ::csv::Reader::from_reader(input);
 
+    let header = reader.headers()?;
+    let missing: Vec<&str> = REQUIRED_COLUMNS
+        .iter()
+        .cloned()
+        .filter(|&col| !header.iter().any(|h| h == col))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::Schema(format!(
+            "candidate file is missing columns: {}",
+            missing.join(", ")
+        )));
+    }
+    let format = CandidateFormat::detect(header);
+
     for (id, raw_row) in reader.deserialize::<CandidateRow>().enumerate() {
         let row = raw_row?;
-        if row.nom_ty != "S" {
+        if !format.is_senate(&row, senate_nom_ty) {
             continue;
         }
         result.push(Candidate {
-            id: id as CandidateId,
+            id: CandidateId(id as u16),
             surname: row.surname,
             other_names: row.ballot_given_nm,
             group_name: row.ticket,
             party: row.party_ballot_nm,
             state: row.state_ab,
+            withdrawn: is_withdrawn(&row.withdrawn),
         });
     }
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEADER: &str = "txn_nm,nom_ty,state_ab,div_nm,ticket,ballot_position,surname,\
+                          ballot_given_nm,party_ballot_nm,occupation,address_1,address_2,\
+                          postcode,suburb,address_state_ab,contact_work_ph,contact_home_ph,\
+                          postal_address_1,postal_address_2,postal_suburb,postal_postcode,\
+                          contact_fax,postal_state_ab,contact_mobile_no,contact_email\n";
+
+    fn row(nom_ty: &str, state: &str, ticket: &str, surname: &str, party: &str) -> String {
+        format!(
+            "2016 Federal Election,{},{},,{},1,{},Jo,{},,,,,,,,,,,,,,,,\n",
+            nom_ty, state, ticket, surname, party
+        )
+    }
+
+    #[test]
+    fn senate_candidates() {
+        let data = [
+            HEADER.to_string(),
+            row("S", "TAS", "A", "SMITH", "Party A"),
+            row("H", "TAS", "", "JONES", "Party B"),
+            row("S", "TAS", "UG", "BROWN", "Independent"),
+        ]
+        .concat();
+
+        let candidates = parse(data.as_bytes()).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].id, CandidateId(0));
+        assert_eq!(candidates[0].surname, "SMITH");
+        assert_eq!(candidates[0].other_names, "Jo");
+        assert_eq!(candidates[0].party, "Party A");
+        // The House candidate still takes up an ID.
+        assert_eq!(candidates[1].id, CandidateId(2));
+        assert_eq!(candidates[1].group_name, "UG");
+    }
+
+    #[test]
+    fn other_senate_nom_ty() {
+        let data = [
+            HEADER.to_string(),
+            row("SEN", "TAS", "A", "SMITH", "Party A"),
+            row("HOR", "TAS", "", "JONES", "Party B"),
+            row("S", "TAS", "", "GREEN", "Party C"),
+            row("SEN", "TAS", "UG", "BROWN", "Independent"),
+        ]
+        .concat();
+
+        let candidates = parse_with_senate_nom_ty(data.as_bytes(), "SEN").unwrap();
+        let surnames: Vec<_> = candidates.iter().map(|c| &c.surname[..]).collect();
+        assert_eq!(surnames, vec!["SMITH", "BROWN"]);
+        assert_eq!(candidates[1].id, CandidateId(3));
+        // Only GREEN is marked the 2016 way.
+        assert_eq!(parse(data.as_bytes()).unwrap()[0].surname, "GREEN");
+    }
+
+    #[test]
+    fn missing_columns() {
+        let data = HEADER.replace("surname,", "").replace("ticket,", "");
+        match parse(data.as_bytes()) {
+            Err(Error::Schema(msg)) => assert!(msg.ends_with("missing columns: ticket, surname")),
+            res => panic!("expected a schema error, got {:?}", res.map(|c| c.len())),
+        }
+    }
+
+    #[test]
+    fn columns_by_name() {
+        // Columns in a different order, with optional columns missing and an extra column.
+        let data = "surname,ballot_given_nm,ticket,extra,nom_ty,state_ab,ballot_position,\
+                    party_ballot_nm\n\
+                    SMITH,Jo,A,x,S,TAS,1,Party A\n\
+                    JONES,Al,,x,H,TAS,1,Party B\n";
+        let candidates = parse(data.as_bytes()).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].surname, "SMITH");
+        assert_eq!(candidates[0].group_name, "A");
+        assert_eq!(candidates[0].state, "TAS");
+    }
+
+    #[test]
+    fn senate_only_file() {
+        // No nom_ty column, so every row is a Senate candidate.
+        let data = "txn_nm,state_ab,ticket,ballot_position,surname,ballot_given_nm,\
+                    party_ballot_nm\n\
+                    2019 Federal Election,TAS,A,1,SMITH,Jo,Party A\n\
+                    2019 Federal Election,TAS,UG,1,BROWN,Al,Independent\n";
+        let candidates = parse(data.as_bytes()).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].id, CandidateId(1));
+        assert_eq!(candidates[1].group_name, "UG");
+    }
+
+    #[test]
+    fn withdrawn_candidates() {
+        let data = "state_ab,ticket,ballot_position,surname,ballot_given_nm,party_ballot_nm,\
+                    withdrawn\n\
+                    TAS,A,1,SMITH,Jo,Party A,\n\
+                    TAS,A,2,JONES,Al,Party A,Y\n\
+                    TAS,UG,1,BROWN,Al,Independent,no\n";
+        let candidates = parse(data.as_bytes()).unwrap();
+        let withdrawn: Vec<_> = candidates.iter().map(|c| c.withdrawn).collect();
+        assert_eq!(withdrawn, vec![false, true, false]);
+    }
+}
@@ -0,0 +1,148 @@
+use super::prelude::*;
+
+/// Official votes for each candidate at each count of a state's distribution of preferences, by
+/// the number of the count.
+pub type OfficialDistribution = BTreeMap<usize, BTreeMap<CandidateId, u64>>;
+
+/// Row of the AEC's distribution of preferences file, giving one candidate's votes at one count.
+///
+/// Only the columns needed to match the row to a candidate and a count are read, the rest are
+/// ignored.
+#[derive(Deserialize, Debug)]
+struct DopRow {
+    #[serde(rename = "State")]
+    state: String,
+    #[serde(rename = "Count")]
+    count: usize,
+    /// Position of the candidate within their group, which is empty for the rows of votes that
+    /// aren't a candidate's, like those exhausted.
+    #[serde(rename = "Ballot Position")]
+    ballot_position: Option<u32>,
+    #[serde(rename = "Ticket")]
+    ticket: String,
+    #[serde(rename = "Surname")]
+    surname: String,
+    #[serde(rename = "ProgressiveVoteTotal")]
+    progressive_vote_total: u64,
+}
+
+/// Parse the official distribution of preferences for a state from the AEC's distribution of
+/// preferences file, such as `SenateDopDownload-20499-TAS.csv`.
+///
+/// Candidates are matched to rows by their group and position within it, as they are for the
+/// first preferences. Rows without a ballot position, or at position 0, aren't a candidate's and
+/// are skipped. Any lines before the header are skipped.
+pub fn parse<R: Read>(
+    input: R,
+    all_candidates: &[Candidate],
+    state: &str,
+) -> Result<OfficialDistribution, Error> {
+    let mut positions = HashMap::new();
+    let mut group_sizes: HashMap<&str, u32> = HashMap::new();
+    for c in all_candidates.iter().filter(|c| c.state == state) {
+        let position = group_sizes.entry(&c.group_name[..]).or_insert(0);
+        *position += 1;
+        positions.insert((&c.group_name[..], *position), c.id);
+    }
+
+    let mut reader = ::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input);
+    let mut records = reader.records();
+    let header = loop {
+        match records.next() {
+            Some(record) => {
+                let record = record?;
+                if record.get(0) == Some("State") {
+                    break record;
+                }
+            }
+            None => {
+                return Err(Error::Schema(
+                    "distribution of preferences file has no State header".to_string(),
+                ))
+            }
+        }
+    };
+
+    let mut result = OfficialDistribution::new();
+    for record in records {
+        let row: DopRow = record?.deserialize(Some(&header))?;
+        if row.state != state {
+            continue;
+        }
+        let position = match row.ballot_position {
+            Some(position) if position > 0 => position,
+            _ => continue,
+        };
+        let id = *positions.get(&(&row.ticket[..], position)).ok_or_else(|| {
+            Error::Schema(format!(
+                "distribution of preferences file lists {} at position {} of group {}, but \
+                 the candidate file doesn't",
+                row.surname, position, row.ticket
+            ))
+        })?;
+        result
+            .entry(row.count)
+            .or_default()
+            .insert(id, row.progressive_vote_total);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(id: u16, state: &str, group_name: &str) -> Candidate {
+        Candidate {
+            id: CandidateId(id),
+            surname: format!("SURNAME{}", id),
+            other_names: "Jo".to_string(),
+            group_name: group_name.to_string(),
+            party: String::new(),
+            state: state.to_string(),
+            withdrawn: false,
+        }
+    }
+
+    #[test]
+    fn counts_by_candidate() {
+        let candidates = vec![
+            candidate(0, "NSW", "A"),
+            candidate(1, "TAS", "A"),
+            candidate(2, "TAS", "A"),
+            candidate(3, "TAS", "UG"),
+        ];
+        let data = "Distribution of Preferences\n\
+                    State,No Of Vacancies,Count,Ballot Position,Ticket,Surname,GivenNm,Papers,\
+                    VoteTransferred,ProgressiveVoteTotal,Transfer Value,Status\n\
+                    NSW,2,1,1,A,SURNAME0,Jo,9,9,9,1,\n\
+                    TAS,2,1,1,A,SURNAME1,Jo,10,10,10,1,Elected\n\
+                    TAS,2,1,2,A,SURNAME2,Jo,3,3,3,1,\n\
+                    TAS,2,1,1,UG,SURNAME3,Jo,2,2,2,1,\n\
+                    TAS,2,1,,,Exhausted,,0,0,0,1,\n\
+                    TAS,2,2,1,A,SURNAME1,Jo,-5,-5,5,0.5,\n\
+                    TAS,2,2,2,A,SURNAME2,Jo,5,2,5,0.5,Elected\n\
+                    TAS,2,2,1,UG,SURNAME3,Jo,0,0,2,0.5,\n";
+        let distribution = parse(data.as_bytes(), &candidates, "TAS").unwrap();
+        let count = |votes: &[(u16, u64)]| -> BTreeMap<CandidateId, u64> {
+            votes.iter().map(|&(id, v)| (CandidateId(id), v)).collect()
+        };
+        let expected: OfficialDistribution = vec![
+            (1, count(&[(1, 10), (2, 3), (3, 2)])),
+            (2, count(&[(1, 5), (2, 5), (3, 2)])),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(distribution, expected);
+
+        let data = "State,Count,Ballot Position,Ticket,Surname,ProgressiveVoteTotal\n\
+                    TAS,1,3,A,SURNAME9,4\n";
+        match parse(data.as_bytes(), &candidates, "TAS") {
+            Err(Error::Schema(msg)) => assert!(msg.contains("position 3 of group A")),
+            res => panic!("expected a schema error, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::prelude::*;
+
+/// Official first preference votes for each candidate of a state.
+///
+/// Votes for a group above the line count towards the first candidate of the group, as they do
+/// at the first count.
+pub type FirstPreferences = BTreeMap<CandidateId, u64>;
+
+/// Row of the AEC's first preferences by candidate file.
+///
+/// Each group has a row for its ticket votes (above the line), followed by a row for each of its
+/// candidates with their votes below the line. The columns splitting the total by vote type are
+/// ignored.
+#[derive(Deserialize, Debug)]
+struct FirstPrefsRow {
+    #[serde(rename = "StateAb")]
+    state_ab: String,
+    #[serde(rename = "Ticket")]
+    ticket: String,
+    #[serde(rename = "BallotPosition")]
+    ballot_position: u32,
+    #[serde(rename = "CandidateDetails")]
+    candidate_details: String,
+    #[serde(rename = "TotalVotes")]
+    total_votes: u64,
+}
+
+impl FirstPrefsRow {
+    fn is_ticket_votes(&self) -> bool {
+        self.ballot_position == 0 || self.candidate_details.ends_with("Ticket Votes")
+    }
+}
+
+/// Parse the first preferences for a state's candidates from the AEC's first preferences by
+/// candidate file, such as `SenateFirstPrefsByStateByVoteTypeDownload-20499.csv`.
+///
+/// Candidates are matched to rows by their group and position within it. Any lines before the
+/// header, like the title line of the AEC's downloads, are skipped.
+pub fn parse<R: Read>(
+    input: R,
+    all_candidates: &[Candidate],
+    state: &str,
+) -> Result<FirstPreferences, Error> {
+    let mut positions = HashMap::new();
+    let mut group_sizes: HashMap<&str, u32> = HashMap::new();
+    for c in all_candidates.iter().filter(|c| c.state == state) {
+        let position = group_sizes.entry(&c.group_name[..]).or_insert(0);
+        *position += 1;
+        positions.insert((&c.group_name[..], *position), c.id);
+    }
+
+    let mut reader = ::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input);
+    let mut records = reader.records();
+    let header = loop {
+        match records.next() {
+            Some(record) => {
+                let record = record?;
+                if record.get(0) == Some("StateAb") {
+                    break record;
+                }
+            }
+            None => {
+                return Err(Error::Schema(
+                    "first preferences file has no StateAb header".to_string(),
+                ))
+            }
+        }
+    };
+
+    let mut result = FirstPreferences::new();
+    for record in records {
+        let row: FirstPrefsRow = record?.deserialize(Some(&header))?;
+        if row.state_ab != state {
+            continue;
+        }
+        let position = if row.is_ticket_votes() {
+            1
+        } else {
+            row.ballot_position
+        };
+        let id = *positions.get(&(&row.ticket[..], position)).ok_or_else(|| {
+            Error::Schema(format!(
+                "first preferences file lists {} at position {} of group {}, but the \
+                 candidate file doesn't",
+                row.candidate_details, position, row.ticket
+            ))
+        })?;
+        *result.entry(id).or_insert(0) += row.total_votes;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(id: u16, state: &str, group_name: &str) -> Candidate {
+        Candidate {
+            id: CandidateId(id),
+            surname: format!("SURNAME{}", id),
+            other_names: "Jo".to_string(),
+            group_name: group_name.to_string(),
+            party: String::new(),
+            state: state.to_string(),
+            withdrawn: false,
+        }
+    }
+
+    #[test]
+    fn ticket_votes_go_to_first_candidate() {
+        let candidates = vec![
+            candidate(0, "NSW", "A"),
+            candidate(1, "TAS", "A"),
+            candidate(2, "TAS", "A"),
+            candidate(3, "TAS", "UG"),
+        ];
+        let data = "Senate First Preferences By State By Vote Type\n\
+                    StateAb,Ticket,CandidateID,BallotPosition,CandidateDetails,PartyName,\
+                    OrdinaryVotes,AbsentVotes,ProvisionalVotes,PrePollVotes,PostalVotes,\
+                    TotalVotes\n\
+                    NSW,A,0,0,A Ticket Votes,Party A,9,0,0,0,0,9\n\
+                    TAS,A,0,0,A Ticket Votes,Party A,90,5,1,3,1,100\n\
+                    TAS,A,101,1,\"SURNAME1, Jo\",Party A,5,0,0,0,0,5\n\
+                    TAS,A,102,2,\"SURNAME2, Jo\",Party A,2,0,0,0,1,3\n\
+                    TAS,UG,103,1,\"SURNAME3, Jo\",Independent,7,0,0,0,0,7\n";
+        let first_prefs = parse(data.as_bytes(), &candidates, "TAS").unwrap();
+        let expected: FirstPreferences = vec![
+            (CandidateId(1), 105),
+            (CandidateId(2), 3),
+            (CandidateId(3), 7),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(first_prefs, expected);
+    }
+
+    #[test]
+    fn unknown_candidate() {
+        let candidates = vec![candidate(0, "TAS", "A")];
+        let data = "StateAb,Ticket,CandidateID,BallotPosition,CandidateDetails,PartyName,\
+                    TotalVotes\n\
+                    TAS,A,102,2,\"SURNAME2, Jo\",Party A,3\n";
+        match parse(data.as_bytes(), &candidates, "TAS") {
+            Err(Error::Schema(msg)) => assert!(msg.contains("position 2 of group A")),
+            res => panic!("expected a schema error, got {:?}", res),
+        }
+    }
+}
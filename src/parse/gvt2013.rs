@@ -5,6 +5,7 @@ pub type GVT = HashMap<String, HashMap<String, Vec<CandidateId>>>;
 
 /// Holy moley.
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct GVTRow {
     state: String,
     owner_group: u32,
@@ -22,17 +23,15 @@ struct GVTRow {
 }
 
 // NOTE: This is a tad slow, but it beats mucking around with manual row groupings.
-pub fn parse<R: Read>(input: R) -> Result<GVT, Box<Error>> {
+pub fn parse<R: Read>(input: R) -> Result<GVT, Error> {
     let mut data: HashMap<String, HashMap<String, PrefMap>> = HashMap::new();
 
     let mut reader = ::csv::Reader::from_reader(input);
 
     for result in reader.deserialize::<GVTRow>() {
         let row = result?;
-        let ticket_map = data.entry(row.state).or_insert_with(HashMap::new);
-        let pref_map = ticket_map
-            .entry(row.owner_ticket)
-            .or_insert_with(BTreeMap::new);
+        let ticket_map = data.entry(row.state).or_default();
+        let pref_map = ticket_map.entry(row.owner_ticket).or_default();
         pref_map.insert(row.preference, row.candidate_id);
     }
 
@@ -4,6 +4,7 @@ pub type GVTUsage = HashMap<String, HashMap<String, u32>>;
 
 /// GVT usage parsing.
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct GVTUsageRow {
     state: String,
     ticket: String,
@@ -16,7 +17,7 @@ struct GVTUsageRow {
     total_votes: String,
 }
 
-pub fn parse<R: Read>(input: R) -> Result<GVTUsage, Box<Error>> {
+pub fn parse<R: Read>(input: R) -> Result<GVTUsage, Error> {
     let mut gvt_usage = HashMap::new();
 
     let mut reader = ::csv::Reader::from_reader(input);
@@ -3,10 +3,121 @@
 /// Useful imports for parsing are here.
 mod prelude;
 
+pub mod archive;
+pub mod btl2013;
 pub mod candidates2013;
 pub mod candidates2016;
-#[macro_use]
-pub mod prefs2016;
-pub mod btl2013;
+pub mod dop2016;
+pub mod first_prefs2016;
 pub mod gvt2013;
 pub mod gvt_usage2013;
+pub mod prefs2016;
+
+use self::dop2016::OfficialDistribution;
+use self::first_prefs2016::FirstPreferences;
+use self::prefs2016::{concat_rows, BallotParser, ParallelBallots, DEFAULT_CHUNK_SIZE};
+use self::prelude::*;
+use util::open_input;
+
+/// Parse the Senate candidates from a candidate file, which may be gzipped or zipped.
+///
+/// See `candidates2016::parse` for the layout of the file.
+pub fn parse_candidates_from_path(path: &str) -> Result<Vec<Candidate>, Error> {
+    candidates2016::parse(open_input(path)?)
+}
+
+/// Parse a state's official first preferences from a first preferences by candidate file, which
+/// may be gzipped or zipped.
+///
+/// See `first_prefs2016::parse` for how rows are matched to candidates.
+pub fn first_preferences_from_path(
+    path: &str,
+    all_candidates: &[Candidate],
+    state: &str,
+) -> Result<FirstPreferences, Error> {
+    first_prefs2016::parse(open_input(path)?, all_candidates, state)
+}
+
+/// Parse a state's official distribution of preferences, which may be gzipped or zipped.
+///
+/// See `dop2016::parse` for how rows are matched to candidates.
+pub fn official_distribution_from_path(
+    path: &str,
+    all_candidates: &[Candidate],
+    state: &str,
+) -> Result<OfficialDistribution, Error> {
+    dop2016::parse(open_input(path)?, all_candidates, state)
+}
+
+/// Stream the ballots from a preferences file, which may be gzipped or zipped.
+///
+/// The parser must be for the file's state. Ballots are parsed on several threads, but yielded in
+/// file order.
+pub fn ballots_from_prefs_path<'a>(
+    path: &str,
+    parser: BallotParser<'a>,
+) -> Result<impl Iterator<Item = IOBallot> + 'a, Error> {
+    let reader = ::csv::ReaderBuilder::new()
+        .comment(Some(b'-'))
+        .from_reader(open_input(path)?);
+    let rows = concat_rows(vec![reader])?;
+    Ok(ParallelBallots::from_rows(rows, parser, DEFAULT_CHUNK_SIZE))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use group::get_group_list;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn from_paths() {
+        let dir = env::temp_dir();
+        let candidates_file = dir.join("aus_senate_test_from_paths_candidates.csv");
+        let prefs_file = dir.join("aus_senate_test_from_paths_prefs.csv");
+        fs::write(
+            &candidates_file,
+            "state_ab,ticket,ballot_position,surname,ballot_given_nm,party_ballot_nm\n\
+             TAS,A,1,SMITH,Jo,Party A\n\
+             TAS,A,2,JONES,Al,Party A\n",
+        )
+        .unwrap();
+        fs::write(
+            &prefs_file,
+            "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,\
+             Preferences\n\
+             ------------,---------------------,---------------------,-------,-------,-----------\n\
+             Denison,X,1,1,1,\"1,,\"\n\
+             Denison,X,1,1,2,\",2,1\"\n",
+        )
+        .unwrap();
+
+        let all_candidates = parse_candidates_from_path(candidates_file.to_str().unwrap()).unwrap();
+        let groups = get_group_list(&all_candidates, "TAS");
+        let candidate_ids = get_candidate_id_list(&all_candidates, "TAS");
+        let constraints = Constraints::with_min_preferences(1, 2);
+        let parser = BallotParser::new(&groups, &candidate_ids, &constraints);
+        let ballots: Vec<_> = ballots_from_prefs_path(prefs_file.to_str().unwrap(), parser)
+            .unwrap()
+            .map(|b| b.unwrap().prefs)
+            .collect();
+        assert_eq!(ballots, vec![ids(&[0, 1]), ids(&[1, 0])]);
+    }
+
+    #[test]
+    fn byte_order_mark_and_quoted_fields() {
+        let candidates_file = env::temp_dir().join("aus_senate_test_bom_candidates.csv");
+        fs::write(
+            &candidates_file,
+            "\u{feff}state_ab,ticket,ballot_position,surname,ballot_given_nm,party_ballot_nm\n\
+             TAS,A,1,SMITH,\"Jo, Jr\",\"Party A, Tasmania\"\n",
+        )
+        .unwrap();
+
+        let candidates = parse_candidates_from_path(candidates_file.to_str().unwrap()).unwrap();
+        assert_eq!(candidates[0].state, "TAS");
+        assert_eq!(candidates[0].other_names, "Jo, Jr");
+        assert_eq!(candidates[0].party, "Party A, Tasmania");
+    }
+}
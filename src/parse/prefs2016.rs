@@ -1,46 +1,1188 @@
 use super::prelude::*;
+use ballot::Ballot;
+use config::MalformedBallots;
 use csv;
 use group::Group;
+use rayon::prelude::*;
+use stats::{MalformedRow, PreferenceLengths, SectionCounts, SectionFormality};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
 
+/// Row of the AEC's formal preferences file, describing a single ballot paper.
+///
+/// This is the 2016 layout. Rows in other layouts are converted to it by `PrefsFormat::rows`.
+/// Columns are matched by name, in any order, and must all be present (see `PREF_COLUMNS`).
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct PrefRow {
     #[serde(rename = "ElectorateNm")]
-    electorate_name: String,
+    pub electorate_name: String,
     #[serde(rename = "VoteCollectionPointNm")]
-    vote_collection_point: String,
+    pub vote_collection_point: String,
     #[serde(rename = "VoteCollectionPointId")]
-    vote_collection_point_id: String,
+    pub vote_collection_point_id: String,
     #[serde(rename = "BatchNo")]
-    batch_num: String,
+    pub batch_num: String,
     #[serde(rename = "PaperNo")]
-    paper_num: String,
+    pub paper_num: String,
     #[serde(rename = "Preferences")]
-    preferences: String,
-}
-
-pub fn parse_single_ballot(
-    raw_row: csv::Result<PrefRow>,
-    groups: &[Group],
-    candidates: &[CandidateId],
-    constraints: &Constraints,
-) -> IOBallot {
-    match raw_row {
-        Ok(row) => parse_ballot_str(&row.preferences, groups, candidates, constraints),
-        Err(e) => Err(InputError(From::from(e))),
-    }
-}
-
-// NOTE: This macro is to avoid writing the iterator type.
-// One day it may be possible to use `impl Trait`, but at the moment the compiler
-// doesn't understand that the ballots being returned don't depend on the lifetime
-// of the CSV reader's data at all (due to allocations/cloning).
-// See: https://gist.github.com/michaelsproul/20e18f52fc1be2bd05b2767ab6bd166c
-#[macro_export]
-macro_rules! parse_preferences_file {
-    ($reader:expr, $groups:expr, $candidates:expr, $constraints:expr) => {{
-        use $crate::parse::prefs2016::{parse_single_ballot, PrefRow};
-        $reader
-            .deserialize::<PrefRow>()
-            .map(|raw_row| parse_single_ballot(raw_row, $groups, $candidates, $constraints))
-    }};
+    pub preferences: String,
+}
+
+/// Columns of a preferences file in the 2016 layout.
+pub const PREF_COLUMNS: &[&str] = &[
+    "ElectorateNm",
+    "VoteCollectionPointNm",
+    "VoteCollectionPointId",
+    "BatchNo",
+    "PaperNo",
+    "Preferences",
+];
+
+/// Predicate choosing which rows of a preferences file to count, for counting a subset of the
+/// ballots.
+///
+/// Rows that aren't chosen are skipped before they're parsed, so they don't appear in the
+/// statistics at all.
+#[derive(Clone)]
+pub struct RowFilter(Arc<dyn Fn(&PrefRow) -> bool + Send + Sync>);
+
+impl RowFilter {
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(&PrefRow) -> bool + Send + Sync + 'static,
+    {
+        RowFilter(Arc::new(predicate))
+    }
+
+    /// Choose the ballots from a single vote collection point, matched by name or ID.
+    pub fn collection_point(point: &str) -> Self {
+        let point = point.to_string();
+        RowFilter::new(move |row| {
+            row.vote_collection_point == point || row.vote_collection_point_id == point
+        })
+    }
+
+    /// Leave out the ballots from any of the given vote collection points, matched by name or ID.
+    pub fn excluding_collection_points(points: &[String]) -> Self {
+        let points = points.to_vec();
+        RowFilter::new(move |row| {
+            !points
+                .iter()
+                .any(|p| *p == row.vote_collection_point || *p == row.vote_collection_point_id)
+        })
+    }
+
+    /// Leave out the ballots in any of the given batches, matched by batch number.
+    pub fn excluding_batches(batches: &[String]) -> Self {
+        let batches = batches.to_vec();
+        RowFilter::new(move |row| !batches.contains(&row.batch_num))
+    }
+
+    /// Choose the rows chosen by both this filter and `other`.
+    pub fn and(self, other: RowFilter) -> Self {
+        RowFilter::new(move |row| self.accepts(row) && other.accepts(row))
+    }
+
+    pub fn accepts(&self, row: &PrefRow) -> bool {
+        (self.0)(row)
+    }
+}
+
+impl fmt::Debug for RowFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RowFilter")
+    }
+}
+
+/// Layout of a formal preferences file, detected from its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefsFormat {
+    /// 2016 layout, with every preference in a single comma-separated `Preferences` column.
+    Aec2016,
+    /// 2019 and 2022 layout, with one column per box on the ballot paper.
+    ///
+    /// The first preference column is the first whose name contains a colon, such as
+    /// `A:Party Name` for a group box or `A:SMITH Jo` for a candidate box.
+    Aec2019 { first_preference: usize },
+}
+
+impl PrefsFormat {
+    /// Work out the layout of a preferences file from its header.
+    ///
+    /// A file in the 2016 layout must have every column in `PREF_COLUMNS`, in any order.
+    pub fn detect(header: &csv::StringRecord) -> Result<PrefsFormat, Error> {
+        if header.iter().any(|h| h == "Preferences") {
+            let missing: Vec<&str> = PREF_COLUMNS
+                .iter()
+                .cloned()
+                .filter(|&col| !header.iter().any(|h| h == col))
+                .collect();
+            if !missing.is_empty() {
+                return Err(Error::Schema(format!(
+                    "preferences file is missing columns: {}",
+                    missing.join(", ")
+                )));
+            }
+            Ok(PrefsFormat::Aec2016)
+        } else if let Some(first_preference) = header.iter().position(|h| h.contains(':')) {
+            Ok(PrefsFormat::Aec2019 { first_preference })
+        } else {
+            Err(Error::Schema(
+                "unrecognised preferences file: no Preferences column or ballot box columns"
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Decode the rows of a preferences file in this format.
+    ///
+    /// Rows in the 2019 layout are converted to the 2016 representation, so that both can be
+    /// parsed by `BallotParser`.
+    pub fn rows<'r, R: Read + 'r>(
+        self,
+        mut reader: csv::Reader<R>,
+    ) -> Box<dyn Iterator<Item = csv::Result<PrefRow>> + 'r> {
+        let first_preference = match self {
+            PrefsFormat::Aec2016 => return Box::new(reader.into_deserialize()),
+            PrefsFormat::Aec2019 { first_preference } => first_preference,
+        };
+        let columns = match reader.headers() {
+            Ok(header) => {
+                let col = |name: &str| header.iter().position(|h| h == name);
+                [
+                    col("Division"),
+                    col("Vote Collection Point Name"),
+                    col("Vote Collection Point ID"),
+                    col("Batch No"),
+                    col("Paper No"),
+                ]
+            }
+            Err(e) => return Box::new(Some(Err(e)).into_iter()),
+        };
+        Box::new(reader.into_records().map(move |record| {
+            let record = record?;
+            let field = |i: usize| {
+                columns[i]
+                    .and_then(|c| record.get(c))
+                    .unwrap_or("")
+                    .to_string()
+            };
+            let prefs: Vec<&str> = record.iter().skip(first_preference).collect();
+            Ok(PrefRow {
+                electorate_name: field(0),
+                vote_collection_point: field(1),
+                vote_collection_point_id: field(2),
+                batch_num: field(3),
+                paper_num: field(4),
+                preferences: prefs.join(","),
+            })
+        }))
+    }
+}
+
+/// The layout of a state's ballot paper and the rules for reading it, for parsing the rows of its
+/// preferences files.
+///
+/// The groups and candidates are those of the state, from `get_group_list` and
+/// `get_candidate_id_list`.
+#[derive(Clone, Copy)]
+pub struct BallotParser<'a> {
+    pub groups: &'a [Group],
+    pub candidates: &'a [CandidateId],
+    pub constraints: &'a Constraints,
+}
+
+impl<'a> BallotParser<'a> {
+    pub fn new(
+        groups: &'a [Group],
+        candidates: &'a [CandidateId],
+        constraints: &'a Constraints,
+    ) -> Self {
+        BallotParser {
+            groups,
+            candidates,
+            constraints,
+        }
+    }
+
+    /// Parse the ballot on a row.
+    pub fn parse_row(&self, row: &PrefRow) -> IOBallot {
+        self.parse_row_with(&mut ParseScratch::default(), row)
+    }
+
+    /// Parse a row like `parse_row`, reusing the buffers in `scratch`.
+    ///
+    /// An error in the row's preferences is wrapped in an `Error::Row` giving the ballot paper,
+    /// without the number of the row, which the row doesn't know.
+    pub fn parse_row_with(&self, scratch: &mut ParseScratch, row: &PrefRow) -> IOBallot {
+        parse_ballot_str_with(
+            scratch,
+            &row.preferences,
+            self.groups,
+            self.candidates,
+            self.constraints,
+        )
+        .map_err(|e| match e {
+            InputError(error) => InputError(Error::Row {
+                row: None,
+                division: row.electorate_name.clone(),
+                batch: row.batch_num.clone(),
+                paper: row.paper_num.clone(),
+                error: Box::new(error),
+            }),
+            informal => informal,
+        })
+    }
+
+    /// Parse a row as it was read, passing on any error reading it.
+    ///
+    /// The row is the `number`th read, which is added to any `Error::Row`.
+    fn parse_read_row(
+        &self,
+        scratch: &mut ParseScratch,
+        number: u64,
+        row: csv::Result<PrefRow>,
+    ) -> IOBallot {
+        match row {
+            Ok(row) => self.parse_row_with(scratch, &row).map_err(|e| match e {
+                InputError(Error::Row {
+                    division,
+                    batch,
+                    paper,
+                    error,
+                    ..
+                }) => InputError(Error::Row {
+                    row: Some(number),
+                    division,
+                    batch,
+                    paper,
+                    error,
+                }),
+                e => e,
+            }),
+            Err(e) => Err(InputError(From::from(e))),
+        }
+    }
+
+    /// Parse rows one at a time, in order, reusing the same buffers for every row.
+    ///
+    /// See `ParallelBallots` for parsing them on several threads.
+    pub fn parse_rows<I>(self, rows: I) -> impl Iterator<Item = IOBallot> + 'a
+    where
+        I: IntoIterator<Item = csv::Result<PrefRow>>,
+        I::IntoIter: 'a,
+    {
+        let mut scratch = ParseScratch::default();
+        rows.into_iter()
+            .zip(1..)
+            .map(move |(row, number)| self.parse_read_row(&mut scratch, number, row))
+    }
+
+    /// Whether the ballot on a row is formal, and if not, why not.
+    ///
+    /// Parsing errors in the row's preferences are returned as errors, as they are by
+    /// `parse_row`.
+    pub fn formality_with(
+        &self,
+        scratch: &mut ParseScratch,
+        row: &PrefRow,
+    ) -> Result<Formality, Error> {
+        match self.parse_row_with(scratch, row) {
+            Ok(_) => Ok(Formality::Formal),
+            Err(InvalidBallot(reason)) => Ok(Formality::Informal(reason)),
+            Err(InputError(e)) => Err(e),
+        }
+    }
+}
+
+/// Row of the CSV written by `write_formality_csv`, identifying a ballot paper by the fields of
+/// its `PrefRow`.
+#[derive(Serialize)]
+struct FormalityRecord<'a> {
+    #[serde(rename = "ElectorateNm")]
+    electorate_name: &'a str,
+    #[serde(rename = "VoteCollectionPointId")]
+    vote_collection_point_id: &'a str,
+    #[serde(rename = "BatchNo")]
+    batch_num: &'a str,
+    #[serde(rename = "PaperNo")]
+    paper_num: &'a str,
+    #[serde(rename = "Formality")]
+    formality: &'a str,
+    #[serde(rename = "Reason")]
+    reason: String,
+}
+
+/// Write the formality of every row as CSV, with the reason each informal ballot is informal,
+/// for comparing against the AEC's decisions ballot by ballot.
+///
+/// Malformed rows are skipped if `malformed` says to, and stop the writing otherwise, like they
+/// do the count.
+pub fn write_formality_csv<I, W>(
+    rows: I,
+    parser: &BallotParser,
+    malformed: MalformedBallots,
+    writer: W,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = csv::Result<PrefRow>>,
+    W: Write,
+{
+    let mut scratch = ParseScratch::default();
+    let mut wtr = csv::Writer::from_writer(writer);
+    for row in rows {
+        let formality = row.map_err(Error::from).and_then(|row| {
+            let formality = parser.formality_with(&mut scratch, &row)?;
+            Ok((row, formality))
+        });
+        let (row, formality) = match formality {
+            Ok(result) => result,
+            Err(e) => match (malformed, MalformedRow::categorise(&e)) {
+                (MalformedBallots::Skip, Some(_)) => {
+                    warn!("Skipping malformed ballot: {}", e);
+                    continue;
+                }
+                _ => return Err(e),
+            },
+        };
+        let (formality, reason) = match formality {
+            Formality::Formal => ("Formal", String::new()),
+            Formality::Informal(reason) => ("Informal", reason.to_string()),
+        };
+        wtr.serialize(FormalityRecord {
+            electorate_name: &row.electorate_name,
+            vote_collection_point_id: &row.vote_collection_point_id,
+            batch_num: &row.batch_num,
+            paper_num: &row.paper_num,
+            formality,
+            reason,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Kind of vote cast on a ballot paper, as split out by the AEC's files of first preferences by
+/// vote type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoteType {
+    Ordinary,
+    Absent,
+    Provisional,
+    PrePoll,
+    Postal,
+}
+
+impl VoteType {
+    /// Every vote type, in the order of the columns of the AEC's files.
+    pub const ALL: [VoteType; 5] = [
+        VoteType::Ordinary,
+        VoteType::Absent,
+        VoteType::Provisional,
+        VoteType::PrePoll,
+        VoteType::Postal,
+    ];
+
+    /// The kind of vote cast at a vote collection point, from its name.
+    ///
+    /// The preferences files name the collection points of declaration votes like `ABSENT 1` and
+    /// `PRE_POLL 2`. Every other collection point is a polling place or pre-poll voting centre,
+    /// whose votes are ordinary votes.
+    pub fn of_collection_point(name: &str) -> VoteType {
+        let kind = name.split_whitespace().next().unwrap_or("");
+        match kind {
+            "ABSENT" => VoteType::Absent,
+            "PROVISIONAL" => VoteType::Provisional,
+            "PRE_POLL" => VoteType::PrePoll,
+            "POSTAL" => VoteType::Postal,
+            _ => VoteType::Ordinary,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What a formal ballot gave its first preference to, as broken down by the AEC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FirstPreference {
+    /// A group's box above the line, by its index in the groups.
+    Ticket(usize),
+    /// A candidate's box below the line.
+    Candidate(CandidateId),
+}
+
+/// Formal first preferences in each division, split by vote type, collected by
+/// `first_prefs_by_division`.
+#[derive(Debug, Clone, Default)]
+pub struct DivisionFirstPrefs {
+    /// Votes by division name and first preference, in the order of `VoteType::ALL`.
+    divisions: BTreeMap<String, HashMap<FirstPreference, [u64; 5]>>,
+}
+
+impl DivisionFirstPrefs {
+    fn record(&mut self, division: &str, first: FirstPreference, vote_type: VoteType) {
+        if !self.divisions.contains_key(division) {
+            self.divisions.insert(division.to_string(), HashMap::new());
+        }
+        let votes = self.divisions.get_mut(division).expect("division exists");
+        votes.entry(first).or_insert([0; 5])[vote_type.index()] += 1;
+    }
+
+    /// Names of the divisions with formal ballots, in alphabetical order.
+    pub fn divisions(&self) -> impl Iterator<Item = &str> {
+        self.divisions.keys().map(|d| &d[..])
+    }
+
+    /// Formal first preferences in a division, in the order of `VoteType::ALL`.
+    pub fn votes(&self, division: &str, first: FirstPreference) -> [u64; 5] {
+        self.divisions
+            .get(division)
+            .and_then(|votes| votes.get(&first))
+            .cloned()
+            .unwrap_or([0; 5])
+    }
+
+    /// Write the first preferences as CSV, laid out like the AEC's first preferences by division
+    /// by vote type file.
+    ///
+    /// Each division has a row of ticket votes for each group, followed by a row for each of the
+    /// group's candidates with their votes below the line, then rows for any ungrouped
+    /// candidates. The AEC's ID columns are left out, as the preferences files don't have them.
+    pub fn write_csv<W: Write>(
+        &self,
+        state: &str,
+        groups: &[Group],
+        candidates: &CandidateMap,
+        writer: W,
+    ) -> Result<(), Error> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        for division in self.divisions() {
+            for (idx, group) in groups.iter().enumerate() {
+                let party = group
+                    .candidate_ids
+                    .first()
+                    .map_or("", |id| &candidates[id].party[..]);
+                if !group.is_ungrouped() {
+                    let votes = self.votes(division, FirstPreference::Ticket(idx));
+                    wtr.serialize(DivisionFirstPrefsRecord::new(
+                        state,
+                        division,
+                        &group.name,
+                        0,
+                        "Ticket Votes".to_string(),
+                        party,
+                        votes,
+                    ))?;
+                }
+                for (position, id) in group.candidate_ids.iter().enumerate() {
+                    let c = &candidates[id];
+                    let votes = self.votes(division, FirstPreference::Candidate(*id));
+                    wtr.serialize(DivisionFirstPrefsRecord::new(
+                        state,
+                        division,
+                        &group.name,
+                        position + 1,
+                        format!("{}, {}", c.surname, c.other_names),
+                        &c.party,
+                        votes,
+                    ))?;
+                }
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Row of the CSV written by `DivisionFirstPrefs::write_csv`.
+#[derive(Serialize)]
+struct DivisionFirstPrefsRecord<'a> {
+    #[serde(rename = "StateAb")]
+    state_ab: &'a str,
+    #[serde(rename = "DivisionNm")]
+    division_name: &'a str,
+    #[serde(rename = "Ticket")]
+    ticket: &'a str,
+    #[serde(rename = "BallotPosition")]
+    ballot_position: usize,
+    #[serde(rename = "CandidateDetails")]
+    candidate_details: String,
+    #[serde(rename = "PartyName")]
+    party_name: &'a str,
+    #[serde(rename = "OrdinaryVotes")]
+    ordinary_votes: u64,
+    #[serde(rename = "AbsentVotes")]
+    absent_votes: u64,
+    #[serde(rename = "ProvisionalVotes")]
+    provisional_votes: u64,
+    #[serde(rename = "PrePollVotes")]
+    pre_poll_votes: u64,
+    #[serde(rename = "PostalVotes")]
+    postal_votes: u64,
+    #[serde(rename = "TotalVotes")]
+    total_votes: u64,
+}
+
+impl<'a> DivisionFirstPrefsRecord<'a> {
+    fn new(
+        state_ab: &'a str,
+        division_name: &'a str,
+        ticket: &'a str,
+        ballot_position: usize,
+        candidate_details: String,
+        party_name: &'a str,
+        votes: [u64; 5],
+    ) -> Self {
+        DivisionFirstPrefsRecord {
+            state_ab,
+            division_name,
+            ticket,
+            ballot_position,
+            candidate_details,
+            party_name,
+            ordinary_votes: votes[0],
+            absent_votes: votes[1],
+            provisional_votes: votes[2],
+            pre_poll_votes: votes[3],
+            postal_votes: votes[4],
+            total_votes: votes.iter().sum(),
+        }
+    }
+}
+
+/// Collect the first preferences of the formal ballots on every row, by the division and vote
+/// collection point of the row, for reconciling against the AEC's published breakdown.
+///
+/// Ballots counted above the line give their first preference to a group's ticket, and those
+/// counted below the line to a candidate. Informal ballots are left out, and malformed rows are
+/// skipped if `malformed` says to, like they are by the count.
+pub fn first_prefs_by_division<I>(
+    rows: I,
+    parser: &BallotParser,
+    malformed: MalformedBallots,
+) -> Result<DivisionFirstPrefs, Error>
+where
+    I: IntoIterator<Item = csv::Result<PrefRow>>,
+{
+    let group_of: HashMap<CandidateId, usize> = parser
+        .groups
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, g)| g.candidate_ids.iter().map(move |&id| (id, idx)))
+        .collect();
+    let mut scratch = ParseScratch::default();
+    let mut result = DivisionFirstPrefs::default();
+    for row in rows {
+        let parsed = row.map_err(Error::from).and_then(|row| {
+            match parser.parse_row_with(&mut scratch, &row) {
+                Ok(ballot) => Ok(Some((row, ballot))),
+                Err(InvalidBallot(_)) => Ok(None),
+                Err(InputError(e)) => Err(e),
+            }
+        });
+        let (row, ballot) = match parsed {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => continue,
+            Err(e) => match (malformed, MalformedRow::categorise(&e)) {
+                (MalformedBallots::Skip, Some(_)) => {
+                    warn!("Skipping malformed ballot: {}", e);
+                    continue;
+                }
+                _ => return Err(e),
+            },
+        };
+        let first = ballot.prefs[0];
+        let first = match scratch.section() {
+            Some(Section::AboveTheLine) => FirstPreference::Ticket(group_of[&first]),
+            _ => FirstPreference::Candidate(first),
+        };
+        let vote_type = VoteType::of_collection_point(&row.vote_collection_point);
+        result.record(&row.electorate_name, first, vote_type);
+    }
+    Ok(result)
+}
+
+/// Concatenate the rows of several preferences files for the same state.
+///
+/// The AEC sometimes splits a state's preferences across several files. All of the files must
+/// have the same header, otherwise the preference columns of one file could be silently
+/// misinterpreted. The layout of the files is detected from the header, see `PrefsFormat`.
+pub fn concat_rows<'r, R: Read + 'r>(
+    mut readers: Vec<csv::Reader<R>>,
+) -> Result<impl Iterator<Item = csv::Result<PrefRow>> + 'r, Error> {
+    let mut expected: Option<csv::StringRecord> = None;
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let header = reader.headers()?;
+        match expected {
+            None => expected = Some(header.clone()),
+            Some(ref first) if first.len() != header.len() => Err(Error::Schema(format!(
+                "preferences file {} has {} columns, but the first file has {}",
+                i + 1,
+                header.len(),
+                first.len()
+            )))?,
+            Some(ref first) if first != header => Err(Error::Schema(format!(
+                "preferences file {} has a different header to the first file",
+                i + 1
+            )))?,
+            Some(_) => (),
+        }
+    }
+    let format = match expected {
+        Some(ref header) => PrefsFormat::detect(header)?,
+        None => PrefsFormat::Aec2016,
+    };
+    Ok(readers.into_iter().flat_map(move |r| format.rows(r)))
+}
+
+/// Default number of rows parsed in parallel by `ParallelBallots`.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Iterator over the ballots in a preferences file, which parses preference strings on multiple
+/// threads.
+///
+/// Rows are read from the file in chunks, and the rows of each chunk are parsed in parallel.
+/// Ballots are yielded in the same order as the rows of the file.
+pub struct ParallelBallots<'a, I> {
+    rows: I,
+    parser: BallotParser<'a>,
+    chunk_size: usize,
+    parsed: VecDeque<IOBallot>,
+    /// Number of rows read so far, for numbering the rows in errors.
+    rows_read: u64,
+    donkey_votes: Rc<Cell<u64>>,
+    sections: SectionCounter,
+}
+
+/// How a formal ballot was read, according to the parsing buffers.
+#[derive(Clone, Copy)]
+struct FormalSection {
+    section: Section,
+    both_formal: bool,
+    num_preferences: usize,
+}
+
+/// Running count of the sections that each group's first preferences were counted from.
+#[derive(Clone)]
+pub struct SectionCounter {
+    names: Vec<String>,
+    /// Index in `names` of each candidate's group.
+    group_of: HashMap<CandidateId, usize>,
+    counts: Rc<RefCell<Vec<SectionCounts>>>,
+    formality: Rc<Cell<SectionFormality>>,
+    lengths: Rc<RefCell<PreferenceLengths>>,
+}
+
+impl SectionCounter {
+    fn new(groups: &[Group]) -> Self {
+        let mut group_of = HashMap::new();
+        for (i, group) in groups.iter().enumerate() {
+            for &id in &group.candidate_ids {
+                group_of.insert(id, i);
+            }
+        }
+        SectionCounter {
+            names: groups.iter().map(|g| g.name.clone()).collect(),
+            group_of,
+            counts: Rc::new(RefCell::new(vec![SectionCounts::default(); groups.len()])),
+            formality: Rc::new(Cell::new(SectionFormality::default())),
+            lengths: Rc::new(RefCell::new(PreferenceLengths::default())),
+        }
+    }
+
+    fn record(&self, ballot: &Ballot, formal: FormalSection) {
+        if let Some(i) = ballot.prefs.first().and_then(|id| self.group_of.get(id)) {
+            self.counts.borrow_mut()[*i].record(formal.section);
+        }
+        let mut formality = self.formality.get();
+        formality.record(formal.section, formal.both_formal);
+        self.formality.set(formality);
+        self.lengths
+            .borrow_mut()
+            .record(formal.section, formal.num_preferences);
+    }
+
+    /// Counts so far of the ballots formal in each section or both.
+    pub fn formality(&self) -> SectionFormality {
+        self.formality.get()
+    }
+
+    /// Counts so far of the ballots counted with each number of preferences in each section.
+    pub fn preference_lengths(&self) -> PreferenceLengths {
+        self.lengths.borrow().clone()
+    }
+
+    /// Counts so far for every group, by name.
+    pub fn by_group(&self) -> BTreeMap<String, SectionCounts> {
+        self.names
+            .iter()
+            .cloned()
+            .zip(self.counts.borrow().iter().cloned())
+            .collect()
+    }
+}
+
+impl<'a, R: Read> ParallelBallots<'a, csv::DeserializeRecordsIntoIter<R, PrefRow>> {
+    pub fn new(reader: csv::Reader<R>, parser: BallotParser<'a>, chunk_size: usize) -> Self {
+        ParallelBallots::from_rows(reader.into_deserialize(), parser, chunk_size)
+    }
+}
+
+impl<'a, I> ParallelBallots<'a, I>
+where
+    I: Iterator<Item = csv::Result<PrefRow>>,
+{
+    /// Parse ballots from an arbitrary source of rows, such as several files from `concat_rows`.
+    pub fn from_rows(rows: I, parser: BallotParser<'a>, chunk_size: usize) -> Self {
+        ParallelBallots {
+            rows,
+            parser,
+            chunk_size,
+            parsed: VecDeque::new(),
+            rows_read: 0,
+            donkey_votes: Rc::new(Cell::new(0)),
+            sections: SectionCounter::new(parser.groups),
+        }
+    }
+
+    /// Number of donkey votes among the ballots parsed so far (see `ParseScratch::is_donkey_vote`).
+    pub fn num_donkey_votes(&self) -> u64 {
+        self.donkey_votes.get()
+    }
+
+    /// Shared count of donkey votes, which can be read while the ballots are being consumed.
+    pub fn donkey_vote_counter(&self) -> Rc<Cell<u64>> {
+        self.donkey_votes.clone()
+    }
+
+    /// Shared count of the sections each group's first preferences were counted from, which can
+    /// be read while the ballots are being consumed.
+    pub fn section_counter(&self) -> SectionCounter {
+        self.sections.clone()
+    }
+
+    /// Read and parse the next chunk of rows.
+    ///
+    /// Each thread reuses its parsing buffers for all of the rows it parses in the chunk.
+    fn parse_chunk(&mut self) {
+        let first = self.rows_read + 1;
+        let rows: Vec<(u64, csv::Result<PrefRow>)> = (first..)
+            .zip(self.rows.by_ref().take(self.chunk_size))
+            .collect();
+        self.rows_read += rows.len() as u64;
+
+        let parser = self.parser;
+        // Along with each ballot, whether it's a donkey vote, and how it was read if it's formal.
+        let parsed: Vec<(IOBallot, bool, Option<FormalSection>)> = rows
+            .into_par_iter()
+            .map_init(ParseScratch::default, |scratch, (number, raw_row)| {
+                let ballot = parser.parse_read_row(scratch, number, raw_row);
+                let is_donkey = ballot.is_ok() && scratch.is_donkey_vote();
+                let formal = match scratch.section() {
+                    Some(section) if ballot.is_ok() => Some(FormalSection {
+                        section,
+                        both_formal: scratch.both_sections_formal(),
+                        num_preferences: scratch.num_preferences(),
+                    }),
+                    _ => None,
+                };
+                (ballot, is_donkey, formal)
+            })
+            .collect();
+
+        for (ballot, is_donkey, formal) in parsed {
+            self.donkey_votes
+                .set(self.donkey_votes.get() + u64::from(is_donkey));
+            if let (Ok(ref ballot), Some(formal)) = (&ballot, formal) {
+                self.sections.record(ballot, formal);
+            }
+            self.parsed.push_back(ballot);
+        }
+    }
+}
+
+impl<'a, I> Iterator for ParallelBallots<'a, I>
+where
+    I: Iterator<Item = csv::Result<PrefRow>>,
+{
+    type Item = IOBallot;
+
+    fn next(&mut self) -> Option<IOBallot> {
+        if self.parsed.is_empty() {
+            self.parse_chunk();
+        }
+        self.parsed.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEADER: &str =
+        "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,Preferences\n";
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let mut data = String::from(HEADER);
+        let prefs = [
+            "1,,,,",
+            "\"2,1,,,\"",
+            "\",,1,2,3\"",
+            "\",,1,x,\"",
+            "\"1,,2,1,3\"",
+        ];
+        for i in 0..50 {
+            data.push_str(&format!("A,B,1,1,{},{}\n", i, prefs[i % prefs.len()]));
+        }
+
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0, 1]),
+            },
+            Group {
+                name: "B".to_string(),
+                ticket_letter: Some("B".to_string()),
+                candidate_ids: ids(&[2]),
+            },
+        ];
+        let candidates = ids(&[0, 1, 2]);
+        let constraints = Constraints::with_min_preferences(1, 2);
+
+        let summarise = |ballots: Vec<IOBallot>| -> Vec<Option<Vec<CandidateId>>> {
+            ballots
+                .into_iter()
+                .map(|b| b.ok().map(|b| b.prefs))
+                .collect()
+        };
+
+        let parser = BallotParser::new(&groups, &candidates, &constraints);
+
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let sequential: Vec<_> = parser.parse_rows(reader.into_deserialize()).collect();
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let parallel = ParallelBallots::new(reader, parser, 7);
+        let sections = parallel.section_counter();
+        let parallel: Vec<_> = parallel.collect();
+
+        assert_eq!(sequential.len(), 50);
+        assert_eq!(summarise(sequential), summarise(parallel));
+        // Of each five rows, the first is malformed, as it's unquoted, and the last is formal in
+        // both sections, so it's counted below the line.
+        assert_eq!(
+            sections.formality(),
+            SectionFormality {
+                above_the_line_only: 10,
+                below_the_line_only: 10,
+                both: 10,
+            }
+        );
+        // Two groups above the line, or three candidates below it.
+        let lengths = sections.preference_lengths();
+        assert_eq!(lengths.above_the_line, vec![(2, 10)].into_iter().collect());
+        assert_eq!(lengths.below_the_line, vec![(3, 20)].into_iter().collect());
+        assert_eq!(lengths.percentage(Section::BelowTheLine, 3), 100.0);
+        assert_eq!(lengths.percentage(Section::BelowTheLine, 2), 0.0);
+    }
+
+    #[test]
+    fn empty_preferences_row() {
+        let data = format!("{}A,B,1,1,1,\"1,\"\nA,B,1,1,2,\"\"\nA,B,1,1,3,\n", HEADER);
+        let candidates = ids(&[0, 1]);
+        let constraints = Constraints::with_min_preferences(1, 1);
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let ballots: Vec<_> = BallotParser::new(&[], &candidates, &constraints)
+            .parse_rows(reader.into_deserialize())
+            .collect();
+        assert_eq!(ballots.len(), 3);
+        assert!(ballots[0].is_ok());
+        for ballot in &ballots[1..] {
+            match *ballot {
+                Err(InvalidBallot(BlankBallot)) => (),
+                ref res => panic!("expected a blank ballot, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn errors_give_the_ballot_paper() {
+        let data = format!(
+            "{}Denison,Hobart,1,7,1,\"1,2\"\nDenison,Hobart,1,7,2,\"1,,2\"\n",
+            HEADER
+        );
+        let candidates = ids(&[0, 1]);
+        let constraints = Constraints::with_min_preferences(1, 1);
+        let parser = BallotParser::new(&[], &candidates, &constraints);
+        let message = |ballot: &IOBallot| match *ballot {
+            Err(InputError(ref e)) => {
+                assert_eq!(
+                    MalformedRow::categorise(e),
+                    Some(MalformedRow::WrongNumberOfPreferences)
+                );
+                e.to_string()
+            }
+            ref res => panic!("expected an error, got {:?}", res),
+        };
+
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let sequential: Vec<_> = parser.parse_rows(reader.into_deserialize()).collect();
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let parallel: Vec<_> = ParallelBallots::new(reader, parser, 1).collect();
+        for ballots in &[sequential, parallel] {
+            assert!(ballots[0].is_ok());
+            assert_eq!(
+                message(&ballots[1]),
+                "ballot paper 2 of batch 7 in Denison (row 2): expected 2 preferences on the \
+                 ballot, found 3"
+            );
+        }
+
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let rows: Vec<PrefRow> = reader.into_deserialize().map(Result::unwrap).collect();
+        assert_eq!(
+            message(&parser.parse_row(&rows[1])),
+            "ballot paper 2 of batch 7 in Denison: expected 2 preferences on the ballot, found 3"
+        );
+    }
+
+    #[test]
+    fn formality_csv() {
+        let data = format!(
+            "{}Denison,Hobart,1,3,1,\"1,,\"\nDenison,Hobart,1,3,2,\"\"\n\
+             Denison,Hobart,1,4,1,\",1,\"\n",
+            HEADER
+        );
+        let groups = vec![Group {
+            name: "A".to_string(),
+            ticket_letter: Some("A".to_string()),
+            candidate_ids: ids(&[0, 1]),
+        }];
+        let candidates = ids(&[0, 1]);
+        let constraints = Constraints::with_min_preferences(1, 2);
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let mut out = vec![];
+        write_formality_csv(
+            reader.into_deserialize(),
+            &BallotParser::new(&groups, &candidates, &constraints),
+            MalformedBallots::Abort,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ElectorateNm,VoteCollectionPointId,BatchNo,PaperNo,Formality,Reason\n\
+             Denison,1,3,1,Formal,\n\
+             Denison,1,3,2,Informal,blank ballot\n\
+             Denison,1,4,1,Informal,too few preferences below the line\n"
+        );
+    }
+
+    #[test]
+    fn first_prefs_csv() {
+        let data = format!(
+            "{}Bass,Launceston,1,1,1,\"1,,,\"\nBass,ABSENT 1,2,1,1,\",,2,1\"\n\
+             Bass,POSTAL 1,3,1,1,\"1,,,\"\nBass,Launceston,1,1,2,\"\"\n\
+             Lyons,PRE_POLL 1,4,1,1,\",,1,\"\n",
+            HEADER
+        );
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0, 1]),
+            },
+            Group {
+                name: "UG".to_string(),
+                ticket_letter: None,
+                candidate_ids: ids(&[2]),
+            },
+        ];
+        let mut candidates = test_candidates(3);
+        for (id, c) in candidates.iter_mut() {
+            c.surname = format!("SMITH{}", id);
+            c.other_names = "Jo".to_string();
+            c.party = "Party A".to_string();
+        }
+        let constraints = Constraints::with_min_preferences(1, 1);
+        let reader = csv::Reader::from_reader(data.as_bytes());
+        let candidate_ids = ids(&[0, 1, 2]);
+        let first_prefs = first_prefs_by_division(
+            reader.into_deserialize(),
+            &BallotParser::new(&groups, &candidate_ids, &constraints),
+            MalformedBallots::Abort,
+        )
+        .unwrap();
+        assert_eq!(
+            first_prefs.divisions().collect::<Vec<_>>(),
+            vec!["Bass", "Lyons"]
+        );
+        assert_eq!(
+            first_prefs.votes("Bass", FirstPreference::Ticket(0)),
+            [1, 0, 0, 0, 1]
+        );
+
+        let mut out = vec![];
+        first_prefs
+            .write_csv("TAS", &groups, &candidates, &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "StateAb,DivisionNm,Ticket,BallotPosition,CandidateDetails,PartyName,OrdinaryVotes,\
+             AbsentVotes,ProvisionalVotes,PrePollVotes,PostalVotes,TotalVotes\n\
+             TAS,Bass,A,0,Ticket Votes,Party A,1,0,0,0,1,2\n\
+             TAS,Bass,A,1,\"SMITH0, Jo\",Party A,0,0,0,0,0,0\n\
+             TAS,Bass,A,2,\"SMITH1, Jo\",Party A,0,0,0,0,0,0\n\
+             TAS,Bass,UG,1,\"SMITH2, Jo\",Party A,0,1,0,0,0,1\n\
+             TAS,Lyons,A,0,Ticket Votes,Party A,0,0,0,0,0,0\n\
+             TAS,Lyons,A,1,\"SMITH0, Jo\",Party A,0,0,0,0,0,0\n\
+             TAS,Lyons,A,2,\"SMITH1, Jo\",Party A,0,0,0,1,0,1\n\
+             TAS,Lyons,UG,1,\"SMITH2, Jo\",Party A,0,0,0,0,0,0\n"
+        );
+    }
+
+    #[test]
+    fn concatenated_files() {
+        let first = format!("{}A,B,1,1,1,\"1,2\"\nA,B,1,1,2,\"2,1\"\n", HEADER);
+        let second = format!("{}A,B,1,2,1,\"1,\"\n", HEADER);
+        let readers = vec![
+            csv::Reader::from_reader(first.as_bytes()),
+            csv::Reader::from_reader(second.as_bytes()),
+        ];
+        let prefs: Vec<String> = concat_rows(readers)
+            .unwrap()
+            .map(|row| row.unwrap().preferences)
+            .collect();
+        assert_eq!(prefs, vec!["1,2", "2,1", "1,"]);
+    }
+
+    #[test]
+    fn mismatched_files() {
+        let first = format!("{}A,B,1,1,1,\"1,2\"\n", HEADER);
+        let extra_column = "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,\
+                            PaperNo,Preferences,Extra\n";
+        let renamed = HEADER.replace("PaperNo", "Paper");
+        for other in &[extra_column.to_string(), renamed] {
+            let readers = vec![
+                csv::Reader::from_reader(first.as_bytes()),
+                csv::Reader::from_reader(other.as_bytes()),
+            ];
+            assert!(concat_rows(readers).is_err());
+        }
+    }
+
+    fn detect(header: &str) -> Result<PrefsFormat, Error> {
+        let mut reader = csv::Reader::from_reader(header.as_bytes());
+        PrefsFormat::detect(reader.headers().unwrap())
+    }
+
+    fn read_prefs(data: &str) -> Vec<PrefRow> {
+        let readers = vec![csv::Reader::from_reader(data.as_bytes())];
+        concat_rows(readers).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    // Two groups of one candidate each, plus an ungrouped candidate.
+    const BOX_COLUMNS: &str = "A:Party A,B:Party B,A:SMITH Jo,B:JONES Al,UG:BROWN Sam";
+
+    #[test]
+    fn format_2016() {
+        assert_eq!(detect(HEADER).unwrap(), PrefsFormat::Aec2016);
+        let rows = read_prefs(&format!("{}Denison,Hobart,1,2,3,\"1,2,,,\"\n", HEADER));
+        assert_eq!(rows[0].preferences, "1,2,,,");
+        assert_eq!(rows[0].paper_num, "3");
+    }
+
+    #[test]
+    fn columns_by_name() {
+        // The 2016 columns in a different order, with an extra column.
+        let header = "Preferences,PaperNo,Extra,BatchNo,VoteCollectionPointId,\
+                      VoteCollectionPointNm,ElectorateNm\n";
+        assert_eq!(detect(header).unwrap(), PrefsFormat::Aec2016);
+        let rows = read_prefs(&format!("{}\"1,2,,,\",3,x,2,1,Hobart,Denison\n", header));
+        assert_eq!(rows[0].preferences, "1,2,,,");
+        assert_eq!(rows[0].paper_num, "3");
+        assert_eq!(rows[0].batch_num, "2");
+        assert_eq!(rows[0].electorate_name, "Denison");
+
+        let missing = HEADER.replace("BatchNo,", "").replace("PaperNo,", "");
+        match detect(&missing) {
+            Err(Error::Schema(msg)) => {
+                assert!(
+                    msg.ends_with("missing columns: BatchNo, PaperNo"),
+                    "{}",
+                    msg
+                )
+            }
+            res => panic!("expected a schema error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn format_2019() {
+        let header = format!(
+            "State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,\
+             Paper No,{}\n",
+            BOX_COLUMNS
+        );
+        assert_eq!(
+            detect(&header).unwrap(),
+            PrefsFormat::Aec2019 {
+                first_preference: 6
+            }
+        );
+        let rows = read_prefs(&format!("{}TAS,Clark,Hobart,1,2,3,1,2,,,\n", header));
+        assert_eq!(rows[0].preferences, "1,2,,,");
+        assert_eq!(rows[0].electorate_name, "Clark");
+        assert_eq!(rows[0].vote_collection_point, "Hobart");
+        assert_eq!(rows[0].batch_num, "2");
+        assert_eq!(rows[0].paper_num, "3");
+    }
+
+    #[test]
+    fn format_2022() {
+        // Column per box layout, with fewer metadata columns before the boxes.
+        let header = format!(
+            "Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,{}\n",
+            BOX_COLUMNS
+        );
+        assert_eq!(
+            detect(&header).unwrap(),
+            PrefsFormat::Aec2019 {
+                first_preference: 5
+            }
+        );
+        let rows = read_prefs(&format!("{}Clark,Hobart,1,2,3,,,2,1,3\n", header));
+        assert_eq!(rows[0].preferences, ",,2,1,3");
+
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                ticket_letter: Some("A".to_string()),
+                candidate_ids: ids(&[0]),
+            },
+            Group {
+                name: "B".to_string(),
+                ticket_letter: Some("B".to_string()),
+                candidate_ids: ids(&[1]),
+            },
+        ];
+        let constraints = Constraints::with_min_preferences(1, 3);
+        let candidates = ids(&[0, 1, 2]);
+        let parser = BallotParser::new(&groups, &candidates, &constraints);
+        let ballot = parser.parse_row(&rows[0]).unwrap();
+        assert_eq!(ballot.prefs, ids(&[1, 0, 2]));
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(detect("Division,Batch No,Paper No\n").is_err());
+    }
 }
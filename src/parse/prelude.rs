@@ -1,5 +1,5 @@
+pub use error::Error;
 pub use std::collections::{BTreeMap, HashMap, HashSet};
-pub use std::error::Error;
 pub use std::io::Read;
 
 pub use ballot_parse::*;
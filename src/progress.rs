@@ -0,0 +1,158 @@
+//! Events reported while the count is in progress.
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use ballot_parse::IOBallot;
+use candidate::{CandidateId, CandidateMap};
+use config::ElectionConfig;
+use error::Error;
+use senate_result::Senate;
+use util::*;
+use voting::decide_election_with_progress;
+
+/// Something that happened during the count, reported to the progress callback of
+/// `decide_election_with_progress`, or pulled from `count_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CountEvent {
+    /// A candidate reached a quota at the given count, and was elected.
+    QuotaReached {
+        count: usize,
+        candidate: CandidateId,
+        votes: Int,
+    },
+    /// The continuing candidates were elected without reaching a quota, because only as many
+    /// candidates as vacancies remained.
+    ElectedRemaining {
+        count: usize,
+        candidates: Vec<CandidateId>,
+    },
+    /// Ballots of one or more excluded candidates were transferred.
+    Exclusion {
+        count: usize,
+        candidates: Vec<CandidateId>,
+        /// Number of ballot papers transferred.
+        papers: u64,
+        transfer_values: Vec<Frac>,
+    },
+    /// An elected candidate's surplus was transferred.
+    SurplusTransfer {
+        count: usize,
+        candidate: CandidateId,
+        /// Number of ballot papers transferred.
+        papers: u64,
+        transfer_values: Vec<Frac>,
+    },
+    /// The count finished, electing these candidates in order.
+    FinalResult { elected: Vec<CandidateId> },
+}
+
+/// An item of `CountEvents`: an event of the count, or its result once it's finished.
+#[derive(Debug)]
+pub enum CountStep {
+    Event(CountEvent),
+    /// The count finished with this result, after its `FinalResult` event.
+    Finished(Box<Senate>),
+}
+
+/// Iterator over the events of a count, created by `count_events`.
+///
+/// The count runs on its own thread, which waits for each event to be taken before carrying on,
+/// so the count only gets one event ahead of the consumer. The iterator yields the events, then
+/// the result, or an error if the count fails. If it's dropped early, the count runs to the end
+/// without being observed.
+pub struct CountEvents {
+    receiver: Receiver<Result<CountStep, Error>>,
+}
+
+impl Iterator for CountEvents {
+    type Item = Result<CountStep, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Count an election like `decide_election_with_progress`, pulling its events from an iterator
+/// instead of having them pushed to a callback.
+///
+/// The events and result are exactly those of `decide_election_with_progress`.
+pub fn count_events<I>(
+    candidates: CandidateMap,
+    disqualified_candidates: Vec<CandidateId>,
+    ballot_stream: I,
+    config: ElectionConfig,
+) -> CountEvents
+where
+    I: IntoIterator<Item = IOBallot> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(0);
+    thread::spawn(move || {
+        let result = decide_election_with_progress(
+            &candidates,
+            &disqualified_candidates,
+            ballot_stream,
+            &config,
+            |event| {
+                // Once the receiver is dropped there's no one to tell.
+                let _ = sender.send(Ok(CountStep::Event(event)));
+            },
+        );
+        let _ = sender.send(result.map(|senate| CountStep::Finished(Box::new(senate))));
+    });
+    CountEvents { receiver }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot::Ballot;
+    use candidate::{ids, test_candidates};
+
+    #[test]
+    fn same_as_callback() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1, 2])),
+            Ballot::multi(2, ids(&[2])),
+            Ballot::multi(1, ids(&[3, 0])),
+        ];
+        let candidates = test_candidates(4);
+        let config = ElectionConfig::new(2);
+
+        let mut pushed = vec![];
+        let expected = decide_election_with_progress(
+            &candidates,
+            &[],
+            ballots.clone().into_iter().map(Ok),
+            &config,
+            |event| pushed.push(event),
+        )
+        .unwrap();
+
+        let mut pulled = vec![];
+        let mut result = None;
+        for step in count_events(candidates, vec![], ballots.into_iter().map(Ok), config) {
+            match step.unwrap() {
+                CountStep::Event(event) => pulled.push(event),
+                CountStep::Finished(senate) => result = Some(senate),
+            }
+        }
+        assert_eq!(pulled, pushed);
+        let result = result.unwrap();
+        assert_eq!(result.summary(), expected.summary());
+        assert_eq!(result.final_standings, expected.final_standings);
+    }
+
+    #[test]
+    fn error() {
+        let mut steps = count_events(
+            test_candidates(2),
+            vec![],
+            vec![Ok(Ballot::single(ids(&[0])))],
+            ElectionConfig::new(0),
+        );
+        assert!(steps.next().unwrap().is_err());
+        assert!(steps.next().is_none());
+    }
+}
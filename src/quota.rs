@@ -0,0 +1,158 @@
+use std::fmt::Debug;
+
+use util::*;
+
+/// Method for computing the number of votes a candidate needs to be elected.
+///
+/// Quotas are shared between threads, so that separate elections can be counted in parallel.
+pub trait Quota: Debug + Send + Sync {
+    /// Compute the quota for an election with `num_votes` formal votes and `num_positions` vacancies.
+    fn compute(&self, num_votes: u64, num_positions: usize) -> Int;
+
+    /// Copy this quota method into a new box.
+    fn clone_box(&self) -> Box<dyn Quota>;
+
+    /// Name of the quota method, as accepted by `from_name`.
+    fn name(&self) -> String;
+}
+
+impl Clone for Box<dyn Quota> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// The Droop quota, floor(votes / (vacancies + 1)) + 1, as per section 273(8).
+#[derive(Debug, Clone, Copy)]
+pub struct Droop;
+
+/// The Hare quota, floor(votes / vacancies).
+#[derive(Debug, Clone, Copy)]
+pub struct Hare;
+
+/// The Imperial quota, floor(votes / (vacancies + 2)).
+///
+/// More candidates than there are vacancies may reach this quota, in which case only the
+/// candidates with the most votes are elected.
+#[derive(Debug, Clone, Copy)]
+pub struct Imperial;
+
+/// A quota fixed in advance, such as the quota from an earlier count.
+#[derive(Debug, Clone)]
+pub struct Fixed(pub Int);
+
+impl Quota for Droop {
+    fn compute(&self, num_votes: u64, num_positions: usize) -> Int {
+        Int::from(droop_quota(num_votes, num_positions as u32))
+    }
+
+    fn clone_box(&self) -> Box<dyn Quota> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "droop".to_string()
+    }
+}
+
+impl Quota for Hare {
+    fn compute(&self, num_votes: u64, num_positions: usize) -> Int {
+        Int::from(num_votes / num_positions as u64)
+    }
+
+    fn clone_box(&self) -> Box<dyn Quota> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "hare".to_string()
+    }
+}
+
+impl Quota for Imperial {
+    fn compute(&self, num_votes: u64, num_positions: usize) -> Int {
+        Int::from(num_votes / (num_positions as u64 + 2))
+    }
+
+    fn clone_box(&self) -> Box<dyn Quota> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "imperial".to_string()
+    }
+}
+
+impl Quota for Fixed {
+    fn compute(&self, _: u64, _: usize) -> Int {
+        self.0.clone()
+    }
+
+    fn clone_box(&self) -> Box<dyn Quota> {
+        Box::new(self.clone())
+    }
+
+    fn name(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Look up a quota method by name: droop, hare or imperial, or a number of votes for a fixed
+/// quota.
+pub fn from_name(name: &str) -> Option<Box<dyn Quota>> {
+    match name {
+        "droop" => Some(Box::new(Droop)),
+        "hare" => Some(Box::new(Hare)),
+        "imperial" => Some(Box::new(Imperial)),
+        _ => name
+            .parse()
+            .ok()
+            .map(|n| Box::new(Fixed(n)) as Box<dyn Quota>),
+    }
+}
+
+/// Serialize a quota method by its name.
+pub mod by_name {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    use super::Quota;
+
+    // Serde passes a reference to the field, which is boxed.
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(
+        quota: &Box<dyn Quota>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&quota.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn Quota>, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        super::from_name(&name).ok_or_else(|| de::Error::custom(format!("unknown quota: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quotas() {
+        assert_eq!(Droop.compute(100, 3), Int::from(26));
+        assert_eq!(Droop.compute(101, 3), Int::from(26));
+        assert_eq!(Hare.compute(100, 3), Int::from(33));
+        assert_eq!(Imperial.compute(100, 3), Int::from(20));
+    }
+
+    #[test]
+    fn names() {
+        for name in &["droop", "hare", "imperial", "1234"] {
+            assert_eq!(from_name(name).unwrap().name(), *name);
+        }
+        assert_eq!(from_name("1234").unwrap().compute(100, 3), Int::from(1234));
+        assert!(from_name("meek").is_none());
+    }
+}
@@ -0,0 +1,905 @@
+//! Reports on the count, mostly in the formats published by the AEC.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use candidate::*;
+use error::Error;
+use parse::dop2016::OfficialDistribution;
+use senate_result::{ElectedBy, Senate};
+use transcript::*;
+use util::*;
+
+/// Number of decimal places transfer values are written with.
+const TRANSFER_VALUE_PLACES: usize = 8;
+
+/// Row of the distribution of preferences, giving one candidate's votes at one count.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct DistributionRow {
+    #[serde(rename = "Count")]
+    pub count: usize,
+    #[serde(rename = "Candidate")]
+    pub candidate: String,
+    #[serde(rename = "BallotPapers")]
+    pub ballot_papers: i64,
+    #[serde(rename = "TransferValue")]
+    pub transfer_value: String,
+    #[serde(rename = "VotesTransferred")]
+    pub votes_transferred: String,
+    #[serde(rename = "ProgressiveTotal")]
+    pub progressive_total: String,
+    /// Ballot papers held at the end of the count, which unlike the votes aren't reduced by
+    /// transfer values.
+    #[serde(rename = "ProgressiveBallotPapers")]
+    pub progressive_ballot_papers: u64,
+    #[serde(rename = "Status")]
+    pub status: String,
+    /// Ballot papers needed to reach the quota at the average value of those held (see
+    /// `CandidateCount::papers_for_quota`), only given if asked for. It's empty for a candidate
+    /// without any votes.
+    #[serde(rename = "QuotaBallotPapers", skip_serializing_if = "Option::is_none")]
+    pub quota_ballot_papers: Option<String>,
+}
+
+/// Format a transfer value as a decimal, truncated to `TRANSFER_VALUE_PLACES` places.
+fn format_transfer_value(value: &Frac) -> String {
+    let scale = Int::from(10).pow(TRANSFER_VALUE_PLACES as u32);
+    let scaled = (value * Frac::from(&scale)).floor();
+    let digits = format!(
+        "{:0>width$}",
+        scaled.to_string(),
+        width = TRANSFER_VALUE_PLACES + 1
+    );
+    let (whole, fraction) = digits.split_at(digits.len() - TRANSFER_VALUE_PLACES);
+    format!("{}.{}", whole, fraction)
+}
+
+/// The rows of the distribution of preferences for a transcript.
+///
+/// Each count has a row for every candidate continuing at the end of the count. A count that
+/// transfers parcels at several transfer values lists all of them, separated by semicolons.
+///
+/// With a `quota`, each row also gives the ballot papers the candidate needs to reach it.
+pub fn distribution_rows(
+    transcript: &Transcript,
+    candidates: &CandidateMap,
+    quota: Option<&Int>,
+) -> Vec<DistributionRow> {
+    let mut rows = vec![];
+    for record in &transcript.counts {
+        let transfer_value = match record.action {
+            CountAction::FirstPreferences => format_transfer_value(&frac!(1u64)),
+            _ => record
+                .transfer_values
+                .iter()
+                .map(format_transfer_value)
+                .collect::<Vec<_>>()
+                .join(";"),
+        };
+        for (id, votes) in &record.votes {
+            let c = &candidates[id];
+            let status = if record.elected.contains(id) {
+                "Elected"
+            } else {
+                ""
+            };
+            rows.push(DistributionRow {
+                count: record.count,
+                candidate: format!("{}, {}", c.surname, c.other_names),
+                ballot_papers: votes.papers_received,
+                transfer_value: transfer_value.clone(),
+                votes_transferred: votes.received.to_string(),
+                progressive_total: votes.total.to_string(),
+                progressive_ballot_papers: votes.papers,
+                status: status.to_string(),
+                quota_ballot_papers: quota.map(|q| {
+                    votes
+                        .papers_for_quota(q)
+                        .map_or_else(String::new, |n| n.to_string())
+                }),
+            });
+        }
+    }
+    rows
+}
+
+/// Write a transcript as CSV, with the columns of the AEC's distribution of preferences.
+///
+/// With a `quota`, a final `QuotaBallotPapers` column is added (see `distribution_rows`).
+pub fn write_transcript_csv<W: Write>(
+    transcript: &Transcript,
+    candidates: &CandidateMap,
+    quota: Option<&Int>,
+    writer: W,
+) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for row in distribution_rows(transcript, candidates, quota) {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Row of the transcript in long format, giving one candidate's standing at one count.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LongRow {
+    #[serde(rename = "Count")]
+    pub count: usize,
+    /// `FirstPreferences`, `Surplus` or `Exclusion`.
+    #[serde(rename = "Action")]
+    pub action: String,
+    /// IDs of the candidates whose surplus or ballots were transferred, separated by semicolons.
+    #[serde(rename = "ActionCandidates")]
+    pub action_candidates: String,
+    #[serde(rename = "CandidateId")]
+    pub candidate_id: CandidateId,
+    #[serde(rename = "Candidate")]
+    pub candidate: String,
+    #[serde(rename = "Ticket")]
+    pub ticket: String,
+    /// Votes at the end of the count, empty for a candidate who no longer holds any ballots.
+    #[serde(rename = "Votes")]
+    pub votes: Option<String>,
+    #[serde(rename = "VotesReceived")]
+    pub votes_received: Option<String>,
+    #[serde(rename = "BallotPapers")]
+    pub ballot_papers: Option<u64>,
+    #[serde(rename = "BallotPapersReceived")]
+    pub ballot_papers_received: Option<i64>,
+    /// `Continuing`, `Elected` (at this count or before) or `Excluded`.
+    #[serde(rename = "Status")]
+    pub status: String,
+}
+
+/// The rows of a transcript in long format, with a row for every candidate at every count.
+///
+/// Rows are ordered by count, then by candidate ID. A candidate who has been elected and had
+/// their surplus transferred, or who has been excluded, has no votes or papers.
+pub fn long_rows(transcript: &Transcript, candidates: &CandidateMap) -> Vec<LongRow> {
+    let mut ids: Vec<CandidateId> = candidates.keys().cloned().collect();
+    ids.sort();
+    let mut elected = BTreeSet::new();
+    let mut rows = vec![];
+    for record in &transcript.counts {
+        elected.extend(record.elected.iter().cloned());
+        let (action, action_candidates) = match record.action {
+            CountAction::FirstPreferences => ("FirstPreferences", vec![]),
+            CountAction::Surplus(id) => ("Surplus", vec![id]),
+            CountAction::Exclusion(ref excluded) => ("Exclusion", excluded.clone()),
+        };
+        let action_candidates = action_candidates
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(";");
+        for id in &ids {
+            let c = &candidates[id];
+            let votes = record.votes.get(id);
+            let status = if elected.contains(id) {
+                "Elected"
+            } else if votes.is_some() {
+                "Continuing"
+            } else {
+                "Excluded"
+            };
+            rows.push(LongRow {
+                count: record.count,
+                action: action.to_string(),
+                action_candidates: action_candidates.clone(),
+                candidate_id: *id,
+                candidate: format!("{}, {}", c.surname, c.other_names),
+                ticket: c.group_name.clone(),
+                votes: votes.map(|v| v.total.to_string()),
+                votes_received: votes.map(|v| v.received.to_string()),
+                ballot_papers: votes.map(|v| v.papers),
+                ballot_papers_received: votes.map(|v| v.papers_received),
+                status: status.to_string(),
+            });
+        }
+    }
+    rows
+}
+
+/// Write a transcript as CSV in long format, with one row per count and candidate (see
+/// `long_rows`), for analysis and plotting.
+pub fn write_transcript_long_csv<W: Write>(
+    transcript: &Transcript,
+    candidates: &CandidateMap,
+    writer: W,
+) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for row in long_rows(transcript, candidates) {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Ballot papers that flowed from the candidates of one group to those of another.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FlowRow {
+    #[serde(rename = "From")]
+    pub from: String,
+    #[serde(rename = "To")]
+    pub to: String,
+    #[serde(rename = "BallotPapers")]
+    pub ballot_papers: i64,
+}
+
+/// Matrix of preference flows at exclusions, from the group of the excluded candidates to the
+/// group of each continuing candidate, in ballot papers.
+///
+/// Flows at each exclusion are summed over the whole count. A bulk exclusion of candidates from
+/// several groups is attributed to all of them jointly, named by joining their tickets with `+`.
+/// Papers that exhaust aren't included.
+pub fn preference_flows(
+    transcript: &Transcript,
+    candidates: &CandidateMap,
+) -> BTreeMap<String, BTreeMap<String, i64>> {
+    let mut flows: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for record in &transcript.counts {
+        let excluded = match record.action {
+            CountAction::Exclusion(ref ids) => ids,
+            _ => continue,
+        };
+        let mut from: Vec<&str> = excluded
+            .iter()
+            .map(|id| candidates[id].group_name.as_str())
+            .collect();
+        from.sort();
+        from.dedup();
+        let row = flows.entry(from.join("+")).or_default();
+
+        for (id, votes) in &record.votes {
+            if votes.papers_received > 0 {
+                let to = candidates[id].group_name.clone();
+                *row.entry(to).or_insert(0) += votes.papers_received;
+            }
+        }
+    }
+    flows
+}
+
+/// Write the matrix of preference flows as CSV, with one row per pair of groups.
+pub fn write_flows_csv<W: Write>(
+    transcript: &Transcript,
+    candidates: &CandidateMap,
+    writer: W,
+) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for (from, row) in preference_flows(transcript, candidates) {
+        for (to, ballot_papers) in row {
+            wtr.serialize(FlowRow {
+                from: from.clone(),
+                to,
+                ballot_papers,
+            })?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A candidate whose first preferences differ from the official total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirstPreferenceDiscrepancy {
+    pub candidate: CandidateId,
+    pub official: u64,
+    pub computed: u64,
+}
+
+/// Compare the first preferences from the ballots to the official totals, such as those from
+/// `parse::first_preferences_from_path`.
+///
+/// A candidate missing from either side counts as having no first preferences. Returns the
+/// discrepancies in order of candidate ID, so an empty list means every total matches.
+pub fn validate_first_preferences(
+    summary: &BTreeMap<CandidateId, u64>,
+    computed: &BTreeMap<CandidateId, u64>,
+) -> Vec<FirstPreferenceDiscrepancy> {
+    let ids: BTreeSet<_> = summary.keys().chain(computed.keys()).collect();
+    ids.into_iter()
+        .filter_map(|id| {
+            let official = summary.get(id).cloned().unwrap_or(0);
+            let computed = computed.get(id).cloned().unwrap_or(0);
+            if official == computed {
+                None
+            } else {
+                Some(FirstPreferenceDiscrepancy {
+                    candidate: *id,
+                    official,
+                    computed,
+                })
+            }
+        })
+        .collect()
+}
+
+/// How far the number of formal ballots counted is from the total of the official first
+/// preferences, as a fraction of the official total, if it's more than `threshold`.
+///
+/// A difference usually means the preferences files weren't all read, or were parsed wrongly.
+pub fn formal_total_divergence(
+    summary: &BTreeMap<CandidateId, u64>,
+    formal: u64,
+    threshold: f64,
+) -> Option<f64> {
+    let official: u64 = summary.values().sum();
+    let divergence = if official == 0 {
+        if formal == 0 {
+            0.0
+        } else {
+            f64::INFINITY
+        }
+    } else {
+        (formal as f64 - official as f64).abs() / official as f64
+    };
+    if divergence > threshold {
+        Some(divergence)
+    } else {
+        None
+    }
+}
+
+/// The first place a count differs from the official distribution of preferences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CountDivergence {
+    /// A continuing candidate's votes at a count differ from the official votes, which are
+    /// `None` if the official distribution doesn't list the candidate at that count.
+    Votes {
+        count: usize,
+        candidate: CandidateId,
+        official: Option<u64>,
+        computed: Int,
+    },
+    /// The count went on to a count that the official distribution doesn't have.
+    ExtraCount { count: usize },
+    /// The official distribution goes on to a count that the count didn't reach.
+    MissingCount { count: usize },
+}
+
+/// Compare a transcript to the official distribution of preferences, such as that from
+/// `parse::official_distribution_from_path`, count by count.
+///
+/// At each count, the votes of every candidate continuing at the end of the count (including
+/// those elected at it) must match the official votes. Candidates who have been elected or
+/// excluded before the count aren't compared, as the official distribution gives them a quota
+/// or nothing. Returns the first divergence, so `None` means the counts match throughout.
+pub fn first_count_divergence(
+    official: &OfficialDistribution,
+    transcript: &Transcript,
+) -> Option<CountDivergence> {
+    for record in &transcript.counts {
+        let official_votes = match official.get(&record.count) {
+            Some(votes) => votes,
+            None => {
+                return Some(CountDivergence::ExtraCount {
+                    count: record.count,
+                })
+            }
+        };
+        for (id, votes) in &record.votes {
+            let official = official_votes.get(id).cloned();
+            if official.map(Int::from) != Some(votes.total.clone()) {
+                return Some(CountDivergence::Votes {
+                    count: record.count,
+                    candidate: *id,
+                    official,
+                    computed: votes.total.clone(),
+                });
+            }
+        }
+    }
+    official
+        .keys()
+        .find(|&&count| count > transcript.counts.len())
+        .map(|&count| CountDivergence::MissingCount { count })
+}
+
+/// Escape the characters in a table cell that Markdown would otherwise treat as markup.
+fn markdown_cell(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Write the elected senators as a GitHub-flavoured Markdown table, with the count action that
+/// elected each of them, followed by a line giving the numbers from `Senate::summary` and the
+/// number of ties broken at random.
+pub fn write_markdown<W: Write>(out: &mut W, result: &Senate) -> io::Result<()> {
+    writeln!(
+        out,
+        "| Order | Name | Party | Group | Elected at count | Elected on |"
+    )?;
+    writeln!(out, "| ---: | --- | --- | --- | ---: | --- |")?;
+    for ((c, _), e) in result.senators.iter().zip(&result.elections) {
+        let name = format!("{} {}", c.other_names, c.surname);
+        let elected_on = match e.elected_on {
+            Some(ref on) => on.to_string(),
+            None => "remaining candidates".to_string(),
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} | {} | {} | {} |",
+            e.elected_order,
+            markdown_cell(name.trim()),
+            markdown_cell(&c.party),
+            markdown_cell(&c.group_name),
+            e.elected_at_count,
+            markdown_cell(&elected_on)
+        )?;
+    }
+    let summary = result.summary();
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Quota: {} votes. Counts: {} ({} surplus transfers, {} exclusions). Exhausted votes: {}. \
+         Ties broken at random: {}.",
+        summary.quota,
+        summary.counts,
+        summary.surplus_transfers,
+        summary.exclusions,
+        summary.exhausted_votes,
+        result.ties.len()
+    )
+}
+
+/// Describe the parcels transferred at a count, like "6 ballot papers at 0.33333333".
+fn describe_parcels(parcels: &[ParcelRecord]) -> String {
+    if parcels.is_empty() {
+        return "no ballot papers".to_string();
+    }
+    let parcels: Vec<String> = parcels
+        .iter()
+        .map(|p| {
+            format!(
+                "{} ballot {} at {}",
+                p.papers,
+                if p.papers == 1 { "paper" } else { "papers" },
+                format_transfer_value(&p.transfer_value)
+            )
+        })
+        .collect();
+    parcels.join(" and ")
+}
+
+/// Write the story of the count from its transcript, one paragraph per count, saying what was
+/// transferred and who was elected, in words a scrutineer could check against the distribution
+/// of preferences.
+///
+/// The same count always gives the same text. It's an error if there's no transcript.
+pub fn write_narrative<W: Write>(
+    out: &mut W,
+    result: &Senate,
+    candidates: &CandidateMap,
+) -> Result<(), Error> {
+    let transcript = match result.transcript {
+        Some(ref transcript) => transcript,
+        None => {
+            return Err(Error::Config(
+                "explaining the count needs a transcript".to_string(),
+            ))
+        }
+    };
+    let name = |id: &CandidateId| distinct_name(candidates, *id);
+    let names = |ids: &[CandidateId]| ids.iter().map(&name).collect::<Vec<_>>().join(" and ");
+
+    writeln!(
+        out,
+        "{} formal votes for {} vacancies, making a quota of {} votes.",
+        result.stats.num_valid_votes(),
+        result.vacancies,
+        result.quota
+    )?;
+    let mut previous = None;
+    for record in &transcript.counts {
+        write!(out, "\nCount {}: ", record.count)?;
+        let continued = previous == Some(&record.action);
+        match record.action {
+            CountAction::FirstPreferences => write!(out, "first preferences.")?,
+            CountAction::Surplus(id) => {
+                let elected_on = result
+                    .senators
+                    .iter()
+                    .find(|(c, _)| c.id == id)
+                    .map(|(_, votes)| votes.clone() - &result.quota)
+                    .unwrap_or_else(|| Int::from(0));
+                write!(
+                    out,
+                    "surplus of {} votes of {} transferred, as {}.",
+                    elected_on,
+                    name(&id),
+                    describe_parcels(&record.parcels)
+                )?;
+            }
+            CountAction::Exclusion(ref ids) if continued => write!(
+                out,
+                "exclusion of {} continued, transferring {}.",
+                names(ids),
+                describe_parcels(&record.parcels)
+            )?,
+            CountAction::Exclusion(ref ids) => write!(
+                out,
+                "{} excluded, transferring {}.",
+                names(ids),
+                describe_parcels(&record.parcels)
+            )?,
+        }
+        for id in &record.elected {
+            let votes = &record.votes[id].total;
+            let by = result
+                .senators
+                .iter()
+                .zip(&result.elections)
+                .find(|((c, _), _)| c.id == *id)
+                .map(|(_, e)| e.elected_by);
+            match by {
+                Some(ElectedBy::Remaining) => write!(
+                    out,
+                    " {} was elected as one of the candidates left standing, with {} votes.",
+                    name(id),
+                    votes
+                )?,
+                _ => write!(
+                    out,
+                    " {} reached the quota with {} votes and was elected.",
+                    name(id),
+                    votes
+                )?,
+            }
+        }
+        writeln!(out)?;
+        previous = Some(&record.action);
+    }
+
+    writeln!(out)?;
+    if result.count_limit_reached {
+        writeln!(
+            out,
+            "The count stopped at its limit of counts before every vacancy was filled."
+        )?;
+    }
+    writeln!(
+        out,
+        "Elected, in order: {}.",
+        result.senator_names.join(", ")
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot::Ballot;
+    use config::ElectionConfig;
+    use voting::decide_election;
+
+    #[test]
+    fn transfer_value_format() {
+        assert_eq!(format_transfer_value(&frac!(1u64)), "1.00000000");
+        assert_eq!(format_transfer_value(&frac!(1u64, 6u64)), "0.16666666");
+        assert_eq!(format_transfer_value(&frac!(3u64, 1000u64)), "0.00300000");
+    }
+
+    #[test]
+    fn distribution_of_preferences() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let candidates = test_candidates(3);
+        let config = ElectionConfig {
+            transcript: true,
+            ..ElectionConfig::new(2)
+        };
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+        let transcript = result.transcript.unwrap();
+
+        let rows = distribution_rows(&transcript, &candidates, None);
+        // Quota = floor(11 / 3) + 1 = 4. Candidate 0 is elected on first preferences, and their
+        // surplus of 2 is transferred at 1/3, on all 6 ballot papers, electing candidate 1.
+        assert_eq!(rows[0].status, "Elected");
+        assert_eq!(rows[0].progressive_total, "6");
+        let surplus = rows.iter().find(|r| r.count == 2 && r.ballot_papers == 6);
+        let surplus = surplus.expect("candidate 1 receives the surplus");
+        assert_eq!(surplus.transfer_value, "0.33333333");
+        assert_eq!(surplus.votes_transferred, "2");
+        assert_eq!(surplus.progressive_total, "5");
+        assert_eq!(surplus.progressive_ballot_papers, 9);
+        assert_eq!(surplus.status, "Elected");
+
+        let mut csv = vec![];
+        write_transcript_csv(&transcript, &candidates, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with(
+            "Count,Candidate,BallotPapers,TransferValue,VotesTransferred,ProgressiveTotal,\
+             ProgressiveBallotPapers,Status\n"
+        ));
+        assert_eq!(csv.lines().count(), rows.len() + 1);
+
+        // Candidate 1's 9 papers are worth 5 votes, so 8 of them would make the quota of 4.
+        let rows = distribution_rows(&transcript, &candidates, Some(&result.quota));
+        let surplus = rows.iter().find(|r| r.count == 2 && r.ballot_papers == 6);
+        assert_eq!(surplus.unwrap().quota_ballot_papers, Some("8".to_string()));
+        let mut csv = vec![];
+        write_transcript_csv(&transcript, &candidates, Some(&result.quota), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with(
+            "Count,Candidate,BallotPapers,TransferValue,VotesTransferred,ProgressiveTotal,\
+             ProgressiveBallotPapers,Status,QuotaBallotPapers\n"
+        ));
+        assert_eq!(csv.lines().count(), rows.len() + 1);
+    }
+
+    #[test]
+    fn narrative() {
+        let ballots = [
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(1, ids(&[1])),
+            Ballot::multi(3, ids(&[2, 1])),
+            Ballot::multi(1, ids(&[3, 2])),
+        ];
+        let mut candidates = test_candidates(4);
+        for c in candidates.values_mut() {
+            c.surname = ["ADAMS", "BROWN", "CHAN", "DAVIS"][c.id.0 as usize].to_string();
+            c.other_names = "Jo".to_string();
+            c.party = "Independent".to_string();
+        }
+        let count = |config: &ElectionConfig| {
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), config).unwrap()
+        };
+        let result = count(&ElectionConfig::builder(2).transcript(true).build());
+
+        let mut text = vec![];
+        write_narrative(&mut text, &result, &candidates).unwrap();
+        // Quota = floor(11 / 3) + 1 = 4. Candidate 0's surplus puts no one over the quota, so
+        // candidate 3 is excluded, and their ballot elects candidate 2.
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "11 formal votes for 2 vacancies, making a quota of 4 votes.\n\
+             \n\
+             Count 1: first preferences. Jo ADAMS (Independent) reached the quota with 6 votes \
+             and was elected.\n\
+             \n\
+             Count 2: surplus of 2 votes of Jo ADAMS (Independent) transferred, as 6 ballot \
+             papers at 0.33333333.\n\
+             \n\
+             Count 3: Jo DAVIS (Independent) excluded, transferring 1 ballot paper at \
+             1.00000000. Jo CHAN (Independent) reached the quota with 4 votes and was elected.\n\
+             \n\
+             Elected, in order: Jo ADAMS (Independent), Jo CHAN (Independent).\n"
+        );
+
+        let result = count(&ElectionConfig::new(2));
+        assert!(write_narrative(&mut vec![], &result, &candidates).is_err());
+    }
+
+    #[test]
+    fn official_distribution() {
+        let ballots = [
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &config,
+        )
+        .unwrap();
+        let transcript = result.transcript.unwrap();
+
+        // Candidate 0 is elected at the first count, and has a quota from then on.
+        let count = |votes: &[(u16, u64)]| -> BTreeMap<CandidateId, u64> {
+            votes.iter().map(|&(id, v)| (CandidateId(id), v)).collect()
+        };
+        let mut official: OfficialDistribution = vec![
+            (1, count(&[(0, 6), (1, 3), (2, 2)])),
+            (2, count(&[(0, 4), (1, 5), (2, 2)])),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(first_count_divergence(&official, &transcript), None);
+
+        official.insert(3, count(&[(0, 4), (1, 4), (2, 2)]));
+        assert_eq!(
+            first_count_divergence(&official, &transcript),
+            Some(CountDivergence::MissingCount { count: 3 })
+        );
+        official.remove(&3);
+
+        official.get_mut(&2).unwrap().insert(CandidateId(1), 4);
+        assert_eq!(
+            first_count_divergence(&official, &transcript),
+            Some(CountDivergence::Votes {
+                count: 2,
+                candidate: CandidateId(1),
+                official: Some(4),
+                computed: Int::from(5),
+            })
+        );
+        official.get_mut(&2).unwrap().remove(&CandidateId(1));
+        assert_eq!(
+            first_count_divergence(&official, &transcript),
+            Some(CountDivergence::Votes {
+                count: 2,
+                candidate: CandidateId(1),
+                official: None,
+                computed: Int::from(5),
+            })
+        );
+
+        official.remove(&2);
+        assert_eq!(
+            first_count_divergence(&official, &transcript),
+            Some(CountDivergence::ExtraCount { count: 2 })
+        );
+    }
+
+    #[test]
+    fn long_format() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let candidates = test_candidates(3);
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+        let transcript = result.transcript.unwrap();
+
+        let rows = long_rows(&transcript, &candidates);
+        assert_eq!(rows.len(), 3 * transcript.counts.len());
+        // Candidate 0 is elected on first preferences, and has no papers once their surplus is
+        // transferred to candidate 1 at count 2.
+        assert_eq!(rows[0].status, "Elected");
+        assert_eq!(rows[0].votes, Some("6".to_string()));
+        let surplus: Vec<_> = rows.iter().filter(|r| r.count == 2).collect();
+        assert_eq!(surplus[0].action, "Surplus");
+        assert_eq!(surplus[0].action_candidates, "0");
+        assert_eq!(surplus[0].votes, None);
+        assert_eq!(surplus[0].status, "Elected");
+        assert_eq!(surplus[1].votes_received, Some("2".to_string()));
+        assert_eq!(surplus[1].ballot_papers, Some(9));
+        assert_eq!(surplus[1].status, "Elected");
+        assert_eq!(surplus[2].status, "Continuing");
+
+        let mut csv = vec![];
+        write_transcript_long_csv(&transcript, &candidates, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                "Count,Action,ActionCandidates,CandidateId,Candidate,Ticket,Votes,VotesReceived,\
+                 BallotPapers,BallotPapersReceived,Status"
+            )
+        );
+        assert_eq!(lines.count(), rows.len());
+    }
+
+    #[test]
+    fn group_flows() {
+        // Quota = 10. Candidate 3 is excluded, and their papers flow to 1 and 2.
+        let ballots = vec![
+            Ballot::multi(10, ids(&[0])),
+            Ballot::multi(6, ids(&[1])),
+            Ballot::multi(7, ids(&[2])),
+            Ballot::multi(2, ids(&[3, 1])),
+            Ballot::multi(3, ids(&[3, 2])),
+        ];
+        let mut candidates = test_candidates(4);
+        for (id, group) in [(0, "A"), (1, "A"), (2, "B"), (3, "C")].iter() {
+            candidates.get_mut(&CandidateId(*id)).unwrap().group_name = group.to_string();
+        }
+        let config = ElectionConfig {
+            transcript: true,
+            ..ElectionConfig::new(2)
+        };
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+        let transcript = result.transcript.unwrap();
+
+        let flows = preference_flows(&transcript, &candidates);
+        assert_eq!(flows["C"]["A"], 2);
+        assert_eq!(flows["C"]["B"], 3);
+
+        let mut csv = vec![];
+        write_flows_csv(&transcript, &candidates, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("From,To,BallotPapers\n"));
+        assert!(csv.contains("C,A,2\n"));
+    }
+
+    #[test]
+    fn first_preference_discrepancies() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let config = ElectionConfig::new(2);
+        let result = decide_election(
+            &test_candidates(4),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+        )
+        .unwrap();
+        let computed = result.stats.first_preferences();
+
+        let mut summary: BTreeMap<_, _> = ids(&[0, 1, 2]).into_iter().zip(vec![6, 3, 2]).collect();
+        assert_eq!(validate_first_preferences(&summary, computed), vec![]);
+
+        summary.insert(CandidateId(1), 4);
+        summary.insert(CandidateId(3), 1);
+        assert_eq!(
+            validate_first_preferences(&summary, computed),
+            vec![
+                FirstPreferenceDiscrepancy {
+                    candidate: CandidateId(1),
+                    official: 4,
+                    computed: 3,
+                },
+                FirstPreferenceDiscrepancy {
+                    candidate: CandidateId(3),
+                    official: 1,
+                    computed: 0,
+                },
+            ]
+        );
+
+        // 12 formal ballots against an official 13.
+        assert_eq!(formal_total_divergence(&summary, 12, 0.1), None);
+        assert_eq!(
+            formal_total_divergence(&summary, 12, 0.01),
+            Some(1.0 / 13.0)
+        );
+        assert_eq!(formal_total_divergence(&summary, 13, 0.0), None);
+        assert_eq!(
+            formal_total_divergence(&BTreeMap::new(), 1, 0.5),
+            Some(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn markdown_table() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let mut candidates = test_candidates(3);
+        {
+            let c = candidates.get_mut(&CandidateId(0)).unwrap();
+            c.other_names = "Al".to_string();
+            c.party = "Centre".to_string();
+        }
+        {
+            let c = candidates.get_mut(&CandidateId(1)).unwrap();
+            c.other_names = "Jo".to_string();
+            c.party = "Left | Right".to_string();
+            c.group_name = "B".to_string();
+        }
+        let config = ElectionConfig::new(2);
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+
+        let mut md = vec![];
+        write_markdown(&mut md, &result).unwrap();
+        let md = String::from_utf8(md).unwrap();
+        assert_eq!(
+            md,
+            "| Order | Name | Party | Group | Elected at count | Elected on |\n\
+             | ---: | --- | --- | --- | ---: | --- |\n\
+             | 1 | Al C0 | Centre |  | 1 | first preferences |\n\
+             | 2 | Jo C1 | Left \\| Right | B | 2 | surplus of Al C0 (Centre) |\n\
+             \n\
+             Quota: 4 votes. Counts: 2 (1 surplus transfers, 0 exclusions). Exhausted votes: 0. \
+             Ties broken at random: 0.\n"
+        );
+    }
+}
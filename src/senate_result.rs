@@ -1,29 +1,784 @@
 use candidate::*;
-use stats::Stats;
-use util::Int;
+use exhausted_votes::{exhaustion_curve, ExhaustionPoint};
+use group::UNGROUPED;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use stats::{BallotDestination, Stats};
+use std::fmt;
+use transcript::{CountAction, Tally, Transcript};
+use util::{as_pairs, as_string, BTreeMap, Frac, Int};
 
-#[derive(Debug, Default)]
+/// The stage of the count at which a tie occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieKind {
+    /// Candidates tied for the fewest votes, when choosing a candidate to exclude.
+    Exclusion,
+    /// Candidates with a quota tied on votes, when choosing the order they're elected in.
+    ElectionOrder,
+    /// Senators elected at the same count tied on votes, either side of the split between long
+    /// and short terms, so that the order they were elected in decides who serves six years.
+    ///
+    /// These are found by `term::term_allocation_tie` after the count rather than recorded with
+    /// the other ties, and aren't drawn unless the election order tie was.
+    TermAllocation,
+}
+
+impl fmt::Display for TieKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TieKind::Exclusion => write!(f, "exclusion"),
+            TieKind::ElectionOrder => write!(f, "election order"),
+            TieKind::TermAllocation => write!(f, "election order for term allocation"),
+        }
+    }
+}
+
+/// A tie that had to be broken at random.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieEvent {
+    /// The count at which the tie occurred.
+    pub count: usize,
+    /// The tied candidates, in ascending order of ID.
+    pub candidates: Vec<CandidateId>,
+    pub kind: TieKind,
+    /// Whether the tie was broken by drawing lots with the tie break seed, rather than left
+    /// unresolved by the tie break policy.
+    pub drawn: bool,
+}
+
+/// How a senator came to be elected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ElectedBy {
+    /// Reached a quota.
+    Quota,
+    /// Elected without a quota, as one of the candidates left standing once the number of
+    /// continuing candidates equalled the number of vacancies.
+    Remaining,
+}
+
+/// The transfer that first put a senator over the quota, or their first preferences.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElectedOn {
+    pub action: CountAction,
+    /// Names of the candidates whose ballots were transferred, told apart as in
+    /// `Senate::senator_names`. This is empty for first preferences.
+    pub from: Vec<String>,
+}
+
+/// When and how a senator was elected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Election {
+    /// Position in the order of election, starting from 1.
+    pub elected_order: usize,
+    /// The count at which the senator was elected.
+    pub elected_at_count: usize,
+    pub elected_by: ElectedBy,
+    /// The senator's votes when elected, as a percentage of the quota at the time.
+    ///
+    /// This is at least 100 for a senator elected with a quota, and below 100 for one elected as
+    /// one of the last candidates standing.
+    pub quota_percentage_at_election: f64,
+    /// Ballot papers the senator held when elected.
+    pub papers: u64,
+    /// The count action at which the senator reached a quota, or `None` for a senator elected as
+    /// one of the last candidates standing.
+    pub elected_on: Option<ElectedOn>,
+}
+
+/// How close the contest for the final vacancy was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalMargin {
+    /// The count at which the final vacancy was decided.
+    ///
+    /// This is the count at which the last senator reached a quota, or if they were elected as
+    /// one of the last candidates standing, the count at which the runner-up was excluded.
+    pub count: usize,
+    /// The senator who filled the final vacancy.
+    pub elected: CandidateId,
+    /// The continuing candidate with the most votes at the deciding count.
+    pub runner_up: CandidateId,
+    /// Votes of the elected senator minus the votes of the runner-up, at the deciding count.
+    pub margin: Int,
+}
+
+/// Whether a candidate was elected, excluded, or still continuing when the count ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    Elected,
+    Excluded,
+    /// Continuing when every vacancy was filled.
+    Continuing,
+}
+
+/// A candidate's standing at the end of the count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CandidateStanding {
+    pub id: CandidateId,
+    pub status: Status,
+    /// Votes when elected or excluded, or at the end of the count if still continuing.
+    #[serde(with = "as_string")]
+    pub votes: Int,
+    /// Ballot papers held at the same time as `votes`.
+    ///
+    /// Papers transferred at a reduced value count in full here, so a candidate can hold many
+    /// papers worth few votes.
+    pub papers: u64,
+    /// The count at which the candidate was elected or excluded, or the last count if still
+    /// continuing. Disqualified candidates are excluded before the first count, at count 0.
+    pub count: usize,
+}
+
+/// Tallies at the first count, of first preferences, before any ballots are transferred.
+///
+/// The formal ballots are split between the candidates and any ballots that exhausted when the
+/// disqualified candidates were excluded, so percentages of the formal ballots add up to 100.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirstPreferenceCount {
+    /// Votes and ballot papers of each candidate in the count, leaving out any disqualified.
+    #[serde(with = "as_pairs")]
+    pub candidates: BTreeMap<CandidateId, Tally>,
+    /// Votes and ballot papers of each group's candidates, by group name.
+    ///
+    /// Ballots reach the count as lists of candidates, so a group's votes above the line are
+    /// included with those below the line for its first candidate. Each ungrouped candidate
+    /// makes a group of their own, named by the candidate as displayed (see `distinct_name`).
+    pub groups: BTreeMap<String, Tally>,
+    /// Votes and ballot papers of each party's candidates, by party name (see `party_name`), for
+    /// parties that stand in more than one group or with ungrouped candidates.
+    #[serde(default)]
+    pub parties: BTreeMap<String, Tally>,
+    pub formal_ballots: u64,
+    pub informal_ballots: u64,
+    /// Ballots with no preference for a candidate in the count after the disqualified candidates.
+    pub exhausted_ballots: u64,
+}
+
+impl FirstPreferenceCount {
+    /// Collect the first count from the tallies of the candidates in it.
+    pub fn new(
+        tallies: BTreeMap<CandidateId, Tally>,
+        candidates: &CandidateMap,
+        stats: &Stats,
+    ) -> Self {
+        let mut groups: BTreeMap<String, Tally> = BTreeMap::new();
+        let mut parties: BTreeMap<String, Tally> = BTreeMap::new();
+        for (id, tally) in &tallies {
+            let group_name = &candidates[id].group_name;
+            let group_name = if group_name == UNGROUPED {
+                distinct_name(candidates, *id)
+            } else {
+                group_name.clone()
+            };
+            let group = groups.entry(group_name).or_insert_with(|| Tally {
+                votes: Int::from(0),
+                papers: 0,
+            });
+            group.votes += &tally.votes;
+            group.papers += tally.papers;
+
+            let party = parties
+                .entry(party_name(&candidates[id]).to_string())
+                .or_insert_with(|| Tally {
+                    votes: Int::from(0),
+                    papers: 0,
+                });
+            party.votes += &tally.votes;
+            party.papers += tally.papers;
+        }
+        FirstPreferenceCount {
+            candidates: tallies,
+            groups,
+            parties,
+            formal_ballots: stats.num_valid_votes(),
+            informal_ballots: stats.num_invalid_votes(),
+            exhausted_ballots: stats.exhausted_votes.get(&0).map_or(0, |&(n, _)| n as u64),
+        }
+    }
+
+    /// A tally as a percentage of the formal ballots.
+    pub fn percentage(&self, tally: &Tally) -> f64 {
+        if self.formal_ballots == 0 {
+            0.0
+        } else {
+            100.0 * f64::from(&tally.votes) / self.formal_ballots as f64
+        }
+    }
+
+    /// Each group's share of the formal ballots as a percentage, the primary vote reported on
+    /// election night, from the largest share to the smallest.
+    ///
+    /// The shares add up to 100 along with that of the ballots exhausted before the first count.
+    pub fn primary_votes(&self) -> Vec<(&str, f64)> {
+        let mut shares: Vec<_> = self
+            .groups
+            .iter()
+            .map(|(name, tally)| (&name[..], tally))
+            .collect();
+        shares.sort_by(|(n1, t1), (n2, t2)| t2.votes.cmp(&t1.votes).then(n1.cmp(n2)));
+        shares
+            .into_iter()
+            .map(|(name, tally)| (name, self.percentage(tally)))
+            .collect()
+    }
+}
+
+/// Result of a count that doesn't guess the winner of a tie.
+#[derive(Debug)]
+pub enum ElectionOutcome {
+    /// Every tie was resolved by the tie break policy, or broken at random with the configured
+    /// seed.
+    Decided(Box<Senate>),
+    /// The tie break policy couldn't resolve a tie, for example because countback couldn't and
+    /// there was no tie break seed to draw lots with.
+    Undecided {
+        /// The vacancy being contested when the tie occurred, counting from 1.
+        contested_seat: usize,
+        /// The tied candidates, in ascending order of ID.
+        candidates: Vec<CandidateId>,
+        /// The count at which the tie occurred.
+        count: usize,
+    },
+}
+
+impl ElectionOutcome {
+    /// The result, if the election was decided.
+    pub fn decided(self) -> Option<Senate> {
+        match self {
+            ElectionOutcome::Decided(senate) => Some(*senate),
+            ElectionOutcome::Undecided { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Senate {
-    /// List of senators and the vote tally they were elected on.
+    /// Number of vacancies to be filled.
+    pub vacancies: usize,
+    /// List of senators and the vote tally they were elected on, in the order they were elected.
+    ///
+    /// Senators elected at the same count are ordered by their votes, highest first. Equal votes
+    /// are ordered by countback and then by lot if they reached a quota, or by ID if they were
+    /// the last candidates standing, so the order is the same every time the count is run with
+    /// the same tie break seed.
     pub senators: Vec<(Candidate, Int)>,
-    pub tied: bool,
+    /// Name of each senator for people to read, in the same order as `senators`, told apart from
+    /// any other candidate of the same name (see `distinct_name`).
+    pub senator_names: Vec<String>,
+    /// When and how each senator was elected, in the same order as `senators`.
+    pub elections: Vec<Election>,
+    /// Ties that the tie break policy broke by drawing lots or couldn't resolve. Lots are drawn
+    /// with the tie break seed, and the count carries on past an unresolved tie by drawing lots
+    /// with a fixed seed of its own.
+    pub ties: Vec<TieEvent>,
+    /// Number of votes required for a candidate to be elected.
+    pub quota: Int,
+    /// Seed used to break ties that countback couldn't resolve, so the count can be replayed.
+    pub tie_break_seed: Option<usize>,
     pub stats: Stats,
+    /// Tallies at the first count, which are kept whether or not there's a transcript.
+    pub first_preferences: FirstPreferenceCount,
+    /// Record of every count, if requested in the configuration.
+    pub transcript: Option<Transcript>,
+    /// Margin for the final vacancy, if any candidate was left to contest it.
+    pub final_margin: Option<FinalMargin>,
+    /// Standing of every candidate: the senators in the order they were elected, then any
+    /// continuing candidates from most to fewest votes, then excluded candidates from last to
+    /// first excluded.
+    pub final_standings: Vec<CandidateStanding>,
+    /// Number of counts at which an elected candidate's surplus was transferred.
+    pub surplus_transfers: usize,
+    /// Whether the count stopped at the configuration's `max_counts`, before every vacancy was
+    /// filled.
+    pub count_limit_reached: bool,
+}
+
+/// Aggregate numbers describing a count, for reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ElectionStats {
+    pub formal_ballots: u64,
+    pub informal_ballots: u64,
+    /// Rows skipped because they couldn't be read, which is only possible when skipping malformed
+    /// rows.
+    pub malformed_rows: u64,
+    pub quota: u64,
+    /// Number of counts, including the first count of first preferences.
+    pub counts: usize,
+    /// Number of candidates excluded during the count, not including any disqualified before it.
+    pub exclusions: usize,
+    /// Number of counts at which a surplus was transferred.
+    pub surplus_transfers: usize,
+    /// Ballot papers exhausted by the end of the count.
+    pub exhausted_ballots: usize,
+    /// Votes exhausted by the end of the count, truncated to whole votes.
+    pub exhausted_votes: u64,
+    /// Votes lost to fractions by truncating the votes transferred to whole votes.
+    pub loss_by_fraction: f64,
+}
+
+impl Default for Senate {
+    fn default() -> Self {
+        Senate::new()
+    }
 }
 
 impl Senate {
     pub fn new() -> Senate {
         Senate {
+            vacancies: 0,
             senators: vec![],
-            tied: false,
+            senator_names: vec![],
+            elections: vec![],
+            ties: vec![],
+            quota: Int::from(0),
+            tie_break_seed: None,
             stats: Stats::new(),
+            first_preferences: FirstPreferenceCount::default(),
+            transcript: None,
+            final_margin: None,
+            final_standings: vec![],
+            surplus_transfers: 0,
+            count_limit_reached: false,
+        }
+    }
+
+    /// Add a senator who reached a quota at `count` on `action`, or who was one of the last
+    /// candidates standing if there's no action.
+    pub fn add_senator(
+        &mut self,
+        id: CandidateId,
+        tally: Int,
+        papers: u64,
+        count: usize,
+        action: Option<&CountAction>,
+        candidates: &CandidateMap,
+    ) {
+        let quota_percentage_at_election = if self.quota == Int::from(0) {
+            0.0
+        } else {
+            100.0 * f64::from(&Frac::ratio(&tally, &self.quota))
+        };
+        let elected_on = action.map(|action| {
+            let from = match *action {
+                CountAction::FirstPreferences => vec![],
+                CountAction::Surplus(id) => vec![id],
+                CountAction::Exclusion(ref ids) => ids.clone(),
+            };
+            ElectedOn {
+                action: action.clone(),
+                from: from
+                    .into_iter()
+                    .map(|id| distinct_name(candidates, id))
+                    .collect(),
+            }
+        });
+        let election = Election {
+            elected_order: self.senators.len() + 1,
+            elected_at_count: count,
+            elected_by: if action.is_some() {
+                ElectedBy::Quota
+            } else {
+                ElectedBy::Remaining
+            },
+            quota_percentage_at_election,
+            papers,
+            elected_on,
+        };
+        self.restore_senator(id, tally, election, candidates);
+    }
+
+    /// Add a senator elected before the count was checkpointed, as they were elected.
+    pub fn restore_senator(
+        &mut self,
+        id: CandidateId,
+        tally: Int,
+        election: Election,
+        candidates: &CandidateMap,
+    ) {
+        self.senators.push((candidates[&id].clone(), tally));
+        self.senator_names.push(distinct_name(candidates, id));
+        self.elections.push(election);
+    }
+
+    /// Whether any tie had to be broken at random.
+    pub fn tied(&self) -> bool {
+        !self.ties.is_empty()
+    }
+
+    /// The result as an `ElectionOutcome`, which is undecided if a tie was left unresolved by the
+    /// tie break policy.
+    ///
+    /// The first such tie decides the seat reported as contested: the vacancy after those filled
+    /// before the count it occurred at.
+    pub fn into_outcome(self) -> ElectionOutcome {
+        match self.ties.iter().find(|tie| !tie.drawn) {
+            Some(tie) => ElectionOutcome::Undecided {
+                contested_seat: self
+                    .elections
+                    .iter()
+                    .filter(|e| e.elected_at_count < tie.count)
+                    .count()
+                    + 1,
+                candidates: tie.candidates.clone(),
+                count: tie.count,
+            },
+            None => ElectionOutcome::Decided(Box::new(self)),
         }
     }
 
-    pub fn add_senator(&mut self, id: CandidateId, tally: Int, candidates: &CandidateMap) {
-        self.senators.push((candidates[&id].clone(), tally))
+    pub fn record_ties(&mut self, count: usize, ties: Vec<(TieKind, Vec<CandidateId>, bool)>) {
+        self.ties
+            .extend(ties.into_iter().map(|(kind, candidates, drawn)| TieEvent {
+                count,
+                candidates,
+                kind,
+                drawn,
+            }));
     }
 
     pub fn num_elected(&self) -> usize {
         self.senators.len()
     }
+
+    /// Number of the last count, at which the final candidates were elected or excluded.
+    pub fn num_counts(&self) -> usize {
+        self.final_standings
+            .iter()
+            .map(|s| s.count)
+            .max()
+            .unwrap_or(0)
+            .max(1)
+    }
+
+    /// Cumulative exhaustion at the end of every count.
+    pub fn exhaustion_curve(&self) -> Vec<ExhaustionPoint> {
+        exhaustion_curve(
+            &self.stats.exhausted_votes,
+            self.stats.num_valid_votes(),
+            self.num_counts(),
+        )
+    }
+
+    /// Number of vacancies left unfilled, because there weren't enough candidates.
+    pub fn unfilled_vacancies(&self) -> usize {
+        self.vacancies.saturating_sub(self.num_elected())
+    }
+
+    /// Number of senators elected for each party (see `party_name`).
+    pub fn seats_by_party(&self) -> BTreeMap<String, usize> {
+        let mut seats = BTreeMap::new();
+        for (c, _) in &self.senators {
+            *seats.entry(party_name(c).to_string()).or_insert(0) += 1;
+        }
+        seats
+    }
+
+    /// Whether every vacancy was filled.
+    pub fn is_complete(&self) -> bool {
+        self.unfilled_vacancies() == 0
+    }
+
+    /// Aggregate numbers describing the count.
+    pub fn summary(&self) -> ElectionStats {
+        ElectionStats {
+            formal_ballots: self.stats.num_valid_votes(),
+            informal_ballots: self.stats.num_invalid_votes(),
+            malformed_rows: self.stats.num_malformed_rows(),
+            quota: int_to_u64(&self.quota),
+            counts: self.num_counts(),
+            exclusions: self
+                .final_standings
+                .iter()
+                .filter(|s| s.status == Status::Excluded && s.count > 0)
+                .count(),
+            surplus_transfers: self.surplus_transfers,
+            exhausted_ballots: self.stats.num_exhausted_ballots(),
+            exhausted_votes: int_to_u64(&self.stats.exhausted_vote_value().floor()),
+            loss_by_fraction: f64::from(&self.stats.loss_by_fraction()),
+        }
+    }
+}
+
+impl fmt::Display for ElectedOn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            CountAction::FirstPreferences => write!(f, "first preferences"),
+            CountAction::Surplus(_) => write!(f, "surplus of {}", self.from.join(", ")),
+            CountAction::Exclusion(_) => write!(f, "exclusion of {}", self.from.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for ElectedBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ElectedBy::Quota => write!(f, "quota"),
+            ElectedBy::Remaining => write!(f, "remaining"),
+        }
+    }
+}
+
+/// Summary of the result for people to read: the quota, the senators in the order they were
+/// elected, and any ties or unfilled vacancies.
+impl fmt::Display for Senate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vacancies: {}", self.vacancies)?;
+        writeln!(f, "Total formal votes: {}", self.stats.num_valid_votes())?;
+        writeln!(f, "Quota: {}", self.quota)?;
+        writeln!(f, "=== Elected ===")?;
+        let senators = self.senators.iter().zip(&self.senator_names);
+        for (((_, votes), name), e) in senators.zip(&self.elections) {
+            writeln!(
+                f,
+                "{}. {} [{} votes ({:.1}% of quota), count {}, {}]",
+                e.elected_order,
+                name,
+                votes,
+                e.quota_percentage_at_election,
+                e.elected_at_count,
+                e.elected_by
+            )?;
+        }
+
+        if self.count_limit_reached {
+            writeln!(
+                f,
+                "Stopped at the count limit, after count {}",
+                self.num_counts()
+            )?;
+        } else if !self.is_complete() {
+            writeln!(
+                f,
+                "Not enough candidates: {} vacancies unfilled",
+                self.unfilled_vacancies()
+            )?;
+        }
+        if self.tied() {
+            writeln!(f, "Tie for the last place")?;
+        }
+        if let Some(ref m) = self.final_margin {
+            writeln!(
+                f,
+                "Final seat margin: {} votes over candidate {} at count {}",
+                m.margin, m.runner_up, m.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert a vote tally to a `u64`, which is plenty for any real election.
+fn int_to_u64(x: &Int) -> u64 {
+    Option::<u64>::from(x).expect("vote tally does not fit in a u64")
+}
+
+/// Senator as serialized in the result.
+#[derive(Serialize)]
+struct ElectedSenator<'a> {
+    id: CandidateId,
+    surname: &'a str,
+    other_names: &'a str,
+    party: &'a str,
+    votes: u64,
+    #[serde(flatten)]
+    election: &'a Election,
+}
+
+/// Margin for the final vacancy as serialized in the result.
+#[derive(Serialize)]
+struct Margin {
+    count: usize,
+    elected: CandidateId,
+    runner_up: CandidateId,
+    votes: u64,
+}
+
+/// Standing of a candidate as serialized in the result.
+#[derive(Serialize)]
+struct Standing {
+    id: CandidateId,
+    status: Status,
+    votes: u64,
+    count: usize,
+}
+
+/// Totals of exhausted ballots as serialized in the result.
+#[derive(Serialize)]
+struct Exhausted {
+    ballots: usize,
+    votes: u64,
+    fraction: f64,
+    by_count: Vec<ExhaustedByCount>,
+}
+
+/// Exhausted ballots by the end of a count, as serialized in the result.
+#[derive(Serialize)]
+struct ExhaustedByCount {
+    count: usize,
+    ballots: usize,
+    votes: u64,
+    fraction: f64,
+}
+
+/// Destination of the ballots with a sequence of preferences, as serialized in the result.
+#[derive(Serialize)]
+struct BallotTrace<'a> {
+    preferences: &'a [CandidateId],
+    #[serde(flatten)]
+    destination: &'a BallotDestination,
+}
+
+/// Serialize the outcome of the count, for consumption by other programs.
+///
+/// Tallies are truncated to whole votes, and the transcript is omitted.
+impl Serialize for Senate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let senators: Vec<_> = self
+            .senators
+            .iter()
+            .zip(&self.elections)
+            .map(|((c, votes), election)| ElectedSenator {
+                id: c.id,
+                surname: &c.surname,
+                other_names: &c.other_names,
+                party: &c.party,
+                votes: int_to_u64(votes),
+                election,
+            })
+            .collect();
+        let exhausted = Exhausted {
+            ballots: self.stats.num_exhausted_ballots(),
+            votes: int_to_u64(&self.stats.exhausted_vote_value().floor()),
+            fraction: self.stats.exhaustion_rate(),
+            by_count: self
+                .exhaustion_curve()
+                .into_iter()
+                .map(|p| ExhaustedByCount {
+                    count: p.count,
+                    ballots: p.ballots,
+                    votes: int_to_u64(&p.votes.floor()),
+                    fraction: p.fraction,
+                })
+                .collect(),
+        };
+
+        let final_margin = self.final_margin.as_ref().map(|m| Margin {
+            count: m.count,
+            elected: m.elected,
+            runner_up: m.runner_up,
+            votes: int_to_u64(&m.margin),
+        });
+
+        let final_standings: Vec<_> = self
+            .final_standings
+            .iter()
+            .map(|s| Standing {
+                id: s.id,
+                status: s.status,
+                votes: int_to_u64(&s.votes),
+                count: s.count,
+            })
+            .collect();
+
+        let ballot_destinations: Option<Vec<_>> = self.stats.ballot_destinations().map(|d| {
+            d.iter()
+                .map(|(prefs, destination)| BallotTrace {
+                    preferences: prefs,
+                    destination,
+                })
+                .collect()
+        });
+
+        let mut state = serializer.serialize_struct("Senate", 19)?;
+        state.serialize_field("vacancies", &self.vacancies)?;
+        state.serialize_field("senators", &senators)?;
+        state.serialize_field("quota", &int_to_u64(&self.quota))?;
+        state.serialize_field("formal_ballots", &self.stats.num_valid_votes())?;
+        state.serialize_field("informal_ballots", &self.stats.num_invalid_votes())?;
+        state.serialize_field("donkey_votes", &self.stats.num_donkey_votes())?;
+        state.serialize_field("section_formality", &self.stats.section_formality())?;
+        state.serialize_field("preference_lengths", &self.stats.preference_lengths())?;
+        state.serialize_field(
+            "group_first_preferences",
+            self.stats.group_first_preferences(),
+        )?;
+        state.serialize_field("first_preferences", &self.first_preferences)?;
+        state.serialize_field("party_seats", &self.seats_by_party())?;
+        state.serialize_field("exhausted", &exhausted)?;
+        state.serialize_field("ties", &self.ties)?;
+        state.serialize_field("count_limit_reached", &self.count_limit_reached)?;
+        state.serialize_field("tie_break_seed", &self.tie_break_seed)?;
+        state.serialize_field("final_margin", &final_margin)?;
+        state.serialize_field("final_standings", &final_standings)?;
+        state.serialize_field("summary", &self.summary())?;
+        if let Some(ref ballot_destinations) = ballot_destinations {
+            state.serialize_field("ballot_destinations", ballot_destinations)?;
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    fn test_senate() -> Senate {
+        let candidates = test_candidates(3);
+        let mut senate = Senate::new();
+        senate.quota = Int::from(4);
+        senate.add_senator(
+            CandidateId(2),
+            Int::from(5),
+            5,
+            2,
+            Some(&CountAction::Exclusion(ids(&[1]))),
+            &candidates,
+        );
+        senate.add_senator(CandidateId(0), Int::from(3), 4, 3, None, &candidates);
+        senate.record_ties(2, vec![(TieKind::Exclusion, ids(&[0, 1]), true)]);
+        senate
+    }
+
+    #[test]
+    fn serialize_json() {
+        let json = serde_json::to_value(test_senate()).unwrap();
+        assert_eq!(json["quota"], 4);
+        assert_eq!(json["senators"][0]["id"], 2);
+        assert_eq!(json["senators"][0]["votes"], 5);
+        assert_eq!(
+            json["senators"][0]["elected_on"]["action"]["Exclusion"][0],
+            1
+        );
+        let excluded = test_candidates(3)[&CandidateId(1)].to_string();
+        assert_eq!(json["senators"][0]["elected_on"]["from"][0], excluded);
+        assert!(json["senators"][1]["elected_on"].is_null());
+        assert_eq!(json["senators"][1]["elected_at_count"], 3);
+        assert_eq!(json["senators"][1]["elected_order"], 2);
+        assert_eq!(json["senators"][1]["elected_by"], "Remaining");
+        assert_eq!(json["senators"][1]["quota_percentage_at_election"], 75.0);
+        assert_eq!(json["ties"][0]["kind"], "Exclusion");
+        assert_eq!(json["ties"][0]["candidates"][1], 1);
+        assert_eq!(json["exhausted"]["ballots"], 0);
+        assert_eq!(json["exhausted"]["fraction"], 0.0);
+        assert!(json["final_margin"].is_null());
+        assert_eq!(json["summary"]["quota"], 4);
+        assert_eq!(json["summary"]["counts"], 1);
+        assert_eq!(json["party_seats"]["Independent"], 2);
+    }
+
+    #[test]
+    fn display() {
+        let mut senate = test_senate();
+        senate.vacancies = 3;
+        assert_eq!(
+            senate.to_string(),
+            "Vacancies: 3\n\
+             Total formal votes: 0\n\
+             Quota: 4\n\
+             === Elected ===\n\
+             1.  C2 () [5 votes (125.0% of quota), count 2, quota]\n\
+             2.  C0 () [3 votes (75.0% of quota), count 3, remaining]\n\
+             Not enough candidates: 1 vacancies unfilled\n\
+             Tie for the last place\n"
+        );
+    }
 }
@@ -1,17 +1,209 @@
 use ballot::*;
 use ballot_parse::*;
+use candidate::CandidateId;
+use csv;
+use error::Error;
 use exhausted_votes::*;
 use util::*;
 
-pub type BallotErrorMap = HashMap<InvalidBallotErr, u32>;
+pub type BallotErrorMap = HashMap<InformalReason, u64>;
+pub type MalformedRowMap = HashMap<MalformedRow, u64>;
+pub type BallotDestinations = BTreeMap<Vec<CandidateId>, BallotDestination>;
 
-#[derive(Debug, Default)]
+/// Where the ballots with a given sequence of preferences ended up at the end of the count.
+///
+/// Ballots that were passed on in a surplus left the rest of their value with the candidate
+/// they helped elect, so only their last stop is recorded here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum BallotDestination {
+    /// Held by a candidate at the end of the count, at the given value per ballot paper.
+    Candidate {
+        candidate: CandidateId,
+        /// Whether the candidate was elected, meaning the ballots helped elect them.
+        elected: bool,
+        #[serde(with = "as_string")]
+        value: Frac,
+    },
+    /// Exhausted at the given count, at the given value per ballot paper.
+    Exhausted {
+        count: usize,
+        #[serde(with = "as_string")]
+        value: Frac,
+    },
+}
+
+/// Numbers of formal ballots counted from each section of the ballot paper.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionCounts {
+    pub above_the_line: u64,
+    pub below_the_line: u64,
+}
+
+impl SectionCounts {
+    pub fn record(&mut self, section: Section) {
+        match section {
+            Section::AboveTheLine => self.above_the_line += 1,
+            Section::BelowTheLine => self.below_the_line += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.above_the_line + self.below_the_line
+    }
+
+    /// Percentage of the ballots that were counted from above the line.
+    pub fn above_the_line_percentage(&self) -> f64 {
+        if self.total() > 0 {
+            100.0 * self.above_the_line as f64 / self.total() as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Numbers of formal ballots by the sections of the ballot paper that were formal on them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionFormality {
+    pub above_the_line_only: u64,
+    pub below_the_line_only: u64,
+    /// Formal in both sections, and counted from the one that takes precedence.
+    pub both: u64,
+}
+
+impl SectionFormality {
+    /// Record a formal ballot counted from `section`.
+    pub fn record(&mut self, section: Section, both_formal: bool) {
+        match (section, both_formal) {
+            (_, true) => self.both += 1,
+            (Section::AboveTheLine, false) => self.above_the_line_only += 1,
+            (Section::BelowTheLine, false) => self.below_the_line_only += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.above_the_line_only + self.below_the_line_only + self.both
+    }
+}
+
+/// Numbers of formal ballots by how many preferences they were counted with in the section they
+/// were counted from, which is the number of boxes numbered up to the first gap or repeated
+/// number.
+///
+/// Above the line the preferences are for groups, and below the line for candidates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreferenceLengths {
+    #[serde(with = "as_pairs")]
+    pub above_the_line: BTreeMap<usize, u64>,
+    #[serde(with = "as_pairs")]
+    pub below_the_line: BTreeMap<usize, u64>,
+}
+
+impl PreferenceLengths {
+    /// Record a formal ballot counted from `section` with `num_preferences` preferences.
+    pub fn record(&mut self, section: Section, num_preferences: usize) {
+        let lengths = match section {
+            Section::AboveTheLine => &mut self.above_the_line,
+            Section::BelowTheLine => &mut self.below_the_line,
+        };
+        *lengths.entry(num_preferences).or_insert(0) += 1;
+    }
+
+    /// Numbers of ballots counted from `section`, by number of preferences.
+    pub fn section(&self, section: Section) -> &BTreeMap<usize, u64> {
+        match section {
+            Section::AboveTheLine => &self.above_the_line,
+            Section::BelowTheLine => &self.below_the_line,
+        }
+    }
+
+    /// Percentage of the ballots counted from `section` that had `num_preferences` preferences.
+    pub fn percentage(&self, section: Section, num_preferences: usize) -> f64 {
+        let lengths = self.section(section);
+        let total: u64 = lengths.values().sum();
+        match lengths.get(&num_preferences) {
+            Some(&n) => 100.0 * n as f64 / total as f64,
+            None => 0.0,
+        }
+    }
+}
+
+/// Kind of problem with a row of input that couldn't be read as a ballot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MalformedRow {
+    /// The row wasn't valid UTF-8.
+    InvalidUtf8,
+    /// The row had a different number of fields to the header.
+    WrongNumberOfFields,
+    /// One of the fields of the row couldn't be read.
+    InvalidField,
+    /// The row had the wrong number of preferences for the groups and candidates.
+    WrongNumberOfPreferences,
+    /// Any other error that doesn't prevent reading the rest of the input.
+    Other,
+}
+
+impl MalformedRow {
+    /// Categorise an error from reading the input.
+    ///
+    /// Returns `None` for IO errors, after which the rest of the input can't be read.
+    pub fn categorise(err: &Error) -> Option<MalformedRow> {
+        let kind = match *err {
+            Error::Io(_) => return None,
+            Error::Csv(ref e) => match *e.kind() {
+                csv::ErrorKind::Io(_) => return None,
+                csv::ErrorKind::Utf8 { .. } => MalformedRow::InvalidUtf8,
+                csv::ErrorKind::UnequalLengths { .. } => MalformedRow::WrongNumberOfFields,
+                csv::ErrorKind::Deserialize { .. } => MalformedRow::InvalidField,
+                _ => MalformedRow::Other,
+            },
+            Error::WrongNumberOfPreferences { .. } => MalformedRow::WrongNumberOfPreferences,
+            Error::Row { ref error, .. } => return MalformedRow::categorise(error),
+            _ => MalformedRow::Other,
+        };
+        Some(kind)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Stats {
-    num_valid_votes: u32,
+    num_valid_votes: u64,
+    /// Number of formal ballots giving each candidate their first preference.
+    #[serde(with = "as_pairs")]
+    first_preferences: BTreeMap<CandidateId, u64>,
+    #[serde(with = "as_pairs")]
     invalid_votes: BallotErrorMap,
+    #[serde(with = "as_pairs")]
+    malformed_rows: MalformedRowMap,
+    /// Number of formal ballots that were donkey votes, if they were looked for.
+    donkey_votes: Option<u64>,
+    /// Number of rows left out by the configuration's row filter, if it had one.
+    filtered_rows: Option<u64>,
+    /// Formal ballots giving their first preference to each group's candidates, by group name
+    /// and the section they were counted from. Only known for ballots parsed from preferences.
+    group_first_preferences: BTreeMap<String, SectionCounts>,
+    /// Formal ballots by which of their sections were formal, if they were parsed from
+    /// preferences.
+    section_formality: Option<SectionFormality>,
+    /// Formal ballots by how many preferences they were counted with, if they were parsed from
+    /// preferences.
+    preference_lengths: Option<PreferenceLengths>,
+    /// Fingerprint of the formal ballots, if requested.
+    ballots_fingerprint: Option<[u8; 32]>,
     /// Map from vote round to number of ballots exhausted during that round (not cumulative) and
     /// their combined value (sum of transfer value).
+    #[serde(with = "as_rows")]
     pub exhausted_votes: ExhaustedVotes,
+    /// Value lost by truncating the votes received by each candidate to whole votes, indexed by
+    /// vote round (the AEC's "loss by fraction"). It's negative in a round where an excluded
+    /// candidate's ballots return more than their whole votes to the count.
+    #[serde(with = "as_strings")]
+    loss_by_fraction: Vec<Frac>,
+    /// Whether to record the destination of every ballot, which takes memory for each distinct
+    /// sequence of preferences.
+    tracking_destinations: bool,
+    #[serde(with = "as_pairs")]
+    ballot_destinations: BallotDestinations,
 }
 
 impl Stats {
@@ -21,42 +213,252 @@ impl Stats {
 
     pub fn record_valid_vote(&mut self, ballot: &Ballot) {
         self.num_valid_votes += ballot.weight();
+        if let Some(&first) = ballot.prefs.first() {
+            *self.first_preferences.entry(first).or_insert(0) += ballot.weight();
+        }
     }
 
-    pub fn record_invalid_vote(&mut self, err: InvalidBallotErr) {
+    pub fn record_invalid_vote(&mut self, err: InformalReason) {
         let err_count = self.invalid_votes.entry(err.erase_detail()).or_insert(0);
         *err_count += 1;
     }
 
-    pub fn record_exhausted_vote(&mut self, round: usize, transfer_value: &Frac) {
+    pub fn record_malformed_row(&mut self, kind: MalformedRow) {
+        *self.malformed_rows.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn record_exhausted_vote(&mut self, round: usize, ballot: &Ballot, transfer_value: &Frac) {
         let &mut (ref mut count, ref mut value) = self
             .exhausted_votes
             .entry(round)
             .or_insert_with(|| (0, frac!(0u64)));
-        *count += 1;
-        *value += transfer_value;
+        *count += ballot.weight() as usize;
+        *value += transfer_value * frac!(ballot.weight());
+        if self.tracking_destinations {
+            let destination = BallotDestination::Exhausted {
+                count: round + 1,
+                value: transfer_value.clone(),
+            };
+            self.ballot_destinations
+                .insert(ballot.prefs.clone(), destination);
+        }
+    }
+
+    /// Start recording the destination of every ballot, for `ballot_destinations`.
+    pub fn track_ballot_destinations(&mut self) {
+        self.tracking_destinations = true;
+    }
+
+    /// Record that a ballot is held by `candidate` at the end of the count, at `value` per paper.
+    ///
+    /// Their election is filled in by `record_elected_destinations` once the count is over.
+    pub fn record_ballot_held(&mut self, ballot: &Ballot, candidate: CandidateId, value: &Frac) {
+        if self.tracking_destinations {
+            let destination = BallotDestination::Candidate {
+                candidate,
+                elected: false,
+                value: value.clone(),
+            };
+            self.ballot_destinations
+                .insert(ballot.prefs.clone(), destination);
+        }
+    }
+
+    /// Mark the ballots held by the candidates who were elected.
+    pub fn record_elected_destinations(&mut self, elected: &[CandidateId]) {
+        for destination in self.ballot_destinations.values_mut() {
+            if let BallotDestination::Candidate {
+                candidate,
+                elected: ref mut is_elected,
+                ..
+            } = *destination
+            {
+                *is_elected = elected.contains(&candidate);
+            }
+        }
+    }
+
+    /// Where the ballots with each distinct sequence of preferences ended up, if recorded.
+    pub fn ballot_destinations(&self) -> Option<&BallotDestinations> {
+        if self.tracking_destinations {
+            Some(&self.ballot_destinations)
+        } else {
+            None
+        }
     }
 
-    pub fn num_total_votes(&self) -> u32 {
+    /// Record a fraction of a vote lost in `round`, or regained if `loss` is negative.
+    pub fn record_loss_by_fraction(&mut self, round: usize, loss: &Frac) {
+        if self.loss_by_fraction.len() <= round {
+            self.loss_by_fraction.resize(round + 1, frac!(0u64));
+        }
+        self.loss_by_fraction[round] += loss;
+    }
+
+    /// Value lost to fractions in `round`.
+    pub fn loss_by_fraction_in(&self, round: usize) -> Frac {
+        self.loss_by_fraction
+            .get(round)
+            .cloned()
+            .unwrap_or_else(|| frac!(0u64))
+    }
+
+    /// Total value lost to fractions over the whole count.
+    pub fn loss_by_fraction(&self) -> Frac {
+        let mut total = frac!(0u64);
+        for value in &self.loss_by_fraction {
+            total += value;
+        }
+        total
+    }
+
+    /// Total number of ballot papers exhausted over the whole count.
+    pub fn num_exhausted_ballots(&self) -> usize {
+        self.exhausted_votes.values().map(|&(count, _)| count).sum()
+    }
+
+    /// Total value of the votes exhausted over the whole count.
+    pub fn exhausted_vote_value(&self) -> Frac {
+        let mut total = frac!(0u64);
+        for (_, value) in self.exhausted_votes.values() {
+            total += value;
+        }
+        total
+    }
+
+    pub fn num_total_votes(&self) -> u64 {
         self.num_valid_votes() + self.num_invalid_votes()
     }
 
-    pub fn num_valid_votes(&self) -> u32 {
+    pub fn num_valid_votes(&self) -> u64 {
         self.num_valid_votes
     }
 
-    pub fn num_invalid_votes(&self) -> u32 {
+    /// Number of formal ballots giving each candidate their first preference, before any
+    /// candidates are excluded.
+    pub fn first_preferences(&self) -> &BTreeMap<CandidateId, u64> {
+        &self.first_preferences
+    }
+
+    pub fn num_invalid_votes(&self) -> u64 {
         self.invalid_votes.values().sum()
     }
+
+    /// Number of informal ballots, by the reason they're informal.
+    pub fn invalid_votes(&self) -> &BallotErrorMap {
+        &self.invalid_votes
+    }
+
+    /// Number of rows skipped because they couldn't be read, by the kind of problem.
+    pub fn malformed_rows(&self) -> &MalformedRowMap {
+        &self.malformed_rows
+    }
+
+    pub fn num_malformed_rows(&self) -> u64 {
+        self.malformed_rows.values().sum()
+    }
+
+    /// Fraction of the formal vote that had exhausted by the end of the count.
+    pub fn exhaustion_rate(&self) -> f64 {
+        vote_fraction(&self.exhausted_vote_value(), self.num_valid_votes())
+    }
+
+    /// Record the number of donkey votes, found while parsing the ballots.
+    pub fn record_donkey_votes(&mut self, count: u64) {
+        self.donkey_votes = Some(count);
+    }
+
+    /// Number of formal ballots numbered straight down the ballot paper, if they were counted.
+    pub fn num_donkey_votes(&self) -> Option<u64> {
+        self.donkey_votes
+    }
+
+    /// Record the number of rows left out by the configuration's row filter.
+    pub fn record_filtered_rows(&mut self, count: u64) {
+        self.filtered_rows = Some(count);
+    }
+
+    /// Number of rows left out by the configuration's row filter, if it had one.
+    pub fn num_filtered_rows(&self) -> Option<u64> {
+        self.filtered_rows
+    }
+
+    /// Record the sections each group's first preferences were counted from, found while parsing
+    /// the ballots.
+    pub fn record_group_first_preferences(&mut self, counts: BTreeMap<String, SectionCounts>) {
+        self.group_first_preferences = counts;
+    }
+
+    /// Formal ballots giving their first preference to each group's candidates, split by the
+    /// section they were counted from, if the ballots were parsed from preferences.
+    pub fn group_first_preferences(&self) -> &BTreeMap<String, SectionCounts> {
+        &self.group_first_preferences
+    }
+
+    /// Record the sections that were formal on the formal ballots, found while parsing them.
+    pub fn record_section_formality(&mut self, counts: SectionFormality) {
+        self.section_formality = Some(counts);
+    }
+
+    /// Numbers of formal ballots that were formal only above the line, only below the line, or
+    /// in both sections, if the ballots were parsed from preferences.
+    pub fn section_formality(&self) -> Option<SectionFormality> {
+        self.section_formality
+    }
+
+    /// Record how many preferences the formal ballots were counted with, found while parsing them.
+    pub fn record_preference_lengths(&mut self, lengths: PreferenceLengths) {
+        self.preference_lengths = Some(lengths);
+    }
+
+    /// Numbers of formal ballots by how many preferences they were counted with in each section,
+    /// if the ballots were parsed from preferences.
+    pub fn preference_lengths(&self) -> Option<&PreferenceLengths> {
+        self.preference_lengths.as_ref()
+    }
+
+    /// Percentage of formal ballots that were donkey votes, if they were counted.
+    pub fn donkey_vote_percentage(&self) -> Option<f64> {
+        let formal = self.num_valid_votes();
+        self.donkey_votes.map(|n| {
+            if formal > 0 {
+                100.0 * n as f64 / formal as f64
+            } else {
+                0.0
+            }
+        })
+    }
+
+    /// Record what was found while reading ballots that were cached before being counted: the
+    /// malformed rows, and everything found while parsing them.
+    ///
+    /// The formal and informal ballots themselves are recorded as they're counted.
+    pub fn record_reading(&mut self, reading: &Stats) {
+        self.malformed_rows = reading.malformed_rows.clone();
+        self.donkey_votes = reading.donkey_votes;
+        self.filtered_rows = reading.filtered_rows;
+        self.group_first_preferences = reading.group_first_preferences.clone();
+        self.section_formality = reading.section_formality;
+        self.preference_lengths = reading.preference_lengths.clone();
+    }
+
+    pub fn record_ballots_fingerprint(&mut self, fingerprint: [u8; 32]) {
+        self.ballots_fingerprint = Some(fingerprint);
+    }
+
+    /// Fingerprint of the formal ballots, as hexadecimal (see `ballots_fingerprint`).
+    pub fn ballots_fingerprint(&self) -> Option<String> {
+        self.ballots_fingerprint
+            .as_ref()
+            .map(|f| f.iter().map(|b| format!("{:02x}", b)).collect())
+    }
 }
 
-impl InvalidBallotErr {
-    pub fn erase_detail(self) -> InvalidBallotErr {
+impl InformalReason {
+    pub fn erase_detail(self) -> InformalReason {
         match self {
-            InvalidMinAbove(_) => InvalidMinAbove(0),
-            InvalidMaxAbove(_) => InvalidMaxAbove(0),
-            InvalidMinBelow(_) => InvalidMinBelow(0),
-            InvalidMaxBelow(_) => InvalidMaxBelow(0),
+            BelowMinimumPreferences(section, _) => BelowMinimumPreferences(section, 0),
+            AboveMaximumPreferences(section, _) => AboveMaximumPreferences(section, 0),
             x => x,
         }
     }
@@ -0,0 +1,435 @@
+//! Allocation of the terms senators serve: long and short terms after a double dissolution, or
+//! the usual terms after a half-Senate election.
+
+use ballot_parse::IOBallot;
+use candidate::*;
+use config::ElectionConfig;
+use error::Error;
+use senate_result::{ElectedBy, Senate, TieEvent, TieKind};
+use std::fmt;
+use util::is_territory;
+use voting::decide_election;
+
+/// Length of the term a senator serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    /// Six year term.
+    Long,
+    /// Three year term.
+    Short,
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Long => write!(f, "long"),
+            Term::Short => write!(f, "short"),
+        }
+    }
+}
+
+/// A senator given different terms by the order-elected and re-count methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermDifference {
+    pub senator: CandidateId,
+    pub order_elected: Term,
+    pub recount: Term,
+}
+
+/// Terms allocated after a double dissolution by both the order-elected and re-count methods, as
+/// from `allocate_terms`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermAllocation {
+    pub order_elected: Vec<(CandidateId, Term)>,
+    pub recount: Vec<(CandidateId, Term)>,
+    /// The senators given different terms by the two methods, as from `compare_terms`.
+    pub differences: Vec<TermDifference>,
+}
+
+impl TermAllocation {
+    /// Senators given long terms by the order-elected method, in the order they were elected.
+    pub fn long_by_order_elected(&self) -> Vec<CandidateId> {
+        long_terms(&self.order_elected)
+    }
+
+    /// Senators given long terms by the re-count method, in the order they were elected.
+    pub fn long_by_recount(&self) -> Vec<CandidateId> {
+        long_terms(&self.recount)
+    }
+
+    /// Whether the two methods give any senator a different term.
+    pub fn methods_differ(&self) -> bool {
+        !self.differences.is_empty()
+    }
+}
+
+fn long_terms(terms: &[(CandidateId, Term)]) -> Vec<CandidateId> {
+    terms
+        .iter()
+        .filter(|&&(_, term)| term == Term::Long)
+        .map(|&(id, _)| id)
+        .collect()
+}
+
+/// Compare the terms allocated by `order_elected_terms` with those from `recount_terms` for the
+/// same senators, returning the senators whose terms differ, in the order they were elected.
+pub fn compare_terms(
+    order_elected: &[(CandidateId, Term)],
+    recount: &[(CandidateId, Term)],
+) -> Vec<TermDifference> {
+    order_elected
+        .iter()
+        .filter_map(|&(senator, order_term)| {
+            let &(_, recount_term) = recount.iter().find(|&&(id, _)| id == senator)?;
+            if recount_term == order_term {
+                None
+            } else {
+                Some(TermDifference {
+                    senator,
+                    order_elected: order_term,
+                    recount: recount_term,
+                })
+            }
+        })
+        .collect()
+}
+
+/// Terms of the senators elected at a half-Senate election, where no allocation is needed.
+///
+/// Senators for a state serve a full six year term, while senators for a territory serve until
+/// the next election of the House of Representatives, like a short term.
+pub fn half_senate_terms(senate: &Senate, state: &str) -> Vec<(CandidateId, Term)> {
+    let term = if is_territory(state) {
+        Term::Short
+    } else {
+        Term::Long
+    };
+    senate.senators.iter().map(|(c, _)| (c.id, term)).collect()
+}
+
+/// Allocate terms by the order-elected method: the first half of the senators elected receive
+/// long terms.
+pub fn order_elected_terms(senate: &Senate) -> Vec<(CandidateId, Term)> {
+    let num_long = senate.num_elected() / 2;
+    senate
+        .senators
+        .iter()
+        .enumerate()
+        .map(|(i, (c, _))| {
+            (
+                c.id,
+                if i < num_long {
+                    Term::Long
+                } else {
+                    Term::Short
+                },
+            )
+        })
+        .collect()
+}
+
+/// The tie, if any, between senators elected at the same count with equal votes that decides
+/// who gets the last long term by the order-elected method.
+///
+/// The tie was broken like any other in the order of election, by the tie break policy, and the
+/// event tells whether lots were drawn to break it.
+pub fn term_allocation_tie(senate: &Senate) -> Option<TieEvent> {
+    let num_long = senate.num_elected() / 2;
+    if num_long == 0 {
+        return None;
+    }
+    let (last_long, first_short) = (num_long - 1, num_long);
+    let count = senate.elections[last_long].elected_at_count;
+    let votes = &senate.senators[last_long].1;
+    let tied_with = |i: usize| {
+        let e = &senate.elections[i];
+        e.elected_by == ElectedBy::Quota
+            && e.elected_at_count == count
+            && senate.senators[i].1 == *votes
+    };
+    if !tied_with(last_long) || !tied_with(first_short) {
+        return None;
+    }
+
+    let mut candidates: Vec<CandidateId> = (0..senate.num_elected())
+        .filter(|&i| tied_with(i))
+        .map(|i| senate.senators[i].0.id)
+        .collect();
+    candidates.sort();
+    let drawn = senate.ties.iter().any(|tie| {
+        tie.kind == TieKind::ElectionOrder
+            && tie.count == count
+            && tie.drawn
+            && tie.candidates.iter().any(|id| candidates.contains(id))
+    });
+    Some(TieEvent {
+        count,
+        candidates,
+        kind: TieKind::TermAllocation,
+        drawn,
+    })
+}
+
+/// Allocate terms by the re-count method of section 282: the count is re-run as if only half
+/// the vacancies existed, and the senators elected at the re-count receive long terms.
+///
+/// Candidates elected at the re-count who weren't elected at the original count are ignored, and
+/// any long terms left over are filled in the order the remaining senators were elected.
+///
+/// `ballot_stream` should yield the same ballots as the original count, and `config` should be
+/// the original configuration.
+pub fn recount_terms<I>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    senate: &Senate,
+    config: &ElectionConfig,
+) -> Result<Vec<(CandidateId, Term)>, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let num_long = senate.num_elected() / 2;
+    let recount_config = ElectionConfig {
+        num_positions: num_long,
+        ..config.clone()
+    };
+    let recount = decide_election(
+        candidates,
+        disqualified_candidates,
+        ballot_stream,
+        &recount_config,
+    )?;
+
+    let elected: Vec<CandidateId> = senate.senators.iter().map(|(c, _)| c.id).collect();
+
+    let mut long: Vec<CandidateId> = recount
+        .senators
+        .iter()
+        .map(|(c, _)| c.id)
+        .filter(|id| elected.contains(id))
+        .collect();
+    for &id in &elected {
+        if long.len() >= num_long {
+            break;
+        }
+        if !long.contains(&id) {
+            long.push(id);
+        }
+    }
+
+    Ok(elected
+        .into_iter()
+        .map(|id| {
+            let term = if long.contains(&id) {
+                Term::Long
+            } else {
+                Term::Short
+            };
+            (id, term)
+        })
+        .collect())
+}
+
+/// Allocate terms by both the order-elected and re-count methods, and compare them.
+///
+/// The arguments are as for `recount_terms`.
+pub fn allocate_terms<I>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    senate: &Senate,
+    config: &ElectionConfig,
+) -> Result<TermAllocation, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let order_elected = order_elected_terms(senate);
+    let recount = recount_terms(
+        candidates,
+        disqualified_candidates,
+        ballot_stream,
+        senate,
+        config,
+    )?;
+    let differences = compare_terms(&order_elected, &recount);
+    Ok(TermAllocation {
+        order_elected,
+        recount,
+        differences,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballot::Ballot;
+    use util::{num_vacancies, ElectionType};
+
+    #[test]
+    fn order_elected_and_recount_differ() {
+        let ballots: Vec<_> = [(30, ids(&[0])), (22, ids(&[1])), (21, ids(&[2]))]
+            .iter()
+            .chain(&[(15, ids(&[3, 1])), (12, ids(&[4, 2]))])
+            .flat_map(|&(n, ref prefs)| (0..n).map(move |_| Ballot::single(prefs.clone())))
+            .collect();
+        let candidates = test_candidates(5);
+        let config = ElectionConfig::new(4);
+
+        let senate =
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config).unwrap();
+
+        use self::Term::*;
+        let order_elected = order_elected_terms(&senate);
+        assert_eq!(
+            order_elected,
+            vec![
+                (CandidateId(0), Long),
+                (CandidateId(1), Long),
+                (CandidateId(2), Short),
+                (CandidateId(3), Short)
+            ]
+        );
+        let recount = recount_terms(
+            &candidates,
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &senate,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            recount,
+            vec![
+                (CandidateId(0), Short),
+                (CandidateId(1), Long),
+                (CandidateId(2), Long),
+                (CandidateId(3), Short)
+            ]
+        );
+        assert_eq!(
+            compare_terms(&order_elected, &recount),
+            vec![
+                TermDifference {
+                    senator: CandidateId(0),
+                    order_elected: Long,
+                    recount: Short,
+                },
+                TermDifference {
+                    senator: CandidateId(2),
+                    order_elected: Short,
+                    recount: Long,
+                }
+            ]
+        );
+        assert!(compare_terms(&order_elected, &order_elected).is_empty());
+
+        let allocation = allocate_terms(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &senate,
+            &config,
+        )
+        .unwrap();
+        assert!(allocation.methods_differ());
+        assert_eq!(allocation.long_by_order_elected(), ids(&[0, 1]));
+        assert_eq!(allocation.long_by_recount(), ids(&[1, 2]));
+        assert_eq!(allocation.differences.len(), 2);
+    }
+
+    #[test]
+    fn methods_agree() {
+        let ballots = vec![
+            Ballot::multi(30, ids(&[0])),
+            Ballot::multi(25, ids(&[1])),
+            Ballot::multi(20, ids(&[2])),
+            Ballot::multi(15, ids(&[3])),
+            Ballot::multi(10, ids(&[4])),
+        ];
+        let candidates = test_candidates(5);
+        let config = ElectionConfig::new(4);
+        let senate =
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config).unwrap();
+
+        let allocation = allocate_terms(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &senate,
+            &config,
+        )
+        .unwrap();
+        assert!(!allocation.methods_differ());
+        assert_eq!(
+            allocation.long_by_order_elected(),
+            allocation.long_by_recount()
+        );
+    }
+
+    #[test]
+    fn tie_for_last_long_term() {
+        // Candidates 1 and 2 both reach a quota on candidate 0's surplus with equal votes, and
+        // countback puts candidate 2 ahead on first preferences.
+        let ballots = vec![
+            Ballot::multi(15, ids(&[0, 1])),
+            Ballot::multi(12, ids(&[0, 2])),
+            Ballot::multi(1, ids(&[1])),
+            Ballot::multi(3, ids(&[2])),
+            Ballot::multi(5, ids(&[3])),
+            Ballot::multi(4, ids(&[4])),
+        ];
+        let candidates = test_candidates(5);
+        let senate = decide_election(
+            &candidates,
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &ElectionConfig::new(4),
+        )
+        .unwrap();
+        assert!(senate.ties.is_empty());
+
+        let tie = term_allocation_tie(&senate).unwrap();
+        assert_eq!(tie.candidates, ids(&[1, 2]));
+        assert_eq!(tie.kind, TieKind::TermAllocation);
+        assert!(!tie.drawn);
+        assert_eq!(
+            order_elected_terms(&senate)[..2],
+            [(CandidateId(0), Term::Long), (CandidateId(2), Term::Long)]
+        );
+
+        let senate = decide_election(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(2),
+        )
+        .unwrap();
+        assert!(term_allocation_tie(&senate).is_none());
+    }
+
+    #[test]
+    fn half_senate() {
+        let ballots = vec![
+            Ballot::multi(5, ids(&[0])),
+            Ballot::multi(4, ids(&[1])),
+            Ballot::multi(1, ids(&[2])),
+        ];
+        let senate = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(num_vacancies("NT", ElectionType::HalfSenate).unwrap()),
+        )
+        .unwrap();
+
+        use self::Term::*;
+        assert_eq!(
+            half_senate_terms(&senate, "TAS"),
+            vec![(CandidateId(0), Long), (CandidateId(1), Long)]
+        );
+        assert_eq!(
+            half_senate_terms(&senate, "NT"),
+            vec![(CandidateId(0), Short), (CandidateId(1), Short)]
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Small synthetic elections, for testing the count without large fixtures.
+//!
+//! Elections are generated from a seed, so a failing case can be shared as the handful of numbers
+//! it was built from. The ballots can be made to force a surplus, an exclusion or a tie, to
+//! exercise those parts of the count on demand.
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use ballot::Ballot;
+use candidate::{Candidate, CandidateId, CandidateMap};
+use config::{ElectionConfig, ElectionConfigBuilder};
+use error::Error;
+use group::{ticket_letter, Group};
+use senate_result::Senate;
+use voting::decide_election;
+
+/// A generated election, ready to count with `decide_election`.
+#[derive(Clone)]
+pub struct SyntheticElection {
+    pub candidates: CandidateMap,
+    /// Groups in ballot paper order, each of consecutive candidates.
+    pub groups: Vec<Group>,
+    pub ballots: Vec<Ballot>,
+    pub num_positions: usize,
+}
+
+impl SyntheticElection {
+    /// Start building an election with `num_candidates` candidates for `num_positions` vacancies.
+    pub fn builder(num_candidates: u16, num_positions: usize) -> SyntheticElectionBuilder {
+        SyntheticElectionBuilder {
+            num_candidates,
+            num_positions,
+            num_groups: 1,
+            num_ballots: 50,
+            seed: 0,
+            surplus: false,
+            exclusion: false,
+            tie: false,
+        }
+    }
+
+    /// Start building a configuration for counting the election.
+    pub fn config(&self) -> ElectionConfigBuilder {
+        ElectionConfig::builder(self.num_positions)
+    }
+
+    /// Count the election.
+    pub fn count(&self, config: &ElectionConfig) -> Result<Senate, Error> {
+        let ballots = self.ballots.iter().cloned().map(Ok);
+        decide_election(&self.candidates, &[], ballots, config)
+    }
+}
+
+/// Builder for a `SyntheticElection`.
+///
+/// The scenarios are forced for a count with the Droop quota.
+#[derive(Debug, Clone)]
+pub struct SyntheticElectionBuilder {
+    num_candidates: u16,
+    num_positions: usize,
+    num_groups: usize,
+    num_ballots: usize,
+    seed: usize,
+    surplus: bool,
+    exclusion: bool,
+    tie: bool,
+}
+
+impl SyntheticElectionBuilder {
+    /// Number of groups to split the candidates into [default: 1].
+    pub fn groups(mut self, num_groups: usize) -> Self {
+        self.num_groups = num_groups;
+        self
+    }
+
+    /// Number of random ballots, before any added to force a scenario [default: 50].
+    ///
+    /// Half of them, on average, follow the order of the groups as a vote above the line would.
+    pub fn ballots(mut self, num_ballots: usize) -> Self {
+        self.num_ballots = num_ballots;
+        self
+    }
+
+    /// Seed for the random ballots [default: 0].
+    pub fn seed(mut self, seed: usize) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Give candidate 0 more than a quota of first preferences, so that their surplus is
+    /// transferred at the second count.
+    pub fn surplus(mut self) -> Self {
+        self.surplus = true;
+        self
+    }
+
+    /// Give the last two candidates ballots that go nowhere else, too few to elect either of them,
+    /// but too many for every vacancy to be filled by quota, so that at least one candidate must
+    /// be excluded.
+    pub fn exclusion(mut self) -> Self {
+        self.exclusion = true;
+        self
+    }
+
+    /// Give candidates 0 and 1 the same number of first preferences, each more than a quota, so
+    /// that the order they're elected in is a tie that countback can't break.
+    ///
+    /// The tie is broken at random if the count has a tie break seed, and otherwise leaves the
+    /// count undecided.
+    pub fn tie(mut self) -> Self {
+        self.tie = true;
+        self
+    }
+
+    pub fn build(&self) -> Result<SyntheticElection, Error> {
+        let n = self.num_candidates as usize;
+        let vacancies = self.num_positions;
+        let num_forced = if self.tie {
+            2
+        } else if self.surplus {
+            1
+        } else {
+            0
+        };
+        let num_reserved = if self.exclusion { 2 } else { 0 };
+        // The ballots forcing an exclusion hold more votes than those left over when every
+        // vacancy is filled by quota, which leaves too few votes for other quotas.
+        let min_vacancies = match (self.exclusion, num_forced) {
+            (_, 0) => 1,
+            (false, k) => k,
+            (true, k) => k + 1,
+        };
+        if vacancies < min_vacancies {
+            return Err(Error::Config(format!(
+                "these scenarios need at least {} vacancies",
+                min_vacancies
+            )));
+        }
+        if n < vacancies.max(num_forced) + num_reserved {
+            return Err(Error::Config(format!(
+                "{} candidates are too few for these scenarios and {} vacancies",
+                n, vacancies
+            )));
+        }
+        if self.num_groups == 0 || self.num_groups > n {
+            return Err(Error::Config(format!(
+                "can't split {} candidates into {} groups",
+                n, self.num_groups
+            )));
+        }
+
+        let groups: Vec<Group> = (0..self.num_groups)
+            .map(|g| Group {
+                name: ticket_letter(g),
+                ticket_letter: Some(ticket_letter(g)),
+                candidate_ids: (0..n)
+                    .filter(|id| id * self.num_groups / n == g)
+                    .map(|id| CandidateId(id as u16))
+                    .collect(),
+            })
+            .collect();
+        let mut candidates = CandidateMap::new();
+        for group in &groups {
+            for &id in &group.candidate_ids {
+                let candidate = Candidate {
+                    id,
+                    surname: format!("C{}", id),
+                    other_names: String::new(),
+                    group_name: group.name.clone(),
+                    party: format!("Party {}", group.name),
+                    state: "TAS".to_string(),
+                    withdrawn: false,
+                };
+                candidates.insert(id, candidate);
+            }
+        }
+
+        // The random ballots leave out the candidates reserved for an exclusion, and don't give
+        // their first preference to those forced to a quota.
+        let open: Vec<CandidateId> = (0..n - num_reserved)
+            .map(|id| CandidateId(id as u16))
+            .collect();
+        let first_choices = &open[num_forced..];
+        if first_choices.is_empty() && self.num_ballots > 0 {
+            return Err(Error::Config(
+                "no candidates are left for the random ballots".to_string(),
+            ));
+        }
+        let mut rng: StdRng = SeedableRng::from_seed(&[self.seed][..]);
+        let mut ballots: Vec<Ballot> = (0..self.num_ballots)
+            .map(|_| {
+                let mut prefs: Vec<CandidateId> = if groups.len() > 1 && rng.gen() {
+                    let mut order: Vec<&Group> = groups.iter().collect();
+                    rng.shuffle(&mut order);
+                    order.truncate(rng.gen_range(1, groups.len() + 1));
+                    order
+                        .into_iter()
+                        .flat_map(|g| g.candidate_ids.iter().cloned())
+                        .filter(|id| open.contains(id))
+                        .collect()
+                } else {
+                    let mut prefs = open.clone();
+                    rng.shuffle(&mut prefs);
+                    prefs.truncate(rng.gen_range(1, open.len() + 1));
+                    prefs
+                };
+                if prefs.first().is_none_or(|id| !first_choices.contains(id)) {
+                    let first = first_choices[rng.gen_range(0, first_choices.len())];
+                    prefs.retain(|&id| id != first);
+                    prefs.insert(0, first);
+                }
+                Ballot::single(prefs)
+            })
+            .collect();
+
+        let (forced_votes, reserved_votes) = forced_vote_counts(
+            self.num_ballots as u64,
+            num_forced as u64,
+            vacancies as u64,
+            self.exclusion,
+        )?;
+        for id in 0..num_forced {
+            // Their surpluses go on to another open candidate.
+            let mut prefs = vec![open[id]];
+            if open.len() > 1 {
+                prefs.push(open[(id + 1) % open.len()]);
+            }
+            ballots.push(Ballot::multi(forced_votes, prefs));
+        }
+        if self.exclusion {
+            // The two reserved candidates have different votes, so they don't tie for exclusion.
+            let (more, fewer) = (reserved_votes - reserved_votes / 2, reserved_votes / 2);
+            ballots.push(Ballot::multi(more, vec![CandidateId(n as u16 - 2)]));
+            ballots.push(Ballot::multi(fewer, vec![CandidateId(n as u16 - 1)]));
+        }
+
+        Ok(SyntheticElection {
+            candidates,
+            groups,
+            ballots,
+            num_positions: vacancies,
+        })
+    }
+}
+
+/// Votes for each candidate forced to a quota, and for the candidates reserved for an exclusion
+/// together, on top of `random` ballots.
+fn forced_vote_counts(
+    random: u64,
+    num_forced: u64,
+    vacancies: u64,
+    exclusion: bool,
+) -> Result<(u64, u64), Error> {
+    let quota = |total: u64| total / (vacancies + 1) + 1;
+    let mut forced = 0;
+    loop {
+        let others = random + num_forced * forced;
+        // An odd number of votes, so that they split unevenly between the two.
+        let mut reserved = 0;
+        if exclusion {
+            reserved = 3;
+            while vacancies * quota(others + reserved) + reserved <= others + reserved {
+                reserved += 2;
+            }
+        }
+        let quota = quota(others + reserved);
+        if num_forced == 0 || forced > quota {
+            if reserved - reserved / 2 >= quota {
+                return Err(Error::Config(
+                    "too few ballots to force an exclusion".to_string(),
+                ));
+            }
+            return Ok((forced, reserved));
+        }
+        forced += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use senate_result::TieKind;
+    use transcript::CountAction;
+
+    fn actions(result: &Senate) -> Vec<CountAction> {
+        let transcript = result.transcript.as_ref().unwrap();
+        transcript.counts.iter().map(|c| c.action.clone()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_ballots() {
+        let build = |seed| {
+            SyntheticElection::builder(8, 3)
+                .groups(3)
+                .seed(seed)
+                .build()
+                .unwrap()
+                .ballots
+        };
+        assert_eq!(build(4), build(4));
+        assert_ne!(build(4), build(5));
+
+        let election = SyntheticElection::builder(8, 3).groups(3).build().unwrap();
+        assert_eq!(election.ballots.len(), 50);
+        let sizes: Vec<_> = election
+            .groups
+            .iter()
+            .map(|g| g.candidate_ids.len())
+            .collect();
+        assert_eq!(sizes, vec![3, 3, 2]);
+        assert_eq!(election.candidates[&CandidateId(7)].group_name, "C");
+    }
+
+    #[test]
+    fn forced_scenarios() {
+        for seed in 0..20 {
+            let election = SyntheticElection::builder(6, 2)
+                .seed(seed)
+                .surplus()
+                .exclusion()
+                .build()
+                .unwrap();
+            let result = election
+                .count(
+                    &election
+                        .config()
+                        .tie_break_seed(seed)
+                        .transcript(true)
+                        .build(),
+                )
+                .unwrap();
+            let surplus_actions = actions(&result);
+            assert_eq!(surplus_actions[1], CountAction::Surplus(CandidateId(0)));
+            assert!(surplus_actions
+                .iter()
+                .any(|a| matches!(*a, CountAction::Exclusion(_))));
+
+            let election = SyntheticElection::builder(7, 3)
+                .groups(2)
+                .seed(seed)
+                .tie()
+                .exclusion()
+                .build()
+                .unwrap();
+            let result = election
+                .count(
+                    &election
+                        .config()
+                        .tie_break_seed(seed)
+                        .transcript(true)
+                        .build(),
+                )
+                .unwrap();
+            assert_eq!(result.ties[0].count, 1);
+            assert_eq!(result.ties[0].kind, TieKind::ElectionOrder);
+            assert_eq!(
+                result.ties[0].candidates,
+                vec![CandidateId(0), CandidateId(1)]
+            );
+            assert!(actions(&result)
+                .iter()
+                .any(|a| matches!(*a, CountAction::Exclusion(_))));
+        }
+    }
+
+    #[test]
+    fn impossible_scenarios() {
+        let builder = SyntheticElection::builder(6, 2);
+        assert!(builder.clone().tie().exclusion().build().is_err());
+        assert!(SyntheticElection::builder(6, 1).tie().build().is_err());
+        assert!(SyntheticElection::builder(3, 2)
+            .exclusion()
+            .build()
+            .is_err());
+        assert!(builder.clone().groups(7).build().is_err());
+        assert!(builder.ballots(0).exclusion().build().is_err());
+    }
+}
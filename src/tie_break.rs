@@ -0,0 +1,340 @@
+//! Policies for breaking ties between candidates with equal votes.
+//!
+//! A tie arises when choosing a candidate to exclude from those with the fewest votes, and when
+//! ordering candidates with a quota who have equal votes. The counter hands each tie to the
+//! configured `TieBreak`, along with the tied candidates' votes at every count so far.
+
+use rand::{Rng, SeedableRng, StdRng};
+use std::fmt::Debug;
+
+use candidate::CandidateId;
+use senate_result::TieKind;
+use util::Int;
+
+/// A tie between candidates with equal votes.
+#[derive(Debug, Clone)]
+pub struct Tie<'a> {
+    pub kind: TieKind,
+    /// The tied candidates, in ascending order of ID.
+    pub candidates: Vec<CandidateId>,
+    /// Votes of each tied candidate at every count they've had votes at, in the same order as
+    /// `candidates`.
+    pub history: Vec<&'a [Int]>,
+}
+
+impl<'a> Tie<'a> {
+    /// Number of counts covered by the history.
+    pub fn num_counts(&self) -> usize {
+        self.history.iter().map(|h| h.len()).max().unwrap_or(0)
+    }
+
+    /// Votes of the `i`th tied candidate at count index `idx`, counting from 0.
+    ///
+    /// A candidate's votes are unchanged at counts after the last one in their history.
+    pub fn votes_at(&self, i: usize, idx: usize) -> Int {
+        let history = self.history[i];
+        match history.len() {
+            0 => Int::from(0),
+            n => history[idx.min(n - 1)].clone(),
+        }
+    }
+}
+
+/// How a tie was resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The candidate chosen on the votes, or by some other fixed rule.
+    Resolved(CandidateId),
+    /// The candidate chosen by drawing lots between the given candidates, which is recorded in
+    /// the result.
+    Drawn {
+        chosen: CandidateId,
+        candidates: Vec<CandidateId>,
+    },
+    /// The tie couldn't be resolved between the given candidates.
+    ///
+    /// The count carries on with one of them chosen at random, but the result is undecided.
+    Unresolved(Vec<CandidateId>),
+}
+
+/// Policy for resolving ties.
+///
+/// Policies are shared between threads like quotas, so random draws are made with the `Lots` the
+/// counter passes in, which is seeded from the configuration and repeated when a count resumes
+/// from a checkpoint.
+pub trait TieBreak: Debug + Send + Sync {
+    /// Resolve a tie, to exclude one of the candidates for a tie of kind `Exclusion`, or to elect
+    /// one of them first for a tie of kind `ElectionOrder`.
+    fn resolve(&self, tie: &Tie, lots: &mut Lots) -> Resolution;
+
+    /// Copy this policy into a new box.
+    fn clone_box(&self) -> Box<dyn TieBreak>;
+
+    /// Name of the policy, as accepted by `from_name`.
+    fn name(&self) -> String;
+}
+
+impl Clone for Box<dyn TieBreak> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Lots for breaking ties at random, drawn with the tie break seed if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct Lots {
+    rng: Option<StdRng>,
+}
+
+impl Lots {
+    pub fn new(seed: Option<usize>) -> Self {
+        Lots {
+            rng: seed.map(|seed| SeedableRng::from_seed(&[seed][..])),
+        }
+    }
+
+    /// Draw one of `candidates`, or `None` without a seed.
+    pub fn draw(&mut self, candidates: &[CandidateId]) -> Option<CandidateId> {
+        self.rng
+            .as_mut()
+            .map(|rng| *rng.choose(candidates).unwrap())
+    }
+
+    /// Advance past a draw between `candidates` made earlier in the count, so that later draws
+    /// come out the same way.
+    pub fn replay(&mut self, candidates: &[CandidateId]) {
+        self.draw(candidates);
+    }
+}
+
+/// Countback, as per section 273(13): the tie is resolved by the votes at the most recent count at
+/// which the tied candidates had unequal votes, and lots are drawn between those tied at every
+/// count.
+///
+/// Without a tie break seed, a tie at every count is left unresolved. This is the default.
+#[derive(Debug, Clone, Copy)]
+pub struct Countback;
+
+/// Lots drawn between all the tied candidates, ignoring the earlier counts.
+///
+/// Without a tie break seed, every tie is left unresolved.
+#[derive(Debug, Clone, Copy)]
+pub struct SeededRandom;
+
+/// Every tie left unresolved, to find out whether a count depends on breaking a tie at all.
+#[derive(Debug, Clone, Copy)]
+pub struct Unresolved;
+
+/// Narrow down a tie to the candidates with the fewest votes (for an exclusion) or the most (for
+/// the order of election) at the most recent count at which they had unequal votes.
+pub fn countback(tie: &Tie) -> Vec<CandidateId> {
+    let mut tied: Vec<usize> = (0..tie.candidates.len()).collect();
+
+    for idx in (0..tie.num_counts()).rev() {
+        if tied.len() <= 1 {
+            break;
+        }
+
+        let votes_at_count = tied.iter().map(|&i| tie.votes_at(i, idx));
+        let target = match tie.kind {
+            TieKind::Exclusion => votes_at_count.min(),
+            TieKind::ElectionOrder | TieKind::TermAllocation => votes_at_count.max(),
+        }
+        .unwrap();
+
+        tied.retain(|&i| tie.votes_at(i, idx) == target);
+    }
+
+    tied.into_iter().map(|i| tie.candidates[i]).collect()
+}
+
+fn draw_lots(candidates: Vec<CandidateId>, lots: &mut Lots) -> Resolution {
+    match lots.draw(&candidates) {
+        Some(chosen) => Resolution::Drawn { chosen, candidates },
+        None => Resolution::Unresolved(candidates),
+    }
+}
+
+impl TieBreak for Countback {
+    fn resolve(&self, tie: &Tie, lots: &mut Lots) -> Resolution {
+        let remaining = countback(tie);
+        if remaining.len() == 1 {
+            Resolution::Resolved(remaining[0])
+        } else {
+            draw_lots(remaining, lots)
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn TieBreak> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "countback".to_string()
+    }
+}
+
+impl TieBreak for SeededRandom {
+    fn resolve(&self, tie: &Tie, lots: &mut Lots) -> Resolution {
+        draw_lots(tie.candidates.clone(), lots)
+    }
+
+    fn clone_box(&self) -> Box<dyn TieBreak> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+}
+
+impl TieBreak for Unresolved {
+    fn resolve(&self, tie: &Tie, _: &mut Lots) -> Resolution {
+        Resolution::Unresolved(tie.candidates.clone())
+    }
+
+    fn clone_box(&self) -> Box<dyn TieBreak> {
+        Box::new(*self)
+    }
+
+    fn name(&self) -> String {
+        "unresolved".to_string()
+    }
+}
+
+/// Look up a tie break policy by name: countback, random or unresolved.
+pub fn from_name(name: &str) -> Option<Box<dyn TieBreak>> {
+    match name {
+        "countback" => Some(Box::new(Countback)),
+        "random" => Some(Box::new(SeededRandom)),
+        "unresolved" => Some(Box::new(Unresolved)),
+        _ => None,
+    }
+}
+
+/// Serialize a tie break policy by its name.
+pub mod by_name {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    use super::TieBreak;
+
+    // Serde passes a reference to the field, which is boxed.
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(
+        tie_break: &Box<dyn TieBreak>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&tie_break.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn TieBreak>, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        super::from_name(&name)
+            .ok_or_else(|| de::Error::custom(format!("unknown tie break: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use candidate::ids;
+    use vote_log::VoteLog;
+
+    fn counts(votes: &[u32]) -> Vec<Int> {
+        votes.iter().map(|&v| Int::from(v)).collect()
+    }
+
+    /// Breaks every tie in favour of the lowest ID, for deterministic examples.
+    #[derive(Debug, Clone, Copy)]
+    struct LowestId;
+
+    impl TieBreak for LowestId {
+        fn resolve(&self, tie: &Tie, _: &mut Lots) -> Resolution {
+            Resolution::Resolved(tie.candidates[0])
+        }
+
+        fn clone_box(&self) -> Box<dyn TieBreak> {
+            Box::new(*self)
+        }
+
+        fn name(&self) -> String {
+            "lowest-id".to_string()
+        }
+    }
+
+    #[test]
+    fn policies() {
+        let (a, b, c) = (counts(&[4, 5, 7]), counts(&[4, 5, 7]), counts(&[3, 6, 7]));
+        let tie = |kind| Tie {
+            kind,
+            candidates: ids(&[0, 1, 2]),
+            history: vec![&a[..], &b[..], &c[..]],
+        };
+        let mut no_seed = Lots::new(None);
+
+        // Candidate 2 was ahead at the second count, and candidates 0 and 1 were tied at every
+        // count.
+        assert_eq!(
+            Countback.resolve(&tie(TieKind::ElectionOrder), &mut no_seed),
+            Resolution::Resolved(ids(&[2])[0])
+        );
+        assert_eq!(
+            Countback.resolve(&tie(TieKind::Exclusion), &mut no_seed),
+            Resolution::Unresolved(ids(&[0, 1]))
+        );
+        assert_eq!(
+            Unresolved.resolve(&tie(TieKind::Exclusion), &mut Lots::new(Some(1))),
+            Resolution::Unresolved(ids(&[0, 1, 2]))
+        );
+        assert_eq!(
+            LowestId.resolve(&tie(TieKind::Exclusion), &mut no_seed),
+            Resolution::Resolved(ids(&[0])[0])
+        );
+
+        let draw = |tie_break: &dyn TieBreak, seed| {
+            tie_break.resolve(&tie(TieKind::Exclusion), &mut Lots::new(Some(seed)))
+        };
+        for seed in 0..10 {
+            assert_eq!(draw(&SeededRandom, seed), draw(&SeededRandom, seed));
+            match draw(&Countback, seed) {
+                Resolution::Drawn { chosen, candidates } => {
+                    assert_eq!(candidates, ids(&[0, 1]));
+                    assert!(candidates.contains(&chosen));
+                }
+                other => panic!("expected lots to be drawn, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn names() {
+        for name in &["countback", "random", "unresolved"] {
+            assert_eq!(from_name(name).unwrap().name(), *name);
+        }
+        assert!(from_name("alphabetical").is_none());
+    }
+
+    #[test]
+    fn votes_at() {
+        let tie = |v: &VoteLog, count| {
+            let tie = Tie {
+                kind: TieKind::Exclusion,
+                candidates: ids(&[0]),
+                history: vec![v.counts()],
+            };
+            tie.votes_at(0, count)
+        };
+        let mut v = VoteLog::new();
+        assert_eq!(tie(&v, 0), Int::from(0));
+
+        v.update_vote(0, Int::from(5));
+        v.update_vote(2, Int::from(3));
+        assert_eq!(tie(&v, 0), Int::from(5));
+        assert_eq!(tie(&v, 1), Int::from(5));
+        assert_eq!(tie(&v, 2), Int::from(8));
+        assert_eq!(tie(&v, 10), Int::from(8));
+    }
+}
@@ -0,0 +1,369 @@
+//! Record of the count, in the style of the AEC's distribution of preferences.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use candidate::CandidateId;
+use util::*;
+
+/// Whether a transfer of ballots is of an elected candidate's surplus or an excluded candidate's
+/// ballots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferKind {
+    Surplus,
+    Exclusion,
+}
+
+/// The action taken at a single count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CountAction {
+    /// Distribution of first preference votes.
+    FirstPreferences,
+    /// Transfer of an elected candidate's surplus.
+    Surplus(CandidateId),
+    /// Transfer of ballots from one or more excluded candidates.
+    Exclusion(Vec<CandidateId>),
+}
+
+/// A candidate's votes, and the number of ballot papers making them up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    #[serde(with = "as_string")]
+    pub votes: Int,
+    pub papers: u64,
+}
+
+/// A continuing candidate's votes at the end of a count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CandidateCount {
+    /// Votes received during this count.
+    #[serde(with = "as_string")]
+    pub received: Int,
+    /// Running total at the end of this count.
+    #[serde(with = "as_string")]
+    pub total: Int,
+    /// Ballot papers received during this count.
+    pub papers_received: i64,
+    /// Ballot papers held at the end of this count.
+    pub papers: u64,
+}
+
+impl CandidateCount {
+    /// Ballot papers the candidate would need to hold to reach `quota`, if every paper were worth
+    /// the average value of the papers they hold now.
+    ///
+    /// This shows how full the candidate's pile is in papers rather than votes. It's `None` for a
+    /// candidate without any votes, as their papers have no value to go by.
+    pub fn papers_for_quota(&self, quota: &Int) -> Option<u64> {
+        if self.total == Int::from(0) {
+            return None;
+        }
+        // Round up, as a part of a paper doesn't reach the quota.
+        let papers = (quota * Int::from(self.papers) + &self.total - Int::from(1)) / &self.total;
+        Option::<u64>::from(&papers)
+    }
+}
+
+/// A parcel of ballot papers transferred at a single transfer value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParcelRecord {
+    #[serde(with = "as_string")]
+    pub transfer_value: Frac,
+    /// Ballot papers in the parcel.
+    pub papers: u64,
+    /// Votes the parcel is worth: its papers at the transfer value, truncated to a whole number.
+    ///
+    /// Votes are truncated again for each candidate receiving part of the parcel, so the votes
+    /// actually received can sum to less than this.
+    #[serde(with = "as_string")]
+    pub votes: Int,
+}
+
+/// Record of a single count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountRecord {
+    /// The number of the count, starting from 1 for first preferences.
+    pub count: usize,
+    pub action: CountAction,
+    /// Transfer value of each parcel of ballots transferred (empty for first preferences).
+    #[serde(with = "as_strings")]
+    pub transfer_values: Vec<Frac>,
+    /// Each parcel of ballots transferred, in the same order as `transfer_values`.
+    pub parcels: Vec<ParcelRecord>,
+    /// Votes for each candidate that was continuing at the end of the count (including those
+    /// elected at this count).
+    pub votes: BTreeMap<CandidateId, CandidateCount>,
+    /// Candidates elected at this count, in order of election.
+    pub elected: Vec<CandidateId>,
+    /// Value lost at this count by truncating the votes received to whole votes, less any
+    /// fractions regained from the ballots of excluded candidates.
+    #[serde(with = "as_string")]
+    pub loss_by_fraction: Frac,
+}
+
+/// Full record of an election, one entry per count.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    pub counts: Vec<CountRecord>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a count, computing the votes received from the tallies before and after the count.
+    pub fn record_count(
+        &mut self,
+        action: CountAction,
+        parcels: Vec<ParcelRecord>,
+        before: &BTreeMap<CandidateId, Tally>,
+        after: BTreeMap<CandidateId, Tally>,
+    ) {
+        let zero = Tally {
+            votes: Int::from(0),
+            papers: 0,
+        };
+        let votes = after
+            .into_iter()
+            .map(|(id, tally)| {
+                let prev = before.get(&id).unwrap_or(&zero);
+                let count = CandidateCount {
+                    received: &tally.votes - &prev.votes,
+                    total: tally.votes,
+                    papers_received: tally.papers as i64 - prev.papers as i64,
+                    papers: tally.papers,
+                };
+                (id, count)
+            })
+            .collect();
+
+        self.counts.push(CountRecord {
+            count: self.counts.len() + 1,
+            action,
+            transfer_values: parcels.iter().map(|p| p.transfer_value.clone()).collect(),
+            parcels,
+            votes,
+            elected: vec![],
+            loss_by_fraction: frac!(0u64),
+        });
+    }
+
+    /// Set the value lost to fractions at the most recent count.
+    pub fn record_loss_by_fraction(&mut self, loss: Frac) {
+        if let Some(last) = self.counts.last_mut() {
+            last.loss_by_fraction = loss;
+        }
+    }
+
+    /// Mark candidates as elected at the most recent count.
+    pub fn record_elected<I>(&mut self, elected: I)
+    where
+        I: IntoIterator<Item = CandidateId>,
+    {
+        if let Some(last) = self.counts.last_mut() {
+            last.elected.extend(elected);
+        }
+    }
+}
+
+/// The first difference between two transcripts of the same election.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptDiff {
+    /// The two counts took different actions.
+    Action {
+        count: usize,
+        a: CountAction,
+        b: CountAction,
+    },
+    /// A candidate's total differs at the end of the count, or the candidate was only continuing
+    /// in one of the two.
+    Total {
+        count: usize,
+        candidate: CandidateId,
+        a: Option<Int>,
+        b: Option<Int>,
+    },
+    /// Different candidates were elected at the count.
+    Elected {
+        count: usize,
+        a: Vec<CandidateId>,
+        b: Vec<CandidateId>,
+    },
+    /// Every count the transcripts share agrees, but one of them has more counts.
+    Length { a: usize, b: usize },
+}
+
+impl TranscriptDiff {
+    /// The count at which the transcripts diverge.
+    pub fn count(&self) -> usize {
+        match *self {
+            TranscriptDiff::Action { count, .. }
+            | TranscriptDiff::Total { count, .. }
+            | TranscriptDiff::Elected { count, .. } => count,
+            TranscriptDiff::Length { a, b } => a.min(b) + 1,
+        }
+    }
+}
+
+impl fmt::Display for TranscriptDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn total(total: &Option<Int>) -> String {
+            total
+                .as_ref()
+                .map_or_else(|| "not continuing".to_string(), |t| t.to_string())
+        }
+        match *self {
+            TranscriptDiff::Action {
+                count,
+                ref a,
+                ref b,
+            } => write!(f, "count {}: action {:?} vs {:?}", count, a, b),
+            TranscriptDiff::Total {
+                count,
+                candidate,
+                ref a,
+                ref b,
+            } => write!(
+                f,
+                "count {}: candidate {} has {} vs {}",
+                count,
+                candidate,
+                total(a),
+                total(b)
+            ),
+            TranscriptDiff::Elected {
+                count,
+                ref a,
+                ref b,
+            } => write!(f, "count {}: elected {:?} vs {:?}", count, a, b),
+            TranscriptDiff::Length { a, b } => write!(f, "{} counts vs {}", a, b),
+        }
+    }
+}
+
+/// Align two transcripts count by count, and find the first count at which they differ.
+///
+/// Counts are compared by their action, each candidate's total and the candidates elected, so
+/// transcripts from different arithmetic or surplus transfer methods can be compared even though
+/// their transfer values differ. Returns `None` if the transcripts agree at every count.
+pub fn diff_transcripts(a: &Transcript, b: &Transcript) -> Option<TranscriptDiff> {
+    for (ca, cb) in a.counts.iter().zip(&b.counts) {
+        let count = ca.count;
+        if ca.action != cb.action {
+            return Some(TranscriptDiff::Action {
+                count,
+                a: ca.action.clone(),
+                b: cb.action.clone(),
+            });
+        }
+        let candidates: BTreeSet<_> = ca.votes.keys().chain(cb.votes.keys()).collect();
+        for &candidate in candidates {
+            let ta = ca.votes.get(&candidate).map(|c| c.total.clone());
+            let tb = cb.votes.get(&candidate).map(|c| c.total.clone());
+            if ta != tb {
+                return Some(TranscriptDiff::Total {
+                    count,
+                    candidate,
+                    a: ta,
+                    b: tb,
+                });
+            }
+        }
+        if ca.elected != cb.elected {
+            return Some(TranscriptDiff::Elected {
+                count,
+                a: ca.elected.clone(),
+                b: cb.elected.clone(),
+            });
+        }
+    }
+    if a.counts.len() != b.counts.len() {
+        return Some(TranscriptDiff::Length {
+            a: a.counts.len(),
+            b: b.counts.len(),
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tallies(votes: &[(u16, u32)]) -> BTreeMap<CandidateId, Tally> {
+        votes
+            .iter()
+            .map(|&(id, votes)| {
+                let tally = Tally {
+                    votes: Int::from(votes),
+                    papers: u64::from(votes),
+                };
+                (CandidateId(id), tally)
+            })
+            .collect()
+    }
+
+    fn transcript(counts: &[(CountAction, &[(u16, u32)])]) -> Transcript {
+        let mut transcript = Transcript::new();
+        let mut before = BTreeMap::new();
+        for &(ref action, votes) in counts {
+            let after = tallies(votes);
+            transcript.record_count(action.clone(), vec![], &before, after.clone());
+            before = after;
+        }
+        transcript
+    }
+
+    #[test]
+    fn first_divergence() {
+        let a = transcript(&[
+            (CountAction::FirstPreferences, &[(0, 6), (1, 3), (2, 2)]),
+            (
+                CountAction::Exclusion(vec![CandidateId(2)]),
+                &[(0, 7), (1, 4)],
+            ),
+        ]);
+        assert_eq!(diff_transcripts(&a, &a), None);
+
+        let b = transcript(&[
+            (CountAction::FirstPreferences, &[(0, 6), (1, 3), (2, 2)]),
+            (
+                CountAction::Exclusion(vec![CandidateId(2)]),
+                &[(0, 8), (1, 3)],
+            ),
+        ]);
+        let diff = diff_transcripts(&a, &b).unwrap();
+        assert_eq!(
+            diff,
+            TranscriptDiff::Total {
+                count: 2,
+                candidate: CandidateId(0),
+                a: Some(Int::from(7)),
+                b: Some(Int::from(8)),
+            }
+        );
+        assert_eq!(diff.to_string(), "count 2: candidate 0 has 7 vs 8");
+
+        let c = transcript(&[
+            (CountAction::FirstPreferences, &[(0, 6), (1, 3), (2, 2)]),
+            (
+                CountAction::Exclusion(vec![CandidateId(1)]),
+                &[(0, 8), (2, 3)],
+            ),
+        ]);
+        assert_eq!(diff_transcripts(&a, &c).unwrap().count(), 2);
+        match diff_transcripts(&a, &c) {
+            Some(TranscriptDiff::Action { .. }) => (),
+            diff => panic!("unexpected diff: {:?}", diff),
+        }
+
+        let mut d = a.clone();
+        d.counts.pop();
+        assert_eq!(
+            diff_transcripts(&a, &d),
+            Some(TranscriptDiff::Length { a: 2, b: 1 })
+        );
+        assert_eq!(diff_transcripts(&a, &d).unwrap().count(), 2);
+    }
+}
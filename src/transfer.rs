@@ -0,0 +1,28 @@
+/// Method used to compute the transfer value of an elected candidate's surplus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SurplusTransfer {
+    /// All of the candidate's ballot papers are transferred at a single transfer value of
+    /// (surplus / number of ballot papers), regardless of the value they were received at.
+    ///
+    /// This is the method used by the Senate count, section 273(9).
+    #[default]
+    InclusiveGregory,
+    /// All of the candidate's ballot papers are transferred, each at its current value multiplied
+    /// by (surplus / total value of the ballot papers), with the total rounded up to whole votes.
+    WeightedInclusiveGregory,
+    /// Only the last parcel of ballots received by the candidate (the one that brought them over
+    /// quota) is transferred, at a transfer value of (surplus / number of ballot papers in the
+    /// parcel). The parcel is every ballot paper received at that count, and papers can't be
+    /// transferred at more than the value they were received at.
+    ///
+    /// This is the unweighted Gregory method used by the Senate count prior to 1984.
+    LastParcel,
+    /// Meek's method, which instead of transferring surpluses has every elected candidate keep
+    /// just enough of the value of each ballot reaching them for a quota, and looks at every
+    /// ballot again whenever that changes (see the `meek` module).
+    ///
+    /// This isn't used by the Senate count, and is only for comparing it with jurisdictions that
+    /// use Meek's method. It keeps no transcript, so it can't be audited or checkpointed.
+    Meek,
+}
@@ -1,27 +1,27 @@
-use file_logger::FileLogger;
-use std::fs::{DirBuilder, File};
+use error::Error;
+use flate2::bufread::MultiGzDecoder;
+use parse::archive::open_zip_csv;
+use std::fs::File;
 use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
+#[cfg(not(feature = "gmp"))]
+pub use bignum::{Mpq, Mpz};
+#[cfg(feature = "gmp")]
 pub use gmp::mpq::Mpq;
+#[cfg(feature = "gmp")]
 pub use gmp::mpz::Mpz;
 pub use std::collections::{BTreeMap, HashMap};
 
+/// Arbitrary precision integer, used for vote tallies.
 pub type Int = Mpz;
+/// Exact arbitrary precision rational, used for transfer values.
+///
+/// Backed by GMP with the `gmp` feature, and otherwise by pure Rust numbers that behave the same.
+/// Both keep rationals in lowest terms, so values never drift or grow unreduced. Votes derived from
+/// transfer values are explicitly truncated to whole numbers when credited to a candidate.
 pub type Frac = Mpq;
 
-// Globally accessible log outputs.
-// TODO: configurable results path
-lazy_static! {
-    pub static ref RESULTS_DIR: () = DirBuilder::new()
-        .recursive(true)
-        .create("results")
-        .expect("failed to create results directory");
-    pub static ref CANDIDATE_ORDER: FileLogger = {
-        *RESULTS_DIR;
-        FileLogger::new("results/candidates.txt").expect("results/candidates.txt")
-    };
-}
-
 #[macro_export]
 macro_rules! frac {
     ($e:expr) => {
@@ -32,11 +32,344 @@ macro_rules! frac {
     };
 }
 
+/// Serialize a number as a string, for arbitrary precision numbers that serde can't represent.
+///
+/// For use with `#[serde(with = "util::as_string")]`.
+pub mod as_string {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("invalid number: {}", s)))
+    }
+}
+
+/// Serialize a list of numbers as strings, like `as_string`.
+pub mod as_strings {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        values: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(ToString::to_string))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: FromStr,
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|s| {
+                s.parse()
+                    .map_err(|_| de::Error::custom(format!("invalid number: {}", s)))
+            })
+            .collect()
+    }
+}
+
+/// Serialize a map as a list of key-value pairs, for maps with keys that can't be strings.
+pub mod as_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::iter::FromIterator;
+
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// Magic number at the start of every gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// UTF-8 byte order mark, which some exports put at the start of the file.
+const BYTE_ORDER_MARK: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+/// File name standing for standard input, which can only be read once.
+pub const STDIN: &str = "-";
+
+/// Open a file for reading, decompressing it on the fly if it's gzipped or zipped.
+///
+/// The file name `STDIN` reads standard input instead, which is decompressed if it starts with
+/// the gzip magic number.
+///
+/// A file is treated as gzipped if its name ends in `.gz`, or it starts with the gzip magic number.
+/// A file whose name ends in `.zip` is treated as a ZIP archive containing a single CSV file.
+/// A UTF-8 byte order mark at the start of the (decompressed) contents is skipped, so that it
+/// doesn't become part of the first column name.
+pub fn open_input(filename: &str) -> Result<Box<dyn BufRead>, Error> {
+    let mut r: Box<dyn BufRead> = if filename.ends_with(".zip") {
+        open_zip_csv(filename)?
+    } else {
+        let mut r: Box<dyn BufRead> = if filename == STDIN {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            Box::new(BufReader::new(File::open(filename)?))
+        };
+        let is_gzip = filename.ends_with(".gz") || r.fill_buf()?.starts_with(&GZIP_MAGIC);
+        if is_gzip {
+            Box::new(BufReader::new(MultiGzDecoder::new(r)))
+        } else {
+            r
+        }
+    };
+    if r.fill_buf()?.starts_with(&BYTE_ORDER_MARK) {
+        r.consume(BYTE_ORDER_MARK.len());
+    }
+    Ok(r)
+}
+
 /// Open an AEC CSV file for reading, whilst chomping the first line (a comment).
 // FIXME: consider using the CSV reader's comment functionality.
-pub fn open_aec_csv(filename: &str) -> io::Result<BufReader<File>> {
-    let f = File::open(filename)?;
-    let mut r = BufReader::new(f);
+pub fn open_aec_csv(filename: &str) -> Result<Box<dyn BufRead>, Error> {
+    let mut r = open_input(filename)?;
     r.read_line(&mut String::new())?;
     Ok(r)
 }
+
+/// Type of Senate election, which determines the number of vacancies for each state and the
+/// terms the senators elected serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ElectionType {
+    /// Election after a double dissolution, where all 12 seats for each state are vacant.
+    #[default]
+    DoubleDissolution,
+    /// Regular half-Senate election, where 6 seats for each state are vacant.
+    HalfSenate,
+}
+
+const STATES: &[&str] = &["NSW", "VIC", "QLD", "WA", "SA", "TAS"];
+const TERRITORIES: &[&str] = &["ACT", "NT"];
+
+/// Number of vacancies to fill in a state or territory.
+///
+/// The territories always elect 2 senators, regardless of the type of election.
+pub fn num_vacancies(state: &str, election_type: ElectionType) -> Result<usize, Error> {
+    if is_territory(state) {
+        Ok(2)
+    } else if STATES.contains(&state) {
+        match election_type {
+            ElectionType::DoubleDissolution => Ok(12),
+            ElectionType::HalfSenate => Ok(6),
+        }
+    } else {
+        Err(Error::Config(format!(
+            "unknown state or territory: {}",
+            state
+        )))
+    }
+}
+
+/// The Droop quota for `total_formal_votes` and `vacancies`, floor(votes / (vacancies + 1)) + 1,
+/// as per section 273(8).
+///
+/// This is the smallest number of votes that no more than `vacancies` candidates can reach.
+pub fn droop_quota(total_formal_votes: u64, vacancies: u32) -> u64 {
+    total_formal_votes / (u64::from(vacancies) + 1) + 1
+}
+
+/// Whether `state` is one of the territories, whose senators' terms follow the House of
+/// Representatives.
+pub fn is_territory(state: &str) -> bool {
+    TERRITORIES.contains(&state)
+}
+
+/// Every state and territory, states first.
+pub fn states_and_territories() -> impl Iterator<Item = &'static str> {
+    STATES.iter().chain(TERRITORIES).cloned()
+}
+
+/// Find the state or territory with the given abbreviation, ignoring case.
+pub fn normalise_state(state: &str) -> Result<&'static str, Error> {
+    states_and_territories()
+        .find(|s| s.eq_ignore_ascii_case(state))
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "unknown state or territory: {} (expected one of {})",
+                state,
+                states_and_territories().collect::<Vec<_>>().join(", ")
+            ))
+        })
+}
+
+/// The state or territory a preferences file is for, judging by its name.
+///
+/// The abbreviation must appear as a word of its own in the file name, as it does in
+/// `aec-senate-formalpreferences-20499-NSW.csv` or `data/NSW.csv`.
+pub fn state_of_file(filename: &str) -> Option<&'static str> {
+    let name = Path::new(filename).file_name()?.to_str()?;
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .find_map(|word| states_and_territories().find(|&s| s == word))
+}
+
+/// Check that an explicitly chosen number of vacancies is possible for the given state.
+pub fn check_vacancies(state: &str, vacancies: usize) -> Result<(), Error> {
+    if is_territory(state) && vacancies != 2 {
+        return Err(Error::Config(format!(
+            "{} elects 2 senators, not {}",
+            state, vacancies
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn gzipped_input() {
+        let contents = "first line\nsecond line\n";
+        let dir = env::temp_dir();
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // Detected by extension, by magic number, and plain.
+        let files = [
+            ("aus_senate_test_input.csv.gz", &compressed[..]),
+            ("aus_senate_test_input_gz.csv", &compressed[..]),
+            ("aus_senate_test_input.csv", contents.as_bytes()),
+        ];
+        for &(name, data) in &files {
+            let path = dir.join(name);
+            File::create(&path).unwrap().write_all(data).unwrap();
+            let path = path.to_str().unwrap();
+
+            let mut read = String::new();
+            open_input(path).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(read, contents);
+
+            let mut rest = String::new();
+            open_aec_csv(path)
+                .unwrap()
+                .read_to_string(&mut rest)
+                .unwrap();
+            assert_eq!(rest, "second line\n");
+        }
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let contents = "first line\nsecond line\n";
+        let with_bom = [&BYTE_ORDER_MARK[..], contents.as_bytes()].concat();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&with_bom).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let dir = env::temp_dir();
+        let files = [
+            ("aus_senate_test_bom.csv", &with_bom[..]),
+            ("aus_senate_test_bom.csv.gz", &compressed[..]),
+        ];
+        for &(name, data) in &files {
+            let path = dir.join(name);
+            File::create(&path).unwrap().write_all(data).unwrap();
+
+            let mut read = String::new();
+            open_input(path.to_str().unwrap())
+                .unwrap()
+                .read_to_string(&mut read)
+                .unwrap();
+            assert_eq!(read, contents);
+        }
+    }
+
+    #[test]
+    fn vacancies() {
+        use self::ElectionType::*;
+        assert_eq!(num_vacancies("NSW", DoubleDissolution).unwrap(), 12);
+        assert_eq!(num_vacancies("TAS", HalfSenate).unwrap(), 6);
+        assert_eq!(num_vacancies("ACT", DoubleDissolution).unwrap(), 2);
+        assert_eq!(num_vacancies("NT", HalfSenate).unwrap(), 2);
+        assert!(num_vacancies("XYZ", HalfSenate).is_err());
+        assert_eq!(
+            ::serde_json::from_str::<ElectionType>("\"half-senate\"").unwrap(),
+            HalfSenate
+        );
+        assert_eq!(ElectionType::default(), DoubleDissolution);
+
+        assert!(check_vacancies("VIC", 5).is_ok());
+        assert!(check_vacancies("NT", 2).is_ok());
+        assert!(check_vacancies("ACT", 12).is_err());
+    }
+
+    #[test]
+    fn droop() {
+        assert_eq!(droop_quota(0, 1), 1);
+        assert_eq!(droop_quota(10, 1), 6);
+        assert_eq!(droop_quota(100, 3), 26);
+        // Off by one either side of a multiple of vacancies + 1.
+        assert_eq!(droop_quota(99, 3), 25);
+        assert_eq!(droop_quota(101, 3), 26);
+        assert_eq!(droop_quota(103, 3), 26);
+        assert_eq!(droop_quota(104, 3), 27);
+        // The 2016 quota for New South Wales.
+        assert_eq!(droop_quota(4_492_197, 12), 345_554);
+    }
+
+    #[test]
+    fn normalised_states() {
+        assert_eq!(normalise_state("qld").unwrap(), "QLD");
+        assert_eq!(
+            normalise_state("QLD").unwrap(),
+            normalise_state("qld").unwrap()
+        );
+        assert_eq!(normalise_state("Act").unwrap(), "ACT");
+        assert!(normalise_state("Queensland").is_err());
+        assert!(normalise_state("").is_err());
+    }
+
+    #[test]
+    fn states_from_file_names() {
+        assert_eq!(states_and_territories().count(), 8);
+        let name = "aec-senate-formalpreferences-20499-NSW.csv";
+        assert_eq!(state_of_file(name), Some("NSW"));
+        assert_eq!(state_of_file("data/WA.csv.gz"), Some("WA"));
+        assert_eq!(state_of_file("NT/prefs.csv"), None);
+        assert_eq!(state_of_file("data/SWAN.csv"), None);
+    }
+}
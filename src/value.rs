@@ -0,0 +1,277 @@
+//! Numeric representations for the value of ballots during the count.
+
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+use util::*;
+
+/// Arithmetic used to represent transfer values and the value of parcels of ballots.
+///
+/// Vote tallies are always whole numbers: values are truncated when credited to a candidate.
+pub trait VoteValue: Clone + Ord + Debug {
+    /// The value of a ballot that has not been transferred at a reduced value.
+    fn one() -> Self;
+
+    fn zero() -> Self;
+
+    /// The value `num / den`, rounded down if it can't be represented exactly.
+    fn ratio(num: &Int, den: &Int) -> Self;
+
+    fn mul(&self, other: &Self) -> Self;
+
+    fn add(&self, other: &Self) -> Self;
+
+    /// The combined value of `papers` ballot papers at this value.
+    fn times_papers(&self, papers: u64) -> Self;
+
+    /// Truncate to a whole number of votes.
+    fn floor(&self) -> Int;
+
+    /// Convert to an exact rational, for reporting.
+    fn to_frac(&self) -> Frac;
+
+    /// Convert back from an exact rational produced by `to_frac`, rounding down if necessary.
+    fn from_frac(value: &Frac) -> Self;
+}
+
+/// Selector for the arithmetic used during the count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Arithmetic {
+    /// Exact rational arithmetic (`Frac`).
+    #[default]
+    Exact,
+    /// Fixed-point arithmetic with `SCALE` units per vote (`Scaled`).
+    Scaled,
+    /// Floating point arithmetic (`Float`), which is approximate.
+    Float,
+}
+
+impl VoteValue for Frac {
+    fn one() -> Self {
+        frac!(1u64)
+    }
+
+    fn zero() -> Self {
+        frac!(0u64)
+    }
+
+    fn ratio(num: &Int, den: &Int) -> Self {
+        Frac::ratio(num, den)
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn times_papers(&self, papers: u64) -> Self {
+        self * frac!(papers)
+    }
+
+    fn floor(&self) -> Int {
+        Frac::floor(self)
+    }
+
+    fn to_frac(&self) -> Frac {
+        self.clone()
+    }
+
+    fn from_frac(value: &Frac) -> Self {
+        value.clone()
+    }
+}
+
+/// Number of units in a single vote, for `Scaled` arithmetic.
+pub const SCALE: u64 = 1_000_000;
+
+/// Fixed-point vote value, measured in millionths of a vote.
+///
+/// Transfer values are rounded down to the nearest millionth, so a count using this arithmetic may
+/// differ slightly from an exact count, but avoids allocating for every value.
+///
+/// Values are stored as a `u64`, so the combined value of all ballots must not exceed
+/// `u64::MAX / SCALE`. This allows for around 18 trillion ballots, so cannot overflow for any real
+/// election. Overflow is checked nonetheless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Scaled(pub u64);
+
+fn int_to_u64(x: &Int) -> u64 {
+    Option::<u64>::from(x).expect("vote count does not fit in a u64")
+}
+
+impl VoteValue for Scaled {
+    fn one() -> Self {
+        Scaled(SCALE)
+    }
+
+    fn zero() -> Self {
+        Scaled(0)
+    }
+
+    fn ratio(num: &Int, den: &Int) -> Self {
+        let num = u128::from(int_to_u64(num));
+        let den = u128::from(int_to_u64(den));
+        let value = num * u128::from(SCALE) / den;
+        debug_assert!(
+            value <= u128::from(u64::MAX),
+            "overflow in scaled vote value"
+        );
+        Scaled(value as u64)
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let product = u128::from(self.0) * u128::from(other.0) / u128::from(SCALE);
+        debug_assert!(
+            product <= u128::from(u64::MAX),
+            "overflow in scaled vote value"
+        );
+        Scaled(product as u64)
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Scaled(
+            self.0
+                .checked_add(other.0)
+                .expect("overflow in scaled vote value"),
+        )
+    }
+
+    fn times_papers(&self, papers: u64) -> Self {
+        Scaled(
+            self.0
+                .checked_mul(papers)
+                .expect("overflow in scaled vote value"),
+        )
+    }
+
+    fn floor(&self) -> Int {
+        Int::from(self.0 / SCALE)
+    }
+
+    fn to_frac(&self) -> Frac {
+        frac!(self.0, SCALE)
+    }
+
+    fn from_frac(value: &Frac) -> Self {
+        let units = (value * frac!(SCALE)).floor();
+        Scaled(int_to_u64(&units))
+    }
+}
+
+/// Floating point vote value.
+///
+/// Values are rounded down to an `f64`, like `Scaled` values are to whole units, so ballots never
+/// carry more than their exact value. Truncating a total that should be a whole number can lose
+/// a vote to rounding, so a count using this arithmetic can differ from an exact count by the
+/// odd vote. It's for quick approximate counts, and for
+/// comparison with counting programs that use floating point.
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl VoteValue for Float {
+    fn one() -> Self {
+        Float(1.0)
+    }
+
+    fn zero() -> Self {
+        Float(0.0)
+    }
+
+    fn ratio(num: &Int, den: &Int) -> Self {
+        Float::round_down(f64::from(num) / f64::from(den), &Frac::ratio(num, den))
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        Float::round_down(self.0 * other.0, &(self.to_frac() * other.to_frac()))
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Float::round_down(self.0 + other.0, &(self.to_frac() + other.to_frac()))
+    }
+
+    fn times_papers(&self, papers: u64) -> Self {
+        let exact = self.to_frac() * Frac::from(Int::from(papers));
+        Float::round_down(self.0 * papers as f64, &exact)
+    }
+
+    fn floor(&self) -> Int {
+        Int::from(self.0.floor() as u64)
+    }
+
+    fn to_frac(&self) -> Frac {
+        // Every finite f64 is a rational, which GMP converts exactly.
+        let mut frac = Frac::new();
+        frac.set_d(self.0);
+        frac
+    }
+
+    fn from_frac(value: &Frac) -> Self {
+        Float::round_down(f64::from(value), value)
+    }
+}
+
+impl Float {
+    /// The nearest `f64` to `exact`, computed as `nearest`, stepped down to the one below if it's
+    /// too big, so that no calculation makes more of the votes than there are.
+    fn round_down(nearest: f64, exact: &Frac) -> Float {
+        let mut value = Float(nearest);
+        while &value.to_frac() > exact {
+            value = Float(value.0.next_down());
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scaled_rounds_down() {
+        let third = Scaled::ratio(&Int::from(1), &Int::from(3));
+        assert_eq!(third, Scaled(333_333));
+        assert_eq!(third.times_papers(3).floor(), Int::from(0));
+        assert_eq!(Scaled::one().times_papers(3).floor(), Int::from(3));
+        assert_eq!(
+            third.mul(&Scaled::ratio(&Int::from(1), &Int::from(2))),
+            Scaled(166_666)
+        );
+        assert_eq!(Scaled::from_frac(&third.to_frac()), third);
+    }
+
+    #[test]
+    fn float_round_trips() {
+        let third = Float::ratio(&Int::from(1), &Int::from(3));
+        assert!(third.to_frac() < frac!(1u64, 3u64));
+        // Three thirds rounded down fall short of a vote, like they do with `Scaled`.
+        assert_eq!(third.times_papers(3).floor(), Int::from(0));
+        assert_eq!(Float::one().times_papers(3).floor(), Int::from(3));
+        assert_eq!(Float::from_frac(&third.to_frac()), third);
+        assert!(third.to_frac() != frac!(1u64, 3u64));
+        assert!(Float::zero() < third && third < Float::one());
+    }
+}
@@ -13,6 +13,16 @@ impl VoteLog {
         VoteLog { log: vec![] }
     }
 
+    /// Recreate a log from the tallies returned by `counts`.
+    pub fn from_counts(log: Vec<Int>) -> Self {
+        VoteLog { log }
+    }
+
+    /// The tally at each count recorded.
+    pub fn counts(&self) -> &[Int] {
+        &self.log
+    }
+
     pub fn update_vote(&mut self, idx: usize, vote: Int) {
         if idx < self.log.len() {
             self.log[idx] += vote;
@@ -1,81 +1,340 @@
 use arith::*;
 use ballot::*;
 use candidate::*;
+use checkpoint::*;
+use error::Error;
+use senate_result::{TieEvent, TieKind};
 use stats::Stats;
+use tie_break::{Countback, Lots, Resolution, Tie, TieBreak};
+use transcript::{Tally, TransferKind};
+use transfer::SurplusTransfer;
 use util::*;
+use value::VoteValue;
 use vote_log::*;
 
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
 use std::mem;
 
+/// Seed of the lots drawn to carry on past a tie the tie break policy leaves unresolved.
+///
+/// The outcome of such a count is undecided whichever candidate is drawn, but drawing with a fixed
+/// seed means the count carries on the same way every time.
+const UNRESOLVED_TIE_SEED: usize = 0;
+
 /// Map from transfer values to ballots with that transfer value.
-pub type TransferMap<'a> = BTreeMap<Frac, Vec<&'a mut Ballot>>;
+pub type TransferMap<'a, V> = BTreeMap<V, Vec<&'a mut Ballot>>;
 
 /// Intermediate data structure mapping candidates to ballots.
-pub struct VoteMap<'a> {
-    info: HashMap<CandidateId, VoteInfo<'a>>,
+pub struct VoteMap<'a, V: VoteValue> {
+    info: HashMap<CandidateId, VoteInfo<'a, V>>,
     candidates: &'a CandidateMap,
-    one: Frac,
+    /// Policy for resolving ties.
+    tie_break: Box<dyn TieBreak>,
+    /// Lots for the policy to break ties with at random.
+    lots: Lots,
+    /// Lots drawn with `UNRESOLVED_TIE_SEED` for the ties the policy leaves unresolved.
+    unresolved_lots: Lots,
+    /// Ties broken at random or left unresolved that haven't yet been taken by `take_ties`, with
+    /// whether lots were drawn to break them.
+    ties: Vec<(TieKind, Vec<CandidateId>, bool)>,
 }
 
 /// Per-candidate intermediate data.
-struct VoteInfo<'a> {
+struct VoteInfo<'a, V: VoteValue> {
     votes: VoteLog,
-    ballots: TransferMap<'a>,
+    /// Parcels of ballots in the order they were received.
+    ///
+    /// The first parcel always holds the candidate's first preference votes.
+    parcels: Vec<HeldParcel<'a, V>>,
     eliminated: bool,
 }
 
-pub struct PreferenceTransfer<'a>(pub CandidateId, pub Frac, pub Vec<&'a mut Ballot>);
+/// A parcel held by a candidate, with the index of the count at which they received it.
+struct HeldParcel<'a, V> {
+    count: usize,
+    parcel: Parcel<'a, V>,
+}
+
+/// A bundle of ballots that are transferred at the same transfer value.
+pub struct Parcel<'a, V> {
+    pub value: V,
+    pub ballots: Vec<&'a mut Ballot>,
+}
 
-pub struct CandidateElected<'a> {
+/// A transfer of one or more parcels of ballots away from a candidate, processed as a single count.
+pub struct PreferenceTransfer<'a, V> {
+    /// The candidates the ballots are transferred from (more than one for a bulk exclusion).
+    pub from: Vec<CandidateId>,
+    pub kind: TransferKind,
+    pub parcels: Vec<Parcel<'a, V>>,
+}
+
+pub struct CandidateElected<'a, V> {
     pub id: CandidateId,
     pub votes: Int,
-    pub transfers: Vec<PreferenceTransfer<'a>>,
+    /// Ballot papers held when elected.
+    pub papers: u64,
+    pub transfers: Vec<PreferenceTransfer<'a, V>>,
 }
 
-pub struct CandidateExcluded<'a> {
+pub struct CandidateExcluded<'a, V> {
     pub id: CandidateId,
-    pub transfers: Vec<PreferenceTransfer<'a>>,
+    pub transfers: Vec<PreferenceTransfer<'a, V>>,
+}
+
+/// One or more candidates excluded together, with their ballots combined for transfer.
+pub struct BulkExclusion<'a, V> {
+    pub ids: Vec<CandidateId>,
+    pub transfers: Vec<PreferenceTransfer<'a, V>>,
+}
+
+impl<'a, V: VoteValue> Parcel<'a, V> {
+    fn first_preferences() -> Self {
+        Parcel {
+            value: V::one(),
+            ballots: vec![],
+        }
+    }
+
+    /// Total number of ballot papers in this parcel.
+    pub fn num_papers(&self) -> u64 {
+        self.ballots.iter().map(|b| b.weight()).sum()
+    }
+}
+
+/// The ballots of a checkpoint, each of which can be placed in one parcel when restoring a count.
+pub struct CheckpointBallots<'a> {
+    ballots: Vec<Option<&'a mut Ballot>>,
+}
+
+impl<'a> CheckpointBallots<'a> {
+    pub fn new(ballots: &'a mut [Ballot]) -> Self {
+        CheckpointBallots {
+            ballots: ballots.iter_mut().map(Some).collect(),
+        }
+    }
+
+    fn take(&mut self, idx: usize) -> Result<&'a mut Ballot, Error> {
+        self.ballots
+            .get_mut(idx)
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                Error::Checkpoint(format!(
+                    "ballot {} is missing, or in more than one parcel",
+                    idx
+                ))
+            })
+    }
+}
+
+impl<'a, V: VoteValue> Parcel<'a, V> {
+    /// Record the parcel for a checkpoint, adding its ballots to `ballots`.
+    fn to_state(&self, ballots: &mut Vec<BallotState>) -> ParcelState {
+        let indices = self
+            .ballots
+            .iter()
+            .map(|b| {
+                ballots.push(BallotState::from(&**b));
+                ballots.len() - 1
+            })
+            .collect();
+        ParcelState {
+            value: self.value.to_frac(),
+            ballots: indices,
+        }
+    }
+
+    fn from_state(state: &ParcelState, ballots: &mut CheckpointBallots<'a>) -> Result<Self, Error> {
+        Ok(Parcel {
+            value: V::from_frac(&state.value),
+            ballots: state
+                .ballots
+                .iter()
+                .map(|&idx| ballots.take(idx))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl<'a, V: VoteValue> PreferenceTransfer<'a, V> {
+    /// Record the transfer for a checkpoint, adding its ballots to `ballots`.
+    pub fn to_state(&self, ballots: &mut Vec<BallotState>) -> TransferState {
+        TransferState {
+            from: self.from.clone(),
+            kind: self.kind,
+            parcels: self.parcels.iter().map(|p| p.to_state(ballots)).collect(),
+        }
+    }
+
+    pub fn from_state(
+        state: &TransferState,
+        ballots: &mut CheckpointBallots<'a>,
+    ) -> Result<Self, Error> {
+        Ok(PreferenceTransfer {
+            from: state.from.clone(),
+            kind: state.kind,
+            parcels: state
+                .parcels
+                .iter()
+                .map(|p| Parcel::from_state(p, ballots))
+                .collect::<Result<_, _>>()?,
+        })
+    }
 }
 
-impl<'a> VoteInfo<'a> {
+impl<'a, V: VoteValue> HeldParcel<'a, V> {
+    fn first_preferences() -> Self {
+        HeldParcel {
+            count: 0,
+            parcel: Parcel::first_preferences(),
+        }
+    }
+}
+
+impl<'a, V: VoteValue> VoteInfo<'a, V> {
     fn new() -> Self {
+        // Start every candidate on zero votes, so that candidates without first preferences
+        // still have a tally.
+        let mut votes = VoteLog::new();
+        votes.update_vote(0, Int::from(0));
         VoteInfo {
-            votes: VoteLog::new(),
-            ballots: new_transfer_map(),
+            votes,
+            parcels: vec![HeldParcel::first_preferences()],
             eliminated: false,
         }
     }
 
-    fn take_ballots(&mut self) -> TransferMap<'a> {
-        mem::replace(&mut self.ballots, new_transfer_map())
+    fn num_papers(&self) -> u64 {
+        self.parcels.iter().map(|p| p.parcel.num_papers()).sum()
+    }
+
+    fn take_held_parcels(&mut self) -> Vec<HeldParcel<'a, V>> {
+        mem::replace(&mut self.parcels, vec![HeldParcel::first_preferences()])
     }
-}
 
-fn new_transfer_map<'a>() -> TransferMap<'a> {
-    let mut map = TransferMap::new();
-    map.insert(frac!(1u64), vec![]);
-    map
+    fn take_parcels(&mut self) -> Vec<Parcel<'a, V>> {
+        self.take_held_parcels()
+            .into_iter()
+            .map(|p| p.parcel)
+            .collect()
+    }
+
+    /// Group all of this candidate's ballots by the value they were received at.
+    fn take_transfer_map(&mut self) -> TransferMap<'a, V> {
+        let mut map = TransferMap::new();
+        for Parcel { value, ballots } in self.take_parcels() {
+            map.entry(value).or_default().extend(ballots);
+        }
+        map
+    }
 }
 
-impl<'a> VoteMap<'a> {
-    pub fn new(candidates: &'a CandidateMap) -> Result<VoteMap<'a>, String> {
+impl<'a, V: VoteValue> VoteMap<'a, V> {
+    pub fn new(candidates: &'a CandidateMap) -> Result<VoteMap<'a, V>, Error> {
         let mut v = VoteMap {
             info: HashMap::new(),
             candidates,
-            one: frac!(1u64),
+            tie_break: Box::new(Countback),
+            lots: Lots::default(),
+            unresolved_lots: Lots::new(Some(UNRESOLVED_TIE_SEED)),
+            ties: vec![],
         };
         for &id in candidates.keys() {
             let prev = v.info.insert(id, VoteInfo::new());
             if prev.is_some() {
-                return Err(format!("Candidate ID {} appears more than once", id));
+                return Err(Error::Config(format!(
+                    "Candidate ID {} appears more than once",
+                    id
+                )));
             }
         }
         debug_assert_eq!(v.info.len(), candidates.len());
         Ok(v)
     }
 
+    /// Resolve ties with the given policy, drawing lots with the given seed.
+    pub fn set_tie_break(&mut self, tie_break: Box<dyn TieBreak>, seed: Option<usize>) {
+        self.tie_break = tie_break;
+        self.lots = Lots::new(seed);
+    }
+
+    /// Restore the candidates' votes and ballots from a checkpoint.
+    pub fn restore(
+        candidates: &'a CandidateMap,
+        states: &[CandidateState],
+        ballots: &mut CheckpointBallots<'a>,
+    ) -> Result<VoteMap<'a, V>, Error> {
+        let mut expected: Vec<_> = candidates.keys().cloned().collect();
+        expected.sort();
+        if states.iter().map(|c| c.id).ne(expected) {
+            return Err(Error::Checkpoint(
+                "checkpoint is for a different set of candidates".to_string(),
+            ));
+        }
+
+        let mut v = VoteMap::new(candidates)?;
+        for state in states {
+            if state.received.len() != state.parcels.len() {
+                return Err(Error::Checkpoint(format!(
+                    "candidate {} has {} parcels but {} counts they were received at",
+                    state.id,
+                    state.parcels.len(),
+                    state.received.len()
+                )));
+            }
+            let info = v.info.get_mut(&state.id).unwrap();
+            info.votes = VoteLog::from_counts(state.votes.clone());
+            info.parcels = state
+                .parcels
+                .iter()
+                .zip(&state.received)
+                .map(|(p, &count)| {
+                    Ok(HeldParcel {
+                        count,
+                        parcel: Parcel::from_state(p, ballots)?,
+                    })
+                })
+                .collect::<Result<_, Error>>()?;
+            info.eliminated = state.eliminated;
+        }
+        Ok(v)
+    }
+
+    /// Record each candidate's votes and ballots for a checkpoint, adding the ballots to `ballots`.
+    pub fn candidate_states(&self, ballots: &mut Vec<BallotState>) -> Vec<CandidateState> {
+        self.info
+            .iter()
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .into_iter()
+            .map(|(&id, info)| CandidateState {
+                id,
+                votes: info.votes.counts().to_vec(),
+                parcels: info
+                    .parcels
+                    .iter()
+                    .map(|p| p.parcel.to_state(ballots))
+                    .collect(),
+                received: info.parcels.iter().map(|p| p.count).collect(),
+                eliminated: info.eliminated,
+            })
+            .collect()
+    }
+
+    /// Advance the lots past the draws made for `ties`, as if they had just been drawn, so a
+    /// resumed count breaks later ties the same way.
+    pub fn replay_draws<'t, I>(&mut self, ties: I)
+    where
+        I: IntoIterator<Item = &'t TieEvent>,
+    {
+        for tie in ties {
+            if tie.drawn {
+                self.lots.replay(&tie.candidates);
+            } else {
+                self.unresolved_lots.replay(&tie.candidates);
+            }
+        }
+    }
+
     /// Add votes to a candidate's tally according to the weight and current preference of a ballot.
     pub fn add(&mut self, idx: usize, ballot: &'a mut Ballot) {
         let candidate = ballot.prefs[ballot.current()];
@@ -86,29 +345,53 @@ impl<'a> VoteMap<'a> {
         // Add to the candidate's tally.
         info.votes.update_vote(idx, Int::from(ballot.weight()));
 
-        // Add the ballot to the appropriate bucket.
-        let bucket = info.ballots.get_mut(&self.one).unwrap();
-        bucket.push(ballot);
+        // Add the ballot to the first preferences parcel.
+        info.parcels[0].parcel.ballots.push(ballot);
     }
 
-    /// Get the IDs of all candidates whose vote exceeds the quota.
-    pub fn get_candidates_with_quota(&self, quota: &Int) -> Vec<CandidateId> {
+    /// Get the IDs of all candidates whose vote exceeds the quota, in descending order of votes.
+    ///
+    /// Candidates with equal votes are ordered by the tie break policy.
+    pub fn get_candidates_with_quota(&mut self, quota: &Int) -> Vec<CandidateId> {
         let mut candidates_with_quota = self
-            .info
-            .iter()
-            .filter(|&(_, info)| !info.eliminated)
-            .map(|(id, info)| (id, info.votes.latest()))
-            .filter(|&(_, votes)| votes >= quota)
+            .candidates_remaining()
+            .map(|(id, info)| (id, info.votes.latest().clone()))
+            .filter(|(_, votes)| votes >= quota)
             .collect::<Vec<_>>();
 
-        // Sort by vote descending.
-        candidates_with_quota.sort_by(|&(_, v1), &(_, v2)| v1.cmp(v2).reverse());
+        // Sort by vote descending, then by ID so that the order never depends on the order of the
+        // map.
+        candidates_with_quota.sort_by(|(id1, v1), (id2, v2)| v2.cmp(v1).then(id1.cmp(id2)));
+
+        let mut ordered = vec![];
+        let mut i = 0;
 
-        candidates_with_quota.into_iter().map(|(c, _)| *c).collect()
+        while i < candidates_with_quota.len() {
+            let votes = &candidates_with_quota[i].1;
+            let mut equal_candidates: Vec<_> = candidates_with_quota[i..]
+                .iter()
+                .take_while(|&(_, v)| v == votes)
+                .map(|&(id, _)| id)
+                .collect();
+            i += equal_candidates.len();
+
+            while equal_candidates.len() > 1 {
+                let next = self.break_tie(&equal_candidates, TieKind::ElectionOrder);
+                ordered.push(next);
+                equal_candidates.retain(|&id| id != next);
+            }
+            ordered.extend(equal_candidates);
+        }
+
+        ordered
     }
 
     /// Get the ID of the candidate with the least votes.
-    pub fn get_last_candidate(&self) -> CandidateId {
+    ///
+    /// Ties are broken by the tie break policy, which by default is countback: the candidate with
+    /// the fewest votes at the most recent count at which the tied candidates had unequal votes is
+    /// chosen, as per section 273(13).
+    pub fn get_last_candidate(&mut self) -> CandidateId {
         let mut sorted_candidates: Vec<_> = self.candidates_remaining().collect();
         sorted_candidates.sort_by_key(|&(_, info)| info.votes.latest());
 
@@ -118,34 +401,48 @@ impl<'a> VoteMap<'a> {
         let min_candidates: Vec<_> = sorted_candidates
             .into_iter()
             .take_while(|&(_, info)| info.votes.latest() == &min_vote)
+            .map(|(candidate, _)| candidate)
             .collect();
 
         if min_candidates.len() == 1 {
-            let (candidate, _) = min_candidates[0];
-            return candidate;
+            return min_candidates[0];
         }
+        self.break_tie(&min_candidates, TieKind::Exclusion)
+    }
 
-        // Try to break the tie based on past tallies.
-        let historical_min = min_candidates
-            .iter()
-            .map(|&(_, info)| &info.votes)
-            .min()
-            .unwrap();
-
-        let hist_min_candidates: Vec<_> = min_candidates
-            .into_iter()
-            .filter(|&(_, info)| &info.votes == historical_min)
-            .map(|(candidate, _)| candidate)
-            .sorted();
-
-        if hist_min_candidates.len() == 1 {
-            return hist_min_candidates[0];
+    /// Resolve a tie between candidates with equal votes using the tie break policy, recording
+    /// the tie if lots were drawn or it couldn't be resolved.
+    fn break_tie(&mut self, tied: &[CandidateId], kind: TieKind) -> CandidateId {
+        let candidates: Vec<_> = tied.iter().cloned().sorted();
+        let info = &self.info;
+        let tie = Tie {
+            kind,
+            history: candidates
+                .iter()
+                .map(|id| info[id].votes.counts())
+                .collect(),
+            candidates,
+        };
+        match self.tie_break.resolve(&tie, &mut self.lots) {
+            Resolution::Resolved(chosen) => chosen,
+            Resolution::Drawn { chosen, candidates } => {
+                self.ties.push((kind, candidates, true));
+                chosen
+            }
+            Resolution::Unresolved(candidates) => {
+                let chosen = self
+                    .unresolved_lots
+                    .draw(&candidates)
+                    .expect("unresolved ties are drawn with a seed");
+                self.ties.push((kind, candidates, false));
+                chosen
+            }
         }
+    }
 
-        // TODO: ideally we would fork the execution and check that the final result
-        // is the same for all possible exclusion choices, but for now we'll just choose
-        // randomly...
-        *thread_rng().choose(&hist_min_candidates).unwrap()
+    /// Take the ties that have been broken at random or left unresolved since the last call.
+    pub fn take_ties(&mut self) -> Vec<(TieKind, Vec<CandidateId>, bool)> {
+        mem::take(&mut self.ties)
     }
 
     pub fn find_next_valid_preference(&self, b: &Ballot) -> Option<usize> {
@@ -157,46 +454,92 @@ impl<'a> VoteMap<'a> {
         None
     }
 
+    /// Current tally of every continuing candidate.
+    pub fn tallies(&self) -> BTreeMap<CandidateId, Tally> {
+        self.candidates_remaining()
+            .map(|(id, info)| {
+                let votes = info.votes.maybe_latest().cloned();
+                let tally = Tally {
+                    votes: votes.unwrap_or_else(|| Int::from(0)),
+                    papers: info.num_papers(),
+                };
+                (id, tally)
+            })
+            .collect()
+    }
+
     pub fn num_candidates_remaining(&self) -> usize {
         self.candidates_remaining().count()
     }
 
-    fn candidates_remaining<'b>(&'b self) -> impl Iterator<Item = (CandidateId, &'b VoteInfo<'a>)> {
+    fn candidates_remaining<'b>(
+        &'b self,
+    ) -> impl Iterator<Item = (CandidateId, &'b VoteInfo<'a, V>)> {
         self.info
             .iter()
             .filter(|&(_, info)| !info.eliminated)
             .map(|(id, info)| (*id, info))
     }
 
-    pub fn elect_remaining(self) -> Vec<CandidateElected<'a>> {
+    /// Every parcel of ballots held by a candidate.
+    pub fn parcels<'b>(&'b self) -> impl Iterator<Item = &'b Parcel<'a, V>> + 'b {
         self.info
+            .values()
+            .flat_map(|info| info.parcels.iter().map(|p| &p.parcel))
+    }
+
+    /// Elect every continuing candidate, in descending order of votes (then ascending ID).
+    pub fn elect_remaining(self) -> Vec<CandidateElected<'a, V>> {
+        let mut remaining: Vec<_> = self
+            .info
             .into_iter()
-            .filter(|&(_, ref info)| !info.eliminated)
+            .filter(|(_, info)| !info.eliminated)
             .map(|(id, info)| CandidateElected {
                 id,
                 votes: info.votes.latest().clone(),
+                papers: info.num_papers(),
                 transfers: vec![],
             })
-            .collect()
+            .collect();
+        remaining.sort_by(|a, b| b.votes.cmp(&a.votes).then(a.id.cmp(&b.id)));
+        remaining
     }
 
     pub fn transfer_preferences(
         &mut self,
         idx: usize,
-        transfer: PreferenceTransfer<'a>,
+        transfer: PreferenceTransfer<'a, V>,
         stats: &mut Stats,
     ) {
-        let PreferenceTransfer(_, transfer_val, all_ballots) = transfer;
+        // Exact value received by each continuing candidate, and the parcels making it up.
+        let mut received: HashMap<CandidateId, (Frac, Vec<Parcel<'a, V>>)> = HashMap::new();
 
-        let grouped_ballots =
-            group_ballots_by_candidate(self, all_ballots, idx, &transfer_val, stats);
+        for Parcel { value, ballots } in transfer.parcels {
+            let grouped_ballots = group_ballots_by_candidate(self, ballots, idx, &value, stats);
 
-        for (continuing_id, ballots) in grouped_ballots {
-            let mut info = self.info.get_mut(&continuing_id).unwrap();
+            for (continuing_id, ballots) in grouped_ballots {
+                let &mut (ref mut total, ref mut parcels) = received
+                    .entry(continuing_id)
+                    .or_insert_with(|| (frac!(0u64), vec![]));
+                *total += exact_ballot_value(&value, &ballots);
+                parcels.push(Parcel {
+                    value: value.clone(),
+                    ballots,
+                });
+            }
+        }
+
+        for (continuing_id, (total, parcels)) in received {
+            let info = self.info.get_mut(&continuing_id).unwrap();
 
             assert!(!info.eliminated);
 
-            let incr = ballot_value(&transfer_val, &ballots);
+            // Votes are truncated to whole numbers, discarding fractions.
+            let incr = total.floor();
+            let loss = total - Frac::from(incr.clone());
+            if !loss.is_zero() {
+                stats.record_loss_by_fraction(idx, &loss);
+            }
             info.votes.update_vote(idx, incr.clone());
             if !incr.is_zero() {
                 trace!(
@@ -207,16 +550,23 @@ impl<'a> VoteMap<'a> {
                 );
             }
 
-            let bucket = info
-                .ballots
-                .entry(transfer_val.clone())
-                .or_insert_with(Vec::new);
-            bucket.extend(ballots);
+            info.parcels.extend(
+                parcels
+                    .into_iter()
+                    .map(|parcel| HeldParcel { count: idx, parcel }),
+            );
         }
     }
 
-    pub fn elect_candidates_with_quota(&mut self, quota: &Int) -> Vec<CandidateElected<'a>> {
-        let candidates = self.get_candidates_with_quota(quota);
+    /// Elect up to `vacancies` candidates with a quota, in descending order of votes.
+    pub fn elect_candidates_with_quota(
+        &mut self,
+        quota: &Int,
+        vacancies: usize,
+        method: SurplusTransfer,
+    ) -> Vec<CandidateElected<'a, V>> {
+        let mut candidates = self.get_candidates_with_quota(quota);
+        candidates.truncate(vacancies);
         let mut elected = vec![];
 
         for candidate in candidates {
@@ -226,27 +576,109 @@ impl<'a> VoteMap<'a> {
             info.eliminated = true;
 
             let num_votes = info.votes.latest().clone();
+            let num_papers = info.num_papers();
+            let surplus = &num_votes - quota;
 
-            // Create `PreferenceTransfer` events for each transfer value.
-            let transfer_map = info.take_ballots();
+            // A candidate with exactly a quota has no surplus, so their ballots stay with them,
+            // and the count moves on without transferring any.
+            if surplus == Int::from(0) {
+                elected.push(CandidateElected {
+                    id: candidate,
+                    votes: num_votes,
+                    papers: num_papers,
+                    transfers: vec![],
+                });
+                continue;
+            }
 
-            // Collect all ballots (erasing existing transfer values).
-            let all_ballots: Vec<_> = transfer_map
-                .into_iter()
-                .flat_map(|(_, ballots)| ballots)
-                .collect();
+            let parcels = match method {
+                SurplusTransfer::InclusiveGregory => {
+                    let parcels = info.take_parcels();
+                    // Collect all ballots (erasing existing transfer values).
+                    let all_ballots: Vec<_> =
+                        Iterator::flatten(parcels.into_iter().map(|p| p.ballots)).collect();
+
+                    let num_ballots: u64 = all_ballots.iter().map(|b| b.weight()).sum();
 
-            let num_ballots: u32 = all_ballots.iter().map(|b| b.weight()).sum();
+                    // Aggregate transfer value that accounts for the ones we just threw out...
+                    let transfer_value = V::ratio(&surplus, &Int::from(num_ballots));
 
-            // Aggregate transfer value that accounts for the ones we just threw out...
-            let transfer_value = Frac::ratio(&(&num_votes - quota), &Int::from(num_ballots));
+                    vec![Parcel {
+                        value: transfer_value,
+                        ballots: all_ballots,
+                    }]
+                }
+                SurplusTransfer::WeightedInclusiveGregory => {
+                    // Scale the value of every ballot by the fraction of the total that is surplus.
+                    // The total is the value of the ballots, rounded up to whole votes, rather
+                    // than the votes, which lost their fractions on the way in. Dividing by the
+                    // votes would transfer more than the surplus.
+                    let parcels = info.take_parcels();
+                    let mut held = frac!(0u64);
+                    for p in &parcels {
+                        held += exact_ballot_value(&p.value, &p.ballots);
+                    }
+                    let mut held_votes = held.floor();
+                    if Frac::from(held_votes.clone()) < held {
+                        held_votes += 1;
+                    }
+                    let ratio = V::ratio(&surplus, &held_votes);
 
-            let pref_transfers = vec![PreferenceTransfer(candidate, transfer_value, all_ballots)];
+                    parcels
+                        .into_iter()
+                        .filter(|p| !p.ballots.is_empty())
+                        .map(|Parcel { value, ballots }| Parcel {
+                            value: value.mul(&ratio),
+                            ballots,
+                        })
+                        .collect()
+                }
+                SurplusTransfer::Meek => {
+                    unreachable!("Meek's method is counted by meek::count_meek")
+                }
+                SurplusTransfer::LastParcel => {
+                    // Only the parcels received at the last count that gave the candidate ballots
+                    // are transferred, the rest stay with the elected candidate.
+                    let (last, held): (Vec<_>, Vec<_>) = {
+                        let parcels = info.take_held_parcels();
+                        let last_count = parcels
+                            .iter()
+                            .filter(|p| !p.parcel.ballots.is_empty())
+                            .map(|p| p.count)
+                            .max()
+                            .expect("elected candidate has ballots");
+                        parcels
+                            .into_iter()
+                            .partition(|p| p.count == last_count && !p.parcel.ballots.is_empty())
+                    };
+                    info.parcels = held;
+
+                    let last_papers: u64 = last.iter().map(|p| p.parcel.num_papers()).sum();
+                    let transfer_value = V::ratio(&surplus, &Int::from(last_papers));
+
+                    // The transfer value can't exceed the value the ballots were received at.
+                    last.into_iter()
+                        .map(|HeldParcel { parcel, .. }| Parcel {
+                            value: if transfer_value > parcel.value {
+                                parcel.value
+                            } else {
+                                transfer_value.clone()
+                            },
+                            ballots: parcel.ballots,
+                        })
+                        .collect()
+                }
+            };
 
             elected.push(CandidateElected {
                 id: candidate,
                 votes: num_votes,
-                transfers: pref_transfers,
+                papers: num_papers,
+                transfers: vec![PreferenceTransfer {
+                    from: vec![candidate],
+                    kind: TransferKind::Surplus,
+                    parcels,
+                }],
             });
         }
 
@@ -254,42 +686,408 @@ impl<'a> VoteMap<'a> {
     }
 
     /// Panics if the `id` is not the `CandidateId` of a real candidate.
-    pub fn exclude_candidate_by_id(&mut self, candidate: CandidateId) -> CandidateExcluded<'a> {
-        let info = self.info.get_mut(&candidate).unwrap();
+    pub fn exclude_candidate_by_id(&mut self, candidate: CandidateId) -> CandidateExcluded<'a, V> {
+        CandidateExcluded {
+            id: candidate,
+            transfers: self.exclude_group(&[candidate]),
+        }
+    }
 
-        info.eliminated = true;
+    /// Mark a group of candidates excluded, and collect their ballots for transfer.
+    ///
+    /// Ballots are grouped by the value they were received at, regardless of which candidate
+    /// they belonged to, and ordered from largest to smallest transfer value.
+    fn exclude_group(&mut self, group: &[CandidateId]) -> Vec<PreferenceTransfer<'a, V>> {
+        let mut transfer_map = TransferMap::new();
 
-        let transfer_map = info.take_ballots();
+        for candidate in group {
+            let info = self.info.get_mut(candidate).unwrap();
+            info.eliminated = true;
+
+            for (value, ballots) in info.take_transfer_map() {
+                transfer_map.entry(value).or_default().extend(ballots);
+            }
+        }
 
-        let mut pref_transfers: Vec<_> = transfer_map
+        transfer_map
             .into_iter()
-            .map(|(transfer_val, ballots)| PreferenceTransfer(candidate, transfer_val, ballots))
+            .rev()
+            .map(|(value, ballots)| PreferenceTransfer {
+                from: group.to_vec(),
+                kind: TransferKind::Exclusion,
+                parcels: vec![Parcel { value, ballots }],
+            })
+            .collect()
+    }
+
+    /// Get the largest group of lowest candidates that can be excluded together.
+    ///
+    /// As per section 273(13A), a group qualifies if its combined vote is less than the vote of
+    /// the next-highest continuing candidate. The group is never so large that fewer continuing
+    /// candidates than `vacancies` would remain.
+    pub fn get_bulk_exclusion(&self, vacancies: usize) -> Vec<CandidateId> {
+        let mut sorted_candidates: Vec<_> = self
+            .candidates_remaining()
+            .map(|(id, info)| (id, info.votes.latest()))
             .collect();
+        sorted_candidates.sort_by_key(|&(id, votes)| (votes, id));
 
-        // Reverse the preference transfer events so they're ordered from largest to
-        // smallest transfer value.
-        pref_transfers.reverse();
+        let max_excluded = sorted_candidates.len().saturating_sub(vacancies.max(1));
 
-        CandidateExcluded {
-            id: candidate,
-            transfers: pref_transfers,
+        let mut combined_vote = Int::from(0);
+        let mut group_size = 0;
+
+        for i in 0..max_excluded {
+            combined_vote += sorted_candidates[i].1;
+            if &combined_vote < sorted_candidates[i + 1].1 {
+                group_size = i + 1;
+            }
         }
+
+        sorted_candidates
+            .into_iter()
+            .take(group_size)
+            .map(|(id, _)| id)
+            .collect()
     }
 
-    // TODO: bulk exclusions.
-    pub fn exclude_candidates(&mut self) -> Vec<CandidateExcluded<'a>> {
-        let candidate = self.get_last_candidate();
-        vec![self.exclude_candidate_by_id(candidate)]
+    /// Exclude the candidate(s) with the fewest votes, returning the IDs of all those excluded.
+    ///
+    /// Multiple candidates are excluded at once when the bulk exclusion rules allow it,
+    /// otherwise only the single lowest candidate is excluded.
+    pub fn exclude_candidates(&mut self, vacancies: usize) -> BulkExclusion<'a, V> {
+        let mut ids = self.get_bulk_exclusion(vacancies);
+        if ids.len() < 2 {
+            ids = vec![self.get_last_candidate()];
+        }
+        let transfers = self.exclude_group(&ids);
+        BulkExclusion { ids, transfers }
     }
 
     pub fn print_summary(&self) {
         trace!("Vote tallies");
-        for (candidate, info) in self.info.iter().filter(|&(_, i)| !i.eliminated) {
+        for (candidate, info) in self.candidates_remaining() {
             trace!(
                 "{:?}: {:?} votes",
-                self.candidates[candidate],
+                self.candidates[&candidate],
                 info.votes.latest()
             );
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng, StdRng};
+    use tie_break::Unresolved;
+
+    fn surplus_parcel_values(method: SurplusTransfer) -> (Vec<Frac>, Int) {
+        let candidates = test_candidates(3);
+        let mut ballots: Vec<_> = (0..4)
+            .map(|_| Ballot::single(ids(&[0, 1])))
+            .chain((0..4).map(|_| Ballot::single(ids(&[2, 0, 1]))))
+            .chain(Some(Ballot::single(ids(&[1]))))
+            .collect();
+        let mut stats = Stats::new();
+
+        let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+        for ballot in &mut ballots {
+            vote_map.add(0, ballot);
+        }
+
+        // Give candidate 0 four ballots at value 1/2, for a total of 6 votes.
+        let mut excluded = vote_map.exclude_candidate_by_id(CandidateId(2));
+        let mut transfer = excluded.transfers.remove(0);
+        transfer.parcels[0].value = frac!(1u64, 2u64);
+        vote_map.transfer_preferences(1, transfer, &mut stats);
+
+        let mut elected = vote_map.elect_candidates_with_quota(&Int::from(3), 1, method);
+        assert_eq!(elected.len(), 1);
+        let transfer = elected[0].transfers.remove(0);
+        let values = transfer.parcels.iter().map(|p| p.value.clone()).collect();
+
+        vote_map.transfer_preferences(2, transfer, &mut stats);
+        let received = vote_map.info[&CandidateId(1)].votes.latest().clone();
+        (values, received)
+    }
+
+    #[test]
+    fn inclusive_gregory_ignores_previous_value() {
+        let (values, received) = surplus_parcel_values(SurplusTransfer::InclusiveGregory);
+        assert_eq!(values, vec![frac!(3u64, 8u64)]);
+        assert_eq!(received, Int::from(4));
+    }
+
+    #[test]
+    fn weighted_inclusive_gregory_scales_previous_value() {
+        let (values, received) = surplus_parcel_values(SurplusTransfer::WeightedInclusiveGregory);
+        assert_eq!(values, vec![frac!(1u64, 2u64), frac!(1u64, 4u64)]);
+        assert_eq!(received, Int::from(4));
+    }
+
+    #[test]
+    fn last_parcel_transfers_capped_value() {
+        let (values, received) = surplus_parcel_values(SurplusTransfer::LastParcel);
+        assert_eq!(values, vec![frac!(1u64, 2u64)]);
+        assert_eq!(received, Int::from(3));
+    }
+
+    #[test]
+    fn last_parcel_transfers_every_parcel_of_last_count() {
+        let candidates = test_candidates(4);
+        let mut ballots: Vec<_> = (0..4)
+            .map(|_| Ballot::single(ids(&[0, 1])))
+            .chain((0..4).map(|_| Ballot::single(ids(&[2, 0, 1]))))
+            .chain((0..4).map(|_| Ballot::single(ids(&[3, 0, 1]))))
+            .collect();
+        let mut stats = Stats::new();
+
+        let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+        for ballot in &mut ballots {
+            vote_map.add(0, ballot);
+        }
+
+        // Give candidate 0 four ballots at value 1/2 and four at 1/4 in the same count, for a
+        // total of 7 votes.
+        let mut transfer = vote_map
+            .exclude_candidate_by_id(CandidateId(2))
+            .transfers
+            .remove(0);
+        let mut other = vote_map
+            .exclude_candidate_by_id(CandidateId(3))
+            .transfers
+            .remove(0);
+        transfer.parcels[0].value = frac!(1u64, 2u64);
+        other.parcels[0].value = frac!(1u64, 4u64);
+        transfer.parcels.extend(other.parcels);
+        vote_map.transfer_preferences(1, transfer, &mut stats);
+        assert_eq!(*vote_map.info[&CandidateId(0)].votes.latest(), Int::from(7));
+
+        // The surplus of 4 is spread over the 8 ballot papers of the last count, at 1/2 each, but
+        // the papers received at 1/4 go on at that value.
+        let mut elected =
+            vote_map.elect_candidates_with_quota(&Int::from(3), 1, SurplusTransfer::LastParcel);
+        let transfer = elected[0].transfers.remove(0);
+        let values: Vec<_> = transfer.parcels.iter().map(|p| p.value.clone()).collect();
+        assert_eq!(values, vec![frac!(1u64, 2u64), frac!(1u64, 4u64)]);
+        assert_eq!(vote_map.info[&CandidateId(0)].num_papers(), 4);
+
+        vote_map.transfer_preferences(2, transfer, &mut stats);
+        assert_eq!(*vote_map.info[&CandidateId(1)].votes.latest(), Int::from(3));
+    }
+
+    /// Vote map where candidates 0-4 have 10, 6, 1, 1 and 2 first preference votes.
+    fn bulk_exclusion_vote_map<'a>(
+        candidates: &'a CandidateMap,
+        ballots: &'a mut Vec<Ballot>,
+    ) -> VoteMap<'a, Frac> {
+        for (id, &n) in [10, 6, 1, 1, 2].iter().enumerate() {
+            ballots.extend((0..n).map(|_| Ballot::single(ids(&[id as u16, 0]))));
+        }
+        let mut vote_map = VoteMap::new(candidates).unwrap();
+        for ballot in ballots {
+            vote_map.add(0, ballot);
+        }
+        vote_map
+    }
+
+    #[test]
+    fn bulk_exclusion() {
+        let candidates = test_candidates(5);
+        let mut ballots = vec![];
+        let mut vote_map = bulk_exclusion_vote_map(&candidates, &mut ballots);
+
+        // 1 + 1 + 2 < 6, but 1 + 1 + 2 + 6 is not less than 10.
+        assert_eq!(vote_map.get_bulk_exclusion(1), ids(&[2, 3, 4]));
+
+        let excluded = vote_map.exclude_candidates(1);
+        assert_eq!(excluded.ids, ids(&[2, 3, 4]));
+        assert_eq!(excluded.transfers.len(), 1);
+        assert_eq!(excluded.transfers[0].parcels[0].num_papers(), 4);
+
+        let mut stats = Stats::new();
+        for transfer in excluded.transfers {
+            vote_map.transfer_preferences(1, transfer, &mut stats);
+        }
+        assert_eq!(vote_map.num_candidates_remaining(), 2);
+        assert_eq!(
+            vote_map.info[&CandidateId(0)].votes.latest(),
+            &Int::from(14)
+        );
+    }
+
+    #[test]
+    fn bulk_exclusion_leaves_enough_candidates() {
+        let candidates = test_candidates(5);
+        let mut ballots = vec![];
+        let mut vote_map = bulk_exclusion_vote_map(&candidates, &mut ballots);
+
+        // Excluding 3 candidates would leave fewer than 3 to fill the vacancies, and no smaller
+        // group qualifies, so only a single candidate is excluded.
+        assert!(vote_map.get_bulk_exclusion(3).is_empty());
+        assert_eq!(vote_map.exclude_candidates(3).ids.len(), 1);
+        assert_eq!(vote_map.num_candidates_remaining(), 4);
+    }
+
+    fn update_vote(vote_map: &mut VoteMap<Frac>, id: u16, idx: usize, votes: i64) {
+        let info = vote_map.info.get_mut(&CandidateId(id)).unwrap();
+        info.votes.update_vote(idx, Int::from(votes));
+    }
+
+    #[test]
+    fn countback_uses_most_recent_unequal_count() {
+        let candidates = test_candidates(3);
+        let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+
+        // Candidate 0 has tallies [2, 5, 5] and candidate 1 has [3, 4, 5].
+        update_vote(&mut vote_map, 0, 0, 2);
+        update_vote(&mut vote_map, 0, 1, 3);
+        update_vote(&mut vote_map, 1, 0, 3);
+        update_vote(&mut vote_map, 1, 1, 1);
+        update_vote(&mut vote_map, 1, 2, 1);
+        update_vote(&mut vote_map, 2, 0, 100);
+
+        assert_eq!(vote_map.get_last_candidate(), CandidateId(1));
+        assert!(vote_map.take_ties().is_empty());
+
+        // Once candidate 0 also has tallies [3, 4, 5], the tie can't be broken.
+        update_vote(&mut vote_map, 0, 0, 1);
+        update_vote(&mut vote_map, 0, 1, -1);
+        update_vote(&mut vote_map, 0, 2, 1);
+        let candidate = vote_map.get_last_candidate();
+        assert!(candidate == CandidateId(0) || candidate == CandidateId(1));
+        assert_eq!(
+            vote_map.take_ties(),
+            vec![(TieKind::Exclusion, ids(&[0, 1]), false)]
+        );
+
+        // With a seed, the tie is broken the same way every time.
+        vote_map.set_tie_break(Box::new(Countback), Some(10));
+        let seeded_choice = vote_map.get_last_candidate();
+        vote_map.set_tie_break(Box::new(Countback), Some(10));
+        assert_eq!(vote_map.get_last_candidate(), seeded_choice);
+        assert_eq!(
+            vote_map.take_ties(),
+            vec![(TieKind::Exclusion, ids(&[0, 1]), true); 2]
+        );
+    }
+
+    #[test]
+    fn election_order_countback() {
+        let candidates = test_candidates(3);
+        let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+
+        // Candidates 0 and 1 both finish on 10 votes, but candidate 1 was ahead at count 1.
+        update_vote(&mut vote_map, 0, 0, 4);
+        update_vote(&mut vote_map, 0, 1, 6);
+        update_vote(&mut vote_map, 1, 0, 5);
+        update_vote(&mut vote_map, 1, 1, 5);
+        update_vote(&mut vote_map, 2, 0, 10);
+        update_vote(&mut vote_map, 2, 1, 0);
+
+        // Candidate 2 was also ahead of candidate 1 at count 0, so comes first.
+        assert_eq!(
+            vote_map.get_candidates_with_quota(&Int::from(10)),
+            ids(&[2, 1, 0])
+        );
+        assert!(vote_map.take_ties().is_empty());
+
+        // A policy that ignores the earlier counts leaves both ties unresolved.
+        vote_map.set_tie_break(Box::new(Unresolved), Some(1));
+        vote_map.get_candidates_with_quota(&Int::from(10));
+        let ties = vote_map.take_ties();
+        assert_eq!(ties.len(), 2);
+        assert_eq!(ties[0], (TieKind::ElectionOrder, ids(&[0, 1, 2]), false));
+    }
+
+    #[test]
+    fn weighted_ballot_surplus() {
+        let candidates = test_candidates(2);
+        let mut ballot = Ballot::multi(6, ids(&[0, 1]));
+        let mut stats = Stats::new();
+
+        let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+        vote_map.add(0, &mut ballot);
+        assert_eq!(vote_map.info[&CandidateId(0)].votes.latest(), &Int::from(6));
+
+        // A surplus of 2 from 6 papers is transferred at 1/3, worth 2 votes in total.
+        let mut elected =
+            vote_map.elect_candidates_with_quota(&Int::from(4), 1, SurplusTransfer::default());
+        let transfer = elected[0].transfers.remove(0);
+        assert_eq!(transfer.parcels[0].value, frac!(1u64, 3u64));
+        vote_map.transfer_preferences(1, transfer, &mut stats);
+        assert_eq!(vote_map.info[&CandidateId(1)].votes.latest(), &Int::from(2));
+    }
+
+    fn random_ballot(rng: &mut StdRng, num_candidates: u16) -> Ballot {
+        let mut prefs: Vec<_> = (0..num_candidates).map(CandidateId).collect();
+        rng.shuffle(&mut prefs);
+        let len = rng.gen_range(1, prefs.len() + 1);
+        prefs.truncate(len);
+        Ballot::single(prefs)
+    }
+
+    /// Check that each continuing candidate's (truncated) tally never exceeds the exact value
+    /// of the ballots they hold, and that the truncation loss is bounded.
+    fn check_truncation(vote_map: &VoteMap<Frac>, num_transfers: usize) {
+        for (_, info) in vote_map.candidates_remaining() {
+            let mut exact = frac!(0u64);
+            for HeldParcel { parcel, .. } in &info.parcels {
+                exact += exact_ballot_value(&parcel.value, &parcel.ballots);
+            }
+            let tally = Frac::from(info.votes.latest().clone());
+            assert!(tally <= exact);
+            assert!(exact < tally + frac!(num_transfers as u64 + 1));
+        }
+    }
+
+    #[test]
+    fn exact_arithmetic_conserves_ballots() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[6usize][..]);
+        let num_candidates = 8;
+        let candidates = test_candidates(num_candidates);
+
+        for &method in &[
+            SurplusTransfer::InclusiveGregory,
+            SurplusTransfer::WeightedInclusiveGregory,
+            SurplusTransfer::LastParcel,
+        ] {
+            let mut ballots: Vec<_> = (0..300)
+                .map(|_| random_ballot(&mut rng, num_candidates))
+                .collect();
+            let num_ballots = ballots.len();
+            let quota = Int::from(num_ballots as u64 / 4 + 1);
+
+            let mut stats = Stats::new();
+            let mut vote_map: VoteMap<Frac> = VoteMap::new(&candidates).unwrap();
+            for ballot in &mut ballots {
+                vote_map.add(0, ballot);
+            }
+
+            // Elect candidates with a quota, or exclude the last candidate, until one remains.
+            let mut idx = 1;
+            while vote_map.num_candidates_remaining() > 1 {
+                let mut transfers: Vec<_> = vote_map
+                    .elect_candidates_with_quota(&quota, num_candidates as usize, method)
+                    .into_iter()
+                    .flat_map(|e| e.transfers)
+                    .collect();
+                if transfers.is_empty() {
+                    transfers = vote_map.exclude_candidates(1).transfers;
+                }
+                for transfer in transfers {
+                    vote_map.transfer_preferences(idx, transfer, &mut stats);
+                    check_truncation(&vote_map, idx);
+                    idx += 1;
+                }
+            }
+
+            let papers_held: u64 = vote_map.info.values().map(|info| info.num_papers()).sum();
+            assert_eq!(
+                papers_held as usize + stats.num_exhausted_ballots(),
+                num_ballots
+            );
+        }
+    }
+}
@@ -1,147 +1,2532 @@
 use std::collections::VecDeque;
-use std::error::Error;
 
+use arith::exact_ballot_value;
+use audit::audit;
+use ballot::{ballots_fingerprint, Ballot, BallotSet};
 use ballot_parse::*;
 use candidate::*;
+use checkpoint::*;
+use config::{ElectionConfig, MalformedBallots};
+use error::Error;
+use meek::count_meek;
+use progress::CountEvent;
+use quota::Fixed;
 use senate_result::*;
+use stats::{MalformedRow, Stats};
+use transcript::*;
+use transfer::SurplusTransfer;
 use util::*;
+use value::*;
 use vote_map::*;
 
-pub fn compute_quota(num_votes: u32, num_positions: usize) -> Int {
-    frac!(num_votes, num_positions as u64 + 1).ceil()
-}
-
-fn elect_candidates<'a>(
-    elected: Vec<CandidateElected<'a>>,
+fn elect_candidates<'a, V>(
+    count: usize,
+    elected: Vec<CandidateElected<'a, V>>,
+    action: Option<&CountAction>,
     result: &mut Senate,
-    preference_transfers: &mut VecDeque<PreferenceTransfer<'a>>,
+    preference_transfers: &mut VecDeque<PreferenceTransfer<'a, V>>,
     candidates: &CandidateMap,
 ) {
     for c in elected {
-        CANDIDATE_ORDER.write(format!(
-            "Elected {:?} with {:?} votes",
-            candidates[&c.id], c.votes
-        ));
-        result.add_senator(c.id, c.votes, candidates);
+        info!("Elected {:?} with {:?} votes", candidates[&c.id], c.votes);
+        result.add_senator(c.id, c.votes, c.papers, count, action, candidates);
         preference_transfers.extend(c.transfers);
     }
 }
 
-fn exclude_candidates<'a, 'b: 'a>(
-    excluded: Vec<CandidateExcluded<'a>>,
-    preference_transfers: &mut VecDeque<PreferenceTransfer<'a>>,
+fn report_quotas<V>(
+    count: usize,
+    elected: &[CandidateElected<V>],
+    progress: &mut dyn FnMut(CountEvent),
+) {
+    for c in elected {
+        progress(CountEvent::QuotaReached {
+            count,
+            candidate: c.id,
+            votes: c.votes.clone(),
+        });
+    }
+}
+
+/// Margin for the final vacancy, filled by `elected` reaching a quota at `count`.
+///
+/// Returns `None` if no candidate is left to be the runner-up.
+fn quota_margin<V: VoteValue>(
+    count: usize,
+    elected: &CandidateElected<V>,
+    vote_map: &VoteMap<V>,
+) -> Option<FinalMargin> {
+    let (runner_up, tally) = vote_map
+        .tallies()
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.votes.cmp(&b.votes))?;
+    Some(FinalMargin {
+        count,
+        elected: elected.id,
+        runner_up,
+        margin: &elected.votes - &tally.votes,
+    })
+}
+
+/// Margin for the final vacancy, filled by one of the `remaining` candidates after the last
+/// exclusion, which happened at `count` when the tallies were `before`.
+///
+/// The senator elected to the final vacancy is the remaining candidate with the fewest votes, and
+/// the runner-up is the excluded candidate with the most votes.
+fn remaining_margin<V>(
+    remaining: &[CandidateElected<V>],
+    last_exclusion: Option<&(usize, Vec<CandidateId>, BTreeMap<CandidateId, Tally>)>,
+) -> Option<FinalMargin> {
+    let &(count, ref excluded, ref before) = last_exclusion?;
+    let votes = |id: &CandidateId| &before[id].votes;
+    let elected = remaining.iter().map(|c| c.id).min_by_key(|id| votes(id))?;
+    let runner_up = excluded.iter().cloned().max_by_key(|id| votes(id))?;
+    Some(FinalMargin {
+        count,
+        elected,
+        runner_up,
+        margin: votes(&elected) - votes(&runner_up),
+    })
+}
+
+fn exclude_candidates<'a, 'b: 'a, V>(
+    excluded: BulkExclusion<'a, V>,
+    preference_transfers: &mut VecDeque<PreferenceTransfer<'a, V>>,
     candidates: &'b CandidateMap,
 ) {
-    for CandidateExcluded { id, transfers } in excluded {
-        CANDIDATE_ORDER.write(format!("Excluded {:?}", candidates[&id]));
-        preference_transfers.extend(transfers);
+    for id in &excluded.ids {
+        debug!("Excluded {:?}", candidates[id]);
     }
+    preference_transfers.extend(excluded.transfers);
 }
 
+/// Exact value of the ballots in some transfers.
+fn transfers_value<V: VoteValue>(transfers: &[PreferenceTransfer<V>]) -> Frac {
+    let mut value = frac!(0u64);
+    for parcel in transfers.iter().flat_map(|t| &t.parcels) {
+        value += exact_ballot_value(&parcel.value, &parcel.ballots);
+    }
+    value
+}
+
+/// Count an election.
+///
+/// Counting performs no IO: ballots are taken from any iterator, and progress is only reported
+/// through the `log` crate.
+///
+/// The ballot stream is consumed once, and only one copy of each distinct preference sequence is
+/// kept in memory, along with the number of ballots cast with that sequence. Memory use is
+/// therefore proportional to the number of distinct sequences, which is much smaller than the
+/// number of ballots for a real election, as many voters vote above the line.
 pub fn decide_election<I>(
     candidates: &CandidateMap,
     disqualified_candidates: &[CandidateId],
     ballot_stream: I,
-    num_positions: usize,
-) -> Result<Senate, Box<Error>>
+    config: &ElectionConfig,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    decide_election_with_progress(
+        candidates,
+        disqualified_candidates,
+        ballot_stream,
+        config,
+        |_| (),
+    )
+}
+
+/// Count an election like `decide_election`, but without guessing the winner of a tie.
+///
+/// If the tie break policy can't resolve a tie, for example when countback can't and `config` has
+/// no tie break seed, the outcome is undecided. Give a seed to have such ties broken at random
+/// instead.
+pub fn decide_election_outcome<I>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    config: &ElectionConfig,
+) -> Result<ElectionOutcome, Error>
 where
     I: IntoIterator<Item = IOBallot>,
 {
+    decide_election(candidates, disqualified_candidates, ballot_stream, config)
+        .map(Senate::into_outcome)
+}
+
+/// Count an election from ballots built in memory, such as a slice or `Vec` of `Ballot`s.
+///
+/// This is `decide_election` without the error handling of a ballot stream, for tests and for
+/// embedding the count where the ballots don't come from a file.
+pub fn decide_election_from_ballots<I, B>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: I,
+    config: &ElectionConfig,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = B>,
+    B: ::std::borrow::Borrow<Ballot>,
+{
+    decide_election(
+        candidates,
+        disqualified_candidates,
+        ballots.into_iter().map(|b| Ok(b.borrow().clone())),
+        config,
+    )
+}
+
+/// Count an election, calling `progress` with each event of the count as it happens.
+pub fn decide_election_with_progress<I, F>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    config: &ElectionConfig,
+    mut progress: F,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+    F: FnMut(CountEvent),
+{
+    count_election(
+        candidates,
+        disqualified_candidates,
+        ballot_stream,
+        config,
+        &mut progress,
+        None,
+    )
+}
+
+/// Count an election, passing a checkpoint to `save` at the start of every `every`th count, and
+/// calling `progress` with each event of the count as it happens.
+///
+/// The checkpoint may be amended before it's saved, for example to add statistics gathered while
+/// reading the ballots. Resuming from any of the checkpoints with `resume_election` gives exactly
+/// the same result as this count.
+pub fn decide_election_with_checkpoints<I, F, P>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    config: &ElectionConfig,
+    every: usize,
+    mut save: F,
+    mut progress: P,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+    F: FnMut(&mut CountState) -> Result<(), Error>,
+    P: FnMut(CountEvent),
+{
+    let mut checkpoints = checkpoints(every, &mut save)?;
+    count_election(
+        candidates,
+        disqualified_candidates,
+        ballot_stream,
+        config,
+        &mut progress,
+        Some(&mut checkpoints),
+    )
+}
+
+/// Resume a count from a checkpoint, carrying on saving checkpoints every `every`th count.
+///
+/// The count must be resumed with the same candidates and configuration it was started with.
+/// `progress` is called with the events of the counts from the checkpoint on, which are the same
+/// as those of the original count.
+pub fn resume_election<F, P>(
+    candidates: &CandidateMap,
+    state: CountState,
+    config: &ElectionConfig,
+    every: usize,
+    mut save: F,
+    mut progress: P,
+) -> Result<Senate, Error>
+where
+    F: FnMut(&mut CountState) -> Result<(), Error>,
+    P: FnMut(CountEvent),
+{
+    state.check_config(config)?;
+    check_audit(config)?;
+    let mut checkpoints = checkpoints(every, &mut save)?;
+    let mut result = Senate::new();
+    result.vacancies = config.num_positions;
+    result.tie_break_seed = config.tie_break_seed;
+
+    info!("Resuming at count #{}", state.count);
+    let progress = &mut progress;
+    match config.arithmetic {
+        Arithmetic::Exact => resume_count::<Frac>(
+            candidates,
+            state,
+            config,
+            &mut result,
+            progress,
+            Some(&mut checkpoints),
+        )?,
+        Arithmetic::Scaled => resume_count::<Scaled>(
+            candidates,
+            state,
+            config,
+            &mut result,
+            progress,
+            Some(&mut checkpoints),
+        )?,
+        Arithmetic::Float => resume_count::<Float>(
+            candidates,
+            state,
+            config,
+            &mut result,
+            progress,
+            Some(&mut checkpoints),
+        )?,
+    }
+
+    if config.audit {
+        audit(&result)?;
+    }
+    Ok(result)
+}
+
+/// Check that the count can be audited if `config` asks for it.
+fn check_audit(config: &ElectionConfig) -> Result<(), Error> {
+    if config.audit && !config.transcript {
+        return Err(Error::Config(
+            "auditing the count needs a transcript".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn checkpoints<'f>(
+    every: usize,
+    save: &'f mut dyn FnMut(&mut CountState) -> Result<(), Error>,
+) -> Result<Checkpoints<'f>, Error> {
+    if every == 0 {
+        return Err(Error::Config(
+            "checkpoints must be at least one count apart".to_string(),
+        ));
+    }
+    Ok(Checkpoints { every, save })
+}
+
+fn count_election<I>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    config: &ElectionConfig,
+    progress: &mut dyn FnMut(CountEvent),
+    checkpoints: Option<&mut Checkpoints>,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    if config.num_positions == 0 {
+        return Err(Error::Config("there are no vacancies to fill".to_string()));
+    }
+    if candidates.is_empty() {
+        return Err(Error::NoCandidates { state: None });
+    }
+    let meek = config.surplus_transfer == SurplusTransfer::Meek;
+    if meek && (config.transcript || config.audit || checkpoints.is_some()) {
+        return Err(Error::Config(
+            "Meek's method keeps no transcript, so it can't be audited or checkpointed".to_string(),
+        ));
+    }
+    check_audit(config)?;
+    let num_standing = candidates
+        .len()
+        .saturating_sub(disqualified_candidates.len());
+    if num_standing < config.num_positions {
+        warn!(
+            "Only {} candidates are standing for {} vacancies, so some will be left unfilled",
+            num_standing, config.num_positions
+        );
+    }
     let mut result = Senate::new();
+    result.vacancies = config.num_positions;
+
+    // Ingest ballots, combining identical preference sequences into a single weighted ballot.
+    let mut ballot_set = BallotSet::new();
+    ingest_ballots(ballot_stream, config, &mut result.stats, |ballot| {
+        ballot_set.add(ballot)
+    })?;
+    let mut ballots = ballot_set.into_ballots();
+    if config.fingerprint_ballots {
+        result
+            .stats
+            .record_ballots_fingerprint(ballots_fingerprint(&ballots));
+    }
+
+    result.quota = config
+        .quota
+        .compute(result.stats.num_valid_votes(), config.num_positions);
+    result.tie_break_seed = config.tie_break_seed;
 
-    // Ingest ballots.
-    let mut ballots = vec![];
+    if meek {
+        count_meek(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            config,
+            &mut result,
+            progress,
+        );
+        return Ok(result);
+    }
+    match config.arithmetic {
+        Arithmetic::Exact => count::<Frac>(
+            candidates,
+            disqualified_candidates,
+            &mut ballots,
+            config,
+            &mut result,
+            progress,
+            checkpoints,
+        )?,
+        Arithmetic::Scaled => count::<Scaled>(
+            candidates,
+            disqualified_candidates,
+            &mut ballots,
+            config,
+            &mut result,
+            progress,
+            checkpoints,
+        )?,
+        Arithmetic::Float => count::<Float>(
+            candidates,
+            disqualified_candidates,
+            &mut ballots,
+            config,
+            &mut result,
+            progress,
+            checkpoints,
+        )?,
+    }
+
+    if config.audit {
+        audit(&result)?;
+    }
+    Ok(result)
+}
 
+/// Read every ballot, recording formal and informal ballots in `stats`, and passing each formal
+/// ballot to `formal`.
+///
+/// Malformed rows are skipped or stop the count, according to `config`.
+fn ingest_ballots<I, F>(
+    ballot_stream: I,
+    config: &ElectionConfig,
+    stats: &mut Stats,
+    mut formal: F,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+    F: FnMut(Ballot),
+{
     for maybe_ballot in ballot_stream {
         match maybe_ballot {
             Ok(ballot) => {
-                result.stats.record_valid_vote(&ballot);
-                ballots.push(ballot);
+                stats.record_valid_vote(&ballot);
+                formal(ballot);
             }
             Err(InvalidBallot(err)) => {
-                // TODO: make ballot parsing errors a hard failure.
-                result.stats.record_invalid_vote(err);
-            }
-            Err(InputError(e)) => {
-                return Err(e);
+                // Informal ballots are part of the count's statistics, not errors.
+                stats.record_invalid_vote(err);
             }
+            Err(InputError(e)) => match (config.malformed_ballots, MalformedRow::categorise(&e)) {
+                (MalformedBallots::Skip, Some(kind)) => {
+                    warn!("Skipping malformed ballot: {}", e);
+                    stats.record_malformed_row(kind);
+                }
+                _ => return Err(e),
+            },
         };
     }
+    Ok(())
+}
+
+/// Read every ballot without counting them, to check the input and tally informal ballots.
+///
+/// Ballots are handled exactly as `decide_election` would, including skipping malformed rows
+/// if `config` says to.
+pub fn validate_ballots<I>(ballot_stream: I, config: &ElectionConfig) -> Result<Stats, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let mut stats = Stats::new();
+    let mut ballot_set = BallotSet::new();
+    ingest_ballots(ballot_stream, config, &mut stats, |ballot| {
+        if config.fingerprint_ballots {
+            ballot_set.add(ballot);
+        }
+    })?;
+    if config.fingerprint_ballots {
+        stats.record_ballots_fingerprint(ballots_fingerprint(&ballot_set.into_ballots()));
+    }
+    Ok(stats)
+}
+
+/// Read every ballot without counting them, like `validate_ballots`, and collect the formal
+/// ballots with identical preferences combined.
+pub fn collect_ballots<I>(
+    ballot_stream: I,
+    config: &ElectionConfig,
+) -> Result<(BallotSet, Stats), Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let mut stats = Stats::new();
+    let mut ballot_set = BallotSet::new();
+    ingest_ballots(ballot_stream, config, &mut stats, |ballot| {
+        ballot_set.add(ballot)
+    })?;
+    Ok((ballot_set, stats))
+}
+
+/// Fill a casual vacancy by re-counting the original ballots with the departed senator excluded.
+///
+/// The quota from the original count is used, rather than one computed for a single vacancy, and
+/// exactly one candidate is elected. All other options are taken from `config`.
+pub fn recount_for_vacancy<I>(
+    ballot_stream: I,
+    excluded: CandidateId,
+    candidates: &CandidateMap,
+    original_quota: &Int,
+    config: &ElectionConfig,
+) -> Result<Senate, Error>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let recount_config = ElectionConfig {
+        num_positions: 1,
+        quota: Box::new(Fixed(original_quota.clone())),
+        ..config.clone()
+    };
+    decide_election(candidates, &[excluded], ballot_stream, &recount_config)
+}
+
+/// Saves a checkpoint of the count every `every` counts.
+struct Checkpoints<'f> {
+    every: usize,
+    save: &'f mut dyn FnMut(&mut CountState) -> Result<(), Error>,
+}
+
+/// Everything about a count in progress that changes from one count to the next, besides the
+/// result so far.
+struct LiveCount<'a, V: VoteValue> {
+    quota: Int,
+    /// Map from candidate IDs to numbers of votes.
+    vote_map: VoteMap<'a, V>,
+    preference_transfers: VecDeque<PreferenceTransfer<'a, V>>,
+    /// Excluded candidates, in the order they were excluded.
+    excluded_standings: Vec<CandidateStanding>,
+    /// The most recent exclusion, with the tallies just before it, for the final margin.
+    last_exclusion: Option<(usize, Vec<CandidateId>, BTreeMap<CandidateId, Tally>)>,
+    transcript: Option<Transcript>,
+}
+
+impl<'a, V: VoteValue> LiveCount<'a, V> {
+    /// Take a checkpoint at the start of `count`.
+    fn checkpoint(&self, count: usize, config: &ElectionConfig, result: &Senate) -> CountState {
+        let mut ballots = vec![];
+        let candidates = self.vote_map.candidate_states(&mut ballots);
+        let pending = self
+            .preference_transfers
+            .iter()
+            .map(|t| t.to_state(&mut ballots))
+            .collect();
+        CountState {
+            count,
+            num_positions: config.num_positions,
+            arithmetic: config.arithmetic,
+            surplus_transfer: config.surplus_transfer,
+            tie_break: config.tie_break.name(),
+            tie_break_seed: config.tie_break_seed,
+            quota: self.quota.clone(),
+            ballots,
+            candidates,
+            pending,
+            senators: result
+                .senators
+                .iter()
+                .zip(&result.elections)
+                .map(|((c, votes), election)| SenatorState {
+                    id: c.id,
+                    votes: votes.clone(),
+                    election: election.clone(),
+                })
+                .collect(),
+            ties: result.ties.clone(),
+            excluded: self.excluded_standings.clone(),
+            last_exclusion: self.last_exclusion.as_ref().map(
+                |&(count, ref excluded, ref before)| ExclusionState {
+                    count,
+                    excluded: excluded.clone(),
+                    before: before.clone(),
+                },
+            ),
+            surplus_transfers: result.surplus_transfers,
+            stats: result.stats.clone(),
+            first_preferences: result.first_preferences.clone(),
+            transcript: self.transcript.clone(),
+        }
+    }
+
+    /// Record the candidate holding each ballot still in the count, including those waiting to
+    /// be transferred, if the destinations of ballots are being tracked.
+    fn record_ballot_destinations(&self, stats: &mut Stats) {
+        if stats.ballot_destinations().is_none() {
+            return;
+        }
+        let pending = self.preference_transfers.iter().flat_map(|t| &t.parcels);
+        for parcel in self.vote_map.parcels().chain(pending) {
+            let value = parcel.value.to_frac();
+            for ballot in &parcel.ballots {
+                stats.record_ballot_held(ballot, ballot.prefs[ballot.current()], &value);
+            }
+        }
+    }
+}
+
+/// Run the count on a set of valid ballots, using the given arithmetic for vote values.
+fn count<V: VoteValue>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &mut [Ballot],
+    config: &ElectionConfig,
+    result: &mut Senate,
+    progress: &mut dyn FnMut(CountEvent),
+    checkpoints: Option<&mut Checkpoints>,
+) -> Result<(), Error> {
+    let live = first_count::<V>(
+        candidates,
+        disqualified_candidates,
+        ballots,
+        config,
+        result,
+        progress,
+    )?;
+    later_counts(2, live, candidates, config, result, progress, checkpoints)
+}
+
+/// Carry on with a count from a checkpoint, using the given arithmetic for vote values.
+fn resume_count<V: VoteValue>(
+    candidates: &CandidateMap,
+    state: CountState,
+    config: &ElectionConfig,
+    result: &mut Senate,
+    progress: &mut dyn FnMut(CountEvent),
+    checkpoints: Option<&mut Checkpoints>,
+) -> Result<(), Error> {
+    let CountState {
+        count,
+        quota,
+        ballots,
+        candidates: candidate_states,
+        pending,
+        senators,
+        ties,
+        excluded,
+        last_exclusion,
+        surplus_transfers,
+        stats,
+        first_preferences,
+        transcript,
+        ..
+    } = state;
+
+    let mut ballots: Vec<Ballot> = ballots.into_iter().map(Ballot::from).collect();
+    let mut ballot_refs = CheckpointBallots::new(&mut ballots);
+    let mut vote_map: VoteMap<V> =
+        VoteMap::restore(candidates, &candidate_states, &mut ballot_refs)?;
+    vote_map.set_tie_break(config.tie_break.clone(), config.tie_break_seed);
+    vote_map.replay_draws(&ties);
+    let preference_transfers = pending
+        .iter()
+        .map(|t| PreferenceTransfer::from_state(t, &mut ballot_refs))
+        .collect::<Result<_, _>>()?;
+
+    for s in senators {
+        if !candidates.contains_key(&s.id) {
+            return Err(Error::Checkpoint(format!(
+                "senator {} isn't standing",
+                s.id
+            )));
+        }
+        result.restore_senator(s.id, s.votes, s.election, candidates);
+    }
+    result.ties = ties;
+    result.quota = quota.clone();
+    result.stats = stats;
+    result.first_preferences = first_preferences;
+    result.surplus_transfers = surplus_transfers;
+
+    let live = LiveCount {
+        quota,
+        vote_map,
+        preference_transfers,
+        excluded_standings: excluded,
+        last_exclusion: last_exclusion.map(|e| (e.count, e.excluded, e.before)),
+        transcript,
+    };
+    later_counts(
+        count,
+        live,
+        candidates,
+        config,
+        result,
+        progress,
+        checkpoints,
+    )
+}
+
+/// Distribute first preferences, and elect any candidates with a quota at the first count.
+///
+/// The ballots of disqualified candidates are transferred before the first count.
+fn first_count<'a, V: VoteValue>(
+    candidates: &'a CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &'a mut [Ballot],
+    config: &ElectionConfig,
+    result: &mut Senate,
+    progress: &mut dyn FnMut(CountEvent),
+) -> Result<LiveCount<'a, V>, Error> {
+    let quota = result.quota.clone();
+    let num_positions = config.num_positions;
+    let surplus_transfer = config.surplus_transfer;
 
-    // Map from candidate IDs to numbers of votes.
-    let mut vote_map = VoteMap::new(candidates)?;
+    let mut vote_map: VoteMap<V> = VoteMap::new(candidates)?;
+    vote_map.set_tie_break(config.tie_break.clone(), config.tie_break_seed);
+    if config.ballot_destinations {
+        result.stats.track_ballot_destinations();
+    }
 
     // Allocate first preference votes.
-    for ballot_ref in &mut ballots {
+    for ballot_ref in ballots {
         vote_map.add(0, ballot_ref);
     }
 
-    let quota = compute_quota(result.stats.num_valid_votes(), num_positions);
-
     let mut preference_transfers = VecDeque::new();
 
+    let mut excluded_standings = vec![];
+
     // Exclude all the disqualified candidates.
+    let first_preferences = vote_map.tallies();
     for &disqual_id in disqualified_candidates {
         let CandidateExcluded { id, transfers } = vote_map.exclude_candidate_by_id(disqual_id);
-        CANDIDATE_ORDER.write(format!("Disqualified {:?}", candidates[&id]));
+        info!("Disqualified {:?}", candidates[&id]);
+        excluded_standings.push(CandidateStanding {
+            id,
+            status: Status::Excluded,
+            votes: first_preferences[&id].votes.clone(),
+            papers: first_preferences[&id].papers,
+            count: 0,
+        });
         for transfer in transfers {
             vote_map.transfer_preferences(0, transfer, &mut result.stats);
         }
     }
 
-    info!("Count #1");
-    let elected_on_first_prefs = vote_map.elect_candidates_with_quota(&quota);
+    let mut transcript = if config.transcript {
+        Some(Transcript::new())
+    } else {
+        None
+    };
+
+    result.first_preferences =
+        FirstPreferenceCount::new(vote_map.tallies(), candidates, &result.stats);
+
+    debug!("Count #1: first preferences");
+    if let Some(ref mut transcript) = transcript {
+        let tallies = vote_map.tallies();
+        transcript.record_count(
+            CountAction::FirstPreferences,
+            vec![],
+            &BTreeMap::new(),
+            tallies,
+        );
+        transcript.record_loss_by_fraction(result.stats.loss_by_fraction_in(0));
+    }
+    let elected_on_first_prefs =
+        vote_map.elect_candidates_with_quota(&quota, num_positions, surplus_transfer);
+    if let Some(ref mut transcript) = transcript {
+        transcript.record_elected(elected_on_first_prefs.iter().map(|c| c.id));
+    }
+    report_quotas(1, &elected_on_first_prefs, progress);
+    if result.num_elected() + elected_on_first_prefs.len() == num_positions {
+        result.final_margin = elected_on_first_prefs
+            .last()
+            .and_then(|c| quota_margin(1, c, &vote_map));
+    }
     elect_candidates(
+        1,
         elected_on_first_prefs,
-        &mut result,
+        Some(&CountAction::FirstPreferences),
+        result,
         &mut preference_transfers,
         candidates,
     );
+    result.record_ties(1, vote_map.take_ties());
+
+    Ok(LiveCount {
+        quota,
+        vote_map,
+        preference_transfers,
+        excluded_standings,
+        last_exclusion: None,
+        transcript,
+    })
+}
+
+/// Carry out every count from `first` onwards, until every vacancy is filled.
+fn later_counts<'a, V: VoteValue>(
+    first: usize,
+    mut live: LiveCount<'a, V>,
+    candidates: &'a CandidateMap,
+    config: &ElectionConfig,
+    result: &mut Senate,
+    progress: &mut dyn FnMut(CountEvent),
+    mut checkpoints: Option<&mut Checkpoints>,
+) -> Result<(), Error> {
+    let num_positions = config.num_positions;
+    let surplus_transfer = config.surplus_transfer;
 
-    for i in 2.. {
-        info!("Count #{}", i);
+    // Candidates left over once every vacancy is filled.
+    let mut continuing = vec![];
 
-        if preference_transfers.is_empty() {
-            // If the number of candidates remaining is equal to the number of positions, elect
-            // them all.
-            let positions_remaining = num_positions - result.num_elected();
-            if vote_map.num_candidates_remaining() == positions_remaining {
-                let remaining = vote_map.elect_remaining();
+    for i in first.. {
+        // Once the number of continuing candidates equals the number of vacancies left, elect
+        // them all without transferring any more ballots, as per section 273(17). This includes
+        // stopping as soon as every vacancy is filled. If there were never enough candidates to
+        // fill the vacancies, all of them are elected and the rest of the vacancies are unfilled.
+        let positions_remaining = num_positions - result.num_elected();
+        if positions_remaining == 0
+            || live.vote_map.num_candidates_remaining() <= positions_remaining
+        {
+            live.record_ballot_destinations(&mut result.stats);
+            let remaining = live.vote_map.elect_remaining();
+            if positions_remaining == 0 {
+                continuing = remaining
+                    .into_iter()
+                    .map(|c| CandidateStanding {
+                        id: c.id,
+                        status: Status::Continuing,
+                        votes: c.votes,
+                        papers: c.papers,
+                        count: i - 1,
+                    })
+                    .collect();
+            } else if !remaining.is_empty() {
+                if let Some(ref mut transcript) = live.transcript {
+                    transcript.record_elected(remaining.iter().map(|c| c.id));
+                }
+                result.final_margin = remaining_margin(&remaining, live.last_exclusion.as_ref());
+                progress(CountEvent::ElectedRemaining {
+                    count: i,
+                    candidates: remaining.iter().map(|c| c.id).collect(),
+                });
                 elect_candidates(
+                    i,
                     remaining,
-                    &mut result,
-                    &mut preference_transfers,
+                    None,
+                    result,
+                    &mut live.preference_transfers,
                     candidates,
                 );
-                break;
             }
+            break;
+        }
+
+        if config.max_counts.is_some_and(|max| i > max) {
+            warn!("Stopped at the limit of {} counts", i - 1);
+            live.record_ballot_destinations(&mut result.stats);
+            let mut tallies: Vec<_> = live.vote_map.tallies().into_iter().collect();
+            tallies.sort_by(|(id1, t1), (id2, t2)| t2.votes.cmp(&t1.votes).then(id1.cmp(id2)));
+            continuing = tallies
+                .into_iter()
+                .map(|(id, tally)| CandidateStanding {
+                    id,
+                    status: Status::Continuing,
+                    votes: tally.votes,
+                    papers: tally.papers,
+                    count: i - 1,
+                })
+                .collect();
+            result.count_limit_reached = true;
+            break;
+        }
+
+        if let Some(ref mut checkpoints) = checkpoints {
+            if i % checkpoints.every == 0 {
+                let mut state = live.checkpoint(i, config, result);
+                (checkpoints.save)(&mut state)?;
+            }
+        }
 
-            // Exclude some candidates if we've run out of things to do.
-            // as per section 13 of COMMONWEALTH ELECTORAL ACT 1918 - SECT 273
-            let excluded = vote_map.exclude_candidates();
-            exclude_candidates(excluded, &mut preference_transfers, candidates);
+        if live.preference_transfers.is_empty() {
+            // Exclude some candidates if we've run out of things to do, as per section 273(13),
+            // excluding several at once where section 273(13A) allows.
+            let before = live.vote_map.tallies();
+            let excluded = live.vote_map.exclude_candidates(positions_remaining);
+            for id in &excluded.ids {
+                live.excluded_standings.push(CandidateStanding {
+                    id: *id,
+                    status: Status::Excluded,
+                    votes: before[id].votes.clone(),
+                    papers: before[id].papers,
+                    count: i,
+                });
+            }
+            // The ballots leave at their exact value, which returns any fractions of a vote that
+            // were lost when the excluded candidates received them.
+            let mut tallies = frac!(0u64);
+            for id in &excluded.ids {
+                tallies += Frac::from(before[id].votes.clone());
+            }
+            let regained = transfers_value(&excluded.transfers) - tallies;
+            result.stats.record_loss_by_fraction(i - 1, &-regained);
+            live.last_exclusion = Some((i, excluded.ids.clone(), before));
+            exclude_candidates(excluded, &mut live.preference_transfers, candidates);
         }
 
         // Transfer pending preferences.
-        let transfer = preference_transfers
+        let transfer = live
+            .preference_transfers
             .pop_front()
             .expect("election should terminate before running out of preferences to transfer");
 
-        trace!(
-            "Transferring preferences for {:?} at value {:?}",
-            candidates[&transfer.0],
-            transfer.1
+        let before = live.transcript.as_ref().map(|_| {
+            let parcels: Vec<ParcelRecord> = transfer
+                .parcels
+                .iter()
+                .map(|p| ParcelRecord {
+                    transfer_value: p.value.to_frac(),
+                    papers: p.num_papers(),
+                    votes: p.value.times_papers(p.num_papers()).floor(),
+                })
+                .collect();
+            (parcels, live.vote_map.tallies())
+        });
+        let action = match transfer.kind {
+            TransferKind::Surplus => CountAction::Surplus(transfer.from[0]),
+            TransferKind::Exclusion => CountAction::Exclusion(transfer.from.clone()),
+        };
+        let transfer_values: Vec<Frac> =
+            transfer.parcels.iter().map(|p| p.value.to_frac()).collect();
+        let papers = transfer.parcels.iter().map(Parcel::num_papers).sum();
+        debug!(
+            "Count #{}: {} of {:?}, {} ballot papers at transfer values {}",
+            i,
+            match transfer.kind {
+                TransferKind::Surplus => "surplus",
+                TransferKind::Exclusion => "exclusion",
+            },
+            transfer
+                .from
+                .iter()
+                .map(|id| &candidates[id])
+                .collect::<Vec<_>>(),
+            papers,
+            transfer_values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
         );
-        vote_map.transfer_preferences(i - 1, transfer, &mut result.stats);
+        progress(match transfer.kind {
+            TransferKind::Surplus => CountEvent::SurplusTransfer {
+                count: i,
+                candidate: transfer.from[0],
+                papers,
+                transfer_values: transfer_values.clone(),
+            },
+            TransferKind::Exclusion => CountEvent::Exclusion {
+                count: i,
+                candidates: transfer.from.clone(),
+                papers,
+                transfer_values: transfer_values.clone(),
+            },
+        });
+
+        let is_exclusion = transfer.kind == TransferKind::Exclusion;
+        if !is_exclusion {
+            result.surplus_transfers += 1;
+
+            // Any part of the surplus not carried by the ballots transferred is lost, as the
+            // senator keeps only a quota.
+            let tally = &result
+                .senators
+                .iter()
+                .find(|(c, _)| c.id == transfer.from[0])
+                .expect("surplus is transferred from a senator")
+                .1;
+            let surplus = Frac::from(tally - &live.quota);
+            let transferred = transfers_value(std::slice::from_ref(&transfer));
+            let remainder = surplus - transferred;
+            // Transfer values are rounded down, and capped by the last parcel method, so the
+            // ballots can carry less than the surplus, but never more.
+            assert!(
+                remainder >= frac!(0u64),
+                "ballots transferred at count {} carry more than the surplus",
+                i
+            );
+            result.stats.record_loss_by_fraction(i - 1, &remainder);
+        }
+        live.vote_map
+            .transfer_preferences(i - 1, transfer, &mut result.stats);
+
+        if is_exclusion && config.recalculate_quota_on_exhaustion {
+            // Votes still in the count, which excludes those lost to fractions as well as those
+            // exhausted.
+            let mut lost = result.stats.exhausted_vote_value();
+            lost += result.stats.loss_by_fraction();
+            let lost = Option::<u64>::from(&lost.floor()).expect("lost votes fit in a u64");
+            let active_votes = result.stats.num_valid_votes() - lost;
+            let new_quota = config.quota.compute(active_votes, num_positions);
+            if new_quota < live.quota {
+                info!("Quota reduced to {} after exhaustion", new_quota);
+                live.quota = new_quota;
+                result.quota = live.quota.clone();
+            }
+        }
+
+        if let (Some(transcript), Some((parcels, before))) = (live.transcript.as_mut(), before) {
+            transcript.record_count(action.clone(), parcels, &before, live.vote_map.tallies());
+            transcript.record_loss_by_fraction(result.stats.loss_by_fraction_in(i - 1));
+        }
 
         // Elect any candidates with a full quota, and stage their preference transfers.
-        let elected = vote_map.elect_candidates_with_quota(&quota);
-        elect_candidates(elected, &mut result, &mut preference_transfers, candidates);
+        let vacancies = num_positions - result.num_elected();
+        let elected =
+            live.vote_map
+                .elect_candidates_with_quota(&live.quota, vacancies, surplus_transfer);
+        if let Some(ref mut transcript) = live.transcript {
+            transcript.record_elected(elected.iter().map(|c| c.id));
+        }
+        report_quotas(i, &elected, progress);
+        if !elected.is_empty() && result.num_elected() + elected.len() == num_positions {
+            result.final_margin = elected
+                .last()
+                .and_then(|c| quota_margin(i, c, &live.vote_map));
+        }
+        elect_candidates(
+            i,
+            elected,
+            Some(&action),
+            result,
+            &mut live.preference_transfers,
+            candidates,
+        );
+        result.record_ties(i, live.vote_map.take_ties());
 
-        vote_map.print_summary();
+        live.vote_map.print_summary();
     }
 
-    assert_eq!(result.num_elected(), num_positions);
+    assert!(result.num_elected() <= num_positions);
 
-    Ok(result)
+    let mut standings: Vec<_> = result
+        .senators
+        .iter()
+        .zip(&result.elections)
+        .map(|((c, votes), e)| CandidateStanding {
+            id: c.id,
+            status: Status::Elected,
+            votes: votes.clone(),
+            papers: e.papers,
+            count: e.elected_at_count,
+        })
+        .collect();
+    standings.extend(continuing);
+    standings.extend(live.excluded_standings.into_iter().rev());
+    result.final_standings = standings;
+
+    progress(CountEvent::FinalResult {
+        elected: result.senators.iter().map(|(c, _)| c.id).collect(),
+    });
+
+    let elected: Vec<_> = result.senators.iter().map(|(c, _)| c.id).collect();
+    result.stats.record_elected_destinations(&elected);
+    result.transcript = live.transcript;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::FormalityRules;
+    use csv;
+    use proptest::prelude::*;
+    use rand::{Rng, SeedableRng, StdRng};
+    use serde_json;
+    use stats::BallotDestination;
+    use std::io;
+    use std::iter;
+    use tie_break::Unresolved;
+    use transfer::SurplusTransfer;
+
+    fn repeat(n: usize, prefs: &[u16]) -> Vec<Ballot> {
+        (0..n).map(|_| Ballot::single(ids(prefs))).collect()
+    }
+
+    fn elected_ids(result: &Senate) -> Vec<CandidateId> {
+        result.senators.iter().map(|(c, _)| c.id).collect()
+    }
+
+    #[test]
+    fn ballots_from_slice() {
+        let ballots = [
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(3, ids(&[2, 1])),
+        ];
+        let config = ElectionConfig::new(2);
+        let candidates = test_candidates(3);
+        let result = decide_election_from_ballots(&candidates, &[], &ballots[..], &config).unwrap();
+        assert_eq!(elected_ids(&result), ids(&[0, 1]));
+
+        // The same ballots as a `Vec`, counted the same way as a stream of them.
+        let streamed =
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config).unwrap();
+        let owned =
+            decide_election_from_ballots(&candidates, &[], ballots.to_vec(), &config).unwrap();
+        assert_eq!(elected_ids(&owned), elected_ids(&streamed));
+        assert_eq!(owned.summary(), streamed.summary());
+    }
+
+    #[test]
+    fn first_preferences() {
+        // Candidate 3 is disqualified, and one of their ballots exhausts before the first count.
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2, 1])),
+            Ballot::multi(1, ids(&[3, 2])),
+            Ballot::multi(1, ids(&[3])),
+            Ballot::multi(2, ids(&[4])),
+            Ballot::multi(1, ids(&[5, 4])),
+        ];
+        // Candidates 4 and 5 are ungrouped.
+        let mut candidates = test_candidates(6);
+        // Party X stands in group A and with candidate 4, and candidate 5 is an independent.
+        let groups = [
+            (0, "A", "X"),
+            (1, "A", "X"),
+            (2, "B", "Y"),
+            (3, "B", "Y"),
+            (4, "UG", "X"),
+            (5, "UG", "Independent"),
+        ];
+        for &(id, group, party) in &groups {
+            let c = candidates.get_mut(&CandidateId(id)).unwrap();
+            c.group_name = group.to_string();
+            c.party = party.to_string();
+        }
+        let tally = |votes: u64| Tally {
+            votes: Int::from(votes),
+            papers: votes,
+        };
+        let result = decide_election(
+            &candidates,
+            &ids(&[3]),
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(2),
+        )
+        .unwrap();
+        let first = &result.first_preferences;
+        let expected: BTreeMap<_, _> = vec![
+            (CandidateId(0), tally(6)),
+            (CandidateId(1), tally(3)),
+            (CandidateId(2), tally(3)),
+            (CandidateId(4), tally(2)),
+            (CandidateId(5), tally(1)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(first.candidates, expected);
+        assert_eq!(first.groups["A"], tally(9));
+        assert_eq!(first.groups["B"], tally(3));
+        let (c4, c5) = (
+            distinct_name(&candidates, CandidateId(4)),
+            distinct_name(&candidates, CandidateId(5)),
+        );
+        assert_eq!(first.groups[&c4], tally(2));
+        assert_eq!(first.groups[&c5], tally(1));
+        assert_eq!((first.formal_ballots, first.exhausted_ballots), (16, 1));
+        assert_eq!(first.parties["X"], tally(11));
+        assert_eq!(first.parties["Y"], tally(3));
+        assert_eq!(first.parties[INDEPENDENT], tally(1));
+        // Candidate 0 is elected with a quota, and the second seat is decided by a tie.
+        let seats = result.seats_by_party();
+        assert!(seats["X"] >= 1);
+        assert_eq!(seats.values().sum::<usize>(), 2);
+
+        let primary_votes = first.primary_votes();
+        let order: Vec<_> = primary_votes.iter().map(|&(name, _)| name).collect();
+        assert_eq!(order, vec!["A", "B", &c4[..], &c5[..]]);
+        assert_eq!(primary_votes[0].1, 56.25);
+        let total: f64 = primary_votes.iter().map(|&(_, pc)| pc).sum();
+        assert!((total + first.percentage(&tally(first.exhausted_ballots)) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bulk_exclusion_bundles_in_descending_value() {
+        // Candidate 0 is elected on first preferences, passing 1/10 of a vote to each of its
+        // ballots for candidates 3 and 4. Candidates 2, 3 and 4 are then excluded together.
+        let ballots: Vec<_> = repeat(9, &[0, 3, 1])
+            .into_iter()
+            .chain(repeat(1, &[0, 4, 5]))
+            .chain(repeat(7, &[1]))
+            .chain(repeat(7, &[5]))
+            .chain(repeat(2, &[2, 1]))
+            .collect();
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let result = decide_election(
+            &test_candidates(6),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+        )
+        .unwrap();
+        let transcript = result.transcript.as_ref().unwrap();
+        assert_eq!(
+            transcript.counts[2].action,
+            CountAction::Exclusion(ids(&[3, 4, 2]))
+        );
+        // The bundle at full value goes first, and gives candidate 1 a quota of 9 votes, so the
+        // bundle at 1/10 is never transferred.
+        assert_eq!(transcript.counts[2].transfer_values, vec![frac!(1u64)]);
+        assert_eq!(transcript.counts.len(), 3);
+        assert_eq!(elected_ids(&result), ids(&[0, 1]));
+        assert_eq!(result.senators[1].1, Int::from(9));
+        assert_eq!(result.elections[1].elected_at_count, 3);
+        let elected_on = result.elections[1].elected_on.as_ref().unwrap();
+        assert_eq!(elected_on.action, CountAction::Exclusion(ids(&[3, 4, 2])));
+        assert_eq!(elected_on.from.len(), 3);
+    }
+
+    #[test]
+    fn max_counts() {
+        // Candidate 0 is elected on first preferences, and the second seat takes a surplus
+        // transfer and an exclusion to decide.
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(2, &[2, 3]))
+            .chain(repeat(1, &[3]))
+            .collect();
+        let candidates = test_candidates(4);
+        let count = |config: &ElectionConfig| {
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), config).unwrap()
+        };
+        let full = count(&ElectionConfig::new(2));
+        assert!(!full.count_limit_reached);
+        assert!(full.num_counts() > 2);
+
+        let config = ElectionConfig::builder(2)
+            .transcript(true)
+            .max_counts(2)
+            .build();
+        let limited = count(&config);
+        assert!(limited.count_limit_reached);
+        assert_eq!(elected_ids(&limited), ids(&[0]));
+        assert_eq!(limited.transcript.unwrap().counts.len(), 2);
+        // Everyone left is still continuing, as of the last count.
+        let continuing: Vec<_> = limited
+            .final_standings
+            .iter()
+            .filter(|s| s.status == Status::Continuing)
+            .map(|s| (s.id, s.count))
+            .collect();
+        assert_eq!(continuing.len(), 3);
+        assert!(continuing.iter().all(|&(_, count)| count == 2));
+    }
+
+    #[test]
+    fn undecided_tie() {
+        // Candidate 0 is elected on first preferences, and candidates 1 and 2 are tied for the
+        // second seat at every count.
+        let ballots: Vec<_> = repeat(5, &[0])
+            .into_iter()
+            .chain(repeat(2, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let candidates = test_candidates(3);
+        let count = |config: &ElectionConfig| {
+            decide_election_outcome(&candidates, &[], ballots.iter().cloned().map(Ok), config)
+                .unwrap()
+        };
+
+        match count(&ElectionConfig::new(2)) {
+            ElectionOutcome::Undecided {
+                contested_seat,
+                candidates,
+                count,
+            } => {
+                assert_eq!(contested_seat, 2);
+                assert_eq!(candidates, ids(&[1, 2]));
+                // After candidate 0's surplus exhausts at count 2.
+                assert_eq!(count, 3);
+            }
+            outcome => panic!("expected an undecided outcome, got {:?}", outcome),
+        }
+
+        // The count still carries on past the tie, the same way every time.
+        let senators = || {
+            let result = decide_election(
+                &candidates,
+                &[],
+                ballots.iter().cloned().map(Ok),
+                &ElectionConfig::new(2),
+            )
+            .unwrap();
+            result
+                .senators
+                .iter()
+                .map(|(c, _)| c.id)
+                .collect::<Vec<_>>()
+        };
+        let first = senators();
+        assert_eq!(first.len(), 2);
+        for _ in 0..10 {
+            assert_eq!(senators(), first);
+        }
+
+        // With a seed the tie is broken at random, and the result is decided.
+        let result = count(&ElectionConfig::builder(2).tie_break_seed(1).build())
+            .decided()
+            .unwrap();
+        assert_eq!(result.num_elected(), 2);
+        assert!(result.tied());
+
+        // A policy that never breaks a tie leaves it undecided, seed or not.
+        let unresolved = ElectionConfig::builder(2)
+            .tie_break(Unresolved)
+            .tie_break_seed(1)
+            .build();
+        assert!(count(&unresolved).decided().is_none());
+    }
+
+    #[test]
+    fn more_papers_than_u32() {
+        // Around 12 billion papers, but only a handful of distinct preference sequences.
+        let billion = 1_000_000_000;
+        let ballots = vec![
+            Ballot::multi(5 * billion, ids(&[0, 1])),
+            Ballot::multi(3 * billion + billion / 2, ids(&[1])),
+            Ballot::multi(2 * billion + billion / 2, ids(&[2])),
+            Ballot::multi(billion, ids(&[3, 1])),
+        ];
+        for &arithmetic in &[Arithmetic::Exact, Arithmetic::Scaled, Arithmetic::Float] {
+            let config = ElectionConfig::builder(2).arithmetic(arithmetic).build();
+            let result = decide_election(
+                &test_candidates(4),
+                &[],
+                ballots.clone().into_iter().map(Ok),
+                &config,
+            )
+            .unwrap();
+            assert_eq!(result.stats.num_valid_votes(), 12 * billion);
+            assert_eq!(result.quota, Int::from(4 * billion + 1));
+            assert_eq!(elected_ids(&result), ids(&[0, 1]));
+        }
+    }
+
+    #[test]
+    fn inclusive_vs_weighted_gregory() {
+        // A = 0, B = 1, C = 2, D = 3, E = 4.
+        // B's surplus is made up of papers at full value (which flow to D) and papers
+        // received from A at a reduced value (which flow to C). The weighted method favours D.
+        let ballots: Vec<_> = repeat(50, &[0, 1, 2])
+            .into_iter()
+            .chain(repeat(15, &[1, 3]))
+            .chain(repeat(1, &[2]))
+            .chain(repeat(11, &[3]))
+            .chain(repeat(3, &[4]))
+            .collect();
+        let candidates = test_candidates(5);
+
+        let run = |surplus_transfer, arithmetic| {
+            let ballots = ballots.iter().cloned().map(Ok);
+            let config = ElectionConfig {
+                surplus_transfer,
+                arithmetic,
+                ..ElectionConfig::new(3)
+            };
+            decide_election(&candidates, &[], ballots, &config).unwrap()
+        };
+
+        for &arithmetic in &[Arithmetic::Exact, Arithmetic::Scaled, Arithmetic::Float] {
+            let igm = run(SurplusTransfer::InclusiveGregory, arithmetic);
+            let wigm = run(SurplusTransfer::WeightedInclusiveGregory, arithmetic);
+
+            assert_eq!(elected_ids(&igm), ids(&[0, 1, 2]));
+            assert_eq!(elected_ids(&wigm), ids(&[0, 1, 3]));
+        }
+    }
+
+    #[test]
+    fn weighted_ballots_count_like_individual_ballots() {
+        let individual: Vec<_> = repeat(7, &[0, 1])
+            .into_iter()
+            .chain(repeat(2, &[1]))
+            .chain(repeat(4, &[2, 1]))
+            .chain(repeat(3, &[3]))
+            .collect();
+        let weighted = vec![
+            Ballot::multi(5, ids(&[0, 1])),
+            Ballot::multi(2, ids(&[0, 1])),
+            Ballot::multi(2, ids(&[1])),
+            Ballot::multi(4, ids(&[2, 1])),
+            Ballot::multi(3, ids(&[3])),
+        ];
+        let candidates = test_candidates(4);
+        let config = ElectionConfig::new(2);
+
+        let run = |ballots: Vec<Ballot>| {
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap()
+        };
+        let individual = run(individual);
+        let weighted = run(weighted);
+
+        assert_eq!(individual.senators.len(), 2);
+        assert_eq!(
+            individual
+                .senators
+                .iter()
+                .map(|(c, v)| (c.id, v))
+                .collect::<Vec<_>>(),
+            weighted
+                .senators
+                .iter()
+                .map(|(c, v)| (c.id, v))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(weighted.stats.num_valid_votes(), 16);
+        assert_eq!(
+            individual.stats.exhausted_vote_value(),
+            weighted.stats.exhausted_vote_value()
+        );
+    }
+
+    #[test]
+    fn exhausted_ballots() {
+        let ballots: Vec<_> = repeat(5, &[0])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let candidates = test_candidates(3);
+
+        let result = decide_election(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(1),
+        )
+        .unwrap();
+
+        assert_eq!(elected_ids(&result), ids(&[0]));
+        let exhausted = &result.stats.exhausted_votes;
+        assert_eq!(exhausted[&1], (2, frac!(2u64)));
+        assert_eq!(exhausted[&2], (3, frac!(3u64)));
+        assert_eq!(result.stats.num_exhausted_ballots(), 5);
+        assert_eq!(result.stats.exhausted_vote_value(), frac!(5u64));
+        assert_eq!(result.stats.exhaustion_rate(), 0.5);
+
+        // Candidate 2 is excluded at count 2, and candidate 1 at count 3, leaving candidate 0 to be
+        // elected at count 4.
+        let curve: Vec<_> = result
+            .exhaustion_curve()
+            .iter()
+            .map(|p| (p.count, p.ballots, p.fraction))
+            .collect();
+        assert_eq!(
+            curve,
+            vec![(1, 0, 0.0), (2, 2, 0.2), (3, 5, 0.5), (4, 5, 0.5)]
+        );
+    }
+
+    #[test]
+    fn reproducible_order() {
+        // Candidates 0 to 3 all reach a quota with equal votes at the first count, and 4 to 7 are
+        // elected as the last ones standing with equal votes. Each count builds new hash maps,
+        // with their own iteration orders.
+        let ballots: Vec<_> = (0..8)
+            .flat_map(|c| repeat(if c < 4 { 10 } else { 2 }, &[c]))
+            .collect();
+        let config = ElectionConfig::builder(8).tie_break_seed(7).build();
+        let count = || {
+            let result = decide_election(
+                &test_candidates(8),
+                &[],
+                ballots.clone().into_iter().map(Ok),
+                &config,
+            )
+            .unwrap();
+            (
+                elected_ids(&result),
+                result.elections,
+                result.final_standings,
+            )
+        };
+        let first = count();
+        assert_eq!(first.0[4..], ids(&[4, 5, 6, 7])[..]);
+        for _ in 0..10 {
+            assert_eq!(count(), first);
+        }
+    }
+
+    #[test]
+    fn count_formal_ballots() {
+        let candidates = test_candidates(3);
+        let boxes = ids(&[0, 1, 2]);
+        let rows = ["1,2,", "2,1,", ",,", "1,,2", "2,2,", "1,3,2"];
+        let (ballots, tally) =
+            formal_ballots(rows.iter(), &[], &boxes, FormalityRules::strict(1, 1));
+        let result = decide_election(&candidates, &[], ballots, &ElectionConfig::new(1)).unwrap();
+        assert_eq!(elected_ids(&result), ids(&[0]));
+        // Only the formal ballots reach the count.
+        assert_eq!(result.stats.num_valid_votes(), 4);
+        assert_eq!(result.stats.num_invalid_votes(), 0);
+        assert_eq!(tally.borrow().total(), 2);
+    }
+
+    #[test]
+    fn skip_malformed_ballots() {
+        let candidates = test_candidates(2);
+        let ballots = || {
+            let mut rows = csv::Reader::from_reader("a,b\n1\n".as_bytes());
+            let bad_row = rows.records().next().unwrap().unwrap_err();
+            vec![
+                Ok(Ballot::single(ids(&[0]))),
+                Err(InputError(Error::Csv(bad_row))),
+                Err(InvalidBallot(NoFirstPreference)),
+                Ok(Ballot::single(ids(&[0, 1]))),
+            ]
+        };
+
+        assert!(decide_election(&candidates, &[], ballots(), &ElectionConfig::new(1)).is_err());
+
+        let config = ElectionConfig {
+            malformed_ballots: MalformedBallots::Skip,
+            ..ElectionConfig::new(1)
+        };
+        let result = decide_election(&candidates, &[], ballots(), &config).unwrap();
+        assert_eq!(elected_ids(&result), ids(&[0]));
+        assert_eq!(result.stats.num_valid_votes(), 2);
+        assert_eq!(result.stats.num_invalid_votes(), 1);
+        assert_eq!(
+            result.stats.malformed_rows()[&MalformedRow::WrongNumberOfFields],
+            1
+        );
+
+        // Validating reads the ballots the same way, without counting them.
+        assert!(validate_ballots(ballots(), &ElectionConfig::new(1)).is_err());
+        let stats = validate_ballots(ballots(), &config).unwrap();
+        assert_eq!(stats.num_valid_votes(), 2);
+        assert_eq!(stats.num_invalid_votes(), 1);
+        assert_eq!(stats.num_malformed_rows(), 1);
+
+        // IO errors can't be skipped.
+        let io_error = io::Error::other("disk on fire");
+        let ballots = vec![Err(InputError(Error::Io(io_error)))];
+        assert!(decide_election(&candidates, &[], ballots, &config).is_err());
+    }
+
+    #[test]
+    fn recount_for_vacancy_uses_original_quota() {
+        let ballots: Vec<_> = repeat(5, &[0])
+            .into_iter()
+            .chain(repeat(4, &[1]))
+            .chain(repeat(3, &[2]))
+            .chain(repeat(2, &[3, 2]))
+            .collect();
+        let candidates = test_candidates(4);
+        let config = ElectionConfig::new(3);
+
+        // Quota = floor(14 / 4) + 1 = 4.
+        let original =
+            decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config).unwrap();
+        assert_eq!(elected_ids(&original), ids(&[0, 1, 2]));
+
+        // With candidate 0 gone, candidate 1 has the original quota on first preferences.
+        let recount = recount_for_vacancy(
+            ballots.iter().cloned().map(Ok),
+            CandidateId(0),
+            &candidates,
+            &original.quota,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(recount.quota, Int::from(4));
+        assert_eq!(elected_ids(&recount), ids(&[1]));
+
+        // Whereas a fresh count for one vacancy would elect candidate 2 on preferences.
+        let fresh = decide_election(
+            &candidates,
+            &[CandidateId(0)],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(1),
+        )
+        .unwrap();
+        assert_eq!(elected_ids(&fresh), ids(&[2]));
+    }
+
+    #[test]
+    fn progress_events() {
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(1, &[2, 1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let candidates = test_candidates(3);
+
+        let mut events = vec![];
+        decide_election_with_progress(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(2),
+            |event| events.push(event),
+        )
+        .unwrap();
+
+        // Quota = 5, as in the transcript test below.
+        assert_eq!(
+            events,
+            vec![
+                CountEvent::QuotaReached {
+                    count: 1,
+                    candidate: CandidateId(0),
+                    votes: Int::from(6),
+                },
+                CountEvent::SurplusTransfer {
+                    count: 2,
+                    candidate: CandidateId(0),
+                    papers: 6,
+                    transfer_values: vec![frac!(1u64, 6u64)],
+                },
+                CountEvent::Exclusion {
+                    count: 3,
+                    candidates: ids(&[2]),
+                    papers: 3,
+                    transfer_values: vec![frac!(1u64)],
+                },
+                CountEvent::QuotaReached {
+                    count: 3,
+                    candidate: CandidateId(1),
+                    votes: Int::from(5),
+                },
+                // Every vacancy is filled, so the count stops without transferring candidate 1's
+                // surplus.
+                CountEvent::FinalResult {
+                    elected: ids(&[0, 1]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn election_order() {
+        let ballots: Vec<_> = repeat(6, &[0])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let candidates = test_candidates(3);
+        let result = decide_election(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(2),
+        )
+        .unwrap();
+
+        // Quota = 4, which only candidate 0 reaches. Candidate 1 is left standing after candidate
+        // 2 is excluded.
+        assert_eq!(
+            result.elections,
+            vec![
+                Election {
+                    elected_order: 1,
+                    elected_at_count: 1,
+                    elected_by: ElectedBy::Quota,
+                    quota_percentage_at_election: 150.0,
+                    papers: 6,
+                    elected_on: Some(ElectedOn {
+                        action: CountAction::FirstPreferences,
+                        from: vec![],
+                    }),
+                },
+                Election {
+                    elected_order: 2,
+                    elected_at_count: 4,
+                    elected_by: ElectedBy::Remaining,
+                    quota_percentage_at_election: 75.0,
+                    papers: 3,
+                    elected_on: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn exact_quota_has_no_surplus() {
+        // Quota = 6, which candidate 0 reaches exactly on first preferences. With no surplus to
+        // transfer, the next count excludes candidate 4, whose ballots elect candidate 1.
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(5, &[1]))
+            .chain(repeat(4, &[2]))
+            .chain(repeat(3, &[3, 2]))
+            .chain(repeat(2, &[4, 1]))
+            .collect();
+        let candidates = test_candidates(5);
+        for &method in &[
+            SurplusTransfer::InclusiveGregory,
+            SurplusTransfer::WeightedInclusiveGregory,
+            SurplusTransfer::LastParcel,
+        ] {
+            let config = ElectionConfig::builder(3)
+                .transfer(method)
+                .transcript(true)
+                .build();
+            let result =
+                decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config)
+                    .unwrap();
+            check_votes_conserved(&result, 20);
+
+            let counts = &result.transcript.as_ref().unwrap().counts;
+            assert_eq!(counts[0].elected, ids(&[0]));
+            assert_eq!(counts[1].action, CountAction::Exclusion(ids(&[4])));
+            assert_eq!(counts[1].elected, ids(&[1]));
+            assert!(!counts
+                .iter()
+                .any(|c| c.action == CountAction::Surplus(CandidateId(0))));
+            assert_eq!(result.surplus_transfers, 1);
+            assert_eq!(elected_ids(&result), ids(&[0, 1, 2]));
+            assert_eq!(result.senators[0].1, Int::from(6));
+        }
+    }
+
+    #[test]
+    fn territory_count() {
+        // Two seats, as for the ACT, so the quota is a third of the votes plus one. Candidate 0
+        // has a quota on first preferences, and candidate 2 falls one vote short of the second
+        // one, which they win as the last candidate standing.
+        let ballots: Vec<_> = repeat(40, &[0, 1])
+            .into_iter()
+            .chain(repeat(33, &[2, 3]))
+            .chain(repeat(15, &[4, 1, 0]))
+            .chain(repeat(12, &[5, 3]))
+            .collect();
+        let candidates = test_candidates(6);
+        let vacancies = num_vacancies("ACT", ElectionType::HalfSenate).unwrap();
+        let config = ElectionConfig::builder(vacancies).transcript(true).build();
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+        check_votes_conserved(&result, 100);
+
+        assert_eq!(result.quota, Int::from(34));
+        assert_eq!(elected_ids(&result), ids(&[0, 2]));
+        assert_eq!(result.elections[0].elected_at_count, 1);
+        assert_eq!(result.elections[0].elected_by, ElectedBy::Quota);
+        assert_eq!(result.elections[1].elected_by, ElectedBy::Remaining);
+    }
+
+    #[test]
+    fn simultaneous_quotas() {
+        let ballots: Vec<_> = repeat(6, &[0, 3])
+            .into_iter()
+            .chain(repeat(8, &[1, 2]))
+            .chain(repeat(1, &[2]))
+            .chain(repeat(1, &[3]))
+            .collect();
+        let candidates = test_candidates(4);
+        let config = ElectionConfig::builder(3).transcript(true).build();
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+
+        // Quota = 5, which candidates 0 and 1 both reach on first preferences. Candidate 1 has
+        // more votes, so is elected first and has their surplus transferred first.
+        assert_eq!(elected_ids(&result)[..2], ids(&[1, 0])[..]);
+        let counts = result.transcript.unwrap().counts;
+        assert_eq!(counts[0].elected, ids(&[1, 0]));
+        assert_eq!(counts[1].action, CountAction::Surplus(CandidateId(1)));
+        assert_eq!(counts[2].action, CountAction::Surplus(CandidateId(0)));
+    }
+
+    #[test]
+    fn elect_remaining_before_quotas() {
+        // Quota = 7. Once candidates 4 and 3 are excluded, candidate 0 reaches a quota with a
+        // surplus, but that leaves as many continuing candidates as vacancies, so the surplus isn't
+        // transferred.
+        let ballots: Vec<_> = repeat(6, &[0])
+            .into_iter()
+            .chain(repeat(6, &[1]))
+            .chain(repeat(6, &[2]))
+            .chain(repeat(5, &[3, 0]))
+            .chain(repeat(1, &[4]))
+            .collect();
+        let candidates = test_candidates(5);
+        let mut events = vec![];
+        let result = decide_election_with_progress(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(3),
+            |event| events.push(event),
+        )
+        .unwrap();
+
+        assert_eq!(elected_ids(&result), ids(&[0, 1, 2]));
+        let by: Vec<_> = result.elections.iter().map(|e| e.elected_by).collect();
+        assert_eq!(
+            by,
+            vec![ElectedBy::Quota, ElectedBy::Remaining, ElectedBy::Remaining]
+        );
+        assert!(events
+            .iter()
+            .all(|e| !matches!(e, CountEvent::SurplusTransfer { .. })));
+    }
+
+    #[test]
+    fn fewer_candidates_than_vacancies() {
+        let ballots: Vec<_> = repeat(5, &[0, 1])
+            .into_iter()
+            .chain(repeat(2, &[1]))
+            .collect();
+        let result = decide_election(
+            &test_candidates(2),
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(3),
+        )
+        .unwrap();
+        assert_eq!(elected_ids(&result), ids(&[0, 1]));
+        assert_eq!(result.unfilled_vacancies(), 1);
+        assert!(!result.is_complete());
+
+        // Every candidate disqualified.
+        let result = decide_election(
+            &test_candidates(1),
+            &[CandidateId(0)],
+            repeat(3, &[0]).into_iter().map(Ok),
+            &ElectionConfig::new(1),
+        )
+        .unwrap();
+        assert_eq!(result.num_elected(), 0);
+        assert_eq!(result.unfilled_vacancies(), 1);
+
+        // Many more vacancies than candidates, as when counting a territory as a state.
+        let ballots: Vec<_> = repeat(4, &[0, 1])
+            .into_iter()
+            .chain(repeat(3, &[1, 2]))
+            .chain(repeat(1, &[2]))
+            .collect();
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(12),
+        )
+        .unwrap();
+        assert_eq!(result.num_elected(), 3);
+        assert_eq!(result.unfilled_vacancies(), 9);
+        assert!(!result.is_complete());
+    }
+
+    #[test]
+    fn no_vacancies() {
+        let result = decide_election(
+            &test_candidates(2),
+            &[],
+            repeat(1, &[0]).into_iter().map(Ok),
+            &ElectionConfig::new(0),
+        );
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn no_candidates() {
+        let result = decide_election(
+            &CandidateMap::new(),
+            &[],
+            iter::empty(),
+            &ElectionConfig::new(2),
+        );
+        assert!(matches!(result, Err(Error::NoCandidates { state: None })));
+    }
+
+    #[test]
+    fn ballot_papers() {
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(1, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+        )
+        .unwrap();
+        // Quota = 4. Candidate 0's surplus of 2 is transferred on all 6 of their papers, leaving
+        // candidate 1 with 7 papers worth 3 votes.
+        let transcript = result.transcript.as_ref().unwrap();
+        let tally = &transcript.counts[1].votes[&CandidateId(1)];
+        assert_eq!((tally.total.clone(), tally.papers), (Int::from(3), 7));
+        assert_eq!(tally.papers_received, 6);
+
+        let standing = |id| {
+            let s = result
+                .final_standings
+                .iter()
+                .find(|s| s.id == CandidateId(id));
+            s.map(|s| (s.votes.clone(), s.papers)).unwrap()
+        };
+        assert_eq!(standing(0), (Int::from(6), 6));
+        assert_eq!(standing(1), (Int::from(3), 7));
+        assert_eq!(standing(2), (Int::from(2), 2));
+        assert_eq!(result.elections[1].papers, 7);
+    }
+
+    #[test]
+    fn final_standings() {
+        let ballots: Vec<_> = repeat(5, &[0])
+            .into_iter()
+            .chain(repeat(2, &[1, 0]))
+            .chain(repeat(4, &[2]))
+            .chain(repeat(1, &[3, 2]))
+            .chain(repeat(3, &[4]))
+            .collect();
+        let result = decide_election(
+            &test_candidates(5),
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(1),
+        )
+        .unwrap();
+        // Every ballot is at full value, so each candidate holds as many papers as votes.
+        let standing = |id, status, votes: u64, count| CandidateStanding {
+            id: CandidateId(id),
+            status,
+            votes: Int::from(votes),
+            papers: votes,
+            count,
+        };
+        // Candidate 0 is elected as the last one standing, after the others are excluded in
+        // order of their votes.
+        assert_eq!(
+            result.final_standings,
+            vec![
+                standing(0, Status::Elected, 7, 6),
+                standing(2, Status::Excluded, 5, 5),
+                standing(4, Status::Excluded, 3, 4),
+                standing(1, Status::Excluded, 2, 3),
+                standing(3, Status::Excluded, 1, 2),
+            ]
+        );
+
+        // Candidates still continuing once the vacancy is filled.
+        let ballots: Vec<_> = repeat(9, &[0])
+            .into_iter()
+            .chain(repeat(1, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let config = ElectionConfig::new(1);
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            result.final_standings,
+            vec![
+                standing(0, Status::Elected, 9, 1),
+                standing(2, Status::Continuing, 2, 1),
+                standing(1, Status::Continuing, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn recalculate_quota_on_exhaustion() {
+        let ballots = || {
+            repeat(10, &[0])
+                .into_iter()
+                .chain(repeat(8, &[1]))
+                .chain(repeat(4, &[2]))
+                .chain(repeat(3, &[3]))
+                .chain(repeat(2, &[4]))
+                .map(Ok)
+        };
+        let candidates = test_candidates(5);
+
+        // With a fixed quota of 10, candidate 1 is only elected as one of the last two standing.
+        let fixed = decide_election(&candidates, &[], ballots(), &ElectionConfig::new(2)).unwrap();
+        assert_eq!(elected_ids(&fixed), ids(&[0, 1]));
+        assert_eq!(fixed.quota, Int::from(10));
+        assert_eq!(fixed.elections[1].elected_by, ElectedBy::Remaining);
+
+        // Once candidates 2 and 3 are excluded together and 9 votes exhaust, the quota drops to 7.
+        let config = ElectionConfig::builder(2)
+            .recalculate_quota_on_exhaustion(true)
+            .build();
+        let reducing = decide_election(&candidates, &[], ballots(), &config).unwrap();
+        assert_eq!(elected_ids(&reducing), ids(&[0, 1]));
+        assert_eq!(reducing.quota, Int::from(7));
+        assert_eq!(reducing.elections[1].elected_by, ElectedBy::Quota);
+
+        // Candidate 0's surplus is transferred at 1/5 a ballot, losing 4/5 of a vote to fractions.
+        // With 26/5 votes exhausted by the end, 6 votes are out of the count, leaving 17 and a
+        // quota of 6.
+        let ballots = repeat(1, &[0, 1])
+            .into_iter()
+            .chain(repeat(2, &[0, 2]))
+            .chain(repeat(7, &[0, 3]))
+            .chain(repeat(5, &[3]))
+            .chain(repeat(3, &[4]))
+            .chain(repeat(3, &[2]))
+            .chain(repeat(2, &[1]))
+            .map(Ok);
+        let config = ElectionConfig::builder(2)
+            .transfer(SurplusTransfer::WeightedInclusiveGregory)
+            .recalculate_quota_on_exhaustion(true)
+            .build();
+        let lossy = decide_election(&candidates, &[], ballots, &config).unwrap();
+        assert_eq!(elected_ids(&lossy), ids(&[0, 3]));
+        assert_eq!(lossy.stats.loss_by_fraction(), frac!(4u64, 5u64));
+        assert_eq!(lossy.stats.exhausted_vote_value(), frac!(26u64, 5u64));
+        assert_eq!(lossy.quota, Int::from(6));
+    }
+
+    #[test]
+    fn final_margin() {
+        let run = |ballots: Vec<Ballot>| {
+            decide_election(
+                &test_candidates(3),
+                &[],
+                ballots.into_iter().map(Ok),
+                &ElectionConfig::new(2),
+            )
+            .unwrap()
+            .final_margin
+        };
+
+        // Elected as the last candidate standing: decided by the exclusion of candidate 2.
+        let ballots = repeat(6, &[0])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        assert_eq!(
+            run(ballots),
+            Some(FinalMargin {
+                count: 3,
+                elected: CandidateId(1),
+                runner_up: CandidateId(2),
+                margin: Int::from(1),
+            })
+        );
+
+        // Elected on a quota of 5 at the first count.
+        let ballots = repeat(6, &[0])
+            .into_iter()
+            .chain(repeat(5, &[1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        assert_eq!(
+            run(ballots),
+            Some(FinalMargin {
+                count: 1,
+                elected: CandidateId(1),
+                runner_up: CandidateId(2),
+                margin: Int::from(3),
+            })
+        );
+    }
+
+    #[test]
+    fn transcript() {
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(1, &[2, 1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let candidates = test_candidates(3);
+        let config = ElectionConfig {
+            transcript: true,
+            ..ElectionConfig::new(2)
+        };
+
+        // Quota = floor(12 / 3) + 1 = 5.
+        let result =
+            decide_election(&candidates, &[], ballots.into_iter().map(Ok), &config).unwrap();
+        let counts = result.transcript.unwrap().counts;
+
+        assert_eq!(counts[0].action, CountAction::FirstPreferences);
+        assert_eq!(counts[0].votes[&CandidateId(0)].total, Int::from(6));
+        assert_eq!(counts[0].elected, ids(&[0]));
+
+        // Candidate 0's surplus of 1 is transferred at 1/6.
+        assert_eq!(counts[1].action, CountAction::Surplus(CandidateId(0)));
+        assert_eq!(counts[1].transfer_values, vec![frac!(1u64, 6u64)]);
+        assert_eq!(
+            counts[1].parcels,
+            vec![ParcelRecord {
+                transfer_value: frac!(1u64, 6u64),
+                papers: 6,
+                votes: Int::from(1),
+            }]
+        );
+        assert_eq!(counts[1].votes[&CandidateId(1)].received, Int::from(1));
+        assert_eq!(counts[1].votes[&CandidateId(1)].total, Int::from(4));
+        assert!(!counts[1].votes.contains_key(&CandidateId(0)));
+        assert!(counts[1].elected.is_empty());
+
+        // Candidate 2 is excluded, and one of their ballots elects candidate 1.
+        assert_eq!(counts[2].action, CountAction::Exclusion(ids(&[2])));
+        assert_eq!(counts[2].transfer_values, vec![frac!(1u64)]);
+        assert_eq!(counts[2].parcels[0].papers, 3);
+        assert_eq!(counts[2].parcels[0].votes, Int::from(3));
+        assert_eq!(counts[2].votes[&CandidateId(1)].total, Int::from(5));
+        assert_eq!(counts[2].elected, ids(&[1]));
+    }
+
+    #[test]
+    fn summary() {
+        // As in the transcript test: candidate 0's surplus is transferred, then candidate 2 is
+        // excluded, and both of their ballots without a second preference exhaust.
+        let ballots: Vec<_> = repeat(6, &[0, 1])
+            .into_iter()
+            .chain(repeat(3, &[1]))
+            .chain(repeat(1, &[2, 1]))
+            .chain(repeat(2, &[2]))
+            .collect();
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &ElectionConfig::new(2),
+        )
+        .unwrap();
+        assert_eq!(
+            result.summary(),
+            ElectionStats {
+                formal_ballots: 12,
+                informal_ballots: 0,
+                malformed_rows: 0,
+                quota: 5,
+                counts: 3,
+                exclusions: 1,
+                surplus_transfers: 1,
+                exhausted_ballots: 2,
+                exhausted_votes: 2,
+                loss_by_fraction: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn loss_by_fraction() {
+        // Candidate 0's surplus of 3 is transferred at 1/3, giving candidate 1 2 1/3 votes and
+        // candidate 2 2/3 of a vote, which are truncated to 2 and 0.
+        let ballots: Vec<_> = repeat(7, &[0, 1])
+            .into_iter()
+            .chain(repeat(2, &[0, 2]))
+            .chain(repeat(4, &[1]))
+            .chain(repeat(3, &[2]))
+            .collect();
+        let config = ElectionConfig::builder(2).transcript(true).build();
+        let result = decide_election(
+            &test_candidates(3),
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+        )
+        .unwrap();
+        let counts = &result.transcript.as_ref().unwrap().counts;
+        assert_eq!(counts[0].loss_by_fraction, frac!(0u64));
+        assert_eq!(counts[1].votes[&CandidateId(1)].received, Int::from(2));
+        assert_eq!(counts[1].loss_by_fraction, frac!(1u64));
+        assert_eq!(result.stats.loss_by_fraction(), frac!(1u64));
+        assert_eq!(result.summary().loss_by_fraction, 1.0);
+        check_votes_conserved(&result, 16);
+    }
+
+    /// Check that every vote is accounted for at each count of a transcript.
+    ///
+    /// The votes of continuing candidates, senators, surpluses awaiting transfer and exhausted
+    /// ballots must add up to the number of ballot papers, less exactly the loss by fraction
+    /// recorded at each count so far.
+    fn check_votes_conserved(result: &Senate, num_papers: u64) {
+        assert_eq!(result.stats.num_valid_votes(), num_papers);
+        if let Err(e) = audit(result) {
+            panic!("{}", e);
+        }
+    }
+
+    #[test]
+    fn surplus_never_exceeded_in_any_arithmetic() {
+        // Quota = 18. Candidate 0's surplus of 7 over 25 papers is 0.28 a paper, which a
+        // floating point transfer value rounded to nearest would exceed.
+        let ballots = [
+            Ballot::multi(25, ids(&[0, 1])),
+            Ballot::multi(14, ids(&[1])),
+            Ballot::multi(13, ids(&[2])),
+        ];
+        let candidates = test_candidates(3);
+        for &arithmetic in &[Arithmetic::Exact, Arithmetic::Scaled, Arithmetic::Float] {
+            for &method in &[
+                SurplusTransfer::InclusiveGregory,
+                SurplusTransfer::WeightedInclusiveGregory,
+                SurplusTransfer::LastParcel,
+            ] {
+                let config = ElectionConfig::builder(2)
+                    .arithmetic(arithmetic)
+                    .transfer(method)
+                    .transcript(true)
+                    .build();
+                let result =
+                    decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config)
+                        .unwrap();
+                check_votes_conserved(&result, 52);
+                let senators: Vec<_> = result.senators.iter().map(|(c, _)| c.id).collect();
+                assert_eq!(senators, ids(&[0, 1]));
+            }
+        }
+    }
+
+    #[test]
+    fn surplus_remainder_with_exhaustion() {
+        // Quota = 6. Candidate 0's surplus of 5 is spread over 11 papers, 4 of which exhaust, so
+        // the 7 papers going to candidate 1 carry 35/11 votes and only 3 of them count.
+        let ballots = [
+            Ballot::multi(7, ids(&[0, 1])),
+            Ballot::multi(4, ids(&[0])),
+            Ballot::multi(2, ids(&[1])),
+            Ballot::multi(3, ids(&[2])),
+        ];
+        let candidates = test_candidates(3);
+        for &arithmetic in &[Arithmetic::Exact, Arithmetic::Scaled, Arithmetic::Float] {
+            for &method in &[
+                SurplusTransfer::InclusiveGregory,
+                SurplusTransfer::WeightedInclusiveGregory,
+                SurplusTransfer::LastParcel,
+            ] {
+                let config = ElectionConfig::builder(2)
+                    .arithmetic(arithmetic)
+                    .transfer(method)
+                    .transcript(true)
+                    .build();
+                let result =
+                    decide_election(&candidates, &[], ballots.iter().cloned().map(Ok), &config)
+                        .unwrap();
+                check_votes_conserved(&result, 16);
+
+                let surplus_count = &result.transcript.as_ref().unwrap().counts[1];
+                assert_eq!(surplus_count.votes[&CandidateId(1)].total, Int::from(5));
+                if arithmetic == Arithmetic::Exact {
+                    assert_eq!(surplus_count.loss_by_fraction, frac!(2u64, 11u64));
+                    assert_eq!(result.stats.exhausted_votes[&1].1, frac!(20u64, 11u64));
+                }
+            }
+        }
+    }
+
+    /// Elections of 2 to 8 candidates for 1 to 4 vacancies, on ballots each giving preferences
+    /// to some of the candidates in a random order.
+    fn arb_election() -> impl Strategy<Value = (CandidateMap, Vec<Ballot>, usize)> {
+        (2u16..9).prop_flat_map(|num_candidates| {
+            let all: Vec<_> = (0..num_candidates).map(CandidateId).collect();
+            let ballot = (
+                Just(all).prop_shuffle(),
+                1..=num_candidates as usize,
+                1u64..4,
+            )
+                .prop_map(|(mut prefs, len, weight)| {
+                    prefs.truncate(len);
+                    Ballot::multi(weight, prefs)
+                });
+            (
+                Just(test_candidates(num_candidates)),
+                prop::collection::vec(ballot, 10..200),
+                1usize..5,
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn votes_conserved_in_random_elections(
+            (candidates, ballots, num_positions) in arb_election(),
+            method in prop_oneof![
+                Just(SurplusTransfer::InclusiveGregory),
+                Just(SurplusTransfer::WeightedInclusiveGregory),
+                Just(SurplusTransfer::LastParcel),
+            ],
+            arithmetic in prop_oneof![
+                Just(Arithmetic::Exact),
+                Just(Arithmetic::Scaled),
+                Just(Arithmetic::Float),
+            ],
+            seed in any::<usize>(),
+        ) {
+            let num_papers = ballots.iter().map(Ballot::weight).sum();
+            let config = ElectionConfig::builder(num_positions)
+                .transfer(method)
+                .arithmetic(arithmetic)
+                .tie_break_seed(seed)
+                .transcript(true)
+                .build();
+            let ballot_iter = ballots.iter().cloned().map(Ok);
+            let result = decide_election(&candidates, &[], ballot_iter, &config).unwrap();
+            check_votes_conserved(&result, num_papers);
+        }
+    }
+
+    #[test]
+    fn resume_from_checkpoints() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[75usize][..]);
+
+        for _ in 0..20 {
+            let num_candidates = rng.gen_range(3, 9);
+            let candidates = test_candidates(num_candidates);
+            let ballots: Vec<_> = (0..rng.gen_range(5, 40))
+                .map(|_| {
+                    let mut prefs: Vec<_> = (0..num_candidates).map(CandidateId).collect();
+                    rng.shuffle(&mut prefs);
+                    prefs.truncate(rng.gen_range(1, num_candidates as usize + 1));
+                    Ballot::single(prefs)
+                })
+                .collect();
+
+            for &arithmetic in &[Arithmetic::Exact, Arithmetic::Scaled, Arithmetic::Float] {
+                let config = ElectionConfig::builder(rng.gen_range(1, 4))
+                    .arithmetic(arithmetic)
+                    .tie_break_seed(rng.gen())
+                    .transcript(true)
+                    .build();
+                let mut saved = vec![];
+                let result = decide_election_with_checkpoints(
+                    &candidates,
+                    &[CandidateId(0)],
+                    ballots.iter().cloned().map(Ok),
+                    &config,
+                    1,
+                    |state| {
+                        saved.push(serde_json::to_string(state).unwrap());
+                        Ok(())
+                    },
+                    |_| (),
+                )
+                .unwrap();
+                let expected = serde_json::to_value(&result).unwrap();
+                let expected_transcript = serde_json::to_value(&result.transcript).unwrap();
+
+                for (i, checkpoint) in saved.iter().enumerate() {
+                    let state: CountState = serde_json::from_str(checkpoint).unwrap();
+                    let mut resaved = vec![];
+                    let resumed = resume_election(
+                        &candidates,
+                        state,
+                        &config,
+                        1,
+                        |state| {
+                            resaved.push(serde_json::to_string(state).unwrap());
+                            Ok(())
+                        },
+                        |_| (),
+                    )
+                    .unwrap();
+
+                    assert_eq!(serde_json::to_value(&resumed).unwrap(), expected);
+                    assert_eq!(
+                        serde_json::to_value(&resumed.transcript).unwrap(),
+                        expected_transcript
+                    );
+                    // The resumed count takes the same checkpoints from then on.
+                    assert_eq!(&resaved[..], &saved[i..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resume_with_different_config() {
+        let candidates = test_candidates(3);
+        let ballots = vec![
+            Ballot::multi(5, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1])),
+            Ballot::multi(2, ids(&[2])),
+        ];
+        let config = ElectionConfig::new(2);
+        let mut saved = None;
+        decide_election_with_checkpoints(
+            &candidates,
+            &[],
+            ballots.into_iter().map(Ok),
+            &config,
+            1,
+            |state| {
+                saved = Some(state.clone());
+                Ok(())
+            },
+            |_| (),
+        )
+        .unwrap();
+        let state = saved.expect("a checkpoint is taken before the second count");
+
+        let other = ElectionConfig::builder(2).tie_break_seed(1).build();
+        assert!(
+            resume_election(&candidates, state.clone(), &other, 1, |_| Ok(()), |_| ()).is_err()
+        );
+        assert!(
+            resume_election(&test_candidates(4), state, &config, 1, |_| Ok(()), |_| ()).is_err()
+        );
+    }
+
+    #[test]
+    fn resumed_progress() {
+        let candidates = test_candidates(4);
+        let ballots = [
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1, 2])),
+            Ballot::multi(2, ids(&[2])),
+            Ballot::multi(3, ids(&[3, 0])),
+        ];
+        let config = ElectionConfig::new(2);
+        let mut events = vec![];
+        decide_election_with_progress(
+            &candidates,
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &config,
+            |event| events.push(event),
+        )
+        .unwrap();
+
+        let mut saved = vec![];
+        let mut checkpointed_events = vec![];
+        decide_election_with_checkpoints(
+            &candidates,
+            &[],
+            ballots.iter().cloned().map(Ok),
+            &config,
+            1,
+            |state| {
+                saved.push(state.clone());
+                Ok(())
+            },
+            |event| checkpointed_events.push(event),
+        )
+        .unwrap();
+        assert_eq!(checkpointed_events, events);
+
+        for state in saved {
+            let count = state.count;
+            let mut resumed_events = vec![];
+            resume_election(
+                &candidates,
+                state,
+                &config,
+                1,
+                |_| Ok(()),
+                |event| resumed_events.push(event),
+            )
+            .unwrap();
+            let expected: Vec<_> = events
+                .iter()
+                .filter(|event| event_count(event).is_none_or(|c| c >= count))
+                .cloned()
+                .collect();
+            assert_eq!(resumed_events, expected);
+        }
+    }
+
+    fn event_count(event: &CountEvent) -> Option<usize> {
+        match *event {
+            CountEvent::QuotaReached { count, .. }
+            | CountEvent::ElectedRemaining { count, .. }
+            | CountEvent::Exclusion { count, .. }
+            | CountEvent::SurplusTransfer { count, .. } => Some(count),
+            CountEvent::FinalResult { .. } => None,
+        }
+    }
+
+    #[test]
+    fn ballot_destinations() {
+        let ballots = vec![
+            Ballot::multi(6, ids(&[0, 1])),
+            Ballot::multi(3, ids(&[1, 2])),
+            Ballot::multi(3, ids(&[2])),
+            Ballot::multi(2, ids(&[3, 0])),
+        ];
+        let candidates = test_candidates(4);
+        let count = |config: &ElectionConfig| {
+            decide_election(
+                &candidates,
+                &[],
+                ballots.clone().into_iter().map(Ok),
+                config,
+            )
+            .unwrap()
+        };
+
+        let result = count(&ElectionConfig::new(2));
+        assert!(result.stats.ballot_destinations().is_none());
+
+        // Candidate 0 is elected at count 1 and their surplus goes to candidate 1 at 1/6 of a
+        // vote. Candidate 3 is excluded at count 3, and their ballots skip candidate 0 and
+        // exhaust, then candidate 2 is excluded at count 4, leaving candidate 1 to be elected.
+        let result = count(&ElectionConfig::builder(2).ballot_destinations(true).build());
+        assert_eq!(elected_ids(&result), ids(&[0, 1]));
+        let destinations = result.stats.ballot_destinations().unwrap();
+        assert_eq!(destinations.len(), 4);
+        assert_eq!(
+            destinations[&ids(&[0, 1])],
+            BallotDestination::Candidate {
+                candidate: CandidateId(1),
+                elected: true,
+                value: frac!(1u64, 6u64),
+            }
+        );
+        assert_eq!(
+            destinations[&ids(&[1, 2])],
+            BallotDestination::Candidate {
+                candidate: CandidateId(1),
+                elected: true,
+                value: frac!(1u64),
+            }
+        );
+        assert_eq!(
+            destinations[&ids(&[3, 0])],
+            BallotDestination::Exhausted {
+                count: 3,
+                value: frac!(1u64),
+            }
+        );
+        assert_eq!(
+            destinations[&ids(&[2])],
+            BallotDestination::Exhausted {
+                count: 4,
+                value: frac!(1u64),
+            }
+        );
+
+        let json = serde_json::to_value(&result).unwrap();
+        let first = &json["ballot_destinations"][0];
+        assert_eq!(first["preferences"], serde_json::json!([0, 1]));
+        assert_eq!(first["outcome"], "candidate");
+        assert_eq!(first["value"], "1/6");
+    }
 }
@@ -0,0 +1,87 @@
+//! Runs of the `election2016` binary on the small election in `tests/data/small`, for how it
+//! reports its results and the result of a check.
+
+extern crate serde_json;
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn tmp_path(name: &str) -> PathBuf {
+    [env!("CARGO_TARGET_TMPDIR"), name].iter().collect()
+}
+
+fn run_small(args: &[&str]) -> Output {
+    let results_dir = tmp_path("cli-results");
+    Command::new(env!("CARGO_BIN_EXE_election2016"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args([
+            "--candidates",
+            "tests/data/small/candidates.csv",
+            "--prefs",
+            "tests/data/small/prefs.csv",
+            "--state",
+            "TAS",
+            "--seats",
+            "2",
+        ])
+        .arg("--results-dir")
+        .arg(results_dir)
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn distribution_matches() {
+    let output = run_small(&["--check-distribution", "tests/data/small/dop.csv"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("matches tests/data/small/dop.csv at all 5 counts"));
+}
+
+#[test]
+fn distribution_differs() {
+    let output = run_small(&["--check-distribution", "tests/data/small/dop-wrong.csv"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("at count 5, Lee CHAN (Beta Party) has 11 votes, but 12"));
+}
+
+#[test]
+fn invalid_options() {
+    let output = run_small(&["--all-states"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn only_the_result_on_standard_output() {
+    let transcript = tmp_path("cli-transcript.csv");
+    let output = run_small(&[
+        "--format",
+        "json",
+        "--transcript",
+        transcript.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["quota"], 8);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Wrote transcript to"));
+}
+
+#[test]
+fn groups_only_listed_when_verbose() {
+    let output = run_small(&["--validate"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Groups: 3"));
+    assert!(!stdout.contains("Group A: 2 candidates"));
+    assert!(!stdout.contains("First preferences by group:"));
+
+    let output = run_small(&["--validate", "--verbose"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Group A: 2 candidates"));
+    assert!(stdout.contains("First preferences by group:"));
+}
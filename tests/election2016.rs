@@ -1,9 +1,13 @@
-//! You need to have downloaded the CSV data from the AEC before running these tests.
+//! You need to have downloaded the CSV data from the AEC before running the ignored tests.
 //! Run with `cargo test --release -- --ignored`
+//!
+//! The others run on the small elections in `tests/data`.
 
 extern crate aus_senate;
 
+use aus_senate::config::ElectionConfig;
 use aus_senate::election2016;
+use aus_senate::senate_result::ElectedBy;
 use aus_senate::util::Int;
 
 #[test]
@@ -12,9 +16,9 @@ fn nsw_2016_election() {
     let num_senators = 12;
     let result = election2016::run(
         "data/candidate_ordering.csv",
-        "data/NSW.csv",
+        &["data/NSW.csv"],
         "NSW",
-        num_senators,
+        &ElectionConfig::new(num_senators),
     )
     .unwrap();
     let expected = vec![
@@ -42,3 +46,134 @@ fn nsw_2016_election() {
         assert_eq!(obs_count, &Int::from(*exp_count));
     }
 }
+
+#[test]
+#[ignore]
+fn nsw_2016_without_candidate() {
+    let config = ElectionConfig::new(12);
+    let baseline = election2016::run(
+        "data/candidate_ordering.csv",
+        &["data/NSW.csv"],
+        "NSW",
+        &config,
+    )
+    .unwrap();
+    let (last, _) = baseline.senators.last().unwrap();
+
+    let result = election2016::run_without_candidate(
+        "data/candidate_ordering.csv",
+        &["data/NSW.csv"],
+        "NSW",
+        last.id,
+        &config,
+    )
+    .unwrap();
+    assert_eq!(result.senators.len(), 12);
+    assert!(result.senators.iter().all(|(c, _)| c.id != last.id));
+    assert!(result.stats.num_valid_votes() <= baseline.stats.num_valid_votes());
+}
+
+#[test]
+#[ignore]
+fn tas_2016_election() {
+    let num_senators = 12;
+    let result = election2016::run(
+        "data/candidate_ordering.csv",
+        &["data/TAS.csv"],
+        "TAS",
+        &ElectionConfig::new(num_senators),
+    )
+    .unwrap();
+    let elected: Vec<_> = result
+        .senators
+        .iter()
+        .map(|(c, _)| format!("{} {}", c.other_names, c.surname))
+        .collect();
+
+    // In the order they were elected.
+    let expected = vec![
+        "Eric ABETZ",
+        "Anne URQUHART",
+        "Nick McKIM",
+        "Jacqui LAMBIE",
+        "Stephen PARRY",
+        "Carol BROWN",
+        "Jonathon DUNIAM",
+        "Helen POLLEY",
+        "David BUSHBY",
+        "Catryna BILYK",
+        "Peter WHISH-WILSON",
+        "Lisa SINGH",
+    ];
+    assert_eq!(elected, expected);
+}
+
+/// A double dissolution count in Tasmania, on 1300 generated ballots in the AEC's formats rather
+/// than the real ones, shaped so that the order of election can be worked out by hand.
+///
+/// Most ballots are above the line for one group: 520 for the Harbour Party (A), 410 for the
+/// River Party (B), 150 for the Forest Party (C) and 110 for the Lambert Network (D). With the
+/// quota at 101, the heads of those four tickets are elected at count 1, in order of votes, and
+/// each surplus runs down its ticket in the order the candidates were elected, electing nine
+/// more Harbour and River candidates between them. The last seat goes to EDWARDS of the Coast
+/// Alliance, who starts with 60 votes and gets the 20 ballots below the line for the ungrouped
+/// UNWIN once the candidates those ballots go through next are excluded.
+#[test]
+fn tas_generated_election() {
+    let result = election2016::run(
+        "tests/data/tas/candidates.csv",
+        &["tests/data/tas/prefs.csv"],
+        "TAS",
+        &ElectionConfig::new(12),
+    )
+    .unwrap();
+    assert_eq!(result.quota, Int::from(101));
+
+    let elected: Vec<_> = result
+        .senators
+        .iter()
+        .zip(&result.elections)
+        .map(|((c, _), e)| (&c.surname[..], e.elected_at_count, e.elected_by))
+        .collect();
+    let expected = vec![
+        ("ALLEN", 1, ElectedBy::Quota),
+        ("BAKER", 1, ElectedBy::Quota),
+        ("CARTER", 1, ElectedBy::Quota),
+        ("DAWSON", 1, ElectedBy::Quota),
+        ("ASHBY", 2, ElectedBy::Quota),
+        ("BARNES", 3, ElectedBy::Quota),
+        // Counts 4 and 5 transfer the small surpluses of CARTER and DAWSON.
+        ("ATKINS", 6, ElectedBy::Quota),
+        ("BELL", 7, ElectedBy::Quota),
+        ("AVERY", 8, ElectedBy::Quota),
+        ("BISHOP", 9, ElectedBy::Quota),
+        ("AYRES", 10, ElectedBy::Quota),
+        ("EDWARDS", 23, ElectedBy::Remaining),
+    ];
+    assert_eq!(elected, expected);
+}
+
+#[test]
+#[ignore]
+fn act_2016_election() {
+    let result = election2016::run(
+        "data/candidate_ordering.csv",
+        &["data/ACT.csv"],
+        "ACT",
+        &ElectionConfig::new(2),
+    )
+    .unwrap();
+
+    // A third of the formal votes, plus one.
+    assert_eq!(result.quota, Int::from(84923));
+    assert_eq!(
+        result.quota,
+        Int::from(result.stats.num_valid_votes() / 3 + 1)
+    );
+    let elected: Vec<_> = result
+        .senators
+        .iter()
+        .map(|(c, _)| format!("{} {}", c.other_names, c.surname))
+        .collect();
+    assert_eq!(elected, vec!["Katy GALLAGHER", "Zed SESELJA"]);
+}